@@ -0,0 +1,511 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use futures::future;
+use tokio::sync::mpsc;
+use tokio::time;
+use crate::buffer::{BufferPool, PacketBuf, HEADROOM};
+use crate::client::{Client, ClientOptions};
+use crate::error::{Result, VswitchError};
+use crate::server::{Server, ServerOptions};
+use crate::transport::{ListenAddr, ServerEndpoint};
+use crate::tun::{MemoryPort, TunDevice};
+use crate::vnet;
+
+/// 模拟客户端数上限，虚拟IP取自同一个 /24 网段
+pub const MAX_CLIENTS: usize = 250;
+/// 最小数据包大小，容纳IPv4头部、UDP头部和测量数据
+pub const MIN_PACKET_SIZE: usize = 64;
+/// 最大数据包大小，与TUN设备的默认MTU相同
+pub const MAX_PACKET_SIZE: usize = 1500;
+/// 服务端内存中的设备名称
+pub const SERVER_DEVICE: &str = "bench-server";
+/// 等待所有模拟客户端连通的最长时间
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(15);
+/// 连通前重复发送预热数据包的间隔
+const WARMUP_INTERVAL: Duration = Duration::from_millis(100);
+/// 发送结束后等待在途数据包到达的最长时间
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+/// 检查在途数据包是否全部到达的间隔
+const DRAIN_INTERVAL: Duration = Duration::from_millis(50);
+/// 接收任务一次最多取出的数据包数
+const RECEIVE_BATCH: usize = 256;
+/// 测量数据在UDP载荷中的偏移，位于IPv4头部和UDP头部之后
+const PAYLOAD_OFFSET: usize = 28;
+/// 延迟直方图每个数量级的子桶数的位数，相对误差不超过 1/128
+const SUB_BUCKET_BITS: u32 = 7;
+
+/// 测量期间是否统计内存分配
+static COUNTING: AtomicBool = AtomicBool::new(false);
+/// 测量期间的内存分配次数
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// 统计内存分配次数的全局分配器
+///
+/// 只在基准测试测量期间计数，其余时间只多一次原子读取。
+/// 需要由可执行文件以 `#[global_allocator]` 安装，否则报告的分配次数为0
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count_allocation() {
+    if COUNTING.load(Ordering::Relaxed) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 基准测试选项
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// 模拟客户端数
+    pub clients: usize,
+    /// 依次测试的数据包大小
+    pub packet_sizes: Vec<usize>,
+    /// 所有客户端合计的发送速率 (数据包/秒)，0 表示尽快发送
+    pub rate: u64,
+    /// 每种数据包大小的测试时长
+    pub duration: Duration,
+    /// 服务端设备队列数
+    pub queues: usize,
+    /// 服务端为每个队列绑定一个 SO_REUSEPORT UDP套接字
+    pub reuseport: bool,
+}
+
+/// 运行基准测试
+///
+/// 服务端和模拟客户端在进程内经回环地址通信，使用内存中的设备代替TUN设备：
+/// 服务端设备写入的数据包按目标IP放回设备的读取队列，模拟系统协议栈的路由；
+/// 客户端 i 向客户端 i+1 发送带有序号和发送时间的UDP数据包，经服务端中转后在目标客户端的设备上收到。
+/// 每种数据包大小测试一轮，报告吞吐、延迟分位数和每个数据包的平均内存分配次数
+pub async fn run(options: BenchOptions) -> Result<()> {
+    let listen_addr = free_loopback_port()?;
+    let listen: [ListenAddr; 1] = [listen_addr.to_string().parse()?];
+    let endpoint: ServerEndpoint = listen_addr.to_string().parse()?;
+
+    let (tun, port) = TunDevice::memory(SERVER_DEVICE, options.queues);
+    let server = Server::new(tun, ServerOptions {
+        reuseport: options.reuseport,
+        ..Default::default()
+    });
+    tokio::spawn(reflect(port));
+
+    let epoch = Instant::now();
+    let mut clients = Vec::with_capacity(options.clients);
+    let mut peers = Vec::with_capacity(options.clients);
+    for index in 0..options.clients {
+        let (tun, port) = TunDevice::memory(&format!("bench-client{}", index), 1);
+        clients.push(Client::new(tun, vec![endpoint.clone()], ClientOptions {
+            p2p: false,
+            ..Default::default()
+        }));
+
+        let MemoryPort { mut inject, written } = port;
+        let stats = Arc::new(Mutex::new(Stats::new(0)));
+        tokio::spawn(receive(written, stats.clone(), epoch));
+        peers.push(Peer {
+            ip: Ipv4Addr::new(10, 200, 0, index as u8 + 1),
+            inject: inject.remove(0),
+            stats,
+        });
+    }
+
+    log::info!("基准测试: 服务端 {}, 模拟客户端 {} 个, 服务端队列 {} 个", listen_addr, options.clients, options.queues);
+
+    // 服务端和客户端在当前任务中运行，任一方退出时结束测试
+    let clients_run = future::try_join_all(clients.iter().map(|client| client.run()));
+    tokio::select! {
+        result = server.run(&listen) => {
+            result?;
+            Err(VswitchError::IoError(io::Error::other("服务端已退出")))
+        }
+        result = clients_run => {
+            result?;
+            Err(VswitchError::IoError(io::Error::other("模拟客户端已退出")))
+        }
+        result = measure(&options, &peers, epoch) => result,
+    }
+}
+
+/// 预热后依次测试每种数据包大小并输出报告
+async fn measure(options: &BenchOptions, peers: &[Peer], epoch: Instant) -> Result<()> {
+    warm_up(peers, epoch).await?;
+
+    for (index, &size) in options.packet_sizes.iter().enumerate() {
+        let report = measure_size(options, peers, epoch, index as u32 + 1, size).await;
+        report.print(size);
+    }
+    Ok(())
+}
+
+/// 每个客户端向自己发送数据包直到收到，确认已连接服务端，并让服务端记录各客户端的虚拟IP
+async fn warm_up(peers: &[Peer], epoch: Instant) -> Result<()> {
+    log::info!("等待模拟客户端连接服务端...");
+    let deadline = Instant::now() + WARMUP_TIMEOUT;
+    let mut pool = BufferPool::new();
+    let mut seq = 0;
+    loop {
+        let pending: Vec<&Peer> = peers.iter()
+            .filter(|peer| peer.stats.lock().unwrap().packets == 0)
+            .collect();
+        if pending.is_empty() {
+            break;
+        }
+        if Instant::now() >= deadline {
+            return Err(VswitchError::IoError(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} 个模拟客户端未能连通服务端", pending.len()),
+            )));
+        }
+
+        for peer in pending {
+            let packet = build_packet(&mut pool, MIN_PACKET_SIZE, peer.ip, peer.ip, 0, seq, nanos_since(epoch));
+            let _ = peer.inject.send(packet).await;
+            seq += 1;
+        }
+        time::sleep(WARMUP_INTERVAL).await;
+    }
+    log::info!("模拟客户端已全部连通");
+    Ok(())
+}
+
+/// 以一种数据包大小测试一轮
+///
+/// 参数:
+/// - `run`: 本轮的编号，写入数据包以忽略上一轮迟到的数据包
+/// - `size`: 数据包大小
+async fn measure_size(options: &BenchOptions, peers: &[Peer], epoch: Instant, run: u32, size: usize) -> Report {
+    for peer in peers {
+        *peer.stats.lock().unwrap() = Stats::new(run);
+    }
+
+    let rate = options.rate as f64 / peers.len() as f64;
+    let start = nanos_since(epoch);
+    let deadline = Instant::now() + options.duration;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    COUNTING.store(true, Ordering::Relaxed);
+
+    let senders = peers.iter().enumerate().map(|(index, peer)| {
+        let dst = peers[(index + 1) % peers.len()].ip;
+        tokio::spawn(send(peer.inject.clone(), peer.ip, dst, size, run, rate, epoch, deadline))
+    });
+    let sent: u64 = future::join_all(senders).await.into_iter()
+        .map(|result| result.unwrap_or(0))
+        .sum();
+
+    // 等待在途数据包到达，接收数量不再增加时结束
+    let drain_deadline = Instant::now() + DRAIN_TIMEOUT;
+    let mut received = total_received(peers);
+    while received < sent && Instant::now() < drain_deadline {
+        time::sleep(DRAIN_INTERVAL).await;
+        let now = total_received(peers);
+        if now == received {
+            break;
+        }
+        received = now;
+    }
+
+    COUNTING.store(false, Ordering::Relaxed);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    let mut total = Stats::new(run);
+    for peer in peers {
+        total.merge(&peer.stats.lock().unwrap());
+    }
+    Report {
+        sent,
+        received: total.packets,
+        bytes: total.bytes,
+        elapsed: Duration::from_nanos(total.last.saturating_sub(start)),
+        latency: total.latency,
+        allocations,
+    }
+}
+
+/// 以 `rate` (数据包/秒，0 表示尽快发送) 向目标客户端发送数据包直到 `deadline`，返回发送的数据包数
+#[allow(clippy::too_many_arguments)]
+async fn send(
+    inject: mpsc::Sender<PacketBuf>,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    size: usize,
+    run: u32,
+    rate: f64,
+    epoch: Instant,
+    deadline: Instant,
+) -> u64 {
+    let mut pool = BufferPool::new();
+    let start = Instant::now();
+    let mut sent = 0;
+    while Instant::now() < deadline {
+        if rate > 0.0 && sent >= (start.elapsed().as_secs_f64() * rate) as u64 {
+            time::sleep(Duration::from_millis(1)).await;
+            continue;
+        }
+        let packet = build_packet(&mut pool, size, src, dst, run, sent, nanos_since(epoch));
+        if inject.send(packet).await.is_err() {
+            break;
+        }
+        sent += 1;
+    }
+    sent
+}
+
+/// 统计客户端设备写入的数据包
+async fn receive(mut written: mpsc::Receiver<Bytes>, stats: Arc<Mutex<Stats>>, epoch: Instant) {
+    let mut packets = Vec::with_capacity(RECEIVE_BATCH);
+    while written.recv_many(&mut packets, RECEIVE_BATCH).await > 0 {
+        let now = nanos_since(epoch);
+        let mut stats = stats.lock().unwrap();
+        for packet in packets.drain(..) {
+            let Some((run, sent)) = parse_packet(&packet) else {
+                continue;
+            };
+            if run != stats.run {
+                continue;
+            }
+            stats.packets += 1;
+            stats.bytes += packet.len() as u64;
+            stats.latency.record(now.saturating_sub(sent));
+            stats.last = stats.last.max(now);
+        }
+    }
+}
+
+/// 模拟系统协议栈把服务端设备写入的数据包路由回设备，按目标IP分配到各队列
+async fn reflect(port: MemoryPort) {
+    let MemoryPort { inject, mut written } = port;
+    let mut pool = BufferPool::new();
+    let mut packets = Vec::with_capacity(RECEIVE_BATCH);
+    while written.recv_many(&mut packets, RECEIVE_BATCH).await > 0 {
+        for packet in packets.drain(..) {
+            if packet.len() < 20 {
+                continue;
+            }
+            let queue = packet[19] as usize % inject.len();
+            let mut buf = pool.take(HEADROOM + packet.len());
+            buf[HEADROOM..].copy_from_slice(&packet);
+            if inject[queue].send(PacketBuf::new(buf)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// 构造IPv4 UDP数据包，载荷开头为本轮编号、序号和发送时间
+fn build_packet(pool: &mut BufferPool, size: usize, src: Ipv4Addr, dst: Ipv4Addr, run: u32, seq: u64, sent: u64) -> PacketBuf {
+    let mut packet = PacketBuf::new(pool.take(HEADROOM + size));
+    packet.fill(0);
+
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&(size as u16).to_be_bytes());
+    packet[4..6].copy_from_slice(&(seq as u16).to_be_bytes());
+    packet[8] = 64;
+    packet[9] = libc::IPPROTO_UDP as u8;
+    packet[12..16].copy_from_slice(&src.octets());
+    packet[16..20].copy_from_slice(&dst.octets());
+    let checksum = !vnet::fold(vnet::sum(&packet[..20], 0));
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    // UDP校验和为0表示未计算
+    packet[20..22].copy_from_slice(&9u16.to_be_bytes());
+    packet[22..24].copy_from_slice(&9u16.to_be_bytes());
+    packet[24..26].copy_from_slice(&((size - 20) as u16).to_be_bytes());
+
+    let payload = &mut packet[PAYLOAD_OFFSET..];
+    payload[..4].copy_from_slice(&run.to_be_bytes());
+    payload[4..12].copy_from_slice(&seq.to_be_bytes());
+    payload[12..20].copy_from_slice(&sent.to_be_bytes());
+    packet
+}
+
+/// 解析基准测试数据包，返回本轮编号和发送时间
+fn parse_packet(packet: &[u8]) -> Option<(u32, u64)> {
+    let payload = packet.get(PAYLOAD_OFFSET..PAYLOAD_OFFSET + 20)?;
+    if packet[0] != 0x45 || packet[9] != libc::IPPROTO_UDP as u8 {
+        return None;
+    }
+    let run = u32::from_be_bytes(payload[..4].try_into().ok()?);
+    let sent = u64::from_be_bytes(payload[12..20].try_into().ok()?);
+    Some((run, sent))
+}
+
+/// 绑定回环地址的临时端口后释放，作为服务端的监听地址
+fn free_loopback_port() -> Result<std::net::SocketAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).map_err(VswitchError::IoError)?;
+    socket.local_addr().map_err(VswitchError::IoError)
+}
+
+fn nanos_since(epoch: Instant) -> u64 {
+    epoch.elapsed().as_nanos() as u64
+}
+
+fn total_received(peers: &[Peer]) -> u64 {
+    peers.iter().map(|peer| peer.stats.lock().unwrap().packets).sum()
+}
+
+/// 一个模拟客户端
+struct Peer {
+    /// 客户端的虚拟IP
+    ip: Ipv4Addr,
+    /// 注入客户端设备的数据包，由客户端读取后发往服务端
+    inject: mpsc::Sender<PacketBuf>,
+    /// 客户端设备收到的数据包统计
+    stats: Arc<Mutex<Stats>>,
+}
+
+/// 一轮测试中收到的数据包统计
+struct Stats {
+    /// 本轮的编号，其他轮次的数据包不计入
+    run: u32,
+    /// 收到的数据包数
+    packets: u64,
+    /// 收到的字节数
+    bytes: u64,
+    /// 最后一个数据包到达的时间 (纳秒)
+    last: u64,
+    /// 单向延迟 (纳秒)
+    latency: Histogram,
+}
+
+impl Stats {
+    fn new(run: u32) -> Self {
+        Self { run, packets: 0, bytes: 0, last: 0, latency: Histogram::new() }
+    }
+
+    fn merge(&mut self, other: &Stats) {
+        self.packets += other.packets;
+        self.bytes += other.bytes;
+        self.last = self.last.max(other.last);
+        self.latency.merge(&other.latency);
+    }
+}
+
+/// 对数分桶的直方图
+///
+/// 每个2的幂区间分为 2^[`SUB_BUCKET_BITS`] 个等宽的子桶，内存占用固定，记录时不分配
+struct Histogram {
+    /// 各桶的计数
+    counts: Vec<u64>,
+    /// 记录的最大值
+    max: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self { counts: vec![0; 64 << SUB_BUCKET_BITS], max: 0 }
+    }
+
+    fn record(&mut self, value: u64) {
+        self.counts[Self::index(value)] += 1;
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.max = self.max.max(other.max);
+    }
+
+    /// 分位数 `quantile` (0 到 1) 所在桶的上界，没有记录时为0
+    fn percentile(&self, quantile: f64) -> u64 {
+        let total: u64 = self.counts.iter().sum();
+        if total == 0 {
+            return 0;
+        }
+        let target = ((total as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Self::upper_bound(index).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    fn index(value: u64) -> usize {
+        let sub_buckets = 1 << SUB_BUCKET_BITS;
+        if value < sub_buckets {
+            return value as usize;
+        }
+        let shift = 63 - value.leading_zeros() - SUB_BUCKET_BITS;
+        ((shift as usize + 1) << SUB_BUCKET_BITS) + (value >> shift) as usize - sub_buckets as usize
+    }
+
+    fn upper_bound(index: usize) -> u64 {
+        let sub_buckets = 1 << SUB_BUCKET_BITS;
+        if index < 2 * sub_buckets {
+            return index as u64;
+        }
+        let shift = (index >> SUB_BUCKET_BITS) - 1;
+        let mantissa = (index % sub_buckets + sub_buckets) as u64;
+        ((mantissa + 1) << shift) - 1
+    }
+}
+
+/// 一轮测试的结果
+struct Report {
+    /// 发送的数据包数
+    sent: u64,
+    /// 收到的数据包数
+    received: u64,
+    /// 收到的字节数
+    bytes: u64,
+    /// 从开始发送到最后一个数据包到达的时间
+    elapsed: Duration,
+    /// 单向延迟 (纳秒)
+    latency: Histogram,
+    /// 测量期间整个进程的内存分配次数，包括模拟客户端和测试本身
+    allocations: u64,
+}
+
+impl Report {
+    fn print(&self, size: usize) {
+        let secs = self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE);
+        let loss = match self.sent {
+            0 => 0.0,
+            sent => sent.saturating_sub(self.received) as f64 * 100.0 / sent as f64,
+        };
+        let micros = |quantile| self.latency.percentile(quantile) as f64 / 1000.0;
+
+        println!("数据包大小 {} 字节:", size);
+        println!("  发送 {}, 接收 {}, 丢失 {:.3}%", self.sent, self.received, loss);
+        println!(
+            "  吞吐 {:.3} Mpps, {:.3} Gbps",
+            self.received as f64 / secs / 1e6,
+            self.bytes as f64 * 8.0 / secs / 1e9,
+        );
+        println!(
+            "  延迟 (微秒) p50 {:.1}, p90 {:.1}, p99 {:.1}, p99.9 {:.1}, 最大 {:.1}",
+            micros(0.5), micros(0.9), micros(0.99), micros(0.999), self.latency.max as f64 / 1000.0,
+        );
+        println!(
+            "  内存分配 {:.2} 次/数据包",
+            self.allocations as f64 / self.received.max(1) as f64,
+        );
+    }
+}
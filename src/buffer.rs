@@ -0,0 +1,209 @@
+use std::io;
+use std::ops::{Deref, DerefMut};
+use bytes::{Buf, Bytes, BytesMut};
+use crate::protocol::HEADER_LEN;
+
+/// 数据包前预留的空间，编码为消息时消息头直接写入其中
+pub const HEADROOM: usize = HEADER_LEN;
+/// 缓冲池每个内存块的大小，足够容纳一批合并接收的UDP数据报
+const CHUNK_LEN: usize = 512 * 1024;
+
+/// 数据包缓冲池
+///
+/// 从一整块内存中依次切出缓冲区，切出的缓冲区与内存块共享同一次分配，冻结为 `Bytes`
+/// 后交给消息解码、转发和写入，不再单独分配和复制。之前切出的缓冲区全部释放后，
+/// 下一次取用从内存块开头重新开始；仍有缓冲区在使用而剩余空间不足时分配新的内存块，
+/// 旧的内存块随最后一个缓冲区释放。
+///
+/// 每个读取任务或接收队列使用自己的缓冲池，取用时无需加锁
+pub struct BufferPool {
+    /// 当前内存块中尚未切出的部分
+    chunk: BytesMut,
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self { chunk: BytesMut::zeroed(CHUNK_LEN) }
+    }
+
+    /// 取出一个长度为 `len` 的缓冲区，内容是之前使用过的数据
+    pub fn take(&mut self, len: usize) -> BytesMut {
+        self.rewind();
+        if self.chunk.len() < len {
+            self.chunk = BytesMut::zeroed(CHUNK_LEN.max(len));
+        }
+        self.chunk.split_to(len)
+    }
+
+    /// 归还最近取出的缓冲区末尾未使用的部分
+    ///
+    /// 归还的部分与剩余空间相邻时直接合并，不复制数据
+    pub fn give_back(&mut self, mut buf: BytesMut) {
+        buf.unsplit(std::mem::take(&mut self.chunk));
+        self.chunk = buf;
+    }
+
+    /// 取出最多 `max` 字节的缓冲区交给 `read` 填充，只保留实际写入的部分
+    ///
+    /// 参数:
+    /// - `max`: 缓冲区大小
+    /// - `read`: 填充缓冲区并返回写入的字节数
+    pub fn fill<F>(&mut self, max: usize, read: F) -> io::Result<BytesMut>
+    where
+        F: FnOnce(&mut [u8]) -> io::Result<usize>,
+    {
+        let mut buf = self.take(max);
+        match read(&mut buf) {
+            Ok(len) => {
+                self.give_back(buf.split_off(len.min(max)));
+                Ok(buf)
+            }
+            Err(e) => {
+                self.give_back(buf);
+                Err(e)
+            }
+        }
+    }
+
+    /// 之前切出的缓冲区全部释放后，从内存块开头重新使用
+    fn rewind(&mut self) {
+        let len = self.chunk.len();
+        if len == CHUNK_LEN {
+            return;
+        }
+        self.chunk.clear();
+        let capacity = match self.chunk.try_reclaim(CHUNK_LEN) {
+            true => self.chunk.capacity(),
+            false => len,
+        };
+        // 内存块创建时已全部初始化，清空长度不会改变其中的数据
+        unsafe { self.chunk.set_len(capacity) };
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 前面预留了 [`HEADROOM`] 字节的数据包
+///
+/// 解引用得到数据包本身。编码为消息时消息头写入预留空间 ([`crate::protocol::Message::encode_packet`])，
+/// 数据包无需复制
+pub struct PacketBuf {
+    /// 预留空间和数据包
+    buf: BytesMut,
+}
+
+impl PacketBuf {
+    /// 包装前 [`HEADROOM`] 字节为预留空间的缓冲区
+    pub fn new(buf: BytesMut) -> Self {
+        debug_assert!(buf.len() >= HEADROOM);
+        Self { buf }
+    }
+
+    /// 复制数据包到新分配的缓冲区，用于不经过缓冲池的数据包
+    pub fn copy_from_slice(packet: &[u8]) -> Self {
+        let mut buf = BytesMut::zeroed(HEADROOM);
+        buf.extend_from_slice(packet);
+        Self { buf }
+    }
+
+    /// 去掉预留空间，返回数据包本身
+    pub fn into_bytes(self) -> Bytes {
+        let mut buf = self.buf;
+        buf.advance(HEADROOM);
+        buf.freeze()
+    }
+
+    /// 返回包含预留空间的整个缓冲区
+    pub fn into_inner(self) -> BytesMut {
+        self.buf
+    }
+}
+
+impl Deref for PacketBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[HEADROOM..]
+    }
+}
+
+impl DerefMut for PacketBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[HEADROOM..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_splits_from_same_chunk() {
+        let mut pool = BufferPool::new();
+        let first = pool.take(100);
+        let second = pool.take(100);
+        assert_eq!(first.len(), 100);
+        assert_eq!(second.as_ptr(), first.as_ptr().wrapping_add(100));
+
+        // 剩余空间不足时分配新的内存块，超过内存块大小的请求也能满足
+        let large = pool.take(CHUNK_LEN + 1);
+        assert_eq!(large.len(), CHUNK_LEN + 1);
+    }
+
+    #[test]
+    fn rewinds_after_buffers_are_released() {
+        let mut pool = BufferPool::new();
+        let first = pool.take(100);
+        let start = first.as_ptr();
+
+        let held = first.freeze();
+        assert_ne!(pool.take(100).as_ptr(), start);
+
+        drop(held);
+        assert_eq!(pool.take(100).as_ptr(), start);
+    }
+
+    #[test]
+    fn fill_keeps_only_written_bytes() {
+        let mut pool = BufferPool::new();
+        let buf = pool.fill(1000, |buf| {
+            buf[..3].copy_from_slice(b"abc");
+            Ok(3)
+        }).unwrap();
+        assert_eq!(&buf[..], b"abc");
+        // 未使用的部分归还给缓冲池
+        assert_eq!(pool.take(10).as_ptr(), buf.as_ptr().wrapping_add(3));
+    }
+
+    #[test]
+    fn fill_returns_buffer_on_error() {
+        let mut pool = BufferPool::new();
+        let held = pool.take(10);
+        let mut start = std::ptr::null();
+        let result = pool.fill(1000, |buf| {
+            start = buf.as_ptr();
+            Err(io::Error::from(io::ErrorKind::WouldBlock))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(pool.take(10).as_ptr(), start);
+        drop(held);
+    }
+
+    #[test]
+    fn packet_buf_reserves_headroom() {
+        let mut packet = PacketBuf::copy_from_slice(b"packet");
+        assert_eq!(&packet[..], b"packet");
+        packet[0] = b'P';
+
+        let inner = packet.into_inner();
+        assert_eq!(inner.len(), HEADROOM + 6);
+        assert_eq!(&inner[HEADROOM..], b"Packet");
+
+        let packet = PacketBuf::new(inner);
+        assert_eq!(packet.into_bytes(), Bytes::from_static(b"Packet"));
+    }
+}
@@ -1,151 +1,804 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tokio::time::{self, Duration};
-use std::io::Cursor;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tokio::sync::{watch, Mutex, Notify, RwLock};
+use tokio::time::{self, Duration, Instant};
+use tokio_rustls::rustls::ClientConfig;
+use crate::buffer::BufferPool;
 use crate::error::{Result, VswitchError};
+use crate::fec::{FecDecoder, FecEncoder, FecRatio};
+use crate::multipath::MultipathOptions;
+use crate::nat;
+use crate::obfs::{self, Obfuscator};
+use crate::packet::{extract_dst_ip, extract_src_ip};
+use crate::peer::{PeerTable, Route};
+use crate::proxy::HttpProxy;
 use crate::protocol::{Message, MessageType};
+use crate::redirect;
+use crate::resolve::{self, Resolver};
+use crate::socks::Socks5Proxy;
 use crate::tun::TunDevice;
+use crate::transport::{ClientTransport, ClientTransportOptions, ServerEndpoint, TransportKind};
+use crate::udp;
+
+/// 每次打洞发送探测消息的次数
+const PUNCH_ATTEMPTS: usize = 10;
+/// 打洞探测消息的发送间隔
+const PUNCH_INTERVAL: Duration = Duration::from_millis(300);
+/// 默认保活间隔，在NAT探测完成前使用
+const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(10);
+/// 重新探测NAT的间隔，网络环境可能随时变化 (如移动网络切换)
+const NAT_DETECT_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// 连接成功前最多连续跟随的重定向次数，防止重定向环路
+const MAX_REDIRECTS: usize = 5;
+/// 连续多少个保活间隔收不到服务器的任何消息即认为服务器失效
+const DEAD_AFTER_KEEPALIVES: u64 = 3;
+/// 探测更高优先级服务器的间隔
+const FAILBACK_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+/// 更高优先级的服务器连续多少次探测成功后切回
+const FAILBACK_THRESHOLD: usize = 3;
+/// 切换服务器时向原服务器发送断开连接消息的最长等待时间
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 客户端可选配置
+pub struct ClientOptions {
+    /// 是否启用点对点直连
+    pub p2p: bool,
+    /// 固定的保活间隔，为 `None` 时根据NAT探测结果自动调整
+    pub keepalive: Option<Duration>,
+    /// 集群共享密钥，用于校验服务端的重定向消息；为 `None` 时忽略重定向
+    pub cluster_key: Option<Vec<u8>>,
+    /// 建立TCP和WebSocket连接时使用的HTTP代理
+    pub proxy: Option<HttpProxy>,
+    /// SOCKS5代理，流连接和UDP都经代理发送；配置后不使用点对点直连和NAT探测
+    pub socks5: Option<Socks5Proxy>,
+    /// 加密传输方式使用的TLS配置
+    pub tls: Option<Arc<ClientConfig>>,
+    /// UDP数据报混淆，需要与服务端使用相同的密钥
+    pub obfs: Option<Arc<Obfuscator>>,
+    /// 是否随机化心跳和保活消息的发送间隔
+    pub heartbeat_jitter: bool,
+    /// 多路径配置，使用UDP连接服务器时经多个本地路径发送
+    pub multipath: Option<MultipathOptions>,
+    /// 前向纠错比例，连接UDP和QUIC服务器时使用，服务端发往本客户端时使用相同的比例
+    pub fec: Option<FecRatio>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            p2p: true,
+            keepalive: None,
+            cluster_key: None,
+            proxy: None,
+            socks5: None,
+            tls: None,
+            obfs: None,
+            heartbeat_jitter: false,
+            multipath: None,
+            fec: None,
+        }
+    }
+}
 
 /// 客户端结构
 pub struct Client {
     tun: Arc<TunDevice>,
-    server_addr: SocketAddr,
+    /// 服务器列表，按优先级从高到低排列
+    servers: Vec<ServerEndpoint>,
+    /// 服务器域名解析器
+    resolver: Resolver,
+    /// 当前服务器地址解析结果的过期时间，固定地址或跟随重定向后为 `None`
+    dns_valid_until: Mutex<Option<Instant>>,
+    /// 当前使用的服务器在列表中的索引
+    active_server: AtomicUsize,
+    /// 当前服务器地址，故障切换、重新解析或跟随重定向时会改变
+    server_addr: Arc<RwLock<SocketAddr>>,
+    /// 集群共享密钥
+    cluster_key: Option<Vec<u8>>,
+    /// 建立流连接时使用的HTTP代理
+    proxy: Option<HttpProxy>,
+    /// SOCKS5代理
+    socks5: Option<Socks5Proxy>,
+    /// TLS配置
+    tls: Option<Arc<ClientConfig>>,
+    /// UDP数据报混淆
+    obfs: Option<Arc<Obfuscator>>,
+    /// 是否随机化心跳和保活消息的发送间隔
+    heartbeat_jitter: bool,
+    /// 多路径配置
+    multipath: Option<MultipathOptions>,
+    /// 前向纠错比例
+    fec: Option<FecRatio>,
+    /// 发往当前服务器的前向纠错发送端，当前服务器使用流传输方式时为 `None`
+    fec_encoder: Arc<Mutex<Option<FecEncoder>>>,
+    /// 前向纠错发送端开始新分组时通知校验分片发送任务
+    fec_notify: Arc<Notify>,
+    /// 来自服务器的前向纠错接收端
+    fec_decoder: Mutex<FecDecoder>,
+    /// 最近一次连接请求携带的随机数
+    connect_nonce: AtomicU64,
+    /// 对端直连表
+    peers: Arc<PeerTable>,
+    /// 是否启用点对点直连
+    p2p: bool,
+    /// 当前保活间隔 (毫秒)
+    keepalive_ms: Arc<AtomicU64>,
+    /// NAT探测的目标，当前服务器经UDP直接连接时为其地址，否则为 `None`
+    nat_target: watch::Sender<Option<SocketAddr>>,
+    /// 是否根据NAT探测结果自动调整保活间隔
+    auto_keepalive: bool,
+    /// 客户端启动时间，作为下面时间戳的基准
+    started: Instant,
+    /// 最后一次向服务器发送消息的时间 (相对启动时间的毫秒数)
+    last_server_tx: Arc<AtomicU64>,
+    /// 最后一次收到服务器消息的时间 (相对启动时间的毫秒数)
+    last_server_rx: Arc<AtomicU64>,
 }
 
 impl Client {
     /// 创建一个新的客户端实例
-    pub fn new(tun: TunDevice, server_addr: SocketAddr) -> Self {
+    ///
+    /// 参数:
+    /// - `tun`: TUN设备
+    /// - `servers`: 服务器地址列表，按优先级从高到低排列，不能为空
+    /// - `options`: 可选配置
+    pub fn new(tun: TunDevice, servers: Vec<ServerEndpoint>, options: ClientOptions) -> Self {
+        let initial_keepalive = options.keepalive.unwrap_or(DEFAULT_KEEPALIVE);
+
         Self {
             tun: Arc::new(tun),
-            server_addr,
+            servers,
+            resolver: Resolver::new(),
+            dns_valid_until: Mutex::new(None),
+            active_server: AtomicUsize::new(0),
+            // 实际地址在启动时解析
+            server_addr: Arc::new(RwLock::new(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))),
+            cluster_key: options.cluster_key,
+            proxy: options.proxy,
+            // 直连和打洞需要直接收发UDP，经代理时无法进行
+            p2p: options.p2p && options.socks5.is_none(),
+            socks5: options.socks5,
+            tls: options.tls,
+            obfs: options.obfs,
+            heartbeat_jitter: options.heartbeat_jitter,
+            multipath: options.multipath,
+            fec: options.fec,
+            fec_encoder: Arc::new(Mutex::new(None)),
+            fec_notify: Arc::new(Notify::new()),
+            fec_decoder: Mutex::new(FecDecoder::new()),
+            connect_nonce: AtomicU64::new(0),
+            peers: Arc::new(PeerTable::new()),
+            keepalive_ms: Arc::new(AtomicU64::new(initial_keepalive.as_millis() as u64)),
+            nat_target: watch::Sender::new(None),
+            auto_keepalive: options.keepalive.is_none(),
+            started: Instant::now(),
+            last_server_tx: Arc::new(AtomicU64::new(0)),
+            last_server_rx: Arc::new(AtomicU64::new(0)),
         }
     }
 
     /// 启动客户端
     pub async fn run(&self) -> Result<()> {
-        log::info!("客户端启动，连接服务器: {}", self.server_addr);
-        
         // 创建UDP套接字
-        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| {
+        // 套接字不与服务器地址绑定，以便同时与其他客户端直接通信；
+        // 使用双栈套接字，重新解析后服务器地址族改变时无需重建套接字
+        let socket = ClientTransport::bind(ClientTransportOptions {
+            proxy: self.proxy.clone(),
+            socks5: self.socks5.clone(),
+            tls: self.tls.clone(),
+            obfs: self.obfs.clone(),
+            multipath: self.multipath.clone(),
+        }).map_err(|e| {
             log::error!("绑定UDP套接字失败: {}", e);
             VswitchError::IoError(e)
         })?;
-        
-        // 连接到服务器地址
-        socket.connect(self.server_addr).await.map_err(|e| {
-            log::error!("连接服务器失败: {}", e);
-            VswitchError::IoError(e)
-        })?;
-        
+
         let local_addr = socket.local_addr().map_err(|e| {
             log::error!("获取本地地址失败: {}", e);
             VswitchError::IoError(e)
         })?;
-        
+
         log::info!("UDP套接字绑定成功，本地地址: {}", local_addr);
-        
+
         let socket = Arc::new(socket);
-        
-        // 发送连接消息
-        log::info!("向服务器 {} 发送连接请求", self.server_addr);
-        socket.send(&Message::connect().encode()).await.map_err(|e| {
-            log::error!("发送连接消息失败: {}", e);
-            VswitchError::IoError(e)
-        })?;
-        
+
+        // 经SOCKS5代理时先建立UDP转发，确定代理是否支持UDP，不支持时UDP服务器改用TCP连接
+        if self.servers.iter().any(|server| server.transport == TransportKind::Udp) {
+            if let Err(e) = socket.associate().await {
+                log::error!("建立SOCKS5 UDP转发失败: {}", e);
+            }
+        }
+        if !self.servers.iter().any(|server| socket.supports(server)) {
+            return Err(VswitchError::ConfigError("没有可经SOCKS5代理连接的服务器".to_string()));
+        }
+
+        // 按优先级解析服务器地址，使用第一个解析成功的服务器
+        let mut initial = None;
+        for index in 0..self.servers.len() {
+            if !socket.supports(&self.servers[index]) {
+                continue;
+            }
+            if let Some(addr) = self.resolve_server(&socket, index).await {
+                initial = Some((index, addr));
+                break;
+            }
+        }
+        let (index, server_addr) = initial
+            .ok_or_else(|| VswitchError::ConfigError("无法解析任何服务器地址".to_string()))?;
+        self.active_server.store(index, Ordering::Relaxed);
+        *self.server_addr.write().await = server_addr;
+        self.update_nat_target(&socket, server_addr);
+        log::info!("客户端启动，连接服务器: {}", server_addr);
+
+        // 建立连接并发送连接消息，流连接建立失败时由故障切换逻辑稍后重试
+        match socket.connect(&self.servers[index], server_addr).await {
+            Ok(()) => {
+                log::info!("向服务器 {} 发送连接请求", server_addr);
+                self.send_connect(&socket, server_addr).await.map_err(|e| {
+                    log::error!("发送连接消息失败: {}", e);
+                    VswitchError::IoError(e)
+                })?;
+            }
+            Err(e) => {
+                log::error!("连接服务器 {} 失败: {}", server_addr, e);
+            }
+        }
+
         // 启动心跳任务
         let heartbeat_socket = socket.clone();
         self.spawn_heartbeat_task(heartbeat_socket);
-        
+
+        // 启动直连路径保活任务
+        if self.p2p {
+            self.spawn_peer_keepalive_task(socket.clone());
+        }
+
+        // 启动NAT探测任务，探测请求无法经过代理，只在当前服务器使用UDP时探测
+        if self.socks5.is_none() {
+            self.spawn_nat_detect_task();
+        }
+
+        // 启动前向纠错校验分片发送任务
+        if self.fec.is_some() {
+            self.spawn_fec_flush_task(socket.clone());
+        }
+
         // 启动从TUN设备读取数据的任务
         let tun_reader_socket = socket.clone();
         self.spawn_tun_reader_task(tun_reader_socket);
-        
-        // 主循环：处理从服务器接收到的数据
-        let mut recv_buf = vec![0u8; 4096];
-        
+
         log::info!("客户端主循环开始运行，等待服务器数据");
-        
+
+        // 主循环与故障切换检测同时运行，主循环退出时客户端退出
+        tokio::select! {
+            result = self.recv_loop(&socket) => result,
+            _ = self.failover_loop(&socket) => Ok(()),
+        }
+    }
+
+    /// 主循环：处理从服务器和对端接收到的数据
+    async fn recv_loop(&self, socket: &Arc<ClientTransport>) -> Result<()> {
+        let mut redirects = 0;
+
         loop {
-            match socket.recv(&mut recv_buf).await {
-                Ok(size) => {
+            match socket.recv_from().await {
+                Ok((mut received, addr)) => {
+                    let size = received.len();
                     if size == 0 {
                         log::debug!("收到空数据包");
                         continue;
                     }
-                    
-                    let received_data = &recv_buf[..size];
-                    let mut cursor = Cursor::new(received_data);
-                    
-                    match Message::decode(&mut cursor) {
-                        Ok(message) => {
-                            match message.msg_type {
-                                MessageType::Connect => {
-                                    log::info!("收到服务器连接确认");
+
+                    let message = match Message::decode(&mut received) {
+                        Ok(message) => message,
+                        Err(e) => {
+                            log::error!("解码消息错误: {}, 收到 {} bytes from {}", e, size, addr);
+                            continue;
+                        }
+                    };
+
+                    let server_addr = *self.server_addr.read().await;
+                    if addr != server_addr {
+                        self.handle_peer_message(socket, addr, message).await;
+                        continue;
+                    }
+                    self.last_server_rx.store(self.millis_since_start(), Ordering::Relaxed);
+
+                    match message.msg_type {
+                        MessageType::Connect => {
+                            log::info!("收到服务器连接确认");
+                            redirects = 0;
+                        }
+                        MessageType::Data => {
+                            let payload_len = message.payload.len();
+                            log::debug!("从服务器接收数据包，长度: {} bytes", payload_len);
+
+                            // 写入TUN设备
+                            if let Err(e) = self.tun.write_packet(&message.payload).await {
+                                log::error!("写入TUN设备错误: {}, 数据包大小: {}", e, payload_len);
+                            } else {
+                                log::debug!("数据包成功写入TUN设备 ({} bytes)", payload_len);
+                            }
+                        }
+                        MessageType::FecData | MessageType::FecParity => {
+                            let packets = match self.fec_decoder.lock().await.receive(&message) {
+                                Ok(packets) => packets,
+                                Err(e) => {
+                                    log::error!("解析前向纠错分片错误: {}", e);
+                                    continue;
                                 }
-                                MessageType::Data => {
-                                    let payload_len = message.payload.len();
-                                    log::debug!("从服务器接收数据包，长度: {} bytes", payload_len);
-                                    
-                                    // 写入TUN设备
-                                    if let Err(e) = self.tun.write_packet(&message.payload).await {
-                                        log::error!("写入TUN设备错误: {}, 数据包大小: {}", e, payload_len);
-                                    } else {
-                                        log::debug!("数据包成功写入TUN设备 ({} bytes)", payload_len);
-                                    }
+                            };
+
+                            for packet in packets {
+                                if let Err(e) = self.tun.write_packet(&packet).await {
+                                    log::error!("写入TUN设备错误: {}, 数据包大小: {}", e, packet.len());
+                                } else {
+                                    log::debug!("数据包成功写入TUN设备 ({} bytes)", packet.len());
                                 }
-                                MessageType::Heartbeat => {
-                                    log::debug!("收到服务器心跳响应");
+                            }
+                        }
+                        MessageType::Heartbeat => {
+                            log::debug!("收到服务器心跳响应");
+                        }
+                        MessageType::Disconnect => {
+                            log::info!("服务器请求断开连接");
+                            return Ok(());
+                        }
+                        MessageType::PeerInfo => {
+                            match message.parse_peer_info() {
+                                Ok((peer_ip, Some(peer_addr))) => {
+                                    log::debug!("服务器通告对端地址: {} -> {}", peer_ip, peer_addr);
+                                    self.spawn_punch_task(socket.clone(), peer_ip, peer_addr).await;
                                 }
-                                MessageType::Disconnect => {
-                                    log::info!("服务器请求断开连接");
-                                    return Ok(());
+                                Ok((peer_ip, None)) => {
+                                    log::debug!("服务器通告不能与对端 {} 直连", peer_ip);
+                                    self.peers.relay(peer_ip).await;
+                                }
+                                Err(e) => {
+                                    log::error!("解析对端地址通告错误: {}", e);
                                 }
                             }
                         }
-                        Err(e) => {
-                            log::error!("解码消息错误: {}, 收到 {} bytes", e, size);
+                        MessageType::Redirect => {
+                            if redirects >= MAX_REDIRECTS {
+                                log::warn!("连续重定向次数过多，忽略服务器的重定向");
+                                continue;
+                            }
+
+                            if let Some(target) = self.verify_redirect(&message) {
+                                redirects += 1;
+                                log::info!("服务器 {} 将客户端重定向到 {}", server_addr, target);
+                                *self.server_addr.write().await = target;
+                                self.update_nat_target(socket, target);
+                                *self.dns_valid_until.lock().await = None;
+                                self.last_server_rx.store(self.millis_since_start(), Ordering::Relaxed);
+
+                                // 重定向目标使用与当前服务器相同的传输方式
+                                let endpoint = &self.servers[self.active_server.load(Ordering::Relaxed)];
+                                if let Err(e) = socket.connect(endpoint, target).await {
+                                    log::error!("连接新服务器 {} 失败: {}", target, e);
+                                } else if let Err(e) = self.send_connect(socket, target).await {
+                                    log::error!("向新服务器 {} 发送连接消息失败: {}", target, e);
+                                }
+                            }
+                        }
+                        other => {
+                            log::debug!("忽略来自服务器的消息: {:?}", other);
                         }
                     }
                 }
                 Err(e) => {
-                    log::error!("从服务器接收数据错误: {}", e);
+                    log::error!("接收数据错误: {}", e);
                     time::sleep(Duration::from_secs(1)).await;
-                    
-                    // 尝试重新连接服务器
-                    log::info!("尝试重新连接服务器 {}...", self.server_addr);
-                    if let Err(err) = socket.connect(self.server_addr).await {
-                        log::error!("重新连接服务器失败: {}", err);
+
+                    // 重新发送连接消息
+                    let server_addr = *self.server_addr.read().await;
+                    log::info!("尝试重新连接服务器 {}...", server_addr);
+                    if let Err(err) = self.send_connect(socket, server_addr).await {
+                        log::error!("发送连接消息失败: {}", err);
                     } else {
-                        // 重新发送连接消息
-                        log::info!("重新连接服务器成功，发送连接消息");
-                        if let Err(err) = socket.send(&Message::connect().encode()).await {
-                            log::error!("发送连接消息失败: {}", err);
-                        } else {
-                            log::info!("连接消息发送成功");
+                        log::info!("连接消息发送成功");
+                    }
+                }
+            }
+        }
+    }
+
+    /// 故障切换循环
+    ///
+    /// 长时间收不到当前服务器的消息时切换到列表中的下一个服务器；
+    /// 使用低优先级服务器期间定期探测更高优先级的服务器，连续探测成功后切回
+    async fn failover_loop(&self, socket: &ClientTransport) {
+        let mut last_probe = Instant::now();
+        let mut healthy_streaks = vec![0usize; self.servers.len()];
+
+        loop {
+            time::sleep(Duration::from_secs(1)).await;
+
+            // 测量各路径的往返时间和丢包率
+            socket.probe_paths().await;
+
+            // 检测当前服务器是否失效
+            let keepalive = self.keepalive_ms.load(Ordering::Relaxed);
+            let dead_after = keepalive * DEAD_AFTER_KEEPALIVES + 1000;
+            let silent = self.millis_since_start().saturating_sub(self.last_server_rx.load(Ordering::Relaxed));
+            let stream_closed = socket.is_stream_closed().await;
+            if silent > dead_after || stream_closed {
+                let next = self.next_server(socket, self.active_server.load(Ordering::Relaxed));
+                if stream_closed {
+                    log::warn!("到服务器 {} 的连接已断开", *self.server_addr.read().await);
+                } else {
+                    log::warn!("服务器 {} 已 {} 毫秒无响应", *self.server_addr.read().await, silent);
+                }
+                self.switch_server(socket, next).await;
+                continue;
+            }
+
+            // 解析结果过期后重新解析当前服务器
+            self.refresh_server_addr(socket).await;
+
+            // 探测更高优先级的服务器
+            let active = self.active_server.load(Ordering::Relaxed);
+            if active == 0 || last_probe.elapsed() < FAILBACK_PROBE_INTERVAL {
+                continue;
+            }
+            last_probe = Instant::now();
+
+            for (index, server) in self.servers[..active].iter().enumerate() {
+                let alive = socket.supports(server) && match self.resolver.resolve(&server.spec).await {
+                    Ok(resolved) => {
+                        resolve::happy_eyeballs(&resolved.addrs, |addr| socket.probe(server, addr)).await.is_some()
+                    }
+                    Err(e) => {
+                        log::debug!("解析高优先级服务器 {} 失败: {}", server, e);
+                        false
+                    }
+                };
+                if alive {
+                    healthy_streaks[index] += 1;
+                    log::debug!("高优先级服务器 {} 探测成功 ({}/{})", server, healthy_streaks[index], FAILBACK_THRESHOLD);
+                } else {
+                    healthy_streaks[index] = 0;
+                }
+            }
+
+            if let Some(index) = healthy_streaks[..active].iter().position(|streak| *streak >= FAILBACK_THRESHOLD) {
+                log::info!("高优先级服务器 {} 已恢复", self.servers[index]);
+                healthy_streaks.iter_mut().for_each(|streak| *streak = 0);
+                self.switch_server(socket, index).await;
+            }
+        }
+    }
+
+    /// 获取列表中指定服务器之后下一个可以使用的服务器，没有其他可用服务器时返回指定服务器本身
+    fn next_server(&self, socket: &ClientTransport, index: usize) -> usize {
+        (1..=self.servers.len())
+            .map(|offset| (index + offset) % self.servers.len())
+            .find(|next| socket.supports(&self.servers[*next]))
+            .unwrap_or(index)
+    }
+
+    /// 切换到服务器列表中的指定服务器
+    ///
+    /// 每次切换或重连都会重新解析服务器地址；解析失败时只更新索引，
+    /// 下一轮失效检测会继续尝试列表中的下一个服务器
+    async fn switch_server(&self, socket: &ClientTransport, index: usize) {
+        self.active_server.store(index, Ordering::Relaxed);
+        let target = match self.resolve_server(socket, index).await {
+            Some(addr) => addr,
+            None => return,
+        };
+        let previous = std::mem::replace(&mut *self.server_addr.write().await, target);
+        self.update_nat_target(socket, target);
+
+        if previous != target {
+            log::info!("切换服务器: {} -> {} (优先级 {})", previous, target, index + 1);
+
+            // 通知原服务器释放会话，原服务器失效时该消息会被丢弃；流连接的发送队列可能已满，不长时间等待
+            let disconnect = Message::disconnect().encode();
+            match time::timeout(DISCONNECT_TIMEOUT, socket.send_to(&disconnect, previous)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => log::debug!("向原服务器 {} 发送断开连接消息失败: {}", previous, e),
+                Err(_) => log::debug!("向原服务器 {} 发送断开连接消息超时", previous),
+            }
+        } else {
+            log::info!("尝试重新连接服务器 {}...", target);
+        }
+
+        // 流连接建立失败时不重置接收时间，下一轮失效检测会继续切换
+        if let Err(e) = socket.connect(&self.servers[index], target).await {
+            log::error!("连接服务器 {} 失败: {}", target, e);
+            return;
+        }
+
+        // 给新服务器留出响应时间
+        self.last_server_rx.store(self.millis_since_start(), Ordering::Relaxed);
+
+        if let Err(e) = self.send_connect(socket, target).await {
+            log::error!("向服务器 {} 发送连接消息失败: {}", target, e);
+        }
+    }
+
+    /// 解析服务器列表中的指定服务器并选择要使用的地址
+    ///
+    /// 有多个候选地址时使用快乐眼球算法选择最先响应的地址，都无响应时使用第一个地址。
+    /// 解析成功时记录解析结果的过期时间
+    async fn resolve_server(&self, socket: &ClientTransport, index: usize) -> Option<SocketAddr> {
+        let server = &self.servers[index];
+        let spec = &server.spec;
+        let resolved = match self.resolver.resolve(spec).await {
+            Ok(resolved) if !resolved.addrs.is_empty() => resolved,
+            Ok(_) => {
+                log::error!("服务器 {} 没有可用的地址", spec);
+                return None;
+            }
+            Err(e) => {
+                log::error!("解析服务器 {} 失败: {}", spec, e);
+                return None;
+            }
+        };
+        *self.dns_valid_until.lock().await = resolved.valid_until;
+
+        if resolved.addrs.len() == 1 {
+            return Some(resolved.addrs[0]);
+        }
+
+        log::info!("服务器 {} 有 {} 个候选地址: {:?}", spec, resolved.addrs.len(), resolved.addrs);
+        match resolve::happy_eyeballs(&resolved.addrs, |addr| socket.probe(server, addr)).await {
+            Some(addr) => Some(addr),
+            None => {
+                log::warn!("服务器 {} 的所有候选地址均无响应，使用 {}", spec, resolved.addrs[0]);
+                Some(resolved.addrs[0])
+            }
+        }
+    }
+
+    /// 当前服务器的解析结果过期时重新解析
+    ///
+    /// 当前地址仍在新的解析结果中时继续使用，否则重新选择地址并连接；
+    /// 解析失败时保留当前地址，稍后重试
+    async fn refresh_server_addr(&self, socket: &ClientTransport) {
+        let mut valid_until = self.dns_valid_until.lock().await;
+        if !matches!(*valid_until, Some(expiry) if expiry <= Instant::now()) {
+            return;
+        }
+
+        let index = self.active_server.load(Ordering::Relaxed);
+        let spec = &self.servers[index].spec;
+        match self.resolver.resolve(spec).await {
+            Ok(resolved) => {
+                *valid_until = resolved.valid_until;
+                drop(valid_until);
+
+                let server_addr = *self.server_addr.read().await;
+                if !resolved.addrs.contains(&server_addr) {
+                    log::info!("服务器 {} 的地址已变化: {:?}", spec, resolved.addrs);
+                    self.switch_server(socket, index).await;
+                }
+            }
+            Err(e) => {
+                log::warn!("重新解析服务器 {} 失败: {}, 继续使用当前地址", spec, e);
+                *valid_until = Some(Instant::now() + resolve::MIN_TTL);
+            }
+        }
+    }
+
+    /// 获取客户端启动至今的毫秒数
+    fn millis_since_start(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    /// 向服务器发送携带新随机数的连接请求
+    ///
+    /// 当前服务器使用UDP或QUIC时在请求中携带前向纠错比例，并重新开始前向纠错分组
+    async fn send_connect(&self, socket: &ClientTransport, server_addr: SocketAddr) -> std::io::Result<usize> {
+        let nonce = rand::random::<u64>();
+        self.connect_nonce.store(nonce, Ordering::Relaxed);
+
+        let endpoint = &self.servers[self.active_server.load(Ordering::Relaxed)];
+        let fec = self.fec.filter(|_| socket.transport(endpoint).is_datagram());
+        *self.fec_encoder.lock().await = fec.map(FecEncoder::new);
+
+        let connect = Message::connect_with_nonce(nonce, fec.map(|fec| (fec.data, fec.parity)));
+        socket.send_to(&connect.encode(), server_addr).await
+    }
+
+    /// 校验服务器的重定向消息
+    ///
+    /// 未配置集群共享密钥或令牌无效时返回 `None`，客户端继续使用当前服务器
+    fn verify_redirect(&self, message: &Message) -> Option<SocketAddr> {
+        let key = match &self.cluster_key {
+            Some(key) => key,
+            None => {
+                log::warn!("收到重定向消息，但未配置集群共享密钥，忽略");
+                return None;
+            }
+        };
+
+        match redirect::verify(key, self.connect_nonce.load(Ordering::Relaxed), message) {
+            Ok(target) => Some(target),
+            Err(e) => {
+                log::warn!("忽略无效的重定向消息: {}", e);
+                None
+            }
+        }
+    }
+
+    /// 处理来自其他客户端的消息
+    async fn handle_peer_message(&self, socket: &ClientTransport, addr: SocketAddr, message: Message) {
+        if !self.p2p {
+            log::debug!("未启用点对点直连，丢弃来自 {} 的消息", addr);
+            return;
+        }
+
+        match message.msg_type {
+            MessageType::Punch => {
+                let peer_ip = match message.parse_ip() {
+                    Ok(ip) => ip,
+                    Err(e) => {
+                        log::error!("解析打洞消息错误: {} from {}", e, addr);
+                        return;
+                    }
+                };
+                let local_ip = match self.peers.local_ip().await {
+                    Some(ip) => ip,
+                    None => {
+                        log::debug!("本机虚拟IP未知，忽略来自 {} 的打洞消息", addr);
+                        return;
+                    }
+                };
+
+                // 只响应服务端通告过的地址，未经通告的打洞消息不能改变对端的地址
+                if !self.peers.is_advertised(peer_ip, addr).await {
+                    log::debug!("忽略未经服务端通告的打洞消息: {} ({})", peer_ip, addr);
+                    return;
+                }
+
+                log::debug!("收到对端 {} ({}) 的打洞消息", peer_ip, addr);
+                if let Err(e) = socket.send_to(&Message::punch_ack(local_ip).encode(), addr).await {
+                    log::error!("发送打洞确认错误 -> {}: {}", addr, e);
+                }
+
+                // 对端的消息已到达，反向探测一次以确认双向连通
+                if !self.peers.is_direct(peer_ip).await {
+                    if let Err(e) = socket.send_to(&Message::punch(local_ip).encode(), addr).await {
+                        log::error!("发送打洞消息错误 -> {}: {}", addr, e);
+                    }
+                }
+            }
+            MessageType::PunchAck => {
+                match message.parse_ip() {
+                    Ok(peer_ip) => {
+                        if !self.peers.confirm(peer_ip, addr).await {
+                            log::debug!("忽略未请求的打洞确认: {} ({})", peer_ip, addr);
                         }
                     }
+                    Err(e) => {
+                        log::error!("解析打洞确认错误: {} from {}", e, addr);
+                    }
                 }
             }
+            MessageType::Data => {
+                let Some(peer_ip) = self.peers.touch(addr).await else {
+                    log::debug!("丢弃来自未知地址的数据包: {}", addr);
+                    return;
+                };
+
+                // 对端只能发送以自己的虚拟IP为源地址的数据包
+                let src_ip = extract_src_ip(&message.payload);
+                if src_ip != Some(peer_ip) {
+                    log::debug!("丢弃对端 {} ({}) 发送的源地址不符的数据包: {:?}", peer_ip, addr, src_ip);
+                    return;
+                }
+
+                let payload_len = message.payload.len();
+                log::debug!("从对端 {} 接收数据包，长度: {} bytes", addr, payload_len);
+
+                if let Err(e) = self.tun.write_packet(&message.payload).await {
+                    log::error!("写入TUN设备错误: {}, 数据包大小: {}", e, payload_len);
+                }
+            }
+            other => {
+                log::debug!("忽略来自 {} 的消息: {:?}", addr, other);
+            }
+        }
+    }
+
+    /// 启动打洞任务
+    ///
+    /// 在一段时间内反复向对端发送打洞消息，直到收到确认或超时
+    async fn spawn_punch_task(&self, socket: Arc<ClientTransport>, peer_ip: IpAddr, peer_addr: SocketAddr) {
+        if !self.p2p {
+            return;
         }
+
+        let local_ip = match self.peers.local_ip().await {
+            Some(ip) => ip,
+            None => {
+                log::debug!("本机虚拟IP未知，暂不与对端 {} 打洞", peer_ip);
+                return;
+            }
+        };
+
+        if !self.peers.start_punch(peer_ip, peer_addr).await {
+            return;
+        }
+
+        let peers = self.peers.clone();
+
+        tokio::spawn(async move {
+            let punch = Message::punch(local_ip).encode();
+
+            for _ in 0..PUNCH_ATTEMPTS {
+                if !peers.is_punching(peer_ip).await {
+                    break;
+                }
+
+                if let Err(e) = socket.send_to(&punch, peer_addr).await {
+                    log::error!("发送打洞消息错误 -> {}: {}", peer_addr, e);
+                }
+
+                time::sleep(PUNCH_INTERVAL).await;
+            }
+        });
     }
 
     /// 启动心跳任务
-    /// 
-    /// 该任务负责定期向服务器发送心跳消息，确保连接保持活跃
-    fn spawn_heartbeat_task(&self, socket: Arc<UdpSocket>) {
-        log::info!("启动心跳任务，每10秒发送一次心跳");
-        
+    ///
+    /// 该任务负责在与服务器的连接空闲达到保活间隔时发送心跳消息，
+    /// 确保连接和NAT映射保持活跃。启用随机化时每次的间隔在保活间隔的一半到保活间隔之间随机选取
+    fn spawn_heartbeat_task(&self, socket: Arc<ClientTransport>) {
+        let server_addr = self.server_addr.clone();
+        let keepalive_ms = self.keepalive_ms.clone();
+        let last_server_tx = self.last_server_tx.clone();
+        let last_server_rx = self.last_server_rx.clone();
+        let started = self.started;
+        let heartbeat_jitter = self.heartbeat_jitter;
+
+        log::info!("启动心跳任务，初始保活间隔 {} 秒", keepalive_ms.load(Ordering::Relaxed) / 1000);
+
         tokio::spawn(async move {
-            let heartbeat_interval = Duration::from_secs(10);
-            
+            let mut last_heartbeat = 0u64;
+            // 随机化后的间隔及其对应的保活间隔，每次发送心跳或保活间隔改变后重新选取
+            let mut jittered: Option<(u64, u64)> = None;
+
             loop {
-                time::sleep(heartbeat_interval).await;
-                
+                // 双向都有其他消息时推迟心跳；只有发送没有接收时仍需心跳以确认服务器存活
+                let keepalive = keepalive_ms.load(Ordering::Relaxed);
+                let interval = match jittered {
+                    _ if !heartbeat_jitter => keepalive,
+                    Some((base, interval)) if base == keepalive => interval,
+                    _ => {
+                        let interval = obfs::jitter(Duration::from_millis(keepalive)).as_millis() as u64;
+                        jittered = Some((keepalive, interval));
+                        interval
+                    }
+                };
+                let now = started.elapsed().as_millis() as u64;
+                let since_heartbeat = now.saturating_sub(last_heartbeat);
+                let since_tx = now.saturating_sub(last_server_tx.load(Ordering::Relaxed));
+                let since_rx = now.saturating_sub(last_server_rx.load(Ordering::Relaxed));
+
+                let wait = if since_heartbeat < interval {
+                    interval - since_heartbeat
+                } else if since_tx < interval && since_rx < interval {
+                    interval - since_tx.max(since_rx)
+                } else {
+                    0
+                };
+                if wait > 0 {
+                    time::sleep(Duration::from_millis(wait)).await;
+                    continue;
+                }
+
                 let heartbeat = Message::heartbeat().encode();
-                match socket.send(&heartbeat).await {
+                let server_addr = *server_addr.read().await;
+                match socket.send_to(&heartbeat, server_addr).await {
                     Ok(_) => {
+                        last_heartbeat = started.elapsed().as_millis() as u64;
+                        last_server_tx.store(last_heartbeat, Ordering::Relaxed);
+                        jittered = None;
                         log::debug!("心跳发送成功");
                     }
                     Err(e) => {
@@ -154,35 +807,199 @@ impl Client {
                     }
                 }
             }
-            
+
             log::warn!("心跳任务已退出");
         });
     }
 
+    /// 启动直连路径保活任务
+    ///
+    /// 该任务按保活间隔向已直连的对端发送打洞消息，以维持双方的NAT映射
+    fn spawn_peer_keepalive_task(&self, socket: Arc<ClientTransport>) {
+        let peers = self.peers.clone();
+        let keepalive_ms = self.keepalive_ms.clone();
+        let heartbeat_jitter = self.heartbeat_jitter;
+
+        tokio::spawn(async move {
+            loop {
+                let interval = Duration::from_millis(keepalive_ms.load(Ordering::Relaxed));
+                time::sleep(if heartbeat_jitter { obfs::jitter(interval) } else { interval }).await;
+
+                if let Some(local_ip) = peers.local_ip().await {
+                    let punch = Message::punch(local_ip).encode();
+                    for addr in peers.direct_addrs().await {
+                        if let Err(e) = socket.send_to(&punch, addr).await {
+                            log::debug!("向对端 {} 发送保活消息错误: {}", addr, e);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// 启动NAT探测任务
+    ///
+    /// 当前服务器经UDP直接连接时定期探测NAT映射类型和存活时间并输出状态，启用自动保活时据此调整保活间隔。
+    /// 切换或重定向到其他服务器后立即重新探测；切换到流传输方式时停止探测，并恢复初始保活间隔
+    fn spawn_nat_detect_task(&self) {
+        let mut nat_target = self.nat_target.subscribe();
+        let keepalive_ms = self.keepalive_ms.clone();
+        let initial_keepalive = self.keepalive_ms.load(Ordering::Relaxed);
+        let auto_keepalive = self.auto_keepalive;
+        let obfs = self.obfs.clone();
+
+        tokio::spawn(async move {
+            loop {
+                if nat_target.borrow_and_update().is_none() && auto_keepalive
+                    && keepalive_ms.swap(initial_keepalive, Ordering::Relaxed) != initial_keepalive {
+                    log::info!("当前服务器不使用UDP，保活间隔恢复为 {} 秒", initial_keepalive / 1000);
+                }
+                let server_addr = match nat_target.wait_for(Option::is_some).await {
+                    Ok(target) => target.expect("已等待到探测目标"),
+                    Err(_) => return,
+                };
+                log::info!("开始探测NAT类型");
+
+                match nat::detect(server_addr, obfs.as_deref()).await {
+                    // 探测期间切换了服务器时结果已不适用
+                    Ok(_) if nat_target.has_changed().unwrap_or(true) => continue,
+                    Ok(report) => {
+                        log::info!("NAT状态: {}", report);
+
+                        if auto_keepalive {
+                            let keepalive = report.keepalive.as_millis() as u64;
+                            if keepalive_ms.swap(keepalive, Ordering::Relaxed) != keepalive {
+                                log::info!("保活间隔调整为 {} 秒", report.keepalive.as_secs());
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("NAT探测失败: {}", e);
+                    }
+                }
+
+                tokio::select! {
+                    _ = time::sleep(NAT_DETECT_INTERVAL) => {}
+                    changed = nat_target.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// 服务器地址改变后更新NAT探测的目标
+    ///
+    /// 经SOCKS5代理或使用其他传输方式时NAT探测的结果与隧道无关，不探测
+    fn update_nat_target(&self, socket: &ClientTransport, server_addr: SocketAddr) {
+        let endpoint = &self.servers[self.active_server.load(Ordering::Relaxed)];
+        let udp = self.socks5.is_none() && socket.transport(endpoint) == TransportKind::Udp;
+        let target = udp.then_some(server_addr);
+        self.nat_target.send_if_modified(|current| std::mem::replace(current, target) != target);
+    }
+
+    /// 启动前向纠错校验分片发送任务
+    ///
+    /// 分组在超时前没有凑满时，由该任务发出已有数据包的校验分片
+    fn spawn_fec_flush_task(&self, socket: Arc<ClientTransport>) {
+        let encoder = self.fec_encoder.clone();
+        let notify = self.fec_notify.clone();
+        let server_addr = self.server_addr.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let deadline = encoder.lock().await.as_ref().and_then(|encoder| encoder.deadline());
+                match deadline {
+                    Some(deadline) => time::sleep_until(deadline).await,
+                    None => {
+                        notify.notified().await;
+                        continue;
+                    }
+                }
+
+                let messages = match encoder.lock().await.as_mut() {
+                    Some(encoder) => encoder.flush_expired(),
+                    None => continue,
+                };
+                let server_addr = *server_addr.read().await;
+                for message in messages {
+                    if let Err(e) = socket.send_to(&message, server_addr).await {
+                        log::debug!("发送前向纠错校验分片错误: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
     /// 启动从TUN设备读取并发送到服务器的任务
-    /// 
-    /// 该任务负责从TUN设备读取数据包并转发到服务器
-    fn spawn_tun_reader_task(&self, socket: Arc<UdpSocket>) {
+    ///
+    /// 该任务负责从TUN设备读取数据包，已建立直连的目标直接发往对端，
+    /// 其余经服务器转发
+    fn spawn_tun_reader_task(&self, socket: Arc<ClientTransport>) {
         let tun = self.tun.clone();
-        
+        let peers = self.peers.clone();
+        let server_addr = self.server_addr.clone();
+        let p2p = self.p2p;
+        let last_server_tx = self.last_server_tx.clone();
+        let started = self.started;
+        let fec_encoder = self.fec_encoder.clone();
+        let fec_notify = self.fec_notify.clone();
+
         log::info!("启动TUN设备读取任务");
-        
+
         tokio::spawn(async move {
+            let mut pool = BufferPool::new();
             loop {
-                match tun.read_packet().await {
-                    Ok(packet) => {
-                        let packet_len = packet.len();
-                        log::debug!("从TUN设备读取数据包，长度: {} bytes", packet_len);
-                        
-                        let message = Message::data(packet);
-                        let encoded = message.encode();
-                        
-                        match socket.send(&encoded).await {
-                            Ok(_) => {
-                                log::debug!("成功向服务器发送数据包 ({} bytes)", packet_len);
+                match tun.read_packets_from(0, udp::MAX_BATCH, &mut pool).await {
+                    Ok(packets) => {
+                        // 一批数据包选择路径后一起发送，直接经UDP发送的消息合并为尽量少的系统调用
+                        let server_addr = *server_addr.read().await;
+                        let mut outgoing = Vec::with_capacity(packets.len());
+                        for packet in packets {
+                            log::debug!("从TUN设备读取数据包，长度: {} bytes", packet.len());
+
+                            // 选择转发路径
+                            let mut target = server_addr;
+                            if p2p {
+                                if let Some(src_ip) = extract_src_ip(&packet) {
+                                    peers.set_local_ip(src_ip).await;
+                                }
+
+                                if let Some(dst_ip) = extract_dst_ip(&packet).filter(|ip| !ip.is_multicast()) {
+                                    match peers.route(dst_ip).await {
+                                        Route::Direct(addr) => {
+                                            target = addr;
+                                        }
+                                        Route::Relay { request: true } => {
+                                            log::debug!("向服务器查询对端 {} 的地址", dst_ip);
+                                            outgoing.push((Message::peer_request(dst_ip).encode(), server_addr));
+                                        }
+                                        Route::Relay { request: false } => {}
+                                    }
+                                }
+                            }
+
+                            // 启用前向纠错时发往服务器的数据包编码为数据分片，分组凑满时附带校验分片
+                            match fec_encoder.lock().await.as_mut().filter(|_| target == server_addr) {
+                                Some(encoder) => {
+                                    outgoing.extend(encoder.encode(packet.into_bytes()).into_iter().map(|message| (message, target)));
+                                    fec_notify.notify_one();
+                                }
+                                None => outgoing.push((Message::encode_packet(packet), target)),
+                            }
+                        }
+
+                        match socket.send_batch(&outgoing).await {
+                            Ok(()) => {
+                                if outgoing.iter().any(|(_, target)| *target == server_addr) {
+                                    last_server_tx.store(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+                                }
+                                log::debug!("成功发送 {} 条消息", outgoing.len());
                             }
                             Err(e) => {
-                                log::error!("向服务器发送数据错误: {}", e);
+                                log::error!("发送数据错误: {}", e);
                                 time::sleep(Duration::from_secs(1)).await;
                             }
                         }
@@ -195,4 +1012,4 @@ impl Client {
             }
         });
     }
-} 
\ No newline at end of file
+}
@@ -1,6 +1,58 @@
 use clap::{Parser, Subcommand};
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+use crate::bench::{self, BenchOptions};
 use crate::error::{Result, VswitchError};
+use crate::fec::FecRatio;
+use crate::multipath::MultipathOptions;
+use crate::obfs::{self, Obfuscator};
+use crate::proxy::HttpProxy;
+use crate::redirect::Redirector;
+use crate::socks::Socks5Proxy;
+use crate::tls::{self, TlsClientOptions, TlsServerOptions};
+use crate::transport::{ListenAddr, ServerEndpoint};
+use crate::tun;
+
+/// 随机填充长度上限，避免混淆后的数据报超出接收缓冲区
+const MAX_OBFS_PADDING: usize = 1024;
+
+/// UDP套接字和TUN设备的收发后端
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IoBackend {
+    /// 就绪通知 (epoll) 加批量系统调用
+    #[default]
+    Epoll,
+    /// io_uring，需要以 `io-uring` 功能编译
+    IoUring,
+}
+
+impl FromStr for IoBackend {
+    type Err = VswitchError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "epoll" => Ok(IoBackend::Epoll),
+            "io-uring" if cfg!(feature = "io-uring") => Ok(IoBackend::IoUring),
+            "io-uring" => Err(VswitchError::ConfigError("未编译 io_uring 后端，需要以 --features io-uring 构建".to_string())),
+            _ => Err(VswitchError::ConfigError(format!("未知的I/O后端: {} (可选 epoll, io-uring)", s))),
+        }
+    }
+}
+
+impl fmt::Display for IoBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IoBackend::Epoll => "epoll",
+            IoBackend::IoUring => "io-uring",
+        })
+    }
+}
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about)]
@@ -17,9 +69,11 @@ pub struct Config {
 pub enum Mode {
     /// 服务端模式
     Server {
-        /// 监听地址
-        #[arg(short, long, default_value = "0.0.0.0:4789")]
-        listen: String,
+        /// 监听地址，可多次指定以同时监听多个地址和端口 (如 0.0.0.0:4789 和 [::]:4789)。
+        /// 加 tcp:// 前缀监听TCP，加 ws:// 前缀监听WebSocket (可带请求路径，如 ws://0.0.0.0:80/tunnel)，
+        /// tls:// 和 wss:// 为对应的TLS加密方式，quic:// 使用QUIC
+        #[arg(short, long = "listen", default_value = "0.0.0.0:4789")]
+        listens: Vec<String>,
 
         /// TUN设备名称
         #[arg(short, long, default_value = "tun0")]
@@ -28,13 +82,82 @@ pub enum Mode {
         /// TUN设备MTU
         #[arg(short, long, default_value = "1500")]
         mtu: usize,
+
+        /// NAT探测辅助监听地址，端口需与监听地址不同，客户端借此判断NAT映射类型
+        #[arg(long)]
+        probe_listen: Option<String>,
+
+        /// 联邦中邻居服务端的地址，可多次指定
+        #[arg(long = "peer")]
+        peers: Vec<String>,
+
+        /// 过载或维护时可将客户端重定向到的服务端地址，可多次指定
+        #[arg(long = "redirect")]
+        redirects: Vec<String>,
+
+        /// 在线客户端数上限，达到上限后新客户端会被重定向
+        #[arg(long)]
+        max_clients: Option<usize>,
+
+        /// 集群共享密钥，用于签名重定向令牌和与邻居服务端交换的路由通告
+        #[arg(long)]
+        cluster_key: Option<String>,
+
+        /// TLS证书链文件 (PEM)，监听 tls://、wss:// 或 quic:// 时必填
+        #[arg(long)]
+        tls_cert: Option<PathBuf>,
+
+        /// TLS私钥文件 (PEM)
+        #[arg(long)]
+        tls_key: Option<PathBuf>,
+
+        /// 签发客户端证书的CA文件 (PEM)，指定后TLS连接的客户端必须出示由其签发的证书
+        #[arg(long)]
+        tls_client_ca: Option<PathBuf>,
+
+        /// 将客户端证书身份 (CN) 绑定到虚拟IP，格式为 身份=IP，可多次指定
+        #[arg(long = "tls-client-ip")]
+        tls_client_ips: Vec<String>,
+
+        /// UDP混淆密钥，指定后UDP数据报整体加密并添加随机填充；客户端、服务端和邻居服务端需要一致
+        #[arg(long)]
+        obfs_key: Option<String>,
+
+        /// 每个混淆数据报最多添加的随机填充字节数
+        #[arg(long, default_value_t = obfs::DEFAULT_MAX_PADDING)]
+        obfs_padding: usize,
+
+        /// TUN设备队列数，大于1时创建多队列设备，每个队列由单独的任务读取；通常设为CPU核心数
+        #[arg(long, default_value = "1")]
+        queues: usize,
+
+        /// 为每个TUN队列绑定一个 SO_REUSEPORT UDP套接字，由系统将客户端分配到各套接字
+        #[arg(long)]
+        reuseport: bool,
+
+        /// 为TUN设备启用 virtio-net 头部卸载 (TSO和校验和卸载)，系统以64KB的TCP超大分段读写，
+        /// 提高大流量TCP传输的吞吐
+        #[arg(long)]
+        tun_offload: bool,
+
+        /// UDP套接字和TUN设备的收发后端: epoll 或 io-uring (需要以 io-uring 功能编译，Linux 6.0 起)
+        #[arg(long, default_value = "epoll")]
+        io_backend: String,
+
+        /// 在指定的网络接口上挂载 XDP 程序，发往UDP监听地址的数据报经 AF_XDP 套接字直接收发，
+        /// 不经过内核UDP协议栈和防火墙规则；普通UDP套接字仍作为后备 (Linux 5.9 起)
+        #[arg(long)]
+        xdp: Option<String>,
     },
 
     /// 客户端模式
     Client {
-        /// 服务器地址
-        #[arg(short, long)]
-        server: String,
+        /// 服务器地址，可多次指定，按给出的顺序决定优先级 (第一个优先级最高)。
+        /// 支持 IP:端口、域名:端口 和 SRV记录名 (如 _vswitch._udp.example.com)，
+        /// 加 tcp:// 前缀使用TCP连接服务器，加 ws:// 前缀使用WebSocket (可带请求路径)，
+        /// tls:// 和 wss:// 为对应的TLS加密方式，quic:// 使用QUIC
+        #[arg(short, long = "server", required = true)]
+        servers: Vec<String>,
 
         /// TUN设备名称
         #[arg(short, long, default_value = "tun0")]
@@ -43,6 +166,113 @@ pub enum Mode {
         /// TUN设备MTU
         #[arg(short, long, default_value = "1500")]
         mtu: usize,
+
+        /// 禁用客户端之间的点对点直连，所有流量经服务器中转
+        #[arg(long)]
+        no_p2p: bool,
+
+        /// 固定的保活间隔 (秒)，不指定时根据NAT探测结果自动调整
+        #[arg(long)]
+        keepalive: Option<u64>,
+
+        /// 集群共享密钥，用于校验服务器的重定向令牌；不指定时忽略重定向
+        #[arg(long)]
+        cluster_key: Option<String>,
+
+        /// HTTP CONNECT 代理地址，格式为 http://[用户名:密码@]主机:端口，用于TCP和WebSocket传输
+        #[arg(long)]
+        proxy: Option<String>,
+
+        /// SOCKS5 代理地址，格式为 socks5://[用户名:密码@]主机:端口；
+        /// UDP经代理的UDP转发发送，代理不支持时只使用流传输方式的服务器
+        #[arg(long, conflicts_with = "proxy")]
+        socks5: Option<String>,
+
+        /// 用于校验服务器证书的CA文件 (PEM)，不指定时使用内置的公共根证书
+        #[arg(long)]
+        tls_ca: Option<PathBuf>,
+
+        /// 固定的服务器证书 SHA-256 指纹，可多次指定；只指定指纹时不校验证书链和主机名
+        #[arg(long = "tls-pin")]
+        tls_pins: Vec<String>,
+
+        /// 客户端证书链文件 (PEM)，服务器要求客户端证书时使用
+        #[arg(long)]
+        tls_cert: Option<PathBuf>,
+
+        /// 客户端私钥文件 (PEM)
+        #[arg(long)]
+        tls_key: Option<PathBuf>,
+
+        /// UDP混淆密钥，指定后UDP数据报整体加密并添加随机填充；客户端、服务端和邻居服务端需要一致
+        #[arg(long)]
+        obfs_key: Option<String>,
+
+        /// 每个混淆数据报最多添加的随机填充字节数
+        #[arg(long, default_value_t = obfs::DEFAULT_MAX_PADDING)]
+        obfs_padding: usize,
+
+        /// 随机化心跳和保活消息的发送间隔，使其没有固定周期
+        #[arg(long)]
+        obfs_jitter: bool,
+
+        /// 多路径发送使用的本地路径，可以是本地IP或网络接口名，可多次指定；
+        /// 指定后经UDP发往服务器的消息在各路径间调度
+        #[arg(long = "path")]
+        paths: Vec<String>,
+
+        /// 多路径调度策略: min-rtt (有效往返时间最短的路径) 或 weighted (按往返时间加权轮询)
+        #[arg(long, default_value = "min-rtt")]
+        path_scheduler: String,
+
+        /// 在所有可用路径上重复发送每个消息，服务端去除重复
+        #[arg(long)]
+        path_duplicate: bool,
+
+        /// 前向纠错比例，格式为 数据分片数:校验分片数 (如 10:2)；
+        /// 每个分组最多恢复与校验分片数相同数量的丢包，只用于UDP和QUIC服务器
+        #[arg(long)]
+        fec: Option<String>,
+
+        /// 为TUN设备启用 virtio-net 头部卸载 (TSO和校验和卸载)，系统以64KB的TCP超大分段读写，
+        /// 提高大流量TCP传输的吞吐
+        #[arg(long)]
+        tun_offload: bool,
+
+        /// UDP套接字和TUN设备的收发后端: epoll 或 io-uring (需要以 io-uring 功能编译，Linux 6.0 起)
+        #[arg(long, default_value = "epoll")]
+        io_backend: String,
+    },
+    /// 基准测试模式: 在进程内经回环地址运行服务端和多个模拟客户端，以内存中的设备代替TUN设备，
+    /// 测量数据路径的吞吐、延迟和内存分配
+    Bench {
+        /// 模拟客户端数，每个客户端向下一个客户端发送数据包
+        #[arg(long, default_value = "4")]
+        clients: usize,
+
+        /// 数据包大小 (IP数据包的字节数)，可多次指定，依次测试每种大小
+        #[arg(long = "packet-size", default_value = "1400")]
+        packet_sizes: Vec<usize>,
+
+        /// 所有客户端合计的发送速率 (数据包/秒)，0 表示尽快发送
+        #[arg(long, default_value = "0")]
+        rate: u64,
+
+        /// 每种数据包大小的测试时长 (秒)
+        #[arg(long, default_value = "5")]
+        duration: u64,
+
+        /// 服务端设备队列数，每个队列由单独的任务读取
+        #[arg(long, default_value = "1")]
+        queues: usize,
+
+        /// 服务端为每个队列绑定一个 SO_REUSEPORT UDP套接字
+        #[arg(long)]
+        reuseport: bool,
+
+        /// UDP套接字的收发后端: epoll 或 io-uring (需要以 io-uring 功能编译，Linux 6.0 起)
+        #[arg(long, default_value = "epoll")]
+        io_backend: String,
     },
 }
 
@@ -51,29 +281,267 @@ impl Config {
         Config::parse()
     }
 
-    pub fn get_server_addr(&self) -> Result<SocketAddr> {
+    pub fn get_server_endpoints(&self) -> Result<Vec<ServerEndpoint>> {
         match &self.mode {
-            Mode::Client { server, .. } => {
-                server.parse().map_err(|e| VswitchError::ConfigError(format!("无效的服务器地址: {}", e)))
+            Mode::Client { servers, .. } => {
+                servers.iter()
+                    .map(|server| server.parse())
+                    .collect()
             }
             _ => Err(VswitchError::ConfigError("不是客户端模式".to_string())),
         }
     }
 
-    pub fn get_listen_addr(&self) -> Result<SocketAddr> {
+    pub fn get_proxy(&self) -> Result<Option<HttpProxy>> {
+        match &self.mode {
+            Mode::Client { proxy: Some(proxy), .. } => proxy.parse().map(Some),
+            Mode::Client { proxy: None, .. } => Ok(None),
+            _ => Err(VswitchError::ConfigError("不是客户端模式".to_string())),
+        }
+    }
+
+    pub fn get_socks5(&self) -> Result<Option<Socks5Proxy>> {
+        match &self.mode {
+            Mode::Client { socks5: Some(socks5), .. } => socks5.parse().map(Some),
+            Mode::Client { socks5: None, .. } => Ok(None),
+            _ => Err(VswitchError::ConfigError("不是客户端模式".to_string())),
+        }
+    }
+
+    /// 获取多路径配置，未指定路径时返回 `None`
+    pub fn get_multipath(&self) -> Result<Option<MultipathOptions>> {
+        match &self.mode {
+            Mode::Client { paths, .. } if paths.is_empty() => Ok(None),
+            Mode::Client { socks5: Some(_), .. } => {
+                Err(VswitchError::ConfigError("多路径不能与SOCKS5代理同时使用".to_string()))
+            }
+            Mode::Client { paths, path_scheduler, path_duplicate, .. } => Ok(Some(MultipathOptions {
+                paths: paths.iter().map(|path| path.parse()).collect::<Result<_>>()?,
+                scheduler: path_scheduler.parse()?,
+                duplicate: *path_duplicate,
+            })),
+            _ => Err(VswitchError::ConfigError("不是客户端模式".to_string())),
+        }
+    }
+
+    /// 获取前向纠错比例，未启用前向纠错时返回 `None`
+    pub fn get_fec(&self) -> Result<Option<FecRatio>> {
+        match &self.mode {
+            Mode::Client { fec: Some(fec), .. } => fec.parse().map(Some),
+            Mode::Client { fec: None, .. } => Ok(None),
+            _ => Err(VswitchError::ConfigError("不是客户端模式".to_string())),
+        }
+    }
+
+    /// 获取基准测试选项
+    pub fn get_bench_options(&self) -> Result<BenchOptions> {
+        match &self.mode {
+            Mode::Bench { clients, packet_sizes, rate, duration, queues, reuseport, .. } => {
+                if !(2..=bench::MAX_CLIENTS).contains(clients) {
+                    return Err(VswitchError::ConfigError(format!("模拟客户端数必须在 2 到 {} 之间", bench::MAX_CLIENTS)));
+                }
+                if let Some(size) = packet_sizes.iter().find(|size| !(bench::MIN_PACKET_SIZE..=bench::MAX_PACKET_SIZE).contains(size)) {
+                    return Err(VswitchError::ConfigError(format!(
+                        "数据包大小 {} 超出范围，必须在 {} 到 {} 字节之间", size, bench::MIN_PACKET_SIZE, bench::MAX_PACKET_SIZE,
+                    )));
+                }
+                if *duration == 0 {
+                    return Err(VswitchError::ConfigError("测试时长不能为 0".to_string()));
+                }
+                if !(1..=tun::MAX_QUEUES).contains(queues) {
+                    return Err(VswitchError::ConfigError(format!("设备队列数必须在 1 到 {} 之间", tun::MAX_QUEUES)));
+                }
+                Ok(BenchOptions {
+                    clients: *clients,
+                    packet_sizes: packet_sizes.clone(),
+                    rate: *rate,
+                    duration: Duration::from_secs(*duration),
+                    queues: *queues,
+                    reuseport: *reuseport,
+                })
+            }
+            _ => Err(VswitchError::ConfigError("不是基准测试模式".to_string())),
+        }
+    }
+
+    /// 获取TUN设备队列数
+    pub fn get_queues(&self) -> Result<usize> {
         match &self.mode {
-            Mode::Server { listen, .. } => {
-                listen.parse().map_err(|e| VswitchError::ConfigError(format!("无效的监听地址: {}", e)))
+            Mode::Server { queues, .. } if (1..=tun::MAX_QUEUES).contains(queues) => Ok(*queues),
+            Mode::Server { .. } => {
+                Err(VswitchError::ConfigError(format!("TUN设备队列数必须在 1 到 {} 之间", tun::MAX_QUEUES)))
             }
             _ => Err(VswitchError::ConfigError("不是服务端模式".to_string())),
         }
     }
 
+    pub fn get_listen_addrs(&self) -> Result<Vec<ListenAddr>> {
+        match &self.mode {
+            Mode::Server { listens, .. } => {
+                listens.iter()
+                    .map(|listen| listen.parse())
+                    .collect()
+            }
+            _ => Err(VswitchError::ConfigError("不是服务端模式".to_string())),
+        }
+    }
+
+    pub fn get_probe_addr(&self) -> Result<Option<SocketAddr>> {
+        match &self.mode {
+            Mode::Server { probe_listen: Some(probe_listen), .. } => {
+                probe_listen.parse()
+                    .map(Some)
+                    .map_err(|e| VswitchError::ConfigError(format!("无效的NAT探测地址: {}", e)))
+            }
+            Mode::Server { probe_listen: None, .. } => Ok(None),
+            _ => Err(VswitchError::ConfigError("不是服务端模式".to_string())),
+        }
+    }
+
+    /// 获取集群共享密钥
+    pub fn get_cluster_key(&self) -> Option<Vec<u8>> {
+        match &self.mode {
+            Mode::Server { cluster_key, .. } | Mode::Client { cluster_key, .. } => {
+                cluster_key.as_ref().map(|key| key.as_bytes().to_vec())
+            }
+            Mode::Bench { .. } => None,
+        }
+    }
+
+    pub fn get_peer_addrs(&self) -> Result<Vec<SocketAddr>> {
+        match &self.mode {
+            Mode::Server { peers, cluster_key, .. } => {
+                if !peers.is_empty() {
+                    match cluster_key {
+                        None => return Err(VswitchError::ConfigError("--peer 需要同时指定 --cluster-key，用于签名路由通告".to_string())),
+                        Some(key) if key.is_empty() => return Err(VswitchError::ConfigError("集群密钥不能为空".to_string())),
+                        Some(_) => {}
+                    }
+                }
+                peers.iter()
+                    .map(|peer| peer.parse().map_err(|e| VswitchError::ConfigError(format!("无效的邻居服务端地址 {}: {}", peer, e))))
+                    .collect()
+            }
+            _ => Err(VswitchError::ConfigError("不是服务端模式".to_string())),
+        }
+    }
+
+    /// 根据重定向相关参数创建服务端重定向策略，未指定重定向目标时返回 `None`
+    pub fn get_redirector(&self) -> Result<Option<Redirector>> {
+        match &self.mode {
+            Mode::Server { redirects, max_clients, cluster_key, .. } => {
+                if redirects.is_empty() {
+                    if max_clients.is_some() {
+                        return Err(VswitchError::ConfigError("--max-clients 需要同时指定 --redirect".to_string()));
+                    }
+                    return Ok(None);
+                }
+
+                let key = cluster_key.as_ref()
+                    .ok_or_else(|| VswitchError::ConfigError("--redirect 需要同时指定 --cluster-key".to_string()))?;
+                if key.is_empty() {
+                    return Err(VswitchError::ConfigError("集群密钥不能为空".to_string()));
+                }
+                let targets = redirects.iter()
+                    .map(|target| target.parse().map_err(|e| VswitchError::ConfigError(format!("无效的重定向地址 {}: {}", target, e))))
+                    .collect::<Result<Vec<SocketAddr>>>()?;
+
+                Ok(Some(Redirector::new(key.as_bytes().to_vec(), targets, *max_clients)))
+            }
+            _ => Err(VswitchError::ConfigError("不是服务端模式".to_string())),
+        }
+    }
+
+    pub fn get_tls_server_config(&self) -> Result<Option<Arc<ServerConfig>>> {
+        match &self.mode {
+            Mode::Server { tls_cert, tls_key, tls_client_ca, .. } => {
+                let (cert, key) = match (tls_cert, tls_key) {
+                    (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+                    (None, None) if tls_client_ca.is_none() => return Ok(None),
+                    _ => return Err(VswitchError::ConfigError("--tls-cert 和 --tls-key 需要同时指定".to_string())),
+                };
+
+                tls::server_config(&TlsServerOptions {
+                    cert,
+                    key,
+                    client_ca: tls_client_ca.clone(),
+                }).map(Some)
+            }
+            _ => Err(VswitchError::ConfigError("不是服务端模式".to_string())),
+        }
+    }
+
+    /// 获取UDP混淆器，未指定混淆密钥时返回 `None`
+    pub fn get_obfuscator(&self) -> Result<Option<Arc<Obfuscator>>> {
+        let (key, padding) = match &self.mode {
+            Mode::Server { obfs_key, obfs_padding, .. } => (obfs_key, *obfs_padding),
+            Mode::Client { obfs_key, obfs_padding, .. } => (obfs_key, *obfs_padding),
+            Mode::Bench { .. } => return Ok(None),
+        };
+        let Some(key) = key else {
+            return Ok(None);
+        };
+
+        if key.is_empty() {
+            return Err(VswitchError::ConfigError("混淆密钥不能为空".to_string()));
+        }
+        if padding > MAX_OBFS_PADDING {
+            return Err(VswitchError::ConfigError(format!("随机填充长度不能超过 {} 字节", MAX_OBFS_PADDING)));
+        }
+        Ok(Some(Arc::new(Obfuscator::new(key.as_bytes(), padding))))
+    }
+
+    /// 获取UDP套接字和TUN设备的收发后端
+    pub fn get_io_backend(&self) -> Result<IoBackend> {
+        match &self.mode {
+            Mode::Server { io_backend, .. } => io_backend.parse(),
+            Mode::Client { io_backend, .. } => io_backend.parse(),
+            Mode::Bench { io_backend, .. } => io_backend.parse(),
+        }
+    }
+
+    pub fn get_client_ips(&self) -> Result<HashMap<String, IpAddr>> {
+        match &self.mode {
+            Mode::Server { tls_client_ips, .. } => {
+                tls_client_ips.iter()
+                    .map(|binding| {
+                        let (identity, ip) = binding.rsplit_once('=')
+                            .ok_or_else(|| VswitchError::ConfigError(format!("证书身份绑定格式应为 身份=IP: {}", binding)))?;
+                        let ip = ip.parse()
+                            .map_err(|e| VswitchError::ConfigError(format!("无效的虚拟IP {}: {}", ip, e)))?;
+                        Ok((identity.to_string(), ip))
+                    })
+                    .collect()
+            }
+            _ => Err(VswitchError::ConfigError("不是服务端模式".to_string())),
+        }
+    }
+
+    /// 获取客户端TLS配置，服务器地址中没有加密的传输方式时返回 `None`
+    pub fn get_tls_client_config(&self) -> Result<Option<Arc<ClientConfig>>> {
+        match &self.mode {
+            Mode::Client { tls_ca, tls_pins, tls_cert, tls_key, .. } => {
+                if !self.get_server_endpoints()?.iter().any(|server| server.transport.is_secure()) {
+                    return Ok(None);
+                }
+
+                tls::client_config(&TlsClientOptions {
+                    ca: tls_ca.clone(),
+                    pins: tls_pins.iter().map(|pin| pin.parse()).collect::<Result<_>>()?,
+                    cert: tls_cert.clone(),
+                    key: tls_key.clone(),
+                }).map(Some)
+            }
+            _ => Err(VswitchError::ConfigError("不是客户端模式".to_string())),
+        }
+    }
+
     #[allow(dead_code)]
     pub fn get_tun_name(&self) -> &str {
         match &self.mode {
             Mode::Server { tun_name, .. } => tun_name,
             Mode::Client { tun_name, .. } => tun_name,
+            Mode::Bench { .. } => bench::SERVER_DEVICE,
         }
     }
 
@@ -82,6 +550,7 @@ impl Config {
         match &self.mode {
             Mode::Server { mtu, .. } => *mtu,
             Mode::Client { mtu, .. } => *mtu,
+            Mode::Bench { .. } => bench::MAX_PACKET_SIZE,
         }
     }
 } 
\ No newline at end of file
@@ -9,6 +9,12 @@ pub enum VswitchError {
     #[error("TUN设备错误: {0}")]
     TunError(#[from] tun::Error),
 
+    #[error("域名解析错误: {0}")]
+    ResolveError(#[from] hickory_resolver::error::ResolveError),
+
+    #[error("TLS错误: {0}")]
+    TlsError(#[from] rustls::Error),
+
     #[error("配置错误: {0}")]
     ConfigError(String),
 
@@ -0,0 +1,505 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use bytes::{BufMut, Bytes};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use tokio::time::{Duration, Instant};
+use crate::error::{Result, VswitchError};
+use crate::protocol::{Message, MessageType};
+
+/// 未凑满的分组等待该时间后发送校验分片，限制恢复丢包所需的最长等待
+pub const FLUSH_TIMEOUT: Duration = Duration::from_millis(20);
+/// 一个分组中数据分片和校验分片的最大总数 (GF(2^8) 的限制)
+const MAX_SHARDS: usize = 255;
+/// 接收端保留未完成分组的最长时间
+const GROUP_TIMEOUT: Duration = Duration::from_secs(1);
+/// 接收端最多同时保留的分组数
+const MAX_GROUPS: usize = 1024;
+/// 数据分片中数据包长度字段的长度，重建时据此去掉填充
+const LENGTH_PREFIX: usize = 2;
+
+/// 前向纠错比例: 每个分组的数据分片数和校验分片数
+///
+/// 每个分组最多可以恢复与校验分片数相同数量的丢失数据包
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FecRatio {
+    /// 每个分组的数据分片数
+    pub data: u8,
+    /// 每个分组的校验分片数
+    pub parity: u8,
+}
+
+impl FecRatio {
+    /// 创建前向纠错比例
+    ///
+    /// 返回:
+    /// - 成功: 前向纠错比例
+    /// - 错误: 分片数为0或总数超过255
+    pub fn new(data: u8, parity: u8) -> Result<Self> {
+        if data == 0 || parity == 0 {
+            return Err(VswitchError::ConfigError("前向纠错的数据分片数和校验分片数必须大于0".to_string()));
+        }
+        if data as usize + parity as usize > MAX_SHARDS {
+            return Err(VswitchError::ConfigError(format!("前向纠错的分片总数不能超过 {}", MAX_SHARDS)));
+        }
+        Ok(Self { data, parity })
+    }
+}
+
+impl FromStr for FecRatio {
+    type Err = VswitchError;
+
+    /// 解析 `数据分片数:校验分片数` 格式的比例，如 `10:2`
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || VswitchError::ConfigError(format!("无效的前向纠错比例: {} (格式为 数据分片数:校验分片数，如 10:2)", s));
+        let (data, parity) = s.split_once(':').ok_or_else(invalid)?;
+        let data = data.trim().parse().map_err(|_| invalid())?;
+        let parity = parity.trim().parse().map_err(|_| invalid())?;
+        Self::new(data, parity)
+    }
+}
+
+impl fmt::Display for FecRatio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.data, self.parity)
+    }
+}
+
+/// 前向纠错发送端
+///
+/// 数据包立即作为数据分片发出；每凑满一个分组，或分组的第一个数据包发出后超过
+/// `FLUSH_TIMEOUT`，按分组中的数据包计算并发出校验分片
+pub struct FecEncoder {
+    ratio: FecRatio,
+    /// 完整分组使用的编码器，未凑满的分组按实际的数据分片数另建编码器
+    codec: ReedSolomon,
+    /// 当前分组序号
+    group: u32,
+    /// 当前分组中已发出的数据包
+    packets: Vec<Bytes>,
+    /// 当前分组发送校验分片的最晚时间
+    deadline: Option<Instant>,
+}
+
+impl FecEncoder {
+    /// 创建发送端，分组序号从随机值开始，避免重连后与接收端残留的分组混淆
+    pub fn new(ratio: FecRatio) -> Self {
+        Self {
+            ratio,
+            codec: ReedSolomon::new(ratio.data as usize, ratio.parity as usize).expect("前向纠错比例已校验"),
+            group: rand::random(),
+            packets: Vec::with_capacity(ratio.data as usize),
+            deadline: None,
+        }
+    }
+
+    /// 编码一个数据包
+    ///
+    /// 返回:
+    /// - 需要按顺序发送的编码后消息: 数据分片，分组凑满时还包括该分组的校验分片
+    pub fn encode(&mut self, packet: Bytes) -> Vec<Bytes> {
+        let index = self.packets.len() as u8;
+        let mut messages = vec![Message::fec_data(self.group, index, &packet).encode()];
+
+        if self.packets.is_empty() {
+            self.deadline = Some(Instant::now() + FLUSH_TIMEOUT);
+        }
+        self.packets.push(packet);
+
+        if self.packets.len() == self.ratio.data as usize {
+            messages.extend(self.finish());
+        }
+        messages
+    }
+
+    /// 获取当前分组发送校验分片的最晚时间，没有未完成的分组时返回 `None`
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// 为等待超时的分组生成校验分片
+    ///
+    /// 返回:
+    /// - 编码后的校验分片，分组未超时或没有未完成的分组时为空
+    pub fn flush_expired(&mut self) -> Vec<Bytes> {
+        match self.deadline {
+            Some(deadline) if deadline <= Instant::now() => self.finish(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// 为当前分组生成校验分片并开始下一个分组
+    fn finish(&mut self) -> Vec<Bytes> {
+        let data = self.packets.len();
+        let parity = self.ratio.parity as usize;
+        let shard_len = LENGTH_PREFIX + self.packets.iter().map(|packet| packet.len()).max().unwrap_or(0);
+
+        let shards: Vec<Vec<u8>> = self.packets.iter().map(|packet| data_shard(packet, shard_len)).collect();
+        let mut parity_shards = vec![vec![0u8; shard_len]; parity];
+
+        let result = if data == self.ratio.data as usize {
+            self.codec.encode_sep(&shards, &mut parity_shards)
+        } else {
+            ReedSolomon::new(data, parity).and_then(|codec| codec.encode_sep(&shards, &mut parity_shards))
+        };
+
+        let messages = match result {
+            Ok(()) => parity_shards.iter().enumerate()
+                .map(|(index, shard)| Message::fec_parity(self.group, index as u8, data as u8, parity as u8, shard).encode())
+                .collect(),
+            Err(e) => {
+                log::error!("计算前向纠错校验分片失败: {:?}", e);
+                Vec::new()
+            }
+        };
+
+        self.group = self.group.wrapping_add(1);
+        self.packets.clear();
+        self.deadline = None;
+        messages
+    }
+}
+
+/// 构造用于计算校验的数据分片: 2字节数据包长度 + 数据包，以0填充到分片长度
+fn data_shard(packet: &[u8], shard_len: usize) -> Vec<u8> {
+    let mut shard = Vec::with_capacity(shard_len);
+    shard.put_u16(packet.len() as u16);
+    shard.put_slice(packet);
+    shard.resize(shard_len, 0);
+    shard
+}
+
+/// 接收端的一个分组
+struct Group {
+    /// 收到该分组第一个分片的时间
+    created: Instant,
+    /// 数据分片数和校验分片数，收到校验分片前未知
+    counts: Option<(usize, usize)>,
+    /// 已收到或已恢复的数据包，按分片序号索引
+    packets: Vec<Option<Bytes>>,
+    /// 已收到的校验分片，按校验分片序号索引
+    parity: Vec<Option<Bytes>>,
+    /// 分组的所有数据包都已交付，或分组无效
+    done: bool,
+}
+
+/// 前向纠错接收端
+///
+/// 收到的数据包立即交付；分组中收到的分片数达到数据分片数后，重建丢失的数据包并交付
+pub struct FecDecoder {
+    groups: HashMap<u32, Group>,
+    /// 分组按创建时间排列的序号，用于清理过期的分组
+    order: VecDeque<u32>,
+    /// 各比例的解码器，矩阵的构造开销较大，按比例缓存
+    codecs: HashMap<(usize, usize), ReedSolomon>,
+}
+
+impl Default for FecDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FecDecoder {
+    pub fn new() -> Self {
+        Self {
+            groups: HashMap::new(),
+            order: VecDeque::new(),
+            codecs: HashMap::new(),
+        }
+    }
+
+    /// 处理一个前向纠错分片
+    ///
+    /// 参数:
+    /// - `message`: `FecData` 或 `FecParity` 消息
+    ///
+    /// 返回:
+    /// - 成功: 可以交付的数据包，重复的分片或不足以恢复丢包的校验分片返回空
+    /// - 错误: 消息格式无效
+    pub fn receive(&mut self, message: &Message) -> Result<Vec<Bytes>> {
+        match message.msg_type {
+            MessageType::FecData => {
+                let (group, index, packet) = message.parse_fec_data()?;
+                Ok(self.receive_data(group, index as usize, packet))
+            }
+            MessageType::FecParity => {
+                let (group, index, data, parity, shard) = message.parse_fec_parity()?;
+                let (data, parity) = (data as usize, parity as usize);
+                if data == 0 || parity == 0 || data + parity > MAX_SHARDS || index as usize >= parity {
+                    return Err(VswitchError::InvalidProtocolMessage("无效的前向纠错校验分片".to_string()));
+                }
+                Ok(self.receive_parity(group, index as usize, (data, parity), shard))
+            }
+            other => Err(VswitchError::InvalidProtocolMessage(format!("不是前向纠错消息: {:?}", other))),
+        }
+    }
+
+    /// 处理一个数据分片
+    fn receive_data(&mut self, group: u32, index: usize, packet: Bytes) -> Vec<Bytes> {
+        let entry = self.group(group);
+        if entry.done || entry.counts.is_some_and(|(data, _)| index >= data) {
+            return Vec::new();
+        }
+        if entry.packets.len() <= index {
+            entry.packets.resize(index + 1, None);
+        }
+        if entry.packets[index].is_some() {
+            return Vec::new();
+        }
+        entry.packets[index] = Some(packet.clone());
+
+        let mut ready = vec![packet];
+        self.try_recover(group, &mut ready);
+        ready
+    }
+
+    /// 处理一个校验分片
+    fn receive_parity(&mut self, group: u32, index: usize, counts: (usize, usize), shard: Bytes) -> Vec<Bytes> {
+        let entry = self.group(group);
+        if entry.done {
+            return Vec::new();
+        }
+        match entry.counts {
+            Some(known) if known != counts => {
+                log::debug!("前向纠错分组 {} 的分片数不一致，丢弃该分组", group);
+                entry.done = true;
+                return Vec::new();
+            }
+            Some(_) => {}
+            None => {
+                // 分片序号超出数据分片数的数据包不属于该分组
+                entry.packets.resize(counts.0, None);
+                entry.parity.resize(counts.1, None);
+                entry.counts = Some(counts);
+            }
+        }
+        if entry.parity[index].is_some() {
+            return Vec::new();
+        }
+        entry.parity[index] = Some(shard);
+
+        let mut ready = Vec::new();
+        self.try_recover(group, &mut ready);
+        ready
+    }
+
+    /// 获取或创建分组，创建时清理过期的分组
+    fn group(&mut self, group: u32) -> &mut Group {
+        if !self.groups.contains_key(&group) {
+            let now = Instant::now();
+            while let Some(oldest) = self.order.front() {
+                let expired = self.groups.get(oldest).is_none_or(|entry| now.duration_since(entry.created) >= GROUP_TIMEOUT);
+                if !expired && self.order.len() < MAX_GROUPS {
+                    break;
+                }
+                self.groups.remove(oldest);
+                self.order.pop_front();
+            }
+            self.order.push_back(group);
+        }
+
+        self.groups.entry(group).or_insert_with(|| Group {
+            created: Instant::now(),
+            counts: None,
+            packets: Vec::new(),
+            parity: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// 分组收到足够的分片后重建丢失的数据包
+    fn try_recover(&mut self, group: u32, ready: &mut Vec<Bytes>) {
+        let entry = match self.groups.get_mut(&group) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let (data, parity) = match entry.counts {
+            Some(counts) => counts,
+            None => return,
+        };
+
+        let received = entry.packets.iter().filter(|packet| packet.is_some()).count();
+        if received == data {
+            entry.done = true;
+            return;
+        }
+        let parity_received = entry.parity.iter().filter(|shard| shard.is_some()).count();
+        if received + parity_received < data {
+            return;
+        }
+
+        // 所有校验分片的长度相同，数据分片以0填充到该长度
+        let mut lengths = entry.parity.iter().flatten().map(|shard| shard.len());
+        let shard_len = lengths.next().unwrap_or(0);
+        if shard_len < LENGTH_PREFIX
+            || lengths.any(|len| len != shard_len)
+            || entry.packets.iter().flatten().any(|packet| LENGTH_PREFIX + packet.len() > shard_len) {
+            log::debug!("前向纠错分组 {} 的分片长度不一致，无法恢复", group);
+            return;
+        }
+
+        let mut shards: Vec<Option<Vec<u8>>> = entry.packets.iter()
+            .map(|packet| packet.as_ref().map(|packet| data_shard(packet, shard_len)))
+            .chain(entry.parity.iter().map(|shard| shard.as_ref().map(|shard| shard.to_vec())))
+            .collect();
+
+        let codec = match self.codecs.entry((data, parity)) {
+            Entry::Occupied(codec) => codec.into_mut(),
+            Entry::Vacant(slot) => match ReedSolomon::new(data, parity) {
+                Ok(codec) => slot.insert(codec),
+                Err(e) => {
+                    log::debug!("创建前向纠错解码器失败: {:?}", e);
+                    return;
+                }
+            },
+        };
+        if let Err(e) = codec.reconstruct_data(&mut shards) {
+            log::debug!("前向纠错分组 {} 恢复失败: {:?}", group, e);
+            return;
+        }
+        entry.done = true;
+
+        for (index, shard) in shards.into_iter().take(data).enumerate() {
+            if entry.packets[index].is_some() {
+                continue;
+            }
+            let shard = match shard {
+                Some(shard) => shard,
+                None => continue,
+            };
+            let len = u16::from_be_bytes([shard[0], shard[1]]) as usize;
+            if LENGTH_PREFIX + len > shard.len() {
+                log::debug!("前向纠错分组 {} 恢复的数据包长度无效", group);
+                continue;
+            }
+            let packet = Bytes::copy_from_slice(&shard[LENGTH_PREFIX..LENGTH_PREFIX + len]);
+            log::debug!("前向纠错恢复数据包: 分组 {}, 分片 {}, {} bytes", group, index, len);
+            entry.packets[index] = Some(packet.clone());
+            ready.push(packet);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 解码编码后的消息
+    fn decode(encoded: &Bytes) -> Message {
+        Message::decode(&mut encoded.clone()).expect("编码后的消息有效")
+    }
+
+    fn packets() -> Vec<Bytes> {
+        (0..4u8).map(|i| Bytes::from(vec![i; 100 + i as usize * 37])).collect()
+    }
+
+    #[test]
+    fn recovers_lost_packets() {
+        let mut encoder = FecEncoder::new(FecRatio::new(4, 2).unwrap());
+        let messages: Vec<Message> = packets().into_iter()
+            .flat_map(|packet| encoder.encode(packet))
+            .map(|encoded| decode(&encoded))
+            .collect();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages.iter().filter(|message| message.msg_type == MessageType::FecParity).count(), 2);
+
+        // 丢失第2和第3个数据包，校验分片足以恢复
+        let mut decoder = FecDecoder::new();
+        let mut delivered = Vec::new();
+        for (i, message) in messages.iter().enumerate() {
+            if i == 1 || i == 2 {
+                continue;
+            }
+            delivered.extend(decoder.receive(message).unwrap());
+        }
+
+        delivered.sort();
+        let mut expected = packets();
+        expected.sort();
+        assert_eq!(delivered, expected);
+    }
+
+    #[test]
+    fn cannot_recover_more_losses_than_parity() {
+        let mut encoder = FecEncoder::new(FecRatio::new(4, 1).unwrap());
+        let messages: Vec<Message> = packets().into_iter()
+            .flat_map(|packet| encoder.encode(packet))
+            .map(|encoded| decode(&encoded))
+            .collect();
+
+        let mut decoder = FecDecoder::new();
+        let delivered: Vec<Bytes> = messages.iter().enumerate()
+            .filter(|(i, _)| *i != 0 && *i != 3)
+            .flat_map(|(_, message)| decoder.receive(message).unwrap())
+            .collect();
+        assert_eq!(delivered, packets()[1..3].to_vec());
+    }
+
+    #[test]
+    fn ignores_duplicate_shards() {
+        let mut encoder = FecEncoder::new(FecRatio::new(2, 1).unwrap());
+        let messages: Vec<Message> = packets().into_iter().take(2)
+            .flat_map(|packet| encoder.encode(packet))
+            .map(|encoded| decode(&encoded))
+            .collect();
+
+        let mut decoder = FecDecoder::new();
+        assert_eq!(decoder.receive(&messages[0]).unwrap(), vec![packets()[0].clone()]);
+        assert!(decoder.receive(&messages[0]).unwrap().is_empty());
+        // 收齐数据分片后分组完成，校验分片不再恢复任何数据包
+        assert_eq!(decoder.receive(&messages[1]).unwrap(), vec![packets()[1].clone()]);
+        assert!(decoder.receive(&messages[2]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_recovery_keeps_group_open() {
+        let mut encoder = FecEncoder::new(FecRatio::new(2, 1).unwrap());
+        let messages: Vec<Message> = packets().into_iter().take(2)
+            .flat_map(|packet| encoder.encode(packet))
+            .map(|encoded| decode(&encoded))
+            .collect();
+        let (group, _, _) = messages[0].parse_fec_data().unwrap();
+
+        let mut decoder = FecDecoder::new();
+        assert!(decoder.receive(&messages[2]).unwrap().is_empty());
+
+        // 比校验分片更长的数据分片无法参与恢复，分组不能因此完成
+        let oversized = Bytes::from(vec![0xff; 1000]);
+        let forged = Message::fec_data(group, 0, &oversized);
+        assert_eq!(decoder.receive(&forged).unwrap(), vec![oversized]);
+
+        assert_eq!(decoder.receive(&messages[1]).unwrap(), vec![packets()[1].clone()]);
+    }
+
+    #[test]
+    fn rejects_invalid_parity_counts() {
+        let mut decoder = FecDecoder::new();
+        for (index, data, parity) in [(0, 0, 1), (0, 1, 0), (1, 1, 1), (0, 200, 100)] {
+            let message = Message::fec_parity(1, index, data, parity, &[0; 16]);
+            assert!(decoder.receive(&message).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let mut decoder = FecDecoder::new();
+        assert!(decoder.receive(&Message::new(MessageType::FecData, Bytes::from_static(&[0; 4]))).is_err());
+        assert!(decoder.receive(&Message::new(MessageType::FecParity, Bytes::from_static(&[0; 6]))).is_err());
+        assert!(decoder.receive(&Message::heartbeat()).is_err());
+
+        let data = Message::fec_data(5, 1, b"packet");
+        assert_eq!(data.parse_fec_data().unwrap(), (5, 1, Bytes::from_static(b"packet")));
+        let parity = Message::fec_parity(5, 0, 10, 2, b"shard");
+        assert_eq!(parity.parse_fec_parity().unwrap(), (5, 0, 10, 2, Bytes::from_static(b"shard")));
+    }
+
+    #[test]
+    fn parses_ratio() {
+        assert_eq!("10:2".parse::<FecRatio>().unwrap(), FecRatio { data: 10, parity: 2 });
+        for invalid in ["10", "0:2", "10:0", "200:100", "a:b"] {
+            assert!(invalid.parse::<FecRatio>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::{BufMut, Bytes, BytesMut};
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use crate::error::{Result, VswitchError};
+use crate::protocol::{put_ip, Message, MessageType, NEIGHBOR_TAG_LEN};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 不可达的路由度量值
+pub const INFINITY_METRIC: u8 = 16;
+/// 路由通告间隔
+pub const ADVERTISE_INTERVAL: Duration = Duration::from_secs(10);
+/// 路由超时时间，超过该时间未被邻居刷新的路由会被删除
+const ROUTE_TIMEOUT: Duration = Duration::from_secs(30);
+/// 单条路由通告消息最多包含的路由条目数，保证消息不超过接收缓冲区
+const MAX_ENTRIES_PER_UPDATE: usize = 150;
+
+/// 邻居服务端通告的一条路由
+struct NeighborRoute {
+    /// 邻居到达目标的度量值
+    metric: u8,
+    /// 最后一次被通告的时间
+    updated: Instant,
+}
+
+/// 邻居服务端
+struct Neighbor {
+    /// 邻居通告的路由表 (虚拟IP -> 路由)
+    routes: HashMap<IpAddr, NeighborRoute>,
+    /// 最后接受的路由通告序号，不大于该序号的通告视为重放
+    last_sequence: u64,
+}
+
+/// 经邻居服务端可达的远程路由
+#[derive(Debug, Clone, Copy)]
+struct RemoteRoute {
+    /// 下一跳邻居地址
+    next_hop: SocketAddr,
+    /// 本服务端到达目标的度量值
+    metric: u8,
+}
+
+/// 服务端联邦
+///
+/// 服务端之间使用与客户端相同的协议互联，通过距离矢量协议交换各自拥有的客户端路由，
+/// 并将发往远程客户端的数据转发给下一跳服务端。
+///
+/// 路由通告使用集群共享密钥做HMAC-SHA256签名并携带递增的序号，
+/// 只凭源地址无法伪造或重放通告。转发的数据包附带以同一密钥计算的认证标签，
+/// 伪造源地址无法向服务端注入数据包；数据包不带序号，重放由内层协议处理
+pub struct Federation {
+    /// 集群共享密钥
+    key: Vec<u8>,
+    /// 下一个路由通告的序号，从启动时的时间 (微秒) 开始，重启后仍大于之前的序号
+    sequence: AtomicU64,
+    /// 邻居服务端地址，创建后不再变化，每个消息的检查无需加锁
+    addrs: HashSet<SocketAddr>,
+    /// 邻居服务端 (UDP地址 -> 邻居信息)
+    neighbors: Mutex<HashMap<SocketAddr, Neighbor>>,
+    /// 最优远程路由表 (虚拟IP -> 路由)，每次邻居路由变化后重新计算。
+    /// 转发每个数据包都要查询，使用分片的并发哈希表，查询之间互不阻塞
+    routes: DashMap<IpAddr, RemoteRoute>,
+}
+
+impl Federation {
+    /// 创建联邦实例
+    ///
+    /// 参数:
+    /// - `neighbors`: 邻居服务端地址列表
+    /// - `key`: 集群共享密钥，用于签名和校验路由通告
+    pub fn new(neighbors: &[SocketAddr], key: Vec<u8>) -> Self {
+        let neighbors: HashMap<SocketAddr, Neighbor> = neighbors.iter()
+            .map(|addr| (*addr, Neighbor { routes: HashMap::new(), last_sequence: 0 }))
+            .collect();
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+
+        Self {
+            key,
+            sequence: AtomicU64::new(start),
+            addrs: neighbors.keys().copied().collect(),
+            neighbors: Mutex::new(neighbors),
+            routes: DashMap::new(),
+        }
+    }
+
+    /// 检查地址是否为邻居服务端
+    pub fn is_neighbor(&self, addr: SocketAddr) -> bool {
+        self.addrs.contains(&addr)
+    }
+
+    /// 获取所有邻居服务端地址
+    pub fn neighbor_addrs(&self) -> Vec<SocketAddr> {
+        self.addrs.iter().copied().collect()
+    }
+
+    /// 查找目标虚拟IP的下一跳邻居
+    pub fn next_hop(&self, ip: IpAddr) -> Option<SocketAddr> {
+        self.routes.get(&ip).map(|route| route.next_hop)
+    }
+
+    /// 将转发给邻居的数据包编码为附带认证标签的数据消息
+    ///
+    /// 标签只与数据包和集群共享密钥有关，经过多个服务端转发时无需重新计算
+    pub fn seal_data(&self, packet: &[u8]) -> Bytes {
+        let mac = data_mac(&self.key, packet).finalize().into_bytes();
+        let mut tag = [0u8; NEIGHBOR_TAG_LEN];
+        tag.copy_from_slice(&mac[..NEIGHBOR_TAG_LEN]);
+        Message::neighbor_data(packet, &tag).encode()
+    }
+
+    /// 校验邻居转发的数据消息
+    ///
+    /// 返回:
+    /// - 成功: 消息中的数据包
+    /// - 错误: 消息格式错误或认证标签无效
+    pub fn open_data(&self, message: &Message) -> Result<Bytes> {
+        let (packet, tag) = message.parse_neighbor_data()?;
+        data_mac(&self.key, &packet)
+            .verify_truncated_left(&tag)
+            .map_err(|_| VswitchError::InvalidProtocolMessage("邻居数据消息的认证标签无效".to_string()))?;
+        Ok(packet)
+    }
+
+    /// 处理邻居的路由通告
+    ///
+    /// 签名无效或序号不大于上次接受的序号时拒绝通告
+    ///
+    /// 返回:
+    /// - 成功: 通告的路由条目数
+    /// - 错误: 消息格式错误、签名无效或重放的通告
+    pub async fn apply_update(&self, from: SocketAddr, message: &Message) -> Result<usize> {
+        let (sequence, entries, signature) = message.parse_route_update()?;
+        sign(&self.key, sequence, &entries)
+            .verify_slice(&signature)
+            .map_err(|_| VswitchError::InvalidProtocolMessage("路由通告签名无效".to_string()))?;
+
+        let count = entries.len();
+        {
+            let mut neighbors = self.neighbors.lock().await;
+            let neighbor = match neighbors.get_mut(&from) {
+                Some(neighbor) => neighbor,
+                None => return Ok(0),
+            };
+            if sequence <= neighbor.last_sequence {
+                return Err(VswitchError::InvalidProtocolMessage(format!("重放的路由通告，序号: {}", sequence)));
+            }
+            neighbor.last_sequence = sequence;
+
+            let now = Instant::now();
+            for (ip, metric) in entries {
+                if metric >= INFINITY_METRIC {
+                    neighbor.routes.remove(&ip);
+                } else {
+                    neighbor.routes.insert(ip, NeighborRoute { metric, updated: now });
+                }
+            }
+        }
+
+        self.recompute().await;
+        Ok(count)
+    }
+
+    /// 删除超时未刷新的邻居路由
+    pub async fn expire(&self) {
+        let mut expired = 0;
+        {
+            let mut neighbors = self.neighbors.lock().await;
+            let now = Instant::now();
+            for (addr, neighbor) in neighbors.iter_mut() {
+                let before = neighbor.routes.len();
+                neighbor.routes.retain(|_, route| now.duration_since(route.updated) <= ROUTE_TIMEOUT);
+                let removed = before - neighbor.routes.len();
+                if removed > 0 {
+                    log::info!("邻居服务端 {} 的 {} 条路由已超时", addr, removed);
+                    expired += removed;
+                }
+            }
+        }
+
+        if expired > 0 {
+            self.recompute().await;
+        }
+    }
+
+    /// 根据所有邻居的路由重新计算最优路由表
+    async fn recompute(&self) {
+        let neighbors = self.neighbors.lock().await;
+        let mut best: HashMap<IpAddr, RemoteRoute> = HashMap::new();
+
+        for (addr, neighbor) in neighbors.iter() {
+            for (ip, route) in neighbor.routes.iter() {
+                let metric = route.metric.saturating_add(1);
+                if metric >= INFINITY_METRIC {
+                    continue;
+                }
+                match best.get(ip) {
+                    Some(current) if current.metric <= metric => {}
+                    _ => {
+                        best.insert(*ip, RemoteRoute { next_hop: *addr, metric });
+                    }
+                }
+            }
+        }
+
+        // 逐条替换，查询路由的转发任务不会看到空的路由表
+        self.routes.retain(|ip, _| {
+            let keep = best.contains_key(ip);
+            if !keep {
+                log::info!("远程路由删除: {}", ip);
+            }
+            keep
+        });
+        for (ip, route) in best {
+            let changed = self.routes.insert(ip, route).is_none_or(|old| old.next_hop != route.next_hop);
+            if changed {
+                log::info!("远程路由更新: {} via {} (度量值: {})", ip, route.next_hop, route.metric);
+            }
+        }
+    }
+
+    /// 构造发给指定邻居的路由通告
+    ///
+    /// 本地客户端的度量值为0；从该邻居学到的路由使用毒性逆转，以不可达度量值通告回去。
+    /// 链路本地地址只在单个服务端内有意义，不参与通告
+    pub fn build_updates(&self, neighbor: SocketAddr, local_ips: &[IpAddr]) -> Vec<Message> {
+        let mut entries: Vec<(IpAddr, u8)> = local_ips.iter()
+            .filter(|ip| !is_link_local(ip))
+            .map(|ip| (*ip, 0))
+            .collect();
+
+        for route in self.routes.iter() {
+            let (ip, route) = route.pair();
+            if local_ips.contains(ip) {
+                continue;
+            }
+            let metric = if route.next_hop == neighbor { INFINITY_METRIC } else { route.metric };
+            entries.push((*ip, metric));
+        }
+
+        entries.chunks(MAX_ENTRIES_PER_UPDATE)
+            .map(|entries| {
+                let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+                let signature = sign(&self.key, sequence, entries).finalize().into_bytes();
+                Message::route_update(sequence, entries, &signature.into())
+            })
+            .collect()
+    }
+}
+
+/// 计算转发数据包的认证标签
+///
+/// 先写入消息类型以区分路由通告的签名，截取HMAC-SHA256的前16字节作为标签
+fn data_mac(key: &[u8], packet: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC接受任意长度的密钥");
+    mac.update(&[MessageType::NeighborData as u8]);
+    mac.update(packet);
+    mac
+}
+
+/// 计算路由通告签名
+///
+/// 签名内容为序号和全部路由条目，使用集群共享密钥做HMAC-SHA256
+fn sign(key: &[u8], sequence: u64, entries: &[(IpAddr, u8)]) -> HmacSha256 {
+    let mut buf = BytesMut::new();
+    buf.put_u64(sequence);
+    for (ip, metric) in entries {
+        put_ip(&mut buf, *ip);
+        buf.put_u8(*metric);
+    }
+
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC接受任意长度的密钥");
+    mac.update(&buf);
+    mac
+}
+
+/// 检查是否为链路本地地址
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_unicast_link_local(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"cluster-key";
+
+    fn addr(last: u8) -> SocketAddr {
+        SocketAddr::from(([192, 0, 2, last], 4789))
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn signed_update(key: &[u8], sequence: u64, entries: &[(IpAddr, u8)]) -> Message {
+        let signature = sign(key, sequence, entries).finalize().into_bytes();
+        Message::route_update(sequence, entries, &signature.into())
+    }
+
+    #[tokio::test]
+    async fn learns_routes_from_signed_updates() {
+        let local = Federation::new(&[addr(2)], KEY.to_vec());
+        let remote = Federation::new(&[addr(1)], KEY.to_vec());
+
+        let updates = remote.build_updates(addr(1), &[ip("10.0.0.2"), ip("fe80::2")]);
+        assert_eq!(updates.len(), 1);
+        // 链路本地地址不参与通告
+        assert_eq!(local.apply_update(addr(2), &updates[0]).await.unwrap(), 1);
+        assert_eq!(local.next_hop(ip("10.0.0.2")), Some(addr(2)));
+        assert_eq!(local.next_hop(ip("fe80::2")), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_forged_updates() {
+        let federation = Federation::new(&[addr(2)], KEY.to_vec());
+        let entries = [(ip("10.0.0.2"), 0)];
+
+        let forged = signed_update(b"other-key", 1, &entries);
+        assert!(federation.apply_update(addr(2), &forged).await.is_err());
+
+        // 篡改签名覆盖的路由条目
+        let (sequence, _, signature) = signed_update(KEY, 1, &entries).parse_route_update().unwrap();
+        let tampered = Message::route_update(sequence, &[(ip("10.0.0.3"), 0)], &signature);
+        assert!(federation.apply_update(addr(2), &tampered).await.is_err());
+
+        assert_eq!(federation.next_hop(ip("10.0.0.2")), None);
+        assert_eq!(federation.next_hop(ip("10.0.0.3")), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_replayed_updates() {
+        let federation = Federation::new(&[addr(2)], KEY.to_vec());
+        let update = signed_update(KEY, 5, &[(ip("10.0.0.2"), 0)]);
+        federation.apply_update(addr(2), &update).await.unwrap();
+
+        assert!(federation.apply_update(addr(2), &update).await.is_err());
+        let older = signed_update(KEY, 4, &[(ip("10.0.0.2"), INFINITY_METRIC)]);
+        assert!(federation.apply_update(addr(2), &older).await.is_err());
+        assert_eq!(federation.next_hop(ip("10.0.0.2")), Some(addr(2)));
+
+        // 序号递增的通告仍被接受，不可达度量值撤销路由
+        let newer = signed_update(KEY, 6, &[(ip("10.0.0.2"), INFINITY_METRIC)]);
+        federation.apply_update(addr(2), &newer).await.unwrap();
+        assert_eq!(federation.next_hop(ip("10.0.0.2")), None);
+    }
+
+    #[tokio::test]
+    async fn ignores_updates_from_unknown_servers() {
+        let federation = Federation::new(&[addr(2)], KEY.to_vec());
+        let update = signed_update(KEY, 1, &[(ip("10.0.0.2"), 0)]);
+        assert_eq!(federation.apply_update(addr(3), &update).await.unwrap(), 0);
+        assert_eq!(federation.next_hop(ip("10.0.0.2")), None);
+    }
+
+    #[tokio::test]
+    async fn prefers_lowest_metric() {
+        let federation = Federation::new(&[addr(2), addr(3)], KEY.to_vec());
+        federation.apply_update(addr(2), &signed_update(KEY, 1, &[(ip("10.0.0.9"), 2)])).await.unwrap();
+        federation.apply_update(addr(3), &signed_update(KEY, 1, &[(ip("10.0.0.9"), 0)])).await.unwrap();
+        assert_eq!(federation.next_hop(ip("10.0.0.9")), Some(addr(3)));
+
+        // 度量值加一后达到不可达值的路由被忽略
+        federation.apply_update(addr(2), &signed_update(KEY, 2, &[(ip("10.0.0.8"), INFINITY_METRIC - 1)])).await.unwrap();
+        assert_eq!(federation.next_hop(ip("10.0.0.8")), None);
+    }
+
+    #[tokio::test]
+    async fn poisons_routes_learned_from_neighbor() {
+        let federation = Federation::new(&[addr(2), addr(3)], KEY.to_vec());
+        federation.apply_update(addr(2), &signed_update(KEY, 1, &[(ip("10.0.0.9"), 0)])).await.unwrap();
+
+        let entries = |neighbor| {
+            let updates = federation.build_updates(neighbor, &[ip("10.0.0.1")]);
+            let (_, entries, _) = updates[0].parse_route_update().unwrap();
+            entries
+        };
+        assert_eq!(entries(addr(2)), vec![(ip("10.0.0.1"), 0), (ip("10.0.0.9"), INFINITY_METRIC)]);
+        assert_eq!(entries(addr(3)), vec![(ip("10.0.0.1"), 0), (ip("10.0.0.9"), 1)]);
+    }
+
+    #[test]
+    fn build_updates_uses_increasing_sequences() {
+        let federation = Federation::new(&[addr(2)], KEY.to_vec());
+        let local_ips: Vec<IpAddr> = (0..MAX_ENTRIES_PER_UPDATE as u32 + 1)
+            .map(|i| IpAddr::from((0x0a00_0000 + i).to_be_bytes()))
+            .collect();
+
+        let updates = federation.build_updates(addr(2), &local_ips);
+        assert_eq!(updates.len(), 2);
+        let first = updates[0].parse_route_update().unwrap();
+        let second = updates[1].parse_route_update().unwrap();
+        assert_eq!(first.1.len() + second.1.len(), local_ips.len());
+        assert!(second.0 > first.0);
+    }
+
+    #[test]
+    fn authenticates_forwarded_data() {
+        let federation = Federation::new(&[addr(2)], KEY.to_vec());
+        let encoded = federation.seal_data(b"packet");
+        let message = Message::decode(&mut encoded.clone()).unwrap();
+        assert_eq!(message.msg_type, MessageType::NeighborData);
+        assert_eq!(federation.open_data(&message).unwrap(), Bytes::from_static(b"packet"));
+
+        let other = Federation::new(&[addr(2)], b"other-key".to_vec());
+        assert!(other.open_data(&message).is_err());
+
+        let mut payload = message.payload.to_vec();
+        payload[0] ^= 1;
+        let tampered = Message::new(MessageType::NeighborData, Bytes::from(payload));
+        assert!(federation.open_data(&tampered).is_err());
+
+        let short = Message::new(MessageType::NeighborData, message.payload.slice(..NEIGHBOR_TAG_LEN - 1));
+        assert!(federation.open_data(&short).is_err());
+    }
+}
@@ -1,12 +1,35 @@
+pub mod bench;
+pub mod buffer;
 pub mod config;
 pub mod error;
+pub mod fec;
+pub mod federation;
+pub mod multipath;
+pub mod nat;
+pub mod obfs;
+pub mod packet;
+pub mod peer;
 pub mod protocol;
+pub mod proxy;
+pub mod quic;
+pub mod redirect;
+pub mod resolve;
+pub mod socks;
+pub mod tls;
 pub mod tun;
+pub mod udp;
+#[cfg(feature = "io-uring")]
+pub mod uring;
+pub mod vnet;
+pub mod xdp;
 pub mod server;
+pub mod stream;
+pub mod transport;
+pub mod websocket;
 pub mod client;
 
 pub use crate::config::{Config, Mode};
 pub use crate::error::{Result, VswitchError};
 pub use crate::tun::{TunDevice, create_tun_device};
-pub use crate::server::Server;
-pub use crate::client::Client; 
\ No newline at end of file
+pub use crate::server::{Server, ServerOptions};
+pub use crate::client::{Client, ClientOptions}; 
\ No newline at end of file
@@ -1,15 +1,43 @@
+mod bench;
+mod buffer;
 mod config;
 mod error;
+mod fec;
+mod federation;
+mod multipath;
+mod nat;
+mod obfs;
+mod packet;
+mod peer;
 mod protocol;
+mod proxy;
+mod quic;
+mod redirect;
+mod resolve;
+mod socks;
+mod tls;
 mod tun;
+mod udp;
+#[cfg(feature = "io-uring")]
+mod uring;
+mod vnet;
+mod xdp;
 mod server;
+mod stream;
+mod transport;
+mod websocket;
 mod client;
 
 use crate::config::{Config, Mode};
 use crate::error::Result;
 use crate::tun::create_tun_device;
-use crate::server::Server;
-use crate::client::Client;
+use crate::server::{Server, ServerOptions};
+use crate::client::{Client, ClientOptions};
+use std::time::Duration;
+
+/// 统计基准测试期间的内存分配次数
+#[global_allocator]
+static ALLOCATOR: bench::CountingAlloc = bench::CountingAlloc;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -23,46 +51,166 @@ async fn main() -> Result<()> {
     log::info!("虚拟交换机启动, 版本: 0.1.0");
     log::info!("日志级别: {}", config.log_level);
     
+    // 收发后端需要在创建套接字和TUN设备之前启用
+    let io_backend = config.get_io_backend()?;
+    log::info!("I/O后端: {}", io_backend);
+    #[cfg(feature = "io-uring")]
+    if io_backend == config::IoBackend::IoUring {
+        uring::enable().await?;
+    }
+    
     // 根据模式创建TUN设备并启动服务
     match &config.mode {
-        Mode::Server { listen: _, tun_name, mtu } => {
+        Mode::Server { tun_name, mtu, reuseport, tun_offload, xdp, .. } => {
             log::info!("运行模式: 服务端");
             
-            let listen_addr = config.get_listen_addr()?;
+            let listen_addrs = config.get_listen_addrs()?;
+            let probe_addr = config.get_probe_addr()?;
+            let peer_addrs = config.get_peer_addrs()?;
+            let redirector = config.get_redirector()?;
+            let tls = config.get_tls_server_config()?;
+            let client_ips = config.get_client_ips()?;
+            let obfs = config.get_obfuscator()?;
+            let queues = config.get_queues()?;
             
-            log::info!("TUN设备名称: {}, MTU: {}, 监听地址: {}", tun_name, mtu, listen_addr);
+            log::info!("TUN设备名称: {}, MTU: {}, 队列数: {}", tun_name, mtu, queues);
+            if *reuseport {
+                log::info!("每个TUN队列使用一个 SO_REUSEPORT UDP套接字");
+            }
+            if *tun_offload {
+                log::info!("TUN设备启用 virtio-net 头部卸载");
+            }
+            if let Some(xdp) = xdp {
+                log::info!("在网络接口 {} 上启用 AF_XDP 快速路径", xdp);
+            }
+            for listen_addr in &listen_addrs {
+                log::info!("监听地址: {}", listen_addr);
+            }
+            if let Some(probe_addr) = probe_addr {
+                log::info!("NAT探测辅助地址: {}", probe_addr);
+            }
+            for peer_addr in &peer_addrs {
+                log::info!("邻居服务端: {}", peer_addr);
+            }
+            if redirector.is_some() {
+                log::info!("已启用过载/维护重定向");
+            }
+            for (identity, ip) in &client_ips {
+                log::info!("证书身份 {} 绑定虚拟IP: {}", identity, ip);
+            }
+            if let Some(obfs) = &obfs {
+                log::info!("已启用UDP混淆，每个数据报最多增加 {} 字节", obfs.overhead());
+            }
             
             // 创建TUN设备
             log::info!("正在创建TUN设备...");
-            let tun = create_tun_device(tun_name, *mtu as u32)?;
+            let tun = create_tun_device(tun_name, *mtu as u32, queues, *tun_offload)?;
             log::info!("TUN设备创建成功: {}", tun.name());
             
             // 创建并启动服务端
             log::info!("正在初始化服务端...");
-            let server = Server::new(tun);
+            let server = Server::new(tun, ServerOptions {
+                probe_addr,
+                neighbors: peer_addrs,
+                cluster_key: config.get_cluster_key(),
+                redirector,
+                tls,
+                client_ips,
+                obfs,
+                reuseport: *reuseport,
+                xdp: xdp.clone(),
+            });
             
             log::info!("服务端初始化完成，开始运行...");
-            server.run(listen_addr).await?;
+            server.run(&listen_addrs).await?;
         }
-        Mode::Client { server: _, tun_name, mtu } => {
+        Mode::Client { tun_name, mtu, no_p2p, keepalive, cluster_key, obfs_jitter, tun_offload, .. } => {
             log::info!("运行模式: 客户端");
             
-            let server_addr = config.get_server_addr()?;
+            let servers = config.get_server_endpoints()?;
+            let proxy = config.get_proxy()?;
+            let socks5 = config.get_socks5()?;
+            let obfs = config.get_obfuscator()?;
+            let tls = config.get_tls_client_config()?;
+            let multipath = config.get_multipath()?;
+            let fec = config.get_fec()?;
             
-            log::info!("TUN设备名称: {}, MTU: {}, 服务器地址: {}", tun_name, mtu, server_addr);
+            log::info!("TUN设备名称: {}, MTU: {}", tun_name, mtu);
+            for (index, server) in servers.iter().enumerate() {
+                log::info!("服务器地址 (优先级 {}): {}", index + 1, server);
+            }
+            if let Some(proxy) = &proxy {
+                log::info!("HTTP代理: {}", proxy);
+            }
+            if let Some(socks5) = &socks5 {
+                log::info!("SOCKS5代理: {}", socks5);
+                log::info!("点对点直连: 禁用 (使用SOCKS5代理)");
+            } else {
+                log::info!("点对点直连: {}", if *no_p2p { "禁用" } else { "启用" });
+            }
+            match keepalive {
+                Some(secs) => log::info!("保活间隔: {} 秒", secs),
+                None => log::info!("保活间隔: 根据NAT探测结果自动调整"),
+            }
+            if let Some(obfs) = &obfs {
+                log::info!("已启用UDP混淆，每个数据报最多增加 {} 字节", obfs.overhead());
+            }
+            if *obfs_jitter {
+                log::info!("已启用心跳间隔随机化");
+            }
+            if let Some(multipath) = &multipath {
+                let paths: Vec<String> = multipath.paths.iter().map(|path| path.to_string()).collect();
+                log::info!(
+                    "多路径: {}, 调度策略: {}{}",
+                    paths.join(", "), multipath.scheduler, if multipath.duplicate { ", 重复发送" } else { "" },
+                );
+            }
+            if let Some(fec) = &fec {
+                log::info!("前向纠错比例: {} (数据分片:校验分片)", fec);
+            }
+            if *tun_offload {
+                log::info!("TUN设备启用 virtio-net 头部卸载");
+            }
             
             // 创建TUN设备
             log::info!("正在创建TUN设备...");
-            let tun = create_tun_device(tun_name, *mtu as u32)?;
+            let tun = create_tun_device(tun_name, *mtu as u32, 1, *tun_offload)?;
             log::info!("TUN设备创建成功: {}", tun.name());
             
             // 创建并启动客户端
             log::info!("正在初始化客户端...");
-            let client = Client::new(tun, server_addr);
+            let client = Client::new(tun, servers, ClientOptions {
+                p2p: !*no_p2p,
+                keepalive: keepalive.map(Duration::from_secs),
+                cluster_key: cluster_key.as_ref().map(|key| key.as_bytes().to_vec()),
+                proxy,
+                socks5,
+                tls,
+                obfs,
+                heartbeat_jitter: *obfs_jitter,
+                multipath,
+                fec,
+            });
             
-            log::info!("客户端初始化完成，开始连接服务器: {}...", server_addr);
+            log::info!("客户端初始化完成，开始连接服务器...");
             client.run().await?;
         }
+        Mode::Bench { .. } => {
+            log::info!("运行模式: 基准测试");
+            
+            let options = config.get_bench_options()?;
+            let sizes: Vec<String> = options.packet_sizes.iter().map(|size| size.to_string()).collect();
+            log::info!(
+                "模拟客户端: {}, 数据包大小: {} 字节, 每种大小测试 {} 秒",
+                options.clients, sizes.join(", "), options.duration.as_secs(),
+            );
+            match options.rate {
+                0 => log::info!("发送速率: 不限"),
+                rate => log::info!("发送速率: {} 数据包/秒", rate),
+            }
+            
+            bench::run(options).await?;
+        }
     }
     
     log::info!("虚拟交换机已退出");
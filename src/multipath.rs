@@ -0,0 +1,549 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use futures::FutureExt;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use crate::error::{Result, VswitchError};
+use crate::protocol::Message;
+use crate::udp::DualStackSocket;
+
+/// 路径探测请求超过该时间没有响应时计为丢失
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// 路径超过该时间没有探测响应时视为失效
+const PATH_DEAD_AFTER: Duration = Duration::from_secs(3);
+/// 丢包率估计的平滑系数，每个探测结果的权重
+const LOSS_ALPHA: f64 = 0.1;
+/// 乱序消息最长的等待时间，超时后跳过缺失的序号
+const REORDER_TIMEOUT: Duration = Duration::from_millis(100);
+/// 每个会话最多缓存的乱序消息数，超出后跳过缺失的序号
+const REORDER_WINDOW: usize = 256;
+
+/// 客户端的一条本地路径
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSpec {
+    /// 绑定本地IP地址
+    Addr(IpAddr),
+    /// 绑定网络接口
+    Interface(String),
+}
+
+impl FromStr for PathSpec {
+    type Err = VswitchError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() {
+            return Err(VswitchError::ConfigError("路径不能为空".to_string()));
+        }
+        Ok(match s.parse::<IpAddr>() {
+            Ok(ip) => PathSpec::Addr(ip),
+            Err(_) => PathSpec::Interface(s.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for PathSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSpec::Addr(ip) => write!(f, "{}", ip),
+            PathSpec::Interface(name) => f.write_str(name),
+        }
+    }
+}
+
+/// 路径调度策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scheduler {
+    /// 使用有效往返时间最短的路径
+    #[default]
+    MinRtt,
+    /// 按有效往返时间的倒数加权轮询所有可用路径
+    Weighted,
+}
+
+impl FromStr for Scheduler {
+    type Err = VswitchError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "min-rtt" => Ok(Scheduler::MinRtt),
+            "weighted" => Ok(Scheduler::Weighted),
+            _ => Err(VswitchError::ConfigError(format!("未知的路径调度策略: {} (可选 min-rtt, weighted)", s))),
+        }
+    }
+}
+
+impl fmt::Display for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scheduler::MinRtt => "min-rtt",
+            Scheduler::Weighted => "weighted",
+        })
+    }
+}
+
+/// 多路径配置
+#[derive(Debug, Clone, Default)]
+pub struct MultipathOptions {
+    /// 本地路径列表
+    pub paths: Vec<PathSpec>,
+    /// 调度策略
+    pub scheduler: Scheduler,
+    /// 是否在所有可用路径上重复发送每个消息
+    pub duplicate: bool,
+}
+
+/// 一条路径的测量结果
+#[derive(Debug, Default)]
+struct PathStats {
+    /// 平滑往返时间，没有测量结果时为 `None`
+    srtt: Option<Duration>,
+    /// 平滑丢包率
+    loss: f64,
+    /// 最后一次收到探测响应的时间
+    last_ack: Option<Instant>,
+    /// 是否可用，用于输出路径状态变化
+    alive: bool,
+    /// 尚未响应的探测请求及其发送时间
+    outstanding: HashMap<u32, Instant>,
+    /// 加权轮询的当前权重
+    credit: i64,
+}
+
+impl PathStats {
+    /// 检查路径是否可用
+    fn is_alive(&self, now: Instant) -> bool {
+        self.last_ack.is_some_and(|last_ack| now.duration_since(last_ack) < PATH_DEAD_AFTER)
+    }
+
+    /// 计入丢包率的有效往返时间 (微秒)
+    fn score(&self) -> f64 {
+        let rtt = self.srtt.unwrap_or(PATH_DEAD_AFTER).as_micros().max(1) as f64;
+        rtt / (1.0 - self.loss.min(0.9))
+    }
+}
+
+/// 客户端的一条路径
+struct Path {
+    spec: PathSpec,
+    socket: DualStackSocket,
+}
+
+/// 客户端多路径发送
+///
+/// 每条路径使用单独的套接字，发往服务器的消息加上会话标识和序号后按调度策略选择路径发送，
+/// 服务端据此合并各路径上的消息。路径的往返时间和丢包率通过定期的路径探测测量
+pub struct Multipath {
+    /// 会话标识
+    session: u64,
+    /// 下一个消息序号
+    seq: AtomicU64,
+    /// 下一个探测序号
+    probe_id: AtomicU32,
+    paths: Vec<Path>,
+    stats: Mutex<Vec<PathStats>>,
+    scheduler: Scheduler,
+    duplicate: bool,
+    /// 当前服务器地址，只有发往该地址的消息使用多路径
+    server: Mutex<Option<SocketAddr>>,
+}
+
+impl Multipath {
+    /// 绑定所有路径的套接字
+    pub fn bind(options: &MultipathOptions) -> io::Result<Self> {
+        let mut paths = Vec::with_capacity(options.paths.len());
+        for spec in &options.paths {
+            let socket = match spec {
+                PathSpec::Addr(ip) => DualStackSocket::bind_addr(*ip),
+                PathSpec::Interface(name) => DualStackSocket::bind_device(name),
+            }.map_err(|e| io::Error::new(e.kind(), format!("绑定路径 {} 失败: {}", spec, e)))?;
+            log::info!("路径 {} 绑定成功，本地地址: {}", spec, socket.local_addr()?);
+            paths.push(Path { spec: spec.clone(), socket });
+        }
+
+        Ok(Self {
+            session: rand::random(),
+            seq: AtomicU64::new(0),
+            probe_id: AtomicU32::new(0),
+            stats: Mutex::new(paths.iter().map(|_| PathStats::default()).collect()),
+            paths,
+            scheduler: options.scheduler,
+            duplicate: options.duplicate,
+            server: Mutex::new(None),
+        })
+    }
+
+    /// 设置当前服务器地址，为 `None` 时不使用多路径
+    pub async fn set_server(&self, server: Option<SocketAddr>) {
+        *self.server.lock().await = server;
+    }
+
+    /// 获取当前服务器地址
+    pub async fn server(&self) -> Option<SocketAddr> {
+        *self.server.lock().await
+    }
+
+    /// 为发往服务器的消息加上会话标识和序号
+    pub fn encapsulate(&self, message: &[u8]) -> Bytes {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        Message::multipath(self.session, seq, message).encode()
+    }
+
+    /// 按调度策略选择发送消息的路径
+    ///
+    /// 没有可用路径时 (如刚启动或所有路径都失效) 使用所有路径
+    pub async fn select(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let mut stats = self.stats.lock().await;
+        let alive: Vec<usize> = (0..stats.len()).filter(|index| stats[*index].is_alive(now)).collect();
+
+        if alive.is_empty() || self.duplicate {
+            return if alive.is_empty() { (0..stats.len()).collect() } else { alive };
+        }
+
+        match self.scheduler {
+            Scheduler::MinRtt => {
+                let best = alive.iter()
+                    .copied()
+                    .min_by(|a, b| stats[*a].score().total_cmp(&stats[*b].score()));
+                best.into_iter().collect()
+            }
+            Scheduler::Weighted => {
+                // 平滑加权轮询：每轮各路径累加自身权重，选择累计值最大的路径并减去总权重
+                let weights: Vec<i64> = alive.iter()
+                    .map(|index| (1e9 / stats[*index].score()).max(1.0) as i64)
+                    .collect();
+                let total: i64 = weights.iter().sum();
+                for (index, weight) in alive.iter().zip(&weights) {
+                    stats[*index].credit += weight;
+                }
+                let best = alive.iter().copied().max_by_key(|index| stats[*index].credit).unwrap_or(alive[0]);
+                stats[best].credit -= total;
+                vec![best]
+            }
+        }
+    }
+
+    /// 通过指定路径发送数据报
+    pub async fn send(&self, index: usize, datagram: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.paths[index].socket.send_to(datagram, target).await
+    }
+
+    /// 生成各路径的探测请求，同时将超时未响应的探测计为丢失
+    ///
+    /// 返回:
+    /// - 路径索引和编码后的探测请求
+    pub async fn probes(&self) -> Vec<(usize, Bytes)> {
+        let now = Instant::now();
+        let mut stats = self.stats.lock().await;
+        let mut probes = Vec::with_capacity(stats.len());
+
+        for (index, path) in stats.iter_mut().enumerate() {
+            let before = path.outstanding.len();
+            path.outstanding.retain(|_, sent| now.duration_since(*sent) < PROBE_TIMEOUT);
+            for _ in path.outstanding.len()..before {
+                path.loss = path.loss * (1.0 - LOSS_ALPHA) + LOSS_ALPHA;
+            }
+
+            if path.alive && !path.is_alive(now) {
+                path.alive = false;
+                log::warn!("路径 {} 失效", self.paths[index].spec);
+            }
+
+            let id = self.probe_id.fetch_add(1, Ordering::Relaxed);
+            path.outstanding.insert(id, now);
+            probes.push((index, Message::path_probe(id).encode()));
+        }
+
+        probes
+    }
+
+    /// 处理路径探测响应，更新往返时间和丢包率
+    pub async fn on_probe_reply(&self, index: usize, id: u32) {
+        let now = Instant::now();
+        let mut stats = self.stats.lock().await;
+        let Some(path) = stats.get_mut(index) else {
+            return;
+        };
+        let Some(sent) = path.outstanding.remove(&id) else {
+            return;
+        };
+
+        let sample = now.duration_since(sent);
+        path.srtt = Some(match path.srtt {
+            Some(srtt) => (srtt * 7 + sample) / 8,
+            None => sample,
+        });
+        path.loss *= 1.0 - LOSS_ALPHA;
+        path.last_ack = Some(now);
+
+        if !path.alive {
+            path.alive = true;
+            log::info!("路径 {} 可用，往返时间: {:?}", self.paths[index].spec, sample);
+        }
+        log::debug!(
+            "路径 {} 往返时间: {:?}, 平滑往返时间: {:?}, 丢包率: {:.1}%",
+            self.paths[index].spec, sample, path.srtt.unwrap_or_default(), path.loss * 100.0,
+        );
+    }
+
+    /// 等待任一路径的套接字可读，返回路径索引
+    pub async fn readable(&self) -> io::Result<usize> {
+        let readable = self.paths.iter().map(|path| path.socket.readable().boxed());
+        let (result, index, _) = futures::future::select_all(readable).await;
+        result.map(|_| index)
+    }
+
+    /// 尝试从指定路径接收一个数据报
+    pub fn try_recv_from(&self, index: usize) -> io::Result<(BytesMut, SocketAddr)> {
+        self.paths[index].socket.try_recv_from()
+    }
+}
+
+/// 服务端的一个多路径会话
+struct Session {
+    /// 会话在服务端的标识地址，即会话第一个消息的来源地址
+    canonical: SocketAddr,
+    /// 最近收到消息的路径，发往该会话的消息经此路径发送
+    last_path: SocketAddr,
+    /// 最后一次收到消息的时间
+    last_rx: Instant,
+    /// 下一个按序交付的序号
+    next_seq: u64,
+    /// 等待按序交付的消息及其到达时间
+    pending: BTreeMap<u64, (Instant, Bytes)>,
+}
+
+impl Session {
+    /// 从等待队列中交付连续的消息
+    fn drain(&mut self, ready: &mut Vec<Bytes>) {
+        while let Some((_, message)) = self.pending.remove(&self.next_seq) {
+            ready.push(message);
+            self.next_seq += 1;
+        }
+    }
+
+    /// 跳过缺失的序号，从等待队列中最小的序号继续交付
+    fn skip_gap(&mut self, ready: &mut Vec<Bytes>) {
+        if let Some(first) = self.pending.keys().next() {
+            self.next_seq = *first;
+            self.drain(ready);
+        }
+    }
+}
+
+/// 服务端多路径会话表
+///
+/// 合并同一会话在各条路径上的消息：丢弃重复的序号，短暂缓存乱序的消息后按序交付。
+/// 会话中的所有路径对外表现为同一个客户端地址。
+/// 发往每个客户端的消息都要查询会话表，两个表都使用分片的并发哈希表，查询之间互不阻塞
+#[derive(Default)]
+pub struct SessionTable {
+    sessions: DashMap<u64, Session>,
+    /// 标识地址到会话标识的映射
+    canonical: DashMap<SocketAddr, u64>,
+    /// 所有会话中等待按序交付的消息总数，为0时无需检查超时
+    pending: AtomicUsize,
+}
+
+impl SessionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 处理一个多路径消息
+    ///
+    /// 参数:
+    /// - `path`: 消息的来源地址
+    /// - `session`: 会话标识
+    /// - `seq`: 消息序号
+    /// - `message`: 编码后的原始消息
+    ///
+    /// 返回:
+    /// - 会话的标识地址和可以按序交付的消息，消息重复或需要等待时为空
+    pub fn receive(&self, path: SocketAddr, session: u64, seq: u64, message: Bytes) -> (SocketAddr, Vec<Bytes>) {
+        let now = Instant::now();
+        let mut ready = Vec::new();
+
+        let mut entry = self.sessions.entry(session).or_insert_with(|| {
+            log::info!("新的多路径会话 {:016x}，标识地址: {}", session, path);
+            self.canonical.insert(path, session);
+            Session {
+                canonical: path,
+                last_path: path,
+                last_rx: now,
+                next_seq: seq,
+                pending: BTreeMap::new(),
+            }
+        });
+
+        entry.last_path = path;
+        entry.last_rx = now;
+
+        if seq < entry.next_seq || entry.pending.contains_key(&seq) {
+            return (entry.canonical, ready);
+        }
+
+        let before = entry.pending.len();
+        if seq == entry.next_seq {
+            ready.push(message);
+            entry.next_seq += 1;
+            entry.drain(&mut ready);
+        } else {
+            entry.pending.insert(seq, (now, message));
+            if entry.pending.len() > REORDER_WINDOW {
+                entry.skip_gap(&mut ready);
+            }
+        }
+        self.adjust_pending(before, entry.pending.len());
+
+        (entry.canonical, ready)
+    }
+
+    /// 交付等待超时的乱序消息
+    ///
+    /// 返回:
+    /// - 会话的标识地址和按序交付的消息
+    pub fn flush_expired(&self) -> Vec<(SocketAddr, Bytes)> {
+        let now = Instant::now();
+        let mut flushed = Vec::new();
+
+        for mut session in self.sessions.iter_mut() {
+            let mut ready = Vec::new();
+            let before = session.pending.len();
+            while session.pending.values().next().is_some_and(|(arrived, _)| now.duration_since(*arrived) >= REORDER_TIMEOUT) {
+                session.skip_gap(&mut ready);
+            }
+            self.adjust_pending(before, session.pending.len());
+            flushed.extend(ready.into_iter().map(|message| (session.canonical, message)));
+        }
+
+        flushed
+    }
+
+    /// 获取最早的乱序消息等待超时的时间，没有等待的消息时返回 `None`
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.pending.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        self.sessions.iter()
+            .filter_map(|session| session.pending.values().next().map(|(arrived, _)| *arrived + REORDER_TIMEOUT))
+            .min()
+    }
+
+    /// 获取发往指定地址的消息应使用的路径，地址不是会话的标识地址时返回 `None`
+    pub fn route(&self, addr: SocketAddr) -> Option<SocketAddr> {
+        let session = *self.canonical.get(&addr)?;
+        self.sessions.get(&session).map(|session| session.last_path)
+    }
+
+    /// 删除超过指定时间没有收到消息的会话
+    pub fn expire(&self, idle: Duration) {
+        let now = Instant::now();
+
+        self.sessions.retain(|id, session| {
+            let alive = now.duration_since(session.last_rx) < idle;
+            if !alive {
+                log::info!("多路径会话 {:016x} 已过期", id);
+                self.canonical.remove(&session.canonical);
+                self.adjust_pending(session.pending.len(), 0);
+            }
+            alive
+        });
+    }
+
+    /// 按一个会话等待队列长度的变化更新等待消息总数
+    fn adjust_pending(&self, before: usize, after: usize) {
+        if after > before {
+            self.pending.fetch_add(after - before, Ordering::Relaxed);
+        } else {
+            self.pending.fetch_sub(before - after, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: u64 = 0xfeed;
+
+    fn path(port: u16) -> SocketAddr {
+        SocketAddr::new("192.0.2.1".parse().unwrap(), port)
+    }
+
+    fn message(seq: u64) -> Bytes {
+        Bytes::from(seq.to_be_bytes().to_vec())
+    }
+
+    /// 经指定路径交付序号，返回按序交付的序号
+    fn receive(table: &SessionTable, port: u16, seq: u64) -> Vec<u64> {
+        let (canonical, ready) = table.receive(path(port), SESSION, seq, message(seq));
+        assert_eq!(canonical, path(1000));
+        ready.iter().map(|message| u64::from_be_bytes(message[..].try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn reorders_and_drops_duplicates() {
+        let table = SessionTable::new();
+        assert_eq!(receive(&table, 1000, 10), vec![10]);
+        assert_eq!(receive(&table, 1001, 12), Vec::<u64>::new());
+        assert_eq!(receive(&table, 1000, 12), Vec::<u64>::new());
+        assert_eq!(receive(&table, 1001, 13), Vec::<u64>::new());
+        assert!(table.next_deadline().is_some());
+
+        assert_eq!(receive(&table, 1000, 11), vec![11, 12, 13]);
+        assert!(table.next_deadline().is_none());
+
+        // 已交付的序号在任一路径上重复到达都丢弃
+        assert_eq!(receive(&table, 1001, 11), Vec::<u64>::new());
+        assert_eq!(receive(&table, 1000, 10), Vec::<u64>::new());
+        assert_eq!(receive(&table, 1001, 14), vec![14]);
+    }
+
+    #[test]
+    fn routes_via_last_path() {
+        let table = SessionTable::new();
+        receive(&table, 1000, 0);
+        assert_eq!(table.route(path(1000)), Some(path(1000)));
+        receive(&table, 1001, 1);
+        assert_eq!(table.route(path(1000)), Some(path(1001)));
+        assert_eq!(table.route(path(1001)), None);
+
+        table.expire(Duration::ZERO);
+        assert_eq!(table.route(path(1000)), None);
+    }
+
+    #[test]
+    fn flushes_gap_after_timeout() {
+        let table = SessionTable::new();
+        receive(&table, 1000, 0);
+        receive(&table, 1000, 2);
+        receive(&table, 1000, 3);
+        assert!(table.flush_expired().is_empty());
+
+        std::thread::sleep(REORDER_TIMEOUT);
+        let flushed: Vec<(SocketAddr, Bytes)> = table.flush_expired();
+        assert_eq!(flushed, vec![(path(1000), message(2)), (path(1000), message(3))]);
+        assert!(table.next_deadline().is_none());
+
+        // 跳过的序号迟到时丢弃
+        assert_eq!(receive(&table, 1000, 1), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn skips_gap_when_window_is_full() {
+        let table = SessionTable::new();
+        receive(&table, 1000, 0);
+        for seq in 2..2 + REORDER_WINDOW as u64 {
+            assert_eq!(receive(&table, 1000, seq), Vec::<u64>::new());
+        }
+        let ready = receive(&table, 1000, 2 + REORDER_WINDOW as u64);
+        assert_eq!(ready, (2..3 + REORDER_WINDOW as u64).collect::<Vec<_>>());
+    }
+}
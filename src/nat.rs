@@ -0,0 +1,312 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use bytes::Bytes;
+use tokio::net::UdpSocket;
+use tokio::time::{self, Duration, Instant};
+use crate::error::{Result, VswitchError};
+use crate::obfs::Obfuscator;
+use crate::protocol::{Message, MessageType};
+
+/// 立即响应的探测请求的超时时间
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// 立即响应的探测请求的重试次数
+const PROBE_RETRIES: usize = 3;
+/// 延迟响应的探测请求在延迟之外额外等待的时间
+const DELAYED_PROBE_GRACE: Duration = Duration::from_secs(3);
+/// 依次测试的NAT映射空闲存活时间 (秒)
+const LIFETIME_STEPS: [u16; 6] = [5, 10, 15, 20, 25, 30];
+
+/// 保活间隔下限
+pub const MIN_KEEPALIVE: Duration = Duration::from_secs(3);
+/// 保活间隔上限，需小于服务端的心跳超时时间
+pub const MAX_KEEPALIVE: Duration = Duration::from_secs(20);
+
+/// NAT映射类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatMapping {
+    /// 没有NAT，公网地址即本地地址
+    None,
+    /// 与目标无关的映射 (锥形NAT)，适合打洞
+    EndpointIndependent,
+    /// 与目标相关的映射 (对称型NAT)，通常无法打洞
+    EndpointDependent,
+    /// 存在NAT，但服务端未启用辅助探测端口，无法判断映射类型
+    Unknown,
+}
+
+impl fmt::Display for NatMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NatMapping::None => "无NAT",
+            NatMapping::EndpointIndependent => "锥形NAT (映射与目标无关)",
+            NatMapping::EndpointDependent => "对称型NAT (映射与目标相关)",
+            NatMapping::Unknown => "未知类型NAT",
+        };
+        f.write_str(name)
+    }
+}
+
+/// NAT探测结果
+#[derive(Debug, Clone)]
+pub struct NatReport {
+    /// NAT映射类型
+    pub mapping: NatMapping,
+    /// 服务端观察到的公网地址
+    pub public_addr: SocketAddr,
+    /// 已验证的映射最长空闲存活时间，`None` 表示最短的测试也未通过
+    pub lifetime: Option<Duration>,
+    /// 根据探测结果推荐的保活间隔
+    pub keepalive: Duration,
+}
+
+impl fmt::Display for NatReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "类型: {}, 公网地址: {}, ", self.mapping, self.public_addr)?;
+        match (self.mapping, self.lifetime) {
+            (NatMapping::None, _) => {}
+            (_, Some(lifetime)) => write!(f, "映射存活: >= {}秒, ", lifetime.as_secs())?,
+            (_, None) => write!(f, "映射存活: < {}秒, ", LIFETIME_STEPS[0])?,
+        }
+        write!(f, "推荐保活间隔: {}秒", self.keepalive.as_secs())
+    }
+}
+
+/// 借助服务端探测本机的NAT映射类型和映射存活时间
+///
+/// 使用独立的UDP套接字，探测期间该套接字上没有其他流量，
+/// 因此延迟响应能否到达即可反映映射的空闲存活时间。
+/// 服务端启用了混淆时需要提供相同的混淆器
+pub async fn detect(server_addr: SocketAddr, obfs: Option<&Obfuscator>) -> Result<NatReport> {
+    let unspecified = match server_addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+
+    // 通过一个已连接的临时套接字获取访问服务端时使用的本地IP
+    let local_ip = {
+        let route_socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+        route_socket.connect(server_addr).await?;
+        route_socket.local_addr()?.ip()
+    };
+    let local_addr = SocketAddr::new(local_ip, socket.local_addr()?.port());
+
+    let mut next_id = 1u32;
+
+    // 1. 获取公网地址
+    let (public_addr, alt_port) = probe_with_retry(&socket, obfs, server_addr, next_id).await?
+        .ok_or_else(|| VswitchError::InvalidProtocolMessage("NAT探测无响应".to_string()))?;
+    next_id += 1;
+
+    log::debug!("NAT探测: 本地地址 {}, 公网地址 {}", local_addr, public_addr);
+
+    if public_addr == local_addr {
+        return Ok(NatReport {
+            mapping: NatMapping::None,
+            public_addr,
+            lifetime: None,
+            keepalive: MAX_KEEPALIVE,
+        });
+    }
+
+    // 2. 通过辅助端口判断映射类型
+    let mapping = if alt_port != 0 {
+        let alt_addr = SocketAddr::new(server_addr.ip(), alt_port);
+        match probe_with_retry(&socket, obfs, alt_addr, next_id).await? {
+            Some((alt_public_addr, _)) if alt_public_addr == public_addr => NatMapping::EndpointIndependent,
+            Some((alt_public_addr, _)) => {
+                log::debug!("NAT探测: 辅助端口观察到的公网地址为 {}", alt_public_addr);
+                NatMapping::EndpointDependent
+            }
+            None => {
+                log::warn!("NAT探测: 辅助端口 {} 无响应", alt_addr);
+                NatMapping::Unknown
+            }
+        }
+    } else {
+        NatMapping::Unknown
+    };
+    next_id += 1;
+
+    // 3. 逐步增加空闲时间，测试映射存活时间
+    let mut lifetime = None;
+    for delay_secs in LIFETIME_STEPS {
+        let delay = Duration::from_secs(delay_secs as u64);
+        log::debug!("NAT探测: 测试映射空闲 {} 秒后是否仍然有效", delay_secs);
+
+        match probe(&socket, obfs, server_addr, next_id, delay_secs, delay + DELAYED_PROBE_GRACE).await? {
+            Some(_) => lifetime = Some(delay),
+            None => break,
+        }
+        next_id += 1;
+    }
+
+    Ok(NatReport {
+        mapping,
+        public_addr,
+        lifetime,
+        keepalive: keepalive_for(lifetime),
+    })
+}
+
+/// 探测服务端是否存活
+///
+/// 使用NAT探测请求作为健康检查，服务端无需为探测方建立会话
+pub async fn ping(server_addr: SocketAddr, obfs: Option<&Obfuscator>) -> bool {
+    let unspecified = match server_addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = match UdpSocket::bind(SocketAddr::new(unspecified, 0)).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::error!("绑定健康检查套接字失败: {}", e);
+            return false;
+        }
+    };
+
+    matches!(probe_with_retry(&socket, obfs, server_addr, rand::random()).await, Ok(Some(_)))
+}
+
+/// 根据映射存活时间计算保活间隔
+///
+/// 取存活时间的80%，并限制在 [`MIN_KEEPALIVE`, `MAX_KEEPALIVE`] 范围内
+fn keepalive_for(lifetime: Option<Duration>) -> Duration {
+    match lifetime {
+        Some(lifetime) => (lifetime * 4 / 5).clamp(MIN_KEEPALIVE, MAX_KEEPALIVE),
+        None => MIN_KEEPALIVE,
+    }
+}
+
+/// 发送立即响应的探测请求，失败时重试
+async fn probe_with_retry(
+    socket: &UdpSocket,
+    obfs: Option<&Obfuscator>,
+    target: SocketAddr,
+    id: u32,
+) -> Result<Option<(SocketAddr, u16)>> {
+    for _ in 0..PROBE_RETRIES {
+        if let Some(reply) = probe(socket, obfs, target, id, 0, PROBE_TIMEOUT).await? {
+            return Ok(Some(reply));
+        }
+    }
+    Ok(None)
+}
+
+/// 发送一次探测请求并等待对应序号的响应
+///
+/// 返回服务端观察到的公网地址及辅助探测端口，超时返回 `None`
+async fn probe(
+    socket: &UdpSocket,
+    obfs: Option<&Obfuscator>,
+    target: SocketAddr,
+    id: u32,
+    delay_secs: u16,
+    timeout: Duration,
+) -> Result<Option<(SocketAddr, u16)>> {
+    let request = Message::nat_probe(id, delay_secs).encode();
+    match obfs {
+        Some(obfs) => socket.send_to(&obfs.seal(&request), target).await?,
+        None => socket.send_to(&request, target).await?,
+    };
+
+    let deadline = Instant::now() + timeout;
+    let mut recv_buf = vec![0u8; 512 + obfs.map_or(0, |obfs| obfs.overhead())];
+
+    loop {
+        let (size, _) = match time::timeout_at(deadline, socket.recv_from(&mut recv_buf)).await {
+            Ok(result) => result?,
+            Err(_) => return Ok(None),
+        };
+        let size = match obfs {
+            Some(obfs) => match obfs.open(&mut recv_buf, size) {
+                Ok(size) => size,
+                Err(_) => continue,
+            },
+            None => size,
+        };
+
+        let message = match Message::decode(&mut Bytes::copy_from_slice(&recv_buf[..size])) {
+            Ok(message) if message.msg_type == MessageType::NatProbeReply => message,
+            _ => continue,
+        };
+
+        match message.parse_nat_probe_reply() {
+            Ok((reply_id, observed, alt_port)) if reply_id == id => return Ok(Some((observed, alt_port))),
+            Ok(_) => continue,
+            Err(e) => log::debug!("NAT探测: 解析响应错误: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在回环地址上运行的探测服务端，忽略延迟立即响应
+    ///
+    /// `observed` 为 `None` 时响应请求的实际来源地址，否则响应指定地址，模拟NAT映射
+    async fn probe_server(observed: Option<SocketAddr>, alt_port: u16) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+                let message = Message::decode(&mut Bytes::copy_from_slice(&buf[..len])).unwrap();
+                let (id, _) = message.parse_nat_probe().unwrap();
+                let reply = Message::nat_probe_reply(id, observed.unwrap_or(from), alt_port);
+                socket.send_to(&reply.encode(), from).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    fn mapped() -> SocketAddr {
+        "203.0.113.5:40000".parse().unwrap()
+    }
+
+    #[test]
+    fn keepalive_follows_lifetime() {
+        assert_eq!(keepalive_for(None), MIN_KEEPALIVE);
+        assert_eq!(keepalive_for(Some(Duration::from_secs(2))), MIN_KEEPALIVE);
+        assert_eq!(keepalive_for(Some(Duration::from_secs(10))), Duration::from_secs(8));
+        assert_eq!(keepalive_for(Some(Duration::from_secs(30))), MAX_KEEPALIVE);
+    }
+
+    #[tokio::test]
+    async fn detects_no_nat() {
+        let server = probe_server(None, 0).await;
+        let report = detect(server, None).await.unwrap();
+        assert_eq!(report.mapping, NatMapping::None);
+        assert_eq!(report.keepalive, MAX_KEEPALIVE);
+    }
+
+    #[tokio::test]
+    async fn detects_endpoint_independent_mapping() {
+        let alt = probe_server(Some(mapped()), 0).await;
+        let server = probe_server(Some(mapped()), alt.port()).await;
+
+        let report = detect(server, None).await.unwrap();
+        assert_eq!(report.mapping, NatMapping::EndpointIndependent);
+        assert_eq!(report.public_addr, mapped());
+        assert_eq!(report.lifetime, Some(Duration::from_secs(*LIFETIME_STEPS.last().unwrap() as u64)));
+        assert_eq!(report.keepalive, MAX_KEEPALIVE);
+    }
+
+    #[tokio::test]
+    async fn detects_endpoint_dependent_mapping() {
+        let alt = probe_server(Some("203.0.113.5:40001".parse().unwrap()), 0).await;
+        let server = probe_server(Some(mapped()), alt.port()).await;
+        assert_eq!(detect(server, None).await.unwrap().mapping, NatMapping::EndpointDependent);
+
+        let server = probe_server(Some(mapped()), 0).await;
+        assert_eq!(detect(server, None).await.unwrap().mapping, NatMapping::Unknown);
+    }
+
+    #[tokio::test]
+    async fn pings_server() {
+        let server = probe_server(None, 0).await;
+        assert!(ping(server, None).await);
+    }
+}
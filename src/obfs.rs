@@ -0,0 +1,141 @@
+use std::io;
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+use tokio::time::Duration;
+use crate::protocol::HEADER_LEN;
+
+/// 每个数据报开头的随机数长度
+pub const NONCE_LEN: usize = 12;
+/// 默认的最大随机填充长度
+pub const DEFAULT_MAX_PADDING: usize = 64;
+
+/// UDP数据报混淆
+///
+/// 数据报格式为 随机数 (12字节) + 以共享密钥和随机数生成的 ChaCha20 密钥流加密的 (消息 + 随机长度的填充)，
+/// 消息头和长度都被加密，每个数据报的长度也不再等于消息长度，线路上看起来是均匀的随机字节。
+/// 混淆只用于对抗流量识别，不提供完整性保护，需要保密性时应使用TLS或QUIC传输
+#[derive(Clone)]
+pub struct Obfuscator {
+    /// 由共享密钥派生的加密密钥
+    key: [u8; 32],
+    /// 最大随机填充长度
+    max_padding: usize,
+}
+
+impl Obfuscator {
+    /// 创建混淆器
+    ///
+    /// 参数:
+    /// - `key`: 共享密钥，通信双方需要一致
+    /// - `max_padding`: 每个数据报最多添加的随机填充长度
+    pub fn new(key: &[u8], max_padding: usize) -> Self {
+        Self {
+            key: Sha256::digest(key).into(),
+            max_padding,
+        }
+    }
+
+    /// 获取每个数据报最多增加的长度
+    pub fn overhead(&self) -> usize {
+        NONCE_LEN + self.max_padding
+    }
+
+    /// 混淆一条编码后的消息
+    pub fn seal(&self, message: &[u8]) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let padding = rng.gen_range(0..=self.max_padding);
+
+        let mut datagram = vec![0u8; NONCE_LEN + message.len() + padding];
+        rng.fill_bytes(&mut datagram[..NONCE_LEN]);
+        datagram[NONCE_LEN..NONCE_LEN + message.len()].copy_from_slice(message);
+
+        let (nonce, body) = datagram.split_at_mut(NONCE_LEN);
+        self.cipher(nonce).apply_keystream(body);
+        datagram
+    }
+
+    /// 还原混淆后的数据报，消息移到缓冲区开头
+    ///
+    /// 参数:
+    /// - `buf`: 接收缓冲区
+    /// - `len`: 数据报长度
+    ///
+    /// 返回:
+    /// - 成功: 消息长度
+    /// - 错误: 数据报过短或解密后的消息头与长度不符，通常是未混淆或密钥不同的数据报
+    pub fn open(&self, buf: &mut [u8], len: usize) -> io::Result<usize> {
+        if len < NONCE_LEN + HEADER_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "混淆数据报过短"));
+        }
+
+        let (nonce, body) = buf[..len].split_at_mut(NONCE_LEN);
+        self.cipher(nonce).apply_keystream(body);
+
+        let payload_len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        let message_len = HEADER_LEN.saturating_add(payload_len);
+        if message_len > body.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "无效的混淆数据报"));
+        }
+
+        buf.copy_within(NONCE_LEN..NONCE_LEN + message_len, 0);
+        Ok(message_len)
+    }
+
+    /// 创建指定随机数的密钥流
+    fn cipher(&self, nonce: &[u8]) -> ChaCha20 {
+        ChaCha20::new(&self.key.into(), nonce.into())
+    }
+}
+
+/// 随机化定时消息的间隔，结果在间隔的一半到间隔之间均匀分布
+///
+/// 只缩短不延长，保活消息仍能在NAT映射过期前发出
+pub fn jitter(interval: Duration) -> Duration {
+    interval.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Message;
+
+    #[test]
+    fn seal_and_open() {
+        let obfs = Obfuscator::new(b"key", DEFAULT_MAX_PADDING);
+        let message = Message::heartbeat().encode();
+
+        let sealed = obfs.seal(&message);
+        assert!(sealed.len() >= NONCE_LEN + message.len());
+        assert!(sealed.len() <= message.len() + obfs.overhead());
+        assert_ne!(&sealed[NONCE_LEN..NONCE_LEN + message.len()], &message[..]);
+
+        let mut buf = sealed.clone();
+        let len = obfs.open(&mut buf, sealed.len()).unwrap();
+        assert_eq!(&buf[..len], &message[..]);
+    }
+
+    #[test]
+    fn seal_uses_fresh_nonce() {
+        let obfs = Obfuscator::new(b"key", 0);
+        let message = Message::heartbeat().encode();
+        assert_ne!(obfs.seal(&message), obfs.seal(&message));
+    }
+
+    #[test]
+    fn open_rejects_wrong_key() {
+        let message = Message::peer_request("10.0.0.2".parse().unwrap()).encode();
+        let mut sealed = Obfuscator::new(b"key", 0).seal(&message);
+        let len = sealed.len();
+        assert!(Obfuscator::new(b"other", 0).open(&mut sealed, len).is_err());
+    }
+
+    #[test]
+    fn open_rejects_short_datagram() {
+        let obfs = Obfuscator::new(b"key", 0);
+        let mut buf = [0u8; NONCE_LEN + HEADER_LEN - 1];
+        let len = buf.len();
+        assert!(obfs.open(&mut buf, len).is_err());
+    }
+}
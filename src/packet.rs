@@ -0,0 +1,45 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// 提取IP数据包的源IP地址
+pub fn extract_src_ip(packet: &[u8]) -> Option<IpAddr> {
+    // 检查是否为IPv4数据包
+    if packet.len() >= 20 && (packet[0] >> 4) == 4 {
+        // IPv4: 源地址从12字节开始，长度4字节
+        let src_ip = Ipv4Addr::new(
+            packet[12], packet[13], packet[14], packet[15]
+        );
+        return Some(IpAddr::V4(src_ip));
+    }
+    // 检查是否为IPv6数据包
+    else if packet.len() >= 40 && (packet[0] >> 4) == 6 {
+        // IPv6: 源地址从8字节开始，长度16字节
+        let mut src_ip_bytes = [0u8; 16];
+        src_ip_bytes.copy_from_slice(&packet[8..24]);
+        let src_ip = Ipv6Addr::from(src_ip_bytes);
+        return Some(IpAddr::V6(src_ip));
+    }
+
+    None
+}
+
+/// 提取IP数据包的目标IP地址
+pub fn extract_dst_ip(packet: &[u8]) -> Option<IpAddr> {
+    // 检查是否为IPv4数据包
+    if packet.len() >= 20 && (packet[0] >> 4) == 4 {
+        // IPv4: 目标地址从16字节开始，长度4字节
+        let dst_ip = Ipv4Addr::new(
+            packet[16], packet[17], packet[18], packet[19]
+        );
+        return Some(IpAddr::V4(dst_ip));
+    }
+    // 检查是否为IPv6数据包
+    else if packet.len() >= 40 && (packet[0] >> 4) == 6 {
+        // IPv6: 目标地址从24字节开始，长度16字节
+        let mut dst_ip_bytes = [0u8; 16];
+        dst_ip_bytes.copy_from_slice(&packet[24..40]);
+        let dst_ip = Ipv6Addr::from(dst_ip_bytes);
+        return Some(IpAddr::V6(dst_ip));
+    }
+
+    None
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// 等待服务端返回对端地址的超时时间
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// 打洞超时时间，超时后回退到服务端中转
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// 直连路径空闲超时时间，超时后回退到服务端中转
+const DIRECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// 打洞失败后重新尝试的间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 对端直连状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerStatus {
+    /// 已向服务端查询对端地址，等待回复
+    Resolving,
+    /// 正在与对端打洞
+    Punching,
+    /// 直连路径已建立
+    Direct,
+    /// 直连不可用，经服务端中转
+    Relayed,
+}
+
+/// 数据包的转发路径
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    /// 直接发送到对端公网地址
+    Direct(SocketAddr),
+    /// 经服务端中转，`request` 表示是否需要向服务端查询对端地址
+    Relay { request: bool },
+}
+
+/// 单个对端的直连信息
+struct Peer {
+    /// 对端公网地址
+    addr: Option<SocketAddr>,
+    /// 当前状态
+    status: PeerStatus,
+    /// 进入当前状态的时间
+    since: Instant,
+    /// 最后一次收到对端消息的时间
+    last_seen: Instant,
+}
+
+/// 客户端对端表
+///
+/// 记录虚拟IP到对端公网地址的直连状态，由TUN读取任务和接收循环共享
+pub struct PeerTable {
+    /// 本机虚拟IP地址 (从TUN数据包的源地址学习)
+    local_ip: Mutex<Option<IpAddr>>,
+    /// 虚拟IP -> 对端直连信息
+    peers: Mutex<HashMap<IpAddr, Peer>>,
+}
+
+impl PeerTable {
+    /// 创建一个空的对端表
+    pub fn new() -> Self {
+        Self {
+            local_ip: Mutex::new(None),
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 获取本机虚拟IP地址
+    pub async fn local_ip(&self) -> Option<IpAddr> {
+        *self.local_ip.lock().await
+    }
+
+    /// 更新本机虚拟IP地址
+    pub async fn set_local_ip(&self, ip: IpAddr) {
+        let mut local_ip = self.local_ip.lock().await;
+        if *local_ip != Some(ip) {
+            log::info!("本机虚拟IP地址: {}", ip);
+            *local_ip = Some(ip);
+        }
+    }
+
+    /// 为发往 `dst_ip` 的数据包选择转发路径
+    ///
+    /// 首次出现的目标会返回 `Relay { request: true }`，调用方应向服务端查询对端地址
+    pub async fn route(&self, dst_ip: IpAddr) -> Route {
+        let mut peers = self.peers.lock().await;
+        let now = Instant::now();
+
+        let peer = match peers.get_mut(&dst_ip) {
+            Some(peer) => peer,
+            None => {
+                peers.insert(dst_ip, Peer {
+                    addr: None,
+                    status: PeerStatus::Resolving,
+                    since: now,
+                    last_seen: now,
+                });
+                return Route::Relay { request: true };
+            }
+        };
+
+        match peer.status {
+            PeerStatus::Direct => {
+                if now.duration_since(peer.last_seen) <= DIRECT_TIMEOUT {
+                    if let Some(addr) = peer.addr {
+                        return Route::Direct(addr);
+                    }
+                }
+                log::warn!("与对端 {} 的直连路径超时，回退到服务端中转", dst_ip);
+                peer.status = PeerStatus::Relayed;
+                peer.since = now;
+            }
+            PeerStatus::Resolving => {
+                if now.duration_since(peer.since) > RESOLVE_TIMEOUT {
+                    log::debug!("服务端未返回对端 {} 的地址，使用服务端中转", dst_ip);
+                    peer.status = PeerStatus::Relayed;
+                    peer.since = now;
+                }
+            }
+            PeerStatus::Punching => {
+                if now.duration_since(peer.since) > PUNCH_TIMEOUT {
+                    log::info!("与对端 {} 打洞失败，使用服务端中转", dst_ip);
+                    peer.status = PeerStatus::Relayed;
+                    peer.since = now;
+                }
+            }
+            PeerStatus::Relayed => {
+                if now.duration_since(peer.since) > RETRY_INTERVAL {
+                    peer.status = PeerStatus::Resolving;
+                    peer.since = now;
+                    return Route::Relay { request: true };
+                }
+            }
+        }
+
+        Route::Relay { request: false }
+    }
+
+    /// 开始与对端打洞
+    ///
+    /// 只在收到服务端通告的对端地址时调用，此后只接受来自该地址的打洞消息和数据；
+    /// 返回 `false` 表示已与该地址建立直连，无需再次打洞
+    pub async fn start_punch(&self, peer_ip: IpAddr, addr: SocketAddr) -> bool {
+        let mut peers = self.peers.lock().await;
+        let now = Instant::now();
+
+        let peer = peers.entry(peer_ip).or_insert(Peer {
+            addr: None,
+            status: PeerStatus::Resolving,
+            since: now,
+            last_seen: now,
+        });
+
+        if peer.status == PeerStatus::Direct && peer.addr == Some(addr) {
+            return false;
+        }
+
+        log::debug!("开始与对端 {} ({}) 打洞", peer_ip, addr);
+        peer.addr = Some(addr);
+        peer.status = PeerStatus::Punching;
+        peer.since = now;
+        true
+    }
+
+    /// 服务端通告不能与对端直连时改用服务端中转
+    ///
+    /// 只影响等待服务端回复的对端，`RETRY_INTERVAL` 后再次查询
+    pub async fn relay(&self, peer_ip: IpAddr) {
+        let mut peers = self.peers.lock().await;
+        if let Some(peer) = peers.get_mut(&peer_ip) {
+            if peer.status == PeerStatus::Resolving {
+                peer.status = PeerStatus::Relayed;
+                peer.since = Instant::now();
+            }
+        }
+    }
+
+    /// 检查 `addr` 是否为服务端为对端通告的地址，且正在打洞或已直连
+    ///
+    /// 打洞消息只是对端声称的虚拟IP，必须与服务端通告的地址一致才能响应
+    pub async fn is_advertised(&self, peer_ip: IpAddr, addr: SocketAddr) -> bool {
+        let peers = self.peers.lock().await;
+        matches!(
+            peers.get(&peer_ip),
+            Some(peer) if peer.addr == Some(addr) && matches!(peer.status, PeerStatus::Punching | PeerStatus::Direct)
+        )
+    }
+
+    /// 检查是否仍在与对端打洞
+    pub async fn is_punching(&self, peer_ip: IpAddr) -> bool {
+        let peers = self.peers.lock().await;
+        matches!(peers.get(&peer_ip), Some(peer) if peer.status == PeerStatus::Punching)
+    }
+
+    /// 检查是否已与对端建立直连
+    pub async fn is_direct(&self, peer_ip: IpAddr) -> bool {
+        let peers = self.peers.lock().await;
+        matches!(peers.get(&peer_ip), Some(peer) if peer.status == PeerStatus::Direct)
+    }
+
+    /// 确认直连路径
+    ///
+    /// 收到对端的打洞确认时调用，只接受正在打洞或已直连、且确认来自服务端通告的地址的对端，
+    /// 不会改变对端的地址
+    pub async fn confirm(&self, peer_ip: IpAddr, addr: SocketAddr) -> bool {
+        let mut peers = self.peers.lock().await;
+        let now = Instant::now();
+
+        match peers.get_mut(&peer_ip) {
+            Some(peer) if peer.addr == Some(addr) && matches!(peer.status, PeerStatus::Punching | PeerStatus::Direct) => {
+                if peer.status != PeerStatus::Direct {
+                    log::info!("与对端 {} 建立直连路径: {}", peer_ip, addr);
+                    peer.since = now;
+                }
+                peer.status = PeerStatus::Direct;
+                peer.last_seen = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// 记录来自对端地址的活动，返回该地址对应的对端虚拟IP
+    ///
+    /// 返回 `None` 表示该地址不是已知的对端，调用方应丢弃其数据
+    pub async fn touch(&self, addr: SocketAddr) -> Option<IpAddr> {
+        let mut peers = self.peers.lock().await;
+
+        for (peer_ip, peer) in peers.iter_mut() {
+            if peer.addr == Some(addr) && matches!(peer.status, PeerStatus::Punching | PeerStatus::Direct) {
+                peer.last_seen = Instant::now();
+                return Some(*peer_ip);
+            }
+        }
+
+        None
+    }
+
+    /// 获取所有已直连对端的地址，用于保活
+    pub async fn direct_addrs(&self) -> Vec<SocketAddr> {
+        let peers = self.peers.lock().await;
+        peers.values()
+            .filter(|peer| peer.status == PeerStatus::Direct)
+            .filter_map(|peer| peer.addr)
+            .collect()
+    }
+}
+
+impl Default for PeerTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_ip() -> IpAddr {
+        "10.0.0.2".parse().unwrap()
+    }
+
+    fn advertised() -> SocketAddr {
+        "198.51.100.20:40000".parse().unwrap()
+    }
+
+    fn other() -> SocketAddr {
+        "203.0.113.66:40000".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_peer_address_once() {
+        let peers = PeerTable::new();
+        assert_eq!(peers.route(peer_ip()).await, Route::Relay { request: true });
+        assert_eq!(peers.route(peer_ip()).await, Route::Relay { request: false });
+    }
+
+    #[tokio::test]
+    async fn confirms_only_advertised_address() {
+        let peers = PeerTable::new();
+        peers.route(peer_ip()).await;
+        assert!(peers.start_punch(peer_ip(), advertised()).await);
+
+        assert!(peers.is_advertised(peer_ip(), advertised()).await);
+        assert!(!peers.is_advertised(peer_ip(), other()).await);
+        assert!(!peers.confirm(peer_ip(), other()).await);
+        assert!(peers.is_punching(peer_ip()).await);
+
+        assert!(peers.confirm(peer_ip(), advertised()).await);
+        assert!(peers.is_direct(peer_ip()).await);
+        assert_eq!(peers.route(peer_ip()).await, Route::Direct(advertised()));
+        assert_eq!(peers.direct_addrs().await, vec![advertised()]);
+
+        // 已与同一地址直连时无需再次打洞
+        assert!(!peers.start_punch(peer_ip(), advertised()).await);
+    }
+
+    #[tokio::test]
+    async fn rejects_unsolicited_peers() {
+        let peers = PeerTable::new();
+        assert!(!peers.is_advertised(peer_ip(), advertised()).await);
+        assert!(!peers.confirm(peer_ip(), advertised()).await);
+        assert_eq!(peers.touch(advertised()).await, None);
+
+        peers.start_punch(peer_ip(), advertised()).await;
+        assert_eq!(peers.touch(advertised()).await, Some(peer_ip()));
+        assert_eq!(peers.touch(other()).await, None);
+    }
+
+    #[tokio::test]
+    async fn relays_when_server_declines() {
+        let peers = PeerTable::new();
+        peers.route(peer_ip()).await;
+        peers.relay(peer_ip()).await;
+        assert_eq!(peers.route(peer_ip()).await, Route::Relay { request: false });
+        assert!(!peers.is_punching(peer_ip()).await);
+
+        // 已在打洞的对端不受影响
+        let punching: IpAddr = "10.0.0.3".parse().unwrap();
+        peers.start_punch(punching, other()).await;
+        peers.relay(punching).await;
+        assert!(peers.is_punching(punching).await);
+    }
+}
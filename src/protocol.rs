@@ -1,7 +1,19 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use std::io::{Cursor, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use crate::buffer::PacketBuf;
 use crate::error::{Result, VswitchError};
 
+/// 消息头长度 (类型 + 长度)
+pub const HEADER_LEN: usize = 5;
+/// 消息负载的最大长度，超过该长度的消息视为无效
+pub const MAX_PAYLOAD_LEN: usize = 65535;
+
+/// 邻居服务端之间转发的数据消息中认证标签的长度
+pub const NEIGHBOR_TAG_LEN: usize = 16;
+
+/// 一条路由通告条目: 虚拟IP地址和度量值
+pub type RouteEntry = (IpAddr, u8);
+
 /// 消息类型枚举
 ///
 /// 定义了虚拟交换机协议支持的所有消息类型
@@ -15,6 +27,34 @@ pub enum MessageType {
     Heartbeat = 0x03,
     /// 断开连接消息
     Disconnect = 0x04,
+    /// 对端地址查询消息 (客户端 -> 服务端)
+    PeerRequest = 0x05,
+    /// 对端地址通告消息 (服务端 -> 客户端)
+    PeerInfo = 0x06,
+    /// 打洞探测消息 (客户端 <-> 客户端)
+    Punch = 0x07,
+    /// 打洞确认消息 (客户端 <-> 客户端)
+    PunchAck = 0x08,
+    /// NAT探测请求 (客户端 -> 服务端)
+    NatProbe = 0x09,
+    /// NAT探测响应 (服务端 -> 客户端)
+    NatProbeReply = 0x0A,
+    /// 路由通告消息 (服务端 <-> 服务端)
+    RouteUpdate = 0x0B,
+    /// 重定向消息 (服务端 -> 客户端)
+    Redirect = 0x0C,
+    /// 多路径消息，携带会话标识和序号的消息 (客户端 -> 服务端)
+    Multipath = 0x0D,
+    /// 路径探测请求 (客户端 -> 服务端)
+    PathProbe = 0x0E,
+    /// 路径探测响应 (服务端 -> 客户端)
+    PathProbeReply = 0x0F,
+    /// 前向纠错数据分片，携带一个原始数据包 (客户端 <-> 服务端)
+    FecData = 0x10,
+    /// 前向纠错校验分片 (客户端 <-> 服务端)
+    FecParity = 0x11,
+    /// 附带认证标签的数据消息 (服务端 <-> 服务端)
+    NeighborData = 0x12,
 }
 
 impl TryFrom<u8> for MessageType {
@@ -26,6 +66,20 @@ impl TryFrom<u8> for MessageType {
             0x02 => Ok(MessageType::Data),
             0x03 => Ok(MessageType::Heartbeat),
             0x04 => Ok(MessageType::Disconnect),
+            0x05 => Ok(MessageType::PeerRequest),
+            0x06 => Ok(MessageType::PeerInfo),
+            0x07 => Ok(MessageType::Punch),
+            0x08 => Ok(MessageType::PunchAck),
+            0x09 => Ok(MessageType::NatProbe),
+            0x0A => Ok(MessageType::NatProbeReply),
+            0x0B => Ok(MessageType::RouteUpdate),
+            0x0C => Ok(MessageType::Redirect),
+            0x0D => Ok(MessageType::Multipath),
+            0x0E => Ok(MessageType::PathProbe),
+            0x0F => Ok(MessageType::PathProbeReply),
+            0x10 => Ok(MessageType::FecData),
+            0x11 => Ok(MessageType::FecParity),
+            0x12 => Ok(MessageType::NeighborData),
             _ => Err(VswitchError::InvalidProtocolMessage(format!("未知的消息类型: {}", value))),
         }
     }
@@ -56,9 +110,40 @@ impl Message {
         Self::new(MessageType::Connect, Bytes::new())
     }
 
-    /// 创建一个数据消息
-    pub fn data(payload: Bytes) -> Self {
-        Self::new(MessageType::Data, payload)
+    /// 创建一个携带随机数的连接请求
+    ///
+    /// 服务端签发的重定向令牌会绑定该随机数，防止令牌被重放给其他客户端
+    ///
+    /// 负载格式:
+    /// - 8字节: 随机数
+    /// - 2字节 (可选): 客户端请求的前向纠错比例，数据分片数和校验分片数
+    pub fn connect_with_nonce(nonce: u64, fec: Option<(u8, u8)>) -> Self {
+        let mut buf = BytesMut::with_capacity(10);
+        buf.put_u64(nonce);
+        if let Some((data, parity)) = fec {
+            buf.put_u8(data);
+            buf.put_u8(parity);
+        }
+        Self::new(MessageType::Connect, buf.freeze())
+    }
+
+    /// 解析连接请求中的随机数，旧版本客户端的连接请求不携带随机数
+    pub fn parse_connect_nonce(&self) -> Option<u64> {
+        let mut buf = self.payload.clone();
+        if buf.remaining() < 8 {
+            return None;
+        }
+        Some(buf.get_u64())
+    }
+
+    /// 解析连接请求中的前向纠错比例，未启用前向纠错时返回 `None`
+    pub fn parse_connect_fec(&self) -> Option<(u8, u8)> {
+        let mut buf = self.payload.clone();
+        if buf.remaining() < 10 {
+            return None;
+        }
+        buf.advance(8);
+        Some((buf.get_u8(), buf.get_u8()))
     }
 
     /// 创建一个心跳消息
@@ -67,11 +152,322 @@ impl Message {
     }
 
     /// 创建一个断开连接消息
-    #[allow(dead_code)]
     pub fn disconnect() -> Self {
         Self::new(MessageType::Disconnect, Bytes::new())
     }
 
+    /// 创建一个对端地址查询消息
+    ///
+    /// 负载为希望直连的对端虚拟IP地址
+    pub fn peer_request(peer_ip: IpAddr) -> Self {
+        let mut buf = BytesMut::new();
+        put_ip(&mut buf, peer_ip);
+        Self::new(MessageType::PeerRequest, buf.freeze())
+    }
+
+    /// 创建一个对端地址通告消息
+    ///
+    /// 负载为对端虚拟IP地址及服务端观察到的对端公网地址
+    pub fn peer_info(peer_ip: IpAddr, peer_addr: SocketAddr) -> Self {
+        let mut buf = BytesMut::new();
+        put_ip(&mut buf, peer_ip);
+        put_socket_addr(&mut buf, peer_addr);
+        Self::new(MessageType::PeerInfo, buf.freeze())
+    }
+
+    /// 创建一个不能与对端直连的通告消息
+    ///
+    /// 负载只有对端虚拟IP地址，没有公网地址，客户端收到后直接使用服务端中转
+    pub fn peer_unavailable(peer_ip: IpAddr) -> Self {
+        let mut buf = BytesMut::new();
+        put_ip(&mut buf, peer_ip);
+        Self::new(MessageType::PeerInfo, buf.freeze())
+    }
+
+    /// 创建一个打洞探测消息
+    ///
+    /// 负载为发送方自身的虚拟IP地址
+    pub fn punch(local_ip: IpAddr) -> Self {
+        let mut buf = BytesMut::new();
+        put_ip(&mut buf, local_ip);
+        Self::new(MessageType::Punch, buf.freeze())
+    }
+
+    /// 创建一个打洞确认消息
+    ///
+    /// 负载为发送方自身的虚拟IP地址
+    pub fn punch_ack(local_ip: IpAddr) -> Self {
+        let mut buf = BytesMut::new();
+        put_ip(&mut buf, local_ip);
+        Self::new(MessageType::PunchAck, buf.freeze())
+    }
+
+    /// 创建一个NAT探测请求
+    ///
+    /// 负载格式:
+    /// - 4字节: 探测序号
+    /// - 2字节: 服务端延迟响应的秒数 (0表示立即响应)
+    pub fn nat_probe(id: u32, delay_secs: u16) -> Self {
+        let mut buf = BytesMut::with_capacity(6);
+        buf.put_u32(id);
+        buf.put_u16(delay_secs);
+        Self::new(MessageType::NatProbe, buf.freeze())
+    }
+
+    /// 创建一个NAT探测响应
+    ///
+    /// 负载格式:
+    /// - 4字节: 探测序号
+    /// - 变长: 服务端观察到的客户端公网地址
+    /// - 2字节: 服务端辅助探测端口 (0表示未启用)
+    pub fn nat_probe_reply(id: u32, observed: SocketAddr, alt_port: u16) -> Self {
+        let mut buf = BytesMut::new();
+        buf.put_u32(id);
+        put_socket_addr(&mut buf, observed);
+        buf.put_u16(alt_port);
+        Self::new(MessageType::NatProbeReply, buf.freeze())
+    }
+
+    /// 解析NAT探测请求，返回探测序号和延迟秒数
+    pub fn parse_nat_probe(&self) -> Result<(u32, u16)> {
+        let mut buf = self.payload.clone();
+        if buf.remaining() < 6 {
+            return Err(VswitchError::InvalidProtocolMessage("NAT探测请求不完整".to_string()));
+        }
+        Ok((buf.get_u32(), buf.get_u16()))
+    }
+
+    /// 解析NAT探测响应，返回探测序号、公网地址和辅助探测端口
+    pub fn parse_nat_probe_reply(&self) -> Result<(u32, SocketAddr, u16)> {
+        let mut buf = self.payload.clone();
+        if buf.remaining() < 4 {
+            return Err(VswitchError::InvalidProtocolMessage("NAT探测响应不完整".to_string()));
+        }
+        let id = buf.get_u32();
+        let observed = get_socket_addr(&mut buf)?;
+        if buf.remaining() < 2 {
+            return Err(VswitchError::InvalidProtocolMessage("NAT探测响应不完整".to_string()));
+        }
+        Ok((id, observed, buf.get_u16()))
+    }
+
+    /// 创建一个路由通告消息
+    ///
+    /// 负载格式:
+    /// - 8字节: 序号，每个通告递增，用于拒绝重放
+    /// - 2字节: 路由条目数
+    /// - 每个条目: 虚拟IP地址 + 1字节度量值
+    /// - 32字节: 签名
+    pub fn route_update(sequence: u64, entries: &[RouteEntry], signature: &[u8; 32]) -> Self {
+        let mut buf = BytesMut::new();
+        buf.put_u64(sequence);
+        buf.put_u16(entries.len() as u16);
+        for (ip, metric) in entries {
+            put_ip(&mut buf, *ip);
+            buf.put_u8(*metric);
+        }
+        buf.put_slice(signature);
+        Self::new(MessageType::RouteUpdate, buf.freeze())
+    }
+
+    /// 解析路由通告消息，返回序号、路由条目列表和签名
+    pub fn parse_route_update(&self) -> Result<(u64, Vec<RouteEntry>, [u8; 32])> {
+        let mut buf = self.payload.clone();
+        if buf.remaining() < 8 + 2 {
+            return Err(VswitchError::InvalidProtocolMessage("路由通告不完整".to_string()));
+        }
+
+        let sequence = buf.get_u64();
+        let count = buf.get_u16() as usize;
+        let mut entries = Vec::with_capacity(count.min(buf.remaining()));
+        for _ in 0..count {
+            let ip = get_ip(&mut buf)?;
+            if buf.remaining() < 1 {
+                return Err(VswitchError::InvalidProtocolMessage("路由通告不完整".to_string()));
+            }
+            entries.push((ip, buf.get_u8()));
+        }
+        if buf.remaining() < 32 {
+            return Err(VswitchError::InvalidProtocolMessage("路由通告缺少签名".to_string()));
+        }
+        let mut signature = [0u8; 32];
+        buf.copy_to_slice(&mut signature);
+        Ok((sequence, entries, signature))
+    }
+
+    /// 创建一个邻居服务端之间转发的数据消息
+    ///
+    /// 负载格式:
+    /// - 变长: 原始数据包
+    /// - 16字节: 认证标签
+    pub fn neighbor_data(packet: &[u8], tag: &[u8; NEIGHBOR_TAG_LEN]) -> Self {
+        let mut buf = BytesMut::with_capacity(packet.len() + NEIGHBOR_TAG_LEN);
+        buf.put_slice(packet);
+        buf.put_slice(tag);
+        Self::new(MessageType::NeighborData, buf.freeze())
+    }
+
+    /// 解析邻居服务端转发的数据消息，返回原始数据包和认证标签
+    pub fn parse_neighbor_data(&self) -> Result<(Bytes, [u8; NEIGHBOR_TAG_LEN])> {
+        let len = self.payload.len().checked_sub(NEIGHBOR_TAG_LEN)
+            .ok_or_else(|| VswitchError::InvalidProtocolMessage("邻居数据消息缺少认证标签".to_string()))?;
+        let mut tag = [0u8; NEIGHBOR_TAG_LEN];
+        tag.copy_from_slice(&self.payload[len..]);
+        Ok((self.payload.slice(..len), tag))
+    }
+
+    /// 创建一个重定向消息
+    ///
+    /// 负载格式:
+    /// - 变长: 目标服务器地址
+    /// - 8字节: 令牌过期时间 (UNIX时间戳，秒)
+    /// - 32字节: 令牌签名
+    pub fn redirect(target: SocketAddr, expires: u64, signature: &[u8; 32]) -> Self {
+        let mut buf = BytesMut::new();
+        put_socket_addr(&mut buf, target);
+        buf.put_u64(expires);
+        buf.put_slice(signature);
+        Self::new(MessageType::Redirect, buf.freeze())
+    }
+
+    /// 解析重定向消息，返回目标服务器地址、过期时间和签名
+    pub fn parse_redirect(&self) -> Result<(SocketAddr, u64, [u8; 32])> {
+        let mut buf = self.payload.clone();
+        let target = get_socket_addr(&mut buf)?;
+        if buf.remaining() < 8 + 32 {
+            return Err(VswitchError::InvalidProtocolMessage("重定向消息不完整".to_string()));
+        }
+        let expires = buf.get_u64();
+        let mut signature = [0u8; 32];
+        buf.copy_to_slice(&mut signature);
+        Ok((target, expires, signature))
+    }
+
+    /// 创建一个多路径消息
+    ///
+    /// 负载格式:
+    /// - 8字节: 会话标识
+    /// - 8字节: 序号
+    /// - 变长: 编码后的原始消息
+    pub fn multipath(session: u64, seq: u64, message: &[u8]) -> Self {
+        let mut buf = BytesMut::with_capacity(16 + message.len());
+        buf.put_u64(session);
+        buf.put_u64(seq);
+        buf.put_slice(message);
+        Self::new(MessageType::Multipath, buf.freeze())
+    }
+
+    /// 解析多路径消息，返回会话标识、序号和编码后的原始消息
+    pub fn parse_multipath(&self) -> Result<(u64, u64, Bytes)> {
+        let mut buf = self.payload.clone();
+        if buf.remaining() < 16 + HEADER_LEN {
+            return Err(VswitchError::InvalidProtocolMessage("多路径消息不完整".to_string()));
+        }
+        let session = buf.get_u64();
+        let seq = buf.get_u64();
+        Ok((session, seq, buf))
+    }
+
+    /// 创建一个路径探测请求
+    ///
+    /// 负载为4字节探测序号，服务端原样返回
+    pub fn path_probe(id: u32) -> Self {
+        let mut buf = BytesMut::with_capacity(4);
+        buf.put_u32(id);
+        Self::new(MessageType::PathProbe, buf.freeze())
+    }
+
+    /// 创建一个路径探测响应，负载与请求相同
+    pub fn path_probe_reply(probe: &Message) -> Self {
+        Self::new(MessageType::PathProbeReply, probe.payload.clone())
+    }
+
+    /// 解析路径探测请求或响应中的探测序号
+    pub fn parse_path_probe(&self) -> Result<u32> {
+        let mut buf = self.payload.clone();
+        if buf.remaining() < 4 {
+            return Err(VswitchError::InvalidProtocolMessage("路径探测消息不完整".to_string()));
+        }
+        Ok(buf.get_u32())
+    }
+
+    /// 创建一个前向纠错数据分片
+    ///
+    /// 负载格式:
+    /// - 4字节: 分组序号
+    /// - 1字节: 分片在分组中的序号
+    /// - 变长: 原始数据包
+    pub fn fec_data(group: u32, index: u8, packet: &[u8]) -> Self {
+        let mut buf = BytesMut::with_capacity(5 + packet.len());
+        buf.put_u32(group);
+        buf.put_u8(index);
+        buf.put_slice(packet);
+        Self::new(MessageType::FecData, buf.freeze())
+    }
+
+    /// 解析前向纠错数据分片，返回分组序号、分片序号和原始数据包
+    pub fn parse_fec_data(&self) -> Result<(u32, u8, Bytes)> {
+        let mut buf = self.payload.clone();
+        if buf.remaining() < 5 {
+            return Err(VswitchError::InvalidProtocolMessage("前向纠错数据分片不完整".to_string()));
+        }
+        let group = buf.get_u32();
+        let index = buf.get_u8();
+        Ok((group, index, buf))
+    }
+
+    /// 创建一个前向纠错校验分片
+    ///
+    /// 负载格式:
+    /// - 4字节: 分组序号
+    /// - 1字节: 校验分片序号
+    /// - 1字节: 分组的数据分片数
+    /// - 1字节: 分组的校验分片数
+    /// - 变长: 校验数据
+    pub fn fec_parity(group: u32, index: u8, data: u8, parity: u8, shard: &[u8]) -> Self {
+        let mut buf = BytesMut::with_capacity(7 + shard.len());
+        buf.put_u32(group);
+        buf.put_u8(index);
+        buf.put_u8(data);
+        buf.put_u8(parity);
+        buf.put_slice(shard);
+        Self::new(MessageType::FecParity, buf.freeze())
+    }
+
+    /// 解析前向纠错校验分片，返回分组序号、校验分片序号、数据分片数、校验分片数和校验数据
+    pub fn parse_fec_parity(&self) -> Result<(u32, u8, u8, u8, Bytes)> {
+        let mut buf = self.payload.clone();
+        if buf.remaining() < 7 {
+            return Err(VswitchError::InvalidProtocolMessage("前向纠错校验分片不完整".to_string()));
+        }
+        let group = buf.get_u32();
+        let index = buf.get_u8();
+        let data = buf.get_u8();
+        let parity = buf.get_u8();
+        Ok((group, index, data, parity, buf))
+    }
+
+    /// 解析负载中的虚拟IP地址
+    ///
+    /// 适用于 `PeerRequest`、`Punch` 和 `PunchAck` 消息
+    pub fn parse_ip(&self) -> Result<IpAddr> {
+        let mut buf = self.payload.clone();
+        get_ip(&mut buf)
+    }
+
+    /// 解析 `PeerInfo` 消息的负载
+    ///
+    /// 返回对端虚拟IP地址及其公网地址，不能与对端直连时公网地址为 `None`
+    pub fn parse_peer_info(&self) -> Result<(IpAddr, Option<SocketAddr>)> {
+        let mut buf = self.payload.clone();
+        let peer_ip = get_ip(&mut buf)?;
+        if !buf.has_remaining() {
+            return Ok((peer_ip, None));
+        }
+        let peer_addr = get_socket_addr(&mut buf)?;
+        Ok((peer_ip, Some(peer_addr)))
+    }
+
     /// 将消息编码为字节序列
     ///
     /// 返回的字节序列格式:
@@ -89,17 +485,32 @@ impl Message {
         buf.freeze()
     }
 
+    /// 将预留了头部空间的数据包编码为数据消息
+    ///
+    /// 消息头直接写入数据包前的预留空间，数据包不复制，结果与 `Message::new(MessageType::Data, packet).encode()` 相同
+    pub fn encode_packet(packet: PacketBuf) -> Bytes {
+        let payload_len = packet.len() as u32;
+        let mut buf = packet.into_inner();
+
+        buf[0] = MessageType::Data as u8;
+        buf[1..HEADER_LEN].copy_from_slice(&payload_len.to_be_bytes());
+
+        buf.freeze()
+    }
+
     /// 从字节流解码消息
     ///
+    /// 负载是 `buf` 的切片，与接收缓冲区共享内存，不复制数据
+    ///
     /// 参数:
-    /// - `buf`: 包含消息数据的字节缓冲区游标
+    /// - `buf`: 包含消息数据的字节缓冲区，解码后跳过已解码的消息
     ///
     /// 返回:
     /// - 成功: 解码后的消息
     /// - 错误: 解码过程中的错误
-    pub fn decode(buf: &mut Cursor<&[u8]>) -> Result<Self> {
+    pub fn decode(buf: &mut Bytes) -> Result<Self> {
         // 确保缓冲区至少包含消息头(类型+长度)
-        if buf.remaining() < 5 {
+        if buf.remaining() < HEADER_LEN {
             return Err(VswitchError::InvalidProtocolMessage("消息太短".to_string()));
         }
 
@@ -114,13 +525,234 @@ impl Message {
             return Err(VswitchError::InvalidProtocolMessage("消息内容不完整".to_string()));
         }
 
-        // 读取负载内容
-        let mut payload = vec![0; payload_len];
-        buf.read_exact(&mut payload).map_err(|e| VswitchError::IoError(e))?;
+        // 负载取缓冲区的切片
+        let payload = buf.split_to(payload_len);
 
         Ok(Self {
             msg_type,
-            payload: Bytes::from(payload),
+            payload,
         })
     }
-} 
\ No newline at end of file
+}
+
+/// 写入IP地址
+///
+/// 格式: 1字节地址族 (4或6) + 4/16字节地址
+pub fn put_ip(buf: &mut BytesMut, ip: IpAddr) {
+    match ip {
+        IpAddr::V4(ip) => {
+            buf.put_u8(4);
+            buf.put_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.put_u8(6);
+            buf.put_slice(&ip.octets());
+        }
+    }
+}
+
+/// 读取IP地址
+pub fn get_ip(buf: &mut Bytes) -> Result<IpAddr> {
+    if buf.remaining() < 1 {
+        return Err(VswitchError::InvalidProtocolMessage("缺少地址族".to_string()));
+    }
+
+    match buf.get_u8() {
+        4 => {
+            if buf.remaining() < 4 {
+                return Err(VswitchError::InvalidProtocolMessage("IPv4地址不完整".to_string()));
+            }
+            let mut octets = [0u8; 4];
+            buf.copy_to_slice(&mut octets);
+            Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        6 => {
+            if buf.remaining() < 16 {
+                return Err(VswitchError::InvalidProtocolMessage("IPv6地址不完整".to_string()));
+            }
+            let mut octets = [0u8; 16];
+            buf.copy_to_slice(&mut octets);
+            Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        family => Err(VswitchError::InvalidProtocolMessage(format!("未知的地址族: {}", family))),
+    }
+}
+
+/// 写入套接字地址
+///
+/// 格式: IP地址 + 2字节端口 (网络字节序)
+pub fn put_socket_addr(buf: &mut BytesMut, addr: SocketAddr) {
+    put_ip(buf, addr.ip());
+    buf.put_u16(addr.port());
+}
+
+/// 读取套接字地址
+pub fn get_socket_addr(buf: &mut Bytes) -> Result<SocketAddr> {
+    let ip = get_ip(buf)?;
+    if buf.remaining() < 2 {
+        return Err(VswitchError::InvalidProtocolMessage("端口不完整".to_string()));
+    }
+    Ok(SocketAddr::new(ip, buf.get_u16()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4() -> SocketAddr {
+        "198.51.100.10:4789".parse().unwrap()
+    }
+
+    fn v6() -> SocketAddr {
+        "[2001:db8::1]:4789".parse().unwrap()
+    }
+
+    /// 负载的每个长度小于 `min_len` 的前缀都应解析失败，完整的负载应解析成功
+    fn assert_rejects_truncated<T>(message: &Message, min_len: usize, parse: impl Fn(&Message) -> Result<T>) {
+        assert!(parse(message).is_ok());
+        for len in 0..min_len {
+            let truncated = Message::new(message.msg_type, message.payload.slice(..len));
+            assert!(parse(&truncated).is_err(), "{:?} 截断到 {} 字节时应解析失败", message.msg_type, len);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_address_family() {
+        let message = Message::new(MessageType::PeerRequest, Bytes::from_static(&[5, 10, 0, 0, 1]));
+        assert!(message.parse_ip().is_err());
+
+        let message = Message::peer_request(v6().ip());
+        assert_eq!(message.parse_ip().unwrap(), v6().ip());
+        assert_rejects_truncated(&message, message.payload.len(), Message::parse_ip);
+    }
+
+    #[test]
+    fn rejects_truncated_peer_info() {
+        let peer_ip: IpAddr = "10.0.0.2".parse().unwrap();
+        for addr in [v4(), v6()] {
+            let message = Message::peer_info(peer_ip, addr);
+            assert_eq!(message.parse_peer_info().unwrap(), (peer_ip, Some(addr)));
+
+            // 只有虚拟IP的负载表示不能直连，地址不完整的负载无效
+            let ip_len = Message::peer_unavailable(peer_ip).payload.len();
+            assert_rejects_truncated(&message, ip_len, Message::parse_peer_info);
+            for len in ip_len + 1..message.payload.len() {
+                let truncated = Message::new(MessageType::PeerInfo, message.payload.slice(..len));
+                assert!(truncated.parse_peer_info().is_err());
+            }
+        }
+        assert_eq!(Message::peer_unavailable(peer_ip).parse_peer_info().unwrap(), (peer_ip, None));
+    }
+
+    #[test]
+    fn rejects_truncated_nat_probe() {
+        let probe = Message::nat_probe(7, 3);
+        assert_eq!(probe.parse_nat_probe().unwrap(), (7, 3));
+        assert_rejects_truncated(&probe, probe.payload.len(), Message::parse_nat_probe);
+
+        for observed in [v4(), v6()] {
+            let reply = Message::nat_probe_reply(7, observed, 4790);
+            assert_eq!(reply.parse_nat_probe_reply().unwrap(), (7, observed, 4790));
+            assert_rejects_truncated(&reply, reply.payload.len(), Message::parse_nat_probe_reply);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_route_update() {
+        let entries = vec![("10.0.0.2".parse().unwrap(), 1), ("fd00::2".parse().unwrap(), 2)];
+        let message = Message::route_update(42, &entries, &[9; 32]);
+        assert_eq!(message.parse_route_update().unwrap(), (42, entries, [9; 32]));
+        assert_rejects_truncated(&message, message.payload.len(), Message::parse_route_update);
+
+        // 条目数远大于负载能容纳的数量
+        let mut buf = BytesMut::new();
+        buf.put_u64(1);
+        buf.put_u16(u16::MAX);
+        let message = Message::new(MessageType::RouteUpdate, buf.freeze());
+        assert!(message.parse_route_update().is_err());
+    }
+
+    #[test]
+    fn rejects_neighbor_data_without_tag() {
+        let message = Message::neighbor_data(b"packet", &[7; NEIGHBOR_TAG_LEN]);
+        assert_eq!(message.parse_neighbor_data().unwrap(), (Bytes::from_static(b"packet"), [7; NEIGHBOR_TAG_LEN]));
+        assert_rejects_truncated(&message, NEIGHBOR_TAG_LEN, Message::parse_neighbor_data);
+    }
+
+    #[test]
+    fn rejects_truncated_redirect() {
+        let message = Message::redirect(v4(), 1_700_000_000, &[3; 32]);
+        assert_eq!(message.parse_redirect().unwrap(), (v4(), 1_700_000_000, [3; 32]));
+        assert_rejects_truncated(&message, message.payload.len(), Message::parse_redirect);
+    }
+
+    #[test]
+    fn connect_nonce_is_optional() {
+        assert_eq!(Message::connect().parse_connect_nonce(), None);
+        assert_eq!(Message::connect_with_nonce(11, None).parse_connect_nonce(), Some(11));
+
+        let message = Message::connect_with_nonce(11, None);
+        let truncated = Message::new(MessageType::Connect, message.payload.slice(..7));
+        assert_eq!(truncated.parse_connect_nonce(), None);
+    }
+
+    #[test]
+    fn rejects_truncated_multipath() {
+        let inner = Message::heartbeat().encode();
+        let message = Message::multipath(1, 2, &inner);
+        assert_eq!(message.parse_multipath().unwrap(), (1, 2, inner.clone()));
+        assert_rejects_truncated(&message, 16 + HEADER_LEN, Message::parse_multipath);
+    }
+
+    #[test]
+    fn rejects_truncated_path_probe() {
+        let message = Message::path_probe_reply(&Message::path_probe(99));
+        assert_eq!(message.msg_type, MessageType::PathProbeReply);
+        assert_eq!(message.parse_path_probe().unwrap(), 99);
+        assert_rejects_truncated(&message, 4, Message::parse_path_probe);
+    }
+
+    #[test]
+    fn decode_rejects_malformed_header() {
+        assert!(Message::decode(&mut Bytes::from_static(&[0x02, 0, 0, 0])).is_err());
+        assert!(Message::decode(&mut Bytes::from_static(&[0x00, 0, 0, 0, 0])).is_err());
+        assert!(Message::decode(&mut Bytes::from_static(&[0xff, 0, 0, 0, 0])).is_err());
+        assert!(Message::decode(&mut Bytes::from_static(&[0x02, 0, 0, 0, 4, 1, 2, 3])).is_err());
+        assert!(Message::decode(&mut Bytes::from_static(&[0x02, 0xff, 0xff, 0xff, 0xff])).is_err());
+    }
+
+    #[test]
+    fn decode_splits_consecutive_messages() {
+        let mut buf = BytesMut::new();
+        buf.put_slice(&Message::encode_packet(PacketBuf::copy_from_slice(b"abc")));
+        buf.put_slice(&Message::heartbeat().encode());
+        let mut buf = buf.freeze();
+        let start = buf.as_ptr();
+
+        let first = Message::decode(&mut buf).unwrap();
+        assert_eq!(first.msg_type, MessageType::Data);
+        assert_eq!(&first.payload[..], b"abc");
+        // 负载与接收缓冲区共享内存
+        assert_eq!(first.payload.as_ptr(), start.wrapping_add(HEADER_LEN));
+        assert_eq!(Message::decode(&mut buf).unwrap().msg_type, MessageType::Heartbeat);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn encode_packet_matches_encode() {
+        let encoded = Message::encode_packet(PacketBuf::copy_from_slice(b"packet"));
+        assert_eq!(encoded, Message::new(MessageType::Data, Bytes::from_static(b"packet")).encode());
+    }
+
+    #[test]
+    fn connect_fec_ratio_is_optional() {
+        assert_eq!(Message::connect().parse_connect_fec(), None);
+        assert_eq!(Message::connect_with_nonce(11, None).parse_connect_fec(), None);
+
+        let message = Message::connect_with_nonce(11, Some((10, 2)));
+        assert_eq!(message.parse_connect_nonce(), Some(11));
+        assert_eq!(message.parse_connect_fec(), Some((10, 2)));
+        let truncated = Message::new(MessageType::Connect, message.payload.slice(..9));
+        assert_eq!(truncated.parse_connect_fec(), None);
+    }
+}
@@ -0,0 +1,99 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use bytes::Bytes;
+use futures::Stream;
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{Connection, Endpoint, SendDatagramError, TransportConfig};
+use tokio::time::Duration;
+use tokio_rustls::rustls;
+use crate::protocol::MessageType;
+use crate::stream::check_frame;
+
+/// QUIC握手时协商的应用层协议
+const ALPN: &[u8] = b"vswitch";
+/// 客户端发送QUIC保活包的间隔，保证连接在心跳间隔较长时也不会因空闲超时关闭
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 创建QUIC服务端端点
+///
+/// 参数:
+/// - `addr`: 监听地址
+/// - `tls`: TLS配置，QUIC只使用其中的TLS 1.3
+pub fn server_endpoint(addr: SocketAddr, tls: &Arc<rustls::ServerConfig>) -> io::Result<Endpoint> {
+    let mut tls = (**tls).clone();
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(tls)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("TLS配置不支持QUIC: {}", e)))?;
+
+    Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), addr)
+}
+
+/// 建立到服务器的QUIC连接
+///
+/// 每个连接使用单独的端点，端点的地址族与服务器地址一致
+///
+/// 参数:
+/// - `addr`: 服务器地址
+/// - `server_name`: 用于校验服务器证书的名称
+/// - `tls`: TLS配置
+pub async fn connect(addr: SocketAddr, server_name: &str, tls: &Arc<rustls::ClientConfig>) -> io::Result<Connection> {
+    let mut tls = (**tls).clone();
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicClientConfig::try_from(tls)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("TLS配置不支持QUIC: {}", e)))?;
+
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+    config.transport_config(Arc::new(transport));
+
+    let bind_addr = match addr {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let endpoint = Endpoint::client(bind_addr)?;
+
+    let connecting = endpoint.connect_with(config, addr, server_name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    Ok(connecting.await?)
+}
+
+/// 尝试以QUIC数据报发送消息
+///
+/// 只有数据消息和前向纠错分片使用数据报，其余消息需要可靠送达；数据报超过当前路径允许的大小时也改用可靠流。
+///
+/// 返回:
+/// - 成功: 是否已作为数据报发出，为 `false` 时需要通过可靠流发送
+/// - 错误: 连接已断开
+pub fn try_send_datagram(connection: &Connection, buf: &[u8]) -> io::Result<bool> {
+    let unreliable = [MessageType::Data, MessageType::FecData, MessageType::FecParity]
+        .iter()
+        .any(|msg_type| buf.first() == Some(&(*msg_type as u8)));
+    if !unreliable {
+        return Ok(false);
+    }
+
+    match connection.send_datagram(Bytes::copy_from_slice(buf)) {
+        Ok(()) => Ok(true),
+        Err(SendDatagramError::TooLarge) => Ok(false),
+        Err(SendDatagramError::ConnectionLost(e)) => Err(e.into()),
+        Err(e) => Err(io::Error::new(io::ErrorKind::Unsupported, e)),
+    }
+}
+
+/// 将连接收到的数据报转换为消息帧流
+///
+/// 连接关闭时流结束；收到不完整的消息时产生错误后结束
+pub fn datagrams(connection: Connection) -> impl Stream<Item = io::Result<Bytes>> + Send + Unpin {
+    Box::pin(futures::stream::unfold(Some(connection), |connection| async move {
+        let connection = connection?;
+        match connection.read_datagram().await {
+            Ok(datagram) => match check_frame(&datagram) {
+                Ok(()) => Some((Ok(datagram), Some(connection))),
+                Err(e) => Some((Err(e), None)),
+            },
+            Err(_) => None,
+        }
+    }))
+}
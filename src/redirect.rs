@@ -0,0 +1,194 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use bytes::{BufMut, BytesMut};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use crate::error::{Result, VswitchError};
+use crate::protocol::{put_socket_addr, Message};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 重定向令牌有效期
+const TOKEN_TTL: Duration = Duration::from_secs(60);
+
+/// 计算重定向令牌签名
+///
+/// 签名内容为客户端连接随机数、目标服务器地址和过期时间，使用集群共享密钥做HMAC-SHA256
+fn sign(key: &[u8], nonce: u64, target: SocketAddr, expires: u64) -> HmacSha256 {
+    let mut buf = BytesMut::new();
+    buf.put_u64(nonce);
+    put_socket_addr(&mut buf, target);
+    buf.put_u64(expires);
+
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC接受任意长度的密钥");
+    mac.update(&buf);
+    mac
+}
+
+/// 校验服务端发来的重定向消息
+///
+/// 参数:
+/// - `key`: 集群共享密钥
+/// - `nonce`: 客户端最近一次连接请求携带的随机数
+/// - `message`: 重定向消息
+///
+/// 返回:
+/// - 成功: 目标服务器地址
+/// - 错误: 消息格式错误、令牌过期或签名不匹配
+pub fn verify(key: &[u8], nonce: u64, message: &Message) -> Result<SocketAddr> {
+    let (target, expires, signature) = message.parse_redirect()?;
+
+    if expires < unix_time_secs() {
+        return Err(VswitchError::InvalidProtocolMessage("重定向令牌已过期".to_string()));
+    }
+
+    sign(key, nonce, target, expires)
+        .verify_slice(&signature)
+        .map_err(|_| VswitchError::InvalidProtocolMessage("重定向令牌签名无效".to_string()))?;
+
+    Ok(target)
+}
+
+/// 服务端重定向策略
+///
+/// 在线客户端数达到上限或服务端处于维护排空状态时，将客户端重定向到集群中的其他服务端
+pub struct Redirector {
+    /// 集群共享密钥
+    key: Vec<u8>,
+    /// 可重定向到的服务端地址
+    targets: Vec<SocketAddr>,
+    /// 下一个重定向目标的索引，按轮询方式选择
+    next_target: AtomicUsize,
+    /// 在线客户端数上限
+    max_clients: Option<usize>,
+    /// 是否处于维护排空状态
+    draining: AtomicBool,
+}
+
+impl Redirector {
+    /// 创建重定向策略
+    ///
+    /// 参数:
+    /// - `key`: 集群共享密钥，客户端需配置相同的密钥才会接受重定向
+    /// - `targets`: 可重定向到的服务端地址，不能为空
+    /// - `max_clients`: 在线客户端数上限，为 `None` 时只在排空状态下重定向
+    pub fn new(key: Vec<u8>, targets: Vec<SocketAddr>, max_clients: Option<usize>) -> Self {
+        Self {
+            key,
+            targets,
+            next_target: AtomicUsize::new(0),
+            max_clients,
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    /// 检查是否处于维护排空状态
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// 切换维护排空状态，返回切换后的状态
+    pub fn toggle_draining(&self) -> bool {
+        !self.draining.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    /// 判断新连接是否应被重定向
+    ///
+    /// 参数:
+    /// - `client_count`: 当前在线客户端数 (不含发起连接的客户端)
+    pub fn should_redirect(&self, client_count: usize) -> bool {
+        if self.is_draining() {
+            return true;
+        }
+        matches!(self.max_clients, Some(max) if client_count >= max)
+    }
+
+    /// 为客户端签发重定向消息
+    ///
+    /// 参数:
+    /// - `nonce`: 客户端连接请求携带的随机数
+    pub fn redirect(&self, nonce: u64) -> (SocketAddr, Message) {
+        let index = self.next_target.fetch_add(1, Ordering::Relaxed) % self.targets.len();
+        let target = self.targets[index];
+        let expires = unix_time_secs() + TOKEN_TTL.as_secs();
+
+        let signature = sign(&self.key, nonce, target, expires).finalize().into_bytes();
+        (target, Message::redirect(target, expires, &signature.into()))
+    }
+}
+
+/// 获取当前UNIX时间戳（秒）
+fn unix_time_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("时间错误")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"cluster key";
+    const NONCE: u64 = 0x1234_5678_9abc_def0;
+
+    fn target() -> SocketAddr {
+        "203.0.113.20:4789".parse().unwrap()
+    }
+
+    /// 以指定过期时间签发重定向消息
+    fn signed(target: SocketAddr, expires: u64) -> Message {
+        let signature = sign(KEY, NONCE, target, expires).finalize().into_bytes();
+        Message::redirect(target, expires, &signature.into())
+    }
+
+    #[test]
+    fn verifies_issued_token() {
+        let redirector = Redirector::new(KEY.to_vec(), vec![target()], None);
+        let (issued, message) = redirector.redirect(NONCE);
+        assert_eq!(issued, target());
+        assert_eq!(verify(KEY, NONCE, &message).unwrap(), target());
+    }
+
+    #[test]
+    fn rejects_expired_token() {
+        let message = signed(target(), unix_time_secs() - 1);
+        assert!(verify(KEY, NONCE, &message).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_key_or_nonce() {
+        let message = signed(target(), unix_time_secs() + 60);
+        assert!(verify(b"other key", NONCE, &message).is_err());
+        assert!(verify(KEY, NONCE + 1, &message).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_token() {
+        let expires = unix_time_secs() + 60;
+        let (_, _, signature) = signed(target(), expires).parse_redirect().unwrap();
+
+        let other: SocketAddr = "198.51.100.66:4789".parse().unwrap();
+        assert!(verify(KEY, NONCE, &Message::redirect(other, expires, &signature)).is_err());
+        assert!(verify(KEY, NONCE, &Message::redirect(target(), expires + 3600, &signature)).is_err());
+
+        let mut flipped = signature;
+        flipped[0] ^= 1;
+        assert!(verify(KEY, NONCE, &Message::redirect(target(), expires, &flipped)).is_err());
+    }
+
+    #[test]
+    fn rotates_targets() {
+        let targets: Vec<SocketAddr> = vec![target(), "198.51.100.66:4789".parse().unwrap()];
+        let redirector = Redirector::new(KEY.to_vec(), targets.clone(), Some(2));
+        assert_eq!(redirector.redirect(NONCE).0, targets[0]);
+        assert_eq!(redirector.redirect(NONCE).0, targets[1]);
+        assert_eq!(redirector.redirect(NONCE).0, targets[0]);
+
+        assert!(!redirector.should_redirect(1));
+        assert!(redirector.should_redirect(2));
+        assert!(redirector.toggle_draining());
+        assert!(redirector.should_redirect(0));
+    }
+}
@@ -0,0 +1,296 @@
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use futures::stream::{FuturesUnordered, StreamExt};
+use hickory_resolver::config::LookupIpStrategy;
+use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::TokioAsyncResolver;
+use tokio::sync::OnceCell;
+use tokio::time::{self, Duration, Instant};
+use crate::error::{Result, VswitchError};
+
+/// 快乐眼球算法中相邻两次连接尝试的间隔 (RFC 8305 推荐值)
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+/// 解析结果的最短有效期，避免TTL过短时频繁查询
+pub const MIN_TTL: Duration = Duration::from_secs(30);
+
+/// 服务器地址配置
+///
+/// 支持以下格式:
+/// - `IP:PORT` 或 `[IPv6]:PORT`: 固定地址
+/// - `HOST:PORT`: 域名，解析A/AAAA记录
+/// - `_service._proto.name`: SRV记录名，按优先级和权重解析目标主机
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerSpec {
+    /// 固定地址
+    Addr(SocketAddr),
+    /// 域名和端口
+    Host(String, u16),
+    /// SRV记录名
+    Srv(String),
+}
+
+impl FromStr for ServerSpec {
+    type Err = VswitchError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(ServerSpec::Addr(addr));
+        }
+
+        if s.starts_with('_') && !s.contains(':') {
+            return Ok(ServerSpec::Srv(s.to_string()));
+        }
+
+        let (host, port) = s.rsplit_once(':')
+            .ok_or_else(|| VswitchError::ConfigError(format!("服务器地址缺少端口: {}", s)))?;
+        let port = port.parse::<u16>()
+            .map_err(|e| VswitchError::ConfigError(format!("无效的端口 {}: {}", port, e)))?;
+        if host.is_empty() || host.contains(':') {
+            return Err(VswitchError::ConfigError(format!("无效的主机名: {}", host)));
+        }
+
+        Ok(ServerSpec::Host(host.to_string(), port))
+    }
+}
+
+impl fmt::Display for ServerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerSpec::Addr(addr) => write!(f, "{}", addr),
+            ServerSpec::Host(host, port) => write!(f, "{}:{}", host, port),
+            ServerSpec::Srv(name) => write!(f, "SRV {}", name),
+        }
+    }
+}
+
+/// 域名解析结果
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    /// 候选地址，已按快乐眼球算法排序 (IPv6优先，两个地址族交替)
+    pub addrs: Vec<SocketAddr>,
+    /// 解析结果的过期时间，固定地址为 `None`
+    pub valid_until: Option<Instant>,
+}
+
+/// 服务器地址解析器
+///
+/// 只在配置了域名时才读取系统DNS配置
+pub struct Resolver {
+    resolver: OnceCell<TokioAsyncResolver>,
+}
+
+impl Resolver {
+    /// 创建解析器
+    pub fn new() -> Self {
+        Self {
+            resolver: OnceCell::new(),
+        }
+    }
+
+    /// 获取底层DNS解析器，首次调用时读取系统配置
+    async fn resolver(&self) -> Result<&TokioAsyncResolver> {
+        self.resolver.get_or_try_init(|| async {
+            let (config, mut opts) = read_system_conf()?;
+            // 同时查询A和AAAA记录，由快乐眼球算法决定使用哪个地址族
+            opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+            Ok(TokioAsyncResolver::tokio(config, opts))
+        }).await
+    }
+
+    /// 解析服务器地址
+    pub async fn resolve(&self, spec: &ServerSpec) -> Result<Resolved> {
+        match spec {
+            ServerSpec::Addr(addr) => Ok(Resolved {
+                addrs: vec![*addr],
+                valid_until: None,
+            }),
+            ServerSpec::Host(host, port) => {
+                let lookup = self.resolver().await?.lookup_ip(host.as_str()).await?;
+                let ips: Vec<IpAddr> = lookup.iter().collect();
+                log::debug!("解析 {}: {:?}", host, ips);
+
+                Ok(Resolved {
+                    addrs: interleave_families(ips.into_iter().map(|ip| SocketAddr::new(ip, *port)).collect()),
+                    valid_until: Some(clamp_ttl(lookup.valid_until().into())),
+                })
+            }
+            ServerSpec::Srv(name) => {
+                let resolver = self.resolver().await?;
+                let lookup = resolver.srv_lookup(name.as_str()).await?;
+                let mut valid_until: Instant = lookup.as_lookup().valid_until().into();
+
+                // 按优先级升序、权重降序排列目标主机
+                let mut records: Vec<_> = lookup.iter().collect();
+                records.sort_by(|a, b| a.priority().cmp(&b.priority()).then(b.weight().cmp(&a.weight())));
+
+                let mut addrs = Vec::new();
+                for record in records {
+                    let target = record.target().to_utf8();
+                    match resolver.lookup_ip(target.as_str()).await {
+                        Ok(ips) => {
+                            valid_until = valid_until.min(ips.valid_until().into());
+                            let target_addrs = ips.iter().map(|ip| SocketAddr::new(ip, record.port())).collect();
+                            addrs.extend(interleave_families(target_addrs));
+                        }
+                        Err(e) => log::warn!("解析SRV目标 {} 失败: {}", target, e),
+                    }
+                }
+
+                if addrs.is_empty() {
+                    return Err(VswitchError::ConfigError(format!("SRV记录 {} 没有可用的目标地址", name)));
+                }
+
+                log::debug!("解析 SRV {}: {:?}", name, addrs);
+                Ok(Resolved {
+                    addrs,
+                    valid_until: Some(clamp_ttl(valid_until)),
+                })
+            }
+        }
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 使用快乐眼球算法从候选地址中选择服务器地址
+///
+/// 按顺序每隔 250 毫秒使用探测函数向一个候选地址发起探测，最先响应的地址胜出；
+/// 所有候选地址都无响应时返回 `None`
+pub async fn happy_eyeballs<F, Fut>(addrs: &[SocketAddr], probe: F) -> Option<SocketAddr>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let mut attempts: FuturesUnordered<_> = addrs.iter()
+        .enumerate()
+        .map(|(index, addr)| {
+            let probe = probe(*addr);
+            async move {
+                time::sleep(CONNECTION_ATTEMPT_DELAY * index as u32).await;
+                (*addr, probe.await)
+            }
+        })
+        .collect();
+
+    while let Some((addr, alive)) = attempts.next().await {
+        if alive {
+            log::debug!("快乐眼球算法选中地址: {}", addr);
+            return Some(addr);
+        }
+    }
+
+    None
+}
+
+/// 按地址族交替排列候选地址，IPv6优先
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs.into_iter().partition(|addr| addr.is_ipv6());
+    let mut result = Vec::with_capacity(v6.len() + v4.len());
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => result.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    result
+}
+
+/// 限制解析结果的最短有效期
+fn clamp_ttl(valid_until: Instant) -> Instant {
+    valid_until.max(Instant::now() + MIN_TTL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(list: &[&str]) -> Vec<SocketAddr> {
+        list.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_server_specs() {
+        assert_eq!("192.0.2.1:4789".parse::<ServerSpec>().unwrap(), ServerSpec::Addr("192.0.2.1:4789".parse().unwrap()));
+        assert_eq!("[2001:db8::1]:4789".parse::<ServerSpec>().unwrap(), ServerSpec::Addr("[2001:db8::1]:4789".parse().unwrap()));
+        assert_eq!("vpn.example.com:4789".parse::<ServerSpec>().unwrap(), ServerSpec::Host("vpn.example.com".to_string(), 4789));
+        assert_eq!("_vswitch._udp.example.com".parse::<ServerSpec>().unwrap(), ServerSpec::Srv("_vswitch._udp.example.com".to_string()));
+
+        for invalid in ["vpn.example.com", "vpn.example.com:port", ":4789", "2001:db8::1:4789", "vpn.example.com:65536"] {
+            assert!(invalid.parse::<ServerSpec>().is_err(), "{} 应解析失败", invalid);
+        }
+    }
+
+    #[test]
+    fn displays_server_specs() {
+        for spec in ["192.0.2.1:4789", "[2001:db8::1]:4789", "vpn.example.com:4789"] {
+            assert_eq!(spec.parse::<ServerSpec>().unwrap().to_string(), spec);
+        }
+        assert_eq!(ServerSpec::Srv("_vswitch._udp.example.com".to_string()).to_string(), "SRV _vswitch._udp.example.com");
+    }
+
+    #[tokio::test]
+    async fn fixed_address_never_expires() {
+        let addr = "192.0.2.1:4789".parse().unwrap();
+        let resolved = Resolver::new().resolve(&ServerSpec::Addr(addr)).await.unwrap();
+        assert_eq!(resolved.addrs, vec![addr]);
+        assert!(resolved.valid_until.is_none());
+    }
+
+    #[test]
+    fn interleaves_families_ipv6_first() {
+        let result = interleave_families(addrs(&["192.0.2.1:1", "192.0.2.2:1", "192.0.2.3:1", "[2001:db8::1]:1"]));
+        assert_eq!(result, addrs(&["[2001:db8::1]:1", "192.0.2.1:1", "192.0.2.2:1", "192.0.2.3:1"]));
+
+        let result = interleave_families(addrs(&["[2001:db8::1]:1", "[2001:db8::2]:1", "192.0.2.1:1"]));
+        assert_eq!(result, addrs(&["[2001:db8::1]:1", "192.0.2.1:1", "[2001:db8::2]:1"]));
+    }
+
+    #[test]
+    fn clamps_short_ttl() {
+        let now = Instant::now();
+        assert!(clamp_ttl(now) >= now + MIN_TTL);
+        let long = now + MIN_TTL * 10;
+        assert_eq!(clamp_ttl(long), long);
+    }
+
+    #[tokio::test]
+    async fn happy_eyeballs_prefers_first_responsive_address() {
+        let candidates = addrs(&["[2001:db8::1]:1", "192.0.2.1:1"]);
+        assert_eq!(happy_eyeballs(&candidates, |_| async { true }).await, Some(candidates[0]));
+
+        // 第一个地址无响应时使用下一个地址
+        let first = candidates[0];
+        assert_eq!(happy_eyeballs(&candidates, |addr| async move { addr != first }).await, Some(candidates[1]));
+
+        assert_eq!(happy_eyeballs(&candidates, |_| async { false }).await, None);
+        assert_eq!(happy_eyeballs(&[], |_| async { true }).await, None);
+    }
+
+    #[tokio::test]
+    async fn happy_eyeballs_does_not_wait_for_slow_address() {
+        let candidates = addrs(&["[2001:db8::1]:1", "192.0.2.1:1"]);
+        let first = candidates[0];
+        let start = Instant::now();
+
+        let selected = happy_eyeballs(&candidates, |addr| async move {
+            if addr == first {
+                time::sleep(Duration::from_secs(5)).await;
+            }
+            true
+        }).await;
+
+        assert_eq!(selected, Some(candidates[1]));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= CONNECTION_ATTEMPT_DELAY && elapsed < Duration::from_secs(5));
+    }
+}
@@ -1,259 +1,930 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use bytes::Bytes;
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use tokio::sync::Notify;
 use tokio::time::{self, Duration};
-use std::io::Cursor;
+use tokio_rustls::rustls::ServerConfig;
+use crate::buffer::{BufferPool, PacketBuf};
 use crate::error::{Result, VswitchError};
+use crate::fec::{FecDecoder, FecEncoder, FecRatio};
+use crate::federation::{Federation, ADVERTISE_INTERVAL};
+use crate::obfs::Obfuscator;
+use crate::packet::{extract_dst_ip, extract_src_ip};
 use crate::protocol::{Message, MessageType};
+use crate::redirect::Redirector;
 use crate::tun::TunDevice;
-use bytes;
+use crate::udp;
+use crate::transport::{ListenAddr, ServerTransport, TransportKind};
 
 /// 表示一个已连接的客户端
 struct Client {
-    last_heartbeat: u64,
+    /// 最后一次收到客户端消息的时间戳（毫秒），每个数据包都会更新，使用原子变量只需读取客户端表
+    last_heartbeat: AtomicU64,
     /// 客户端的虚拟IP地址
     ip_addr: Option<IpAddr>,
+    /// 客户端最近一次连接请求携带的随机数，用于签发重定向令牌
+    nonce: Option<u64>,
+    /// 客户端证书绑定的虚拟IP，设置后只接受以该地址为源地址的数据包
+    bound_ip: Option<IpAddr>,
+    /// 发往客户端的前向纠错发送端，比例由客户端在连接请求中指定
+    fec_encoder: Option<FecEncoder>,
+    /// 来自客户端的前向纠错接收端
+    fec_decoder: FecDecoder,
+    /// 当前限速窗口开始的时间戳（毫秒）
+    introduction_window: u64,
+    /// 当前限速窗口内已协调的打洞次数
+    introductions: u32,
 }
 
 impl Client {
     fn new(_addr: SocketAddr) -> Self {
         Self {
-            last_heartbeat: current_time_millis(),
+            last_heartbeat: AtomicU64::new(current_time_millis()),
             ip_addr: None,
+            nonce: None,
+            bound_ip: None,
+            fec_encoder: None,
+            fec_decoder: FecDecoder::new(),
+            introduction_window: 0,
+            introductions: 0,
+        }
+    }
+
+    /// 记录一次打洞协调，超过限速窗口内的次数上限时返回 `false`
+    fn allow_introduction(&mut self, now: u64) -> bool {
+        if now.saturating_sub(self.introduction_window) >= INTRODUCTION_WINDOW_MS {
+            self.introduction_window = now;
+            self.introductions = 0;
+        }
+        if self.introductions >= MAX_INTRODUCTIONS {
+            return false;
+        }
+        self.introductions += 1;
+        true
+    }
+}
+
+/// 打洞协调的限速窗口（毫秒）
+const INTRODUCTION_WINDOW_MS: u64 = 10_000;
+/// 每个客户端在一个限速窗口内最多请求协调打洞的次数
+const MAX_INTRODUCTIONS: u32 = 20;
+
+/// 延迟响应NAT探测的最大秒数
+const MAX_PROBE_DELAY: u16 = 60;
+/// 同时等待延迟响应的NAT探测数上限
+const MAX_PENDING_PROBES: usize = 1024;
+/// 每个源IP同时等待延迟响应的NAT探测数上限
+const MAX_PENDING_PROBES_PER_IP: usize = 4;
+
+/// 等待延迟响应的NAT探测计数
+///
+/// 每个延迟响应占用一个任务，按总数和源IP限制，超出时丢弃探测请求
+#[derive(Default)]
+struct PendingProbes {
+    /// 等待中的探测总数
+    total: AtomicUsize,
+    /// 源IP -> 等待中的探测数
+    per_ip: DashMap<IpAddr, usize>,
+}
+
+impl PendingProbes {
+    /// 为来自 `ip` 的探测占用一个名额，超出上限时返回 `false`
+    fn acquire(&self, ip: IpAddr) -> bool {
+        if self.total.fetch_add(1, Ordering::Relaxed) >= MAX_PENDING_PROBES {
+            self.total.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        let mut count = self.per_ip.entry(ip).or_insert(0);
+        if *count >= MAX_PENDING_PROBES_PER_IP {
+            drop(count);
+            self.total.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// 释放 `ip` 占用的一个名额
+    fn release(&self, ip: IpAddr) {
+        self.total.fetch_sub(1, Ordering::Relaxed);
+        if let Entry::Occupied(mut entry) = self.per_ip.entry(ip) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
         }
     }
 }
 
+/// 服务端可选配置
+#[derive(Default)]
+pub struct ServerOptions {
+    /// NAT探测辅助监听地址，客户端通过比较两个端口观察到的公网地址判断NAT映射类型
+    pub probe_addr: Option<SocketAddr>,
+    /// 联邦中邻居服务端的地址
+    pub neighbors: Vec<SocketAddr>,
+    /// 集群共享密钥，用于签名和校验与邻居服务端交换的路由通告
+    pub cluster_key: Option<Vec<u8>>,
+    /// 过载或维护时将客户端重定向到其他服务端的策略
+    pub redirector: Option<Redirector>,
+    /// 加密传输方式使用的TLS配置
+    pub tls: Option<Arc<ServerConfig>>,
+    /// 客户端证书身份到虚拟IP的绑定
+    pub client_ips: HashMap<String, IpAddr>,
+    /// UDP数据报混淆，客户端和邻居服务端需要使用相同的密钥
+    pub obfs: Option<Arc<Obfuscator>>,
+    /// 是否为TUN设备的每个队列绑定一个 SO_REUSEPORT UDP套接字
+    pub reuseport: bool,
+    /// 以 AF_XDP 截取UDP监听端口数据报的网络接口
+    pub xdp: Option<String>,
+}
+
 /// 服务端结构
 pub struct Server {
     tun: Arc<TunDevice>,
+    /// NAT探测辅助监听地址
+    probe_addr: Option<SocketAddr>,
+    /// 重定向策略
+    redirector: Option<Arc<Redirector>>,
+    /// TLS配置
+    tls: Option<Arc<ServerConfig>>,
+    /// 客户端证书身份到虚拟IP的绑定
+    client_ips: HashMap<String, IpAddr>,
+    /// UDP数据报混淆
+    obfs: Option<Arc<Obfuscator>>,
+    /// 是否为TUN设备的每个队列绑定一个 SO_REUSEPORT UDP套接字
+    reuseport: bool,
+    /// 以 AF_XDP 截取UDP监听端口数据报的网络接口
+    xdp: Option<String>,
     /// 客户端连接映射表 (UDP地址 -> 客户端信息)
-    clients: Arc<Mutex<HashMap<SocketAddr, Client>>>,
+    ///
+    /// 转发路径上的每个数据包都要访问客户端表和IP地址映射表，两个表都使用分片的并发哈希表，
+    /// 不同客户端的查询和更新互不阻塞。表项的引用不能跨越 `.await` 持有
+    clients: Arc<DashMap<SocketAddr, Client>>,
     /// IP地址映射表 (IP地址 -> UDP地址)
-    ip_to_addr: Arc<Mutex<HashMap<IpAddr, SocketAddr>>>,
+    ip_to_addr: Arc<DashMap<IpAddr, SocketAddr>>,
+    /// 服务端联邦，记录邻居服务端及经其可达的远程客户端路由
+    federation: Arc<Federation>,
+    /// 任一客户端的前向纠错发送端开始新分组时通知校验分片发送任务
+    fec_notify: Arc<Notify>,
+    /// 等待延迟响应的NAT探测
+    pending_probes: Arc<PendingProbes>,
 }
 
 impl Server {
     /// 创建一个新的服务端实例
-    pub fn new(tun: TunDevice) -> Self {
+    ///
+    /// 参数:
+    /// - `tun`: TUN设备
+    /// - `options`: 可选配置
+    pub fn new(tun: TunDevice, options: ServerOptions) -> Self {
         Self {
             tun: Arc::new(tun),
-            clients: Arc::new(Mutex::new(HashMap::new())),
-            ip_to_addr: Arc::new(Mutex::new(HashMap::new())),
+            probe_addr: options.probe_addr,
+            redirector: options.redirector.map(Arc::new),
+            tls: options.tls,
+            client_ips: options.client_ips,
+            obfs: options.obfs,
+            reuseport: options.reuseport,
+            xdp: options.xdp,
+            clients: Arc::new(DashMap::new()),
+            ip_to_addr: Arc::new(DashMap::new()),
+            federation: Arc::new(Federation::new(&options.neighbors, options.cluster_key.clone().unwrap_or_default())),
+            fec_notify: Arc::new(Notify::new()),
+            pending_probes: Arc::new(PendingProbes::default()),
         }
     }
 
     /// 启动服务端
-    pub async fn run(&self, listen_addr: SocketAddr) -> Result<()> {
-        log::info!("服务端启动，监听地址: {}", listen_addr);
+    ///
+    /// 参数:
+    /// - `listen_addrs`: 监听地址列表，不能为空
+    pub async fn run(&self, listen_addrs: &[ListenAddr]) -> Result<()> {
+        log::info!("服务端启动，监听地址: {:?}", listen_addrs);
         
-        // 创建UDP套接字
-        let socket = UdpSocket::bind(listen_addr).await.map_err(|e| {
-            log::error!("绑定UDP套接字失败 {}: {}", listen_addr, e);
+        // 创建监听套接字
+        // 启用 SO_REUSEPORT 时每个TUN队列对应一个UDP套接字，各自由单独的接收任务处理
+        let reuseport = if self.reuseport { self.tun.queue_count() } else { 1 };
+        let socket = ServerTransport::bind(listen_addrs, self.tls.clone(), self.obfs.clone(), reuseport, self.xdp.as_deref()).await.map_err(|e| {
+            log::error!("绑定监听套接字失败 {}", e);
             VswitchError::IoError(e)
         })?;
         
-        log::info!("UDP套接字绑定成功: {}", listen_addr);
+        for local_addr in socket.local_addrs() {
+            log::info!("监听套接字绑定成功: {}", local_addr);
+        }
         let socket = Arc::new(socket);
         
         // 启动TUN设备读取处理任务
         self.spawn_tun_reader(socket.clone());
         
         // 启动心跳检测任务
-        self.spawn_heartbeat_checker();
-        
-        // 创建接收缓冲区
-        let mut recv_buf = vec![0u8; 4096];
+        self.spawn_heartbeat_checker(socket.clone());
+
+        // 启动前向纠错校验分片发送任务
+        self.spawn_fec_flusher(socket.clone());
+
+        // 启动联邦路由通告任务
+        if !self.federation.neighbor_addrs().is_empty() {
+            self.spawn_route_advertiser(socket.clone());
+        }
+
+        // 启动维护排空信号处理任务
+        if let Some(redirector) = &self.redirector {
+            self.spawn_drain_signal_handler(socket.clone(), redirector.clone())?;
+        }
+
+        // 启动NAT探测辅助端口
+        let alt_port = match self.probe_addr {
+            Some(probe_addr) => {
+                self.spawn_probe_listener(probe_addr).await?;
+                probe_addr.port()
+            }
+            None => 0,
+        };
         
         log::info!("服务端主循环开始运行");
-        
-        // 主循环：处理客户端请求
+
+        // 每个UDP监听套接字一个接收循环，与流连接的接收循环并发运行
+        let udp_loops = (0..socket.udp_listener_count()).map(|index| self.recv_loop(&socket, index, alt_port));
+        tokio::join!(
+            futures::future::join_all(udp_loops),
+            self.stream_recv_loop(&socket, alt_port),
+        );
+
+        Ok(())
+    }
+
+    /// 流连接接收循环：处理所有流连接收到的客户端请求
+    async fn stream_recv_loop(&self, socket: &Arc<ServerTransport>, alt_port: u16) {
+        while let Some((addr, frame)) = socket.recv_stream().await {
+            self.handle_received(socket, addr, frame, alt_port).await;
+        }
+    }
+
+    /// 主循环：处理指定UDP监听套接字收到的客户端请求
+    async fn recv_loop(&self, socket: &Arc<ServerTransport>, listener: usize, alt_port: u16) {
         loop {
-            match socket.recv_from(&mut recv_buf).await {
-                Ok((size, addr)) => {
-                    if size == 0 {
+            match socket.recv_udp(listener).await {
+                Ok((datagram, addr)) => {
+                    if datagram.is_empty() {
                         log::debug!("收到空数据包，来源: {}", addr);
                         continue;
                     }
                     
-                    let received_data = &recv_buf[..size];
-                    let mut cursor = Cursor::new(received_data);
-                    
-                    match Message::decode(&mut cursor) {
-                        Ok(message) => {
-                            match message.msg_type {
-                                MessageType::Connect => {
-                                    log::info!("客户端连接请求: {}", addr);
-                                    
-                                    // 添加或更新客户端
-                                    let mut clients = self.clients.lock().await;
-                                    let is_new_client = !clients.contains_key(&addr);
-                                    if is_new_client {
-                                        clients.insert(addr, Client::new(addr));
-                                        log::info!("新客户端连接成功: {}, 当前客户端总数: {}", addr, clients.len());
-                                    } else {
-                                        log::info!("客户端重新连接: {}", addr);
-                                    }
-                                    
-                                    // 发送连接确认
-                                    if let Err(e) = socket.send_to(&Message::connect().encode(), addr).await {
-                                        log::error!("发送连接确认错误 -> {}: {}", addr, e);
-                                    } else {
-                                        log::debug!("发送连接确认成功 -> {}", addr);
-                                    }
-                                }
-                                MessageType::Data => {
-                                    log::debug!("收到数据包: {} bytes from {}", message.payload.len(), addr);
-                                    
-                                    // 更新心跳时间
-                                    self.update_client_heartbeat(addr).await;
-                                    
-                                    // 提取数据包源IP地址并更新映射表
-                                    if let Some(src_ip) = extract_src_ip(&message.payload) {
-                                        self.update_ip_mapping(addr, src_ip).await;
-                                    }
-                                    
-                                    // 将数据写入TUN设备
-                                    if let Err(e) = self.tun.write_packet(&message.payload).await {
-                                        log::error!("写入TUN设备错误: {} (数据来源: {})", e, addr);
-                                    } else {
-                                        log::debug!("数据包成功写入TUN设备 ({} bytes)", message.payload.len());
-                                    }
-                                }
-                                MessageType::Heartbeat => {
-                                    log::debug!("收到心跳包: {}", addr);
-                                    
-                                    // 更新客户端心跳时间
-                                    self.update_client_heartbeat(addr).await;
-                                    
-                                    // 发送心跳响应
-                                    if let Err(e) = socket.send_to(&Message::heartbeat().encode(), addr).await {
-                                        log::error!("发送心跳响应错误 -> {}: {}", addr, e);
-                                    }
+                    self.handle_received(socket, addr, datagram, alt_port).await;
+                }
+                Err(e) => {
+                    log::error!("UDP接收错误: {}", e);
+                    time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    }
+    
+    /// 处理收到的一个消息
+    ///
+    /// 参数:
+    /// - `socket`: 服务端传输层
+    /// - `addr`: 消息来源地址
+    /// - `data`: 编码后的消息，解码出的负载与其共享内存
+    /// - `alt_port`: NAT探测辅助端口，未启用时为0
+    async fn handle_received(&self, socket: &Arc<ServerTransport>, addr: SocketAddr, mut data: Bytes, alt_port: u16) {
+        match Message::decode(&mut data) {
+            Ok(message) => {
+                // 来自邻居服务端的消息单独处理
+                if self.federation.is_neighbor(addr) {
+                    self.handle_neighbor_message(socket, addr, message).await;
+                    return;
+                }
+
+                match message.msg_type {
+                    MessageType::Connect => {
+                        log::info!("客户端连接请求: {}", addr);
+                        let nonce = message.parse_connect_nonce();
+                        let identity = socket.peer_identity(addr).await;
+
+                        // 过载或维护时重定向到其他服务端
+                        if let Some(nonce) = nonce {
+                            if self.try_redirect(socket, addr, nonce).await {
+                                return;
+                            }
+                        }
+
+                        // 添加或更新客户端
+                        let bound_ip = identity.as_ref().and_then(|identity| self.client_ips.get(identity).copied());
+                        let fec = message.parse_connect_fec().and_then(|(data, parity)| FecRatio::new(data, parity).ok());
+                        let mut is_new_client = false;
+                        {
+                            let mut client = self.clients.entry(addr).or_insert_with(|| {
+                                is_new_client = true;
+                                Client::new(addr)
+                            });
+                            client.nonce = nonce;
+                            client.bound_ip = bound_ip;
+                            client.fec_encoder = fec.map(FecEncoder::new);
+                        }
+                        if is_new_client {
+                            log::info!("新客户端连接成功: {}, 当前客户端总数: {}", addr, self.clients.len());
+                        } else {
+                            log::info!("客户端重新连接: {}", addr);
+                        }
+                        if let Some(fec) = fec {
+                            log::info!("客户端 {} 启用前向纠错，比例: {}", addr, fec);
+                        }
+
+                        // 使用客户端证书时按证书身份绑定虚拟IP，无需等待客户端发送数据
+                        if let Some(identity) = &identity {
+                            match bound_ip {
+                                Some(ip) => {
+                                    log::info!("客户端 {} 证书身份: {}, 虚拟IP: {}", addr, identity, ip);
+                                    self.update_ip_mapping(addr, ip);
                                 }
-                                MessageType::Disconnect => {
-                                    log::info!("客户端主动断开连接请求: {}", addr);
-                                    self.remove_client(addr).await;
+                                None => log::info!("客户端 {} 证书身份: {}", addr, identity),
+                            }
+                        }
+
+                        // 发送连接确认
+                        if let Err(e) = socket.send_to(&Message::connect().encode(), addr).await {
+                            log::error!("发送连接确认错误 -> {}: {}", addr, e);
+                        } else {
+                            log::debug!("发送连接确认成功 -> {}", addr);
+                        }
+                    }
+                    MessageType::Data => {
+                        log::debug!("收到数据包: {} bytes from {}", message.payload.len(), addr);
+
+                        // 更新心跳时间
+                        self.update_client_heartbeat(addr);
+
+                        self.handle_data(socket, addr, message.payload).await;
+                    }
+                    MessageType::FecData | MessageType::FecParity => {
+                        // 更新心跳时间，同时确保客户端存在
+                        self.update_client_heartbeat(addr);
+
+                        let packets = match self.clients.get_mut(&addr) {
+                            Some(mut client) => client.fec_decoder.receive(&message),
+                            None => return,
+                        };
+                        match packets {
+                            Ok(packets) => {
+                                for packet in packets {
+                                    self.handle_data(socket, addr, packet).await;
                                 }
                             }
+                            Err(e) => {
+                                log::error!("解析前向纠错分片错误: {} from {}", e, addr);
+                            }
                         }
-                        Err(e) => {
-                            log::error!("解码消息错误: {} from {}, 数据大小: {}", e, addr, size);
+                    }
+                    MessageType::Heartbeat => {
+                        log::debug!("收到心跳包: {}", addr);
+
+                        // 更新客户端心跳时间
+                        self.update_client_heartbeat(addr);
+
+                        // 发送心跳响应
+                        if let Err(e) = socket.send_to(&Message::heartbeat().encode(), addr).await {
+                            log::error!("发送心跳响应错误 -> {}: {}", addr, e);
+                        }
+                    }
+                    MessageType::Disconnect => {
+                        log::info!("客户端主动断开连接请求: {}", addr);
+                        self.remove_client(addr);
+                    }
+                    MessageType::PeerRequest => {
+                        match message.parse_ip() {
+                            Ok(peer_ip) => {
+                                self.introduce_peers(socket, addr, peer_ip).await;
+                            }
+                            Err(e) => {
+                                log::error!("解析对端地址查询错误: {} from {}", e, addr);
+                            }
+                        }
+                    }
+                    MessageType::NatProbe => {
+                        match message.parse_nat_probe() {
+                            Ok((id, delay_secs)) => {
+                                log::debug!("收到NAT探测请求: {} (序号: {}, 延迟: {}秒)", addr, id, delay_secs);
+                                if delay_secs == 0 {
+                                    send_probe_reply(socket, addr, id, alt_port).await;
+                                } else {
+                                    self.spawn_delayed_probe_reply(socket.clone(), addr, id, delay_secs, alt_port);
+                                }
+                            }
+                            Err(e) => {
+                                log::error!("解析NAT探测请求错误: {} from {}", e, addr);
+                            }
                         }
                     }
+                    other => {
+                        log::debug!("忽略来自 {} 的消息: {:?}", addr, other);
+                    }
                 }
-                Err(e) => {
-                    log::error!("UDP接收错误: {}", e);
-                    time::sleep(Duration::from_millis(100)).await;
+            }
+            Err(e) => {
+                log::error!("解码消息错误: {} from {}, 数据大小: {}", e, addr, data.len());
+            }
+        }
+    }
+
+    /// 处理客户端发来的一个数据包: 更新地址映射，转发给邻居服务端或写入TUN设备
+    async fn handle_data(&self, socket: &ServerTransport, addr: SocketAddr, packet: Bytes) {
+        // 提取数据包源IP地址并更新映射表
+        if let Some(src_ip) = extract_src_ip(&packet) {
+            if !self.is_source_allowed(addr, src_ip) {
+                log::warn!("丢弃来自 {} 的数据包: 源地址 {} 与证书绑定的虚拟IP不符", addr, src_ip);
+                return;
+            }
+            self.update_ip_mapping(addr, src_ip);
+        }
+
+        // 目标为其他服务端的客户端时直接转发给下一跳服务端
+        if let Some(dst_ip) = extract_dst_ip(&packet) {
+            if let Some(next_hop) = self.federation.next_hop(dst_ip) {
+                log::debug!("向邻居服务端 {} 转发数据包 (目标: {})", next_hop, dst_ip);
+                if let Err(e) = socket.send_to(&self.federation.seal_data(&packet), next_hop).await {
+                    log::error!("向邻居服务端 {} 转发数据错误: {}", next_hop, e);
                 }
+                return;
             }
         }
+
+        // 将数据写入TUN设备
+        if let Err(e) = self.tun.write_packet(&packet).await {
+            log::error!("写入TUN设备错误: {} (数据来源: {})", e, addr);
+        } else {
+            log::debug!("数据包成功写入TUN设备 ({} bytes)", packet.len());
+        }
     }
-    
+
     /// 更新客户端的最后心跳时间
-    async fn update_client_heartbeat(&self, addr: SocketAddr) {
-        let mut clients = self.clients.lock().await;
-        if let Some(client) = clients.get_mut(&addr) {
-            client.last_heartbeat = current_time_millis();
+    fn update_client_heartbeat(&self, addr: SocketAddr) {
+        if let Some(client) = self.clients.get(&addr) {
+            client.last_heartbeat.store(current_time_millis(), Ordering::Relaxed);
             log::debug!("更新客户端心跳: {}", addr);
-        } else {
-            // 如果客户端不存在，则添加它
-            clients.insert(addr, Client::new(addr));
-            log::info!("通过活动数据添加新客户端: {}, 当前客户端总数: {}", addr, clients.len());
+            return;
+        }
+
+        // 如果客户端不存在，则添加它
+        let mut added = false;
+        self.clients.entry(addr).or_insert_with(|| {
+            added = true;
+            Client::new(addr)
+        });
+        if added {
+            log::info!("通过活动数据添加新客户端: {}, 当前客户端总数: {}", addr, self.clients.len());
         }
     }
     
     /// 移除客户端及其IP映射
-    async fn remove_client(&self, addr: SocketAddr) {
+    fn remove_client(&self, addr: SocketAddr) {
         // 移除客户端
-        let mut client_ip = None;
-        {
-            let mut clients = self.clients.lock().await;
-            if let Some(client) = clients.remove(&addr) {
-                client_ip = client.ip_addr;
-                log::info!("客户端已移除: {}, 剩余客户端: {}", addr, clients.len());
-            } else {
+        let client_ip = match self.clients.remove(&addr) {
+            Some((_, client)) => {
+                log::info!("客户端已移除: {}, 剩余客户端: {}", addr, self.clients.len());
+                client.ip_addr
+            }
+            None => {
                 log::warn!("移除不存在的客户端: {}", addr);
+                None
             }
-        }
+        };
         
-        // 移除IP映射
+        // 移除IP映射，IP地址已经移动到其他客户端时保留
         if let Some(ip) = client_ip {
-            let mut ip_map = self.ip_to_addr.lock().await;
-            if ip_map.remove(&ip).is_some() {
+            if self.ip_to_addr.remove_if(&ip, |_, mapped| *mapped == addr).is_some() {
                 log::info!("移除IP映射: {} -> {}", ip, addr);
             }
         }
     }
     
+    /// 按重定向策略尝试将发起连接的客户端重定向到其他服务端
+    ///
+    /// 返回 `true` 表示已发送重定向消息，不再接受该客户端的连接
+    async fn try_redirect(&self, socket: &ServerTransport, addr: SocketAddr, nonce: u64) -> bool {
+        let redirector = match &self.redirector {
+            Some(redirector) => redirector,
+            None => return false,
+        };
+
+        let other_clients = self.clients.len() - self.clients.contains_key(&addr) as usize;
+        if !redirector.should_redirect(other_clients) {
+            return false;
+        }
+
+        let (target, message) = redirector.redirect(nonce);
+        log::info!("将客户端 {} 重定向到 {} (在线客户端: {}, 排空: {})",
+            addr, target, other_clients, redirector.is_draining());
+
+        if let Err(e) = socket.send_to(&message.encode(), addr).await {
+            log::error!("发送重定向消息错误 -> {}: {}", addr, e);
+            return false;
+        }
+
+        if self.clients.contains_key(&addr) {
+            self.remove_client(addr);
+        }
+        true
+    }
+
+    /// 启动维护排空信号处理任务
+    ///
+    /// 收到 SIGUSR1 时切换排空状态；进入排空状态时将所有在线客户端重定向到其他服务端
+    fn spawn_drain_signal_handler(&self, socket: Arc<ServerTransport>, redirector: Arc<Redirector>) -> Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut signals = signal(SignalKind::user_defined1())?;
+        let clients = self.clients.clone();
+
+        log::info!("发送 SIGUSR1 信号可切换维护排空状态");
+
+        tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                if !redirector.toggle_draining() {
+                    log::info!("退出维护排空状态，恢复接受新连接");
+                    continue;
+                }
+
+                let targets: Vec<(SocketAddr, u64)> = clients.iter()
+                    .filter_map(|client| client.nonce.map(|nonce| (*client.key(), nonce)))
+                    .collect();
+
+                log::info!("进入维护排空状态，重定向 {} 个在线客户端", targets.len());
+
+                for (addr, nonce) in targets {
+                    let (target, message) = redirector.redirect(nonce);
+                    log::info!("将客户端 {} 重定向到 {}", addr, target);
+                    if let Err(e) = socket.send_to(&message.encode(), addr).await {
+                        log::error!("发送重定向消息错误 -> {}: {}", addr, e);
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// 处理来自邻居服务端的消息
+    async fn handle_neighbor_message(&self, socket: &ServerTransport, addr: SocketAddr, message: Message) {
+        match message.msg_type {
+            MessageType::RouteUpdate => {
+                match self.federation.apply_update(addr, &message).await {
+                    Ok(count) => {
+                        log::debug!("收到邻居服务端 {} 的路由通告: {} 条", addr, count);
+                    }
+                    Err(e) => {
+                        log::warn!("拒绝路由通告: {} from {}", e, addr);
+                    }
+                }
+            }
+            MessageType::NeighborData => {
+                let packet = match self.federation.open_data(&message) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        log::warn!("丢弃邻居服务端 {} 的数据包: {}", addr, e);
+                        return;
+                    }
+                };
+                let dst_ip = match extract_dst_ip(&packet) {
+                    Some(ip) => ip,
+                    None => {
+                        log::debug!("无法从邻居服务端 {} 的数据包解析目标IP, 数据包被丢弃", addr);
+                        return;
+                    }
+                };
+
+                // 目标是本地客户端
+                let local_addr = self.ip_to_addr.get(&dst_ip).map(|addr| *addr);
+                if let Some(client_addr) = local_addr {
+                    log::debug!("将邻居服务端 {} 的数据包转发给客户端 {} (IP: {})", addr, client_addr, dst_ip);
+                    if let Err(e) = send_data(socket, &self.clients, &self.fec_notify, client_addr, &packet).await {
+                        log::error!("向客户端 {} 发送数据错误: {}", client_addr, e);
+                    }
+                    return;
+                }
+
+                // 目标位于另一个服务端，不回传给来源服务端以避免环路；认证标签不变，原样转发
+                if let Some(next_hop) = self.federation.next_hop(dst_ip) {
+                    if next_hop != addr {
+                        log::debug!("向邻居服务端 {} 转发数据包 (目标: {})", next_hop, dst_ip);
+                        if let Err(e) = socket.send_to(&message.encode(), next_hop).await {
+                            log::error!("向邻居服务端 {} 转发数据错误: {}", next_hop, e);
+                        }
+                    } else {
+                        log::debug!("到 {} 的路由指回来源服务端 {}, 数据包被丢弃", dst_ip, addr);
+                    }
+                    return;
+                }
+
+                // 其余数据包交给本机协议栈处理
+                if let Err(e) = self.tun.write_packet(&packet).await {
+                    log::error!("写入TUN设备错误: {} (数据来源: {})", e, addr);
+                }
+            }
+            other => {
+                log::debug!("忽略来自邻居服务端 {} 的消息: {:?}", addr, other);
+            }
+        }
+    }
+
+    /// 为两个客户端交换公网地址，协调双方同时打洞
+    ///
+    /// 只为已连接且虚拟IP当前映射到其地址的客户端协调，每个客户端按限速窗口限制请求次数。
+    /// 只有双方都经UDP连接时才能打洞，流连接的地址不是UDP的NAT映射；
+    /// 对端未知或任一方使用流连接时回复不能直连，双方继续经服务端中转
+    async fn introduce_peers(&self, socket: &ServerTransport, addr: SocketAddr, peer_ip: IpAddr) {
+        let requester_ip = match self.clients.get_mut(&addr) {
+            Some(mut client) => {
+                if !client.allow_introduction(current_time_millis()) {
+                    log::debug!("客户端 {} 请求协调打洞过于频繁，忽略", addr);
+                    return;
+                }
+                client.bound_ip.or(client.ip_addr)
+            }
+            None => {
+                log::debug!("忽略未连接的地址 {} 的对端地址查询", addr);
+                return;
+            }
+        };
+        let requester_ip = match requester_ip {
+            Some(ip) if self.ip_to_addr.get(&ip).is_some_and(|mapped| *mapped == addr) => ip,
+            _ => {
+                log::debug!("客户端 {} 的虚拟IP未知，无法协调打洞", addr);
+                return;
+            }
+        };
+
+        let peer_addr = self.ip_to_addr.get(&peer_ip).map(|peer_addr| *peer_addr);
+        let peer_addr = match peer_addr {
+            Some(peer_addr) if peer_addr != addr && self.clients.contains_key(&peer_addr) => Some(peer_addr),
+            _ => {
+                log::debug!("未找到虚拟IP {} 对应的客户端，无法协调打洞", peer_ip);
+                None
+            }
+        };
+        let peer_addr = match peer_addr {
+            Some(peer_addr) if socket.is_stream(addr) || socket.is_stream(peer_addr) => {
+                log::debug!("{} 与 {} 之间有流连接，无法协调打洞", addr, peer_addr);
+                None
+            }
+            peer_addr => peer_addr,
+        };
+        let Some(peer_addr) = peer_addr else {
+            if let Err(e) = socket.send_to(&Message::peer_unavailable(peer_ip).encode(), addr).await {
+                log::error!("发送对端地址通告错误 -> {}: {}", addr, e);
+            }
+            return;
+        };
+
+        log::info!("协调打洞: {} ({}) <-> {} ({})", requester_ip, addr, peer_ip, peer_addr);
+
+        if let Err(e) = socket.send_to(&Message::peer_info(peer_ip, peer_addr).encode(), addr).await {
+            log::error!("发送对端地址通告错误 -> {}: {}", addr, e);
+        }
+        if let Err(e) = socket.send_to(&Message::peer_info(requester_ip, addr).encode(), peer_addr).await {
+            log::error!("发送对端地址通告错误 -> {}: {}", peer_addr, e);
+        }
+    }
+
+    /// 检查客户端能否使用指定的源地址
+    ///
+    /// 绑定了虚拟IP的客户端只能使用该地址；绑定给证书身份的虚拟IP不能被其他客户端使用
+    fn is_source_allowed(&self, addr: SocketAddr, src_ip: IpAddr) -> bool {
+        if self.client_ips.is_empty() {
+            return true;
+        }
+
+        match self.clients.get(&addr).and_then(|client| client.bound_ip) {
+            Some(bound_ip) => bound_ip == src_ip,
+            None => !self.client_ips.values().any(|ip| *ip == src_ip),
+        }
+    }
+
     /// 更新IP地址与客户端地址的映射关系
-    async fn update_ip_mapping(&self, addr: SocketAddr, ip: IpAddr) {
+    ///
+    /// 每个数据包都会调用，映射没有变化时只读取两个表，不加写锁
+    fn update_ip_mapping(&self, addr: SocketAddr, ip: IpAddr) {
+        let unchanged = self.ip_to_addr.get(&ip).is_some_and(|mapped| *mapped == addr)
+            && self.clients.get(&addr).is_none_or(|client| client.ip_addr == Some(ip));
+        if unchanged {
+            return;
+        }
+
         // 更新客户端的IP地址
-        {
-            let mut clients = self.clients.lock().await;
-            if let Some(client) = clients.get_mut(&addr) {
-                if client.ip_addr != Some(ip) {
-                    log::info!("客户端 {} 的IP地址更新为: {}", addr, ip);
-                    client.ip_addr = Some(ip);
-                }
+        if let Some(mut client) = self.clients.get_mut(&addr) {
+            if client.ip_addr != Some(ip) {
+                log::info!("客户端 {} 的IP地址更新为: {}", addr, ip);
+                client.ip_addr = Some(ip);
             }
         }
         
         // 更新IP到地址的映射
-        let mut ip_map = self.ip_to_addr.lock().await;
-        if let Some(old_addr) = ip_map.get(&ip) {
-            if *old_addr != addr {
+        if let Some(old_addr) = self.ip_to_addr.insert(ip, addr) {
+            if old_addr != addr {
                 log::warn!("IP地址 {} 从 {} 移动到 {}", ip, old_addr, addr);
             }
         }
-        ip_map.insert(ip, addr);
     }
 
-    /// 启动TUN设备读取任务
-    fn spawn_tun_reader(&self, socket: Arc<UdpSocket>) {
-        let tun = self.tun.clone();
+    /// 启动联邦路由通告任务
+    ///
+    /// 定期向每个邻居服务端通告本地客户端及经其他邻居可达的路由，并清理超时路由
+    fn spawn_route_advertiser(&self, socket: Arc<ServerTransport>) {
+        let federation = self.federation.clone();
         let ip_to_addr = self.ip_to_addr.clone();
-        
-        log::info!("启动TUN设备读取任务");
-        
+
+        log::info!("启动联邦路由通告任务，每{}秒通告一次", ADVERTISE_INTERVAL.as_secs());
+
         tokio::spawn(async move {
             loop {
-                match tun.read_packet().await {
-                    Ok(packet) => {
-                        let packet_len = packet.len();
-                        log::debug!("从TUN设备读取数据包, 长度: {}", packet_len);
-                        
-                        // 创建数据消息
-                        let message = Message::data(packet.clone());
-                        let encoded = message.encode();
-                        
-                        // 确定目标客户端
-                        let ip_map = ip_to_addr.lock().await;
-                        
-                        // 提取目标IP
-                        match extract_dst_ip(&packet) {
-                            Some(dst_ip) => {
+                federation.expire().await;
+
+                let local_ips: Vec<IpAddr> = ip_to_addr.iter().map(|entry| *entry.key()).collect();
+
+                for neighbor in federation.neighbor_addrs() {
+                    for update in federation.build_updates(neighbor, &local_ips) {
+                        if let Err(e) = socket.send_to(&update.encode(), neighbor).await {
+                            log::error!("向邻居服务端 {} 发送路由通告错误: {}", neighbor, e);
+                        }
+                    }
+                }
+
+                time::sleep(ADVERTISE_INTERVAL).await;
+            }
+        });
+    }
+
+    /// 启动TUN设备读取任务
+    ///
+    /// 每个TUN队列一个读取任务，多队列设备的转发可以分布到多个CPU核心
+    fn spawn_tun_reader(&self, socket: Arc<ServerTransport>) {
+        for queue in 0..self.tun.queue_count() {
+            let socket = socket.clone();
+            let tun = self.tun.clone();
+            let ip_to_addr = self.ip_to_addr.clone();
+            let federation = self.federation.clone();
+            let clients = self.clients.clone();
+            let fec_notify = self.fec_notify.clone();
+            
+            log::info!("启动TUN设备读取任务 (队列 {})", queue);
+            
+            tokio::spawn(async move {
+                // 每个读取任务使用自己的缓冲池
+                let mut pool = BufferPool::new();
+                loop {
+                    match tun.read_packets_from(queue, udp::MAX_BATCH, &mut pool).await {
+                        Ok(packets) => {
+                            // 一批数据包路由后一起发送，发往UDP客户端的消息合并为尽量少的系统调用
+                            let mut outgoing = Vec::with_capacity(packets.len());
+                            for packet in packets {
+                                let packet_len = packet.len();
+                                log::debug!("从TUN设备读取数据包, 长度: {}", packet_len);
+
+                                // 提取目标IP
+                                let Some(dst_ip) = extract_dst_ip(&packet) else {
+                                    log::debug!("无法从数据包解析目标IP, 数据包被丢弃");
+                                    continue;
+                                };
+
                                 // 查找目标IP对应的客户端地址
-                                if let Some(dst_addr) = ip_map.get(&dst_ip) {
+                                let dst_addr = ip_to_addr.get(&dst_ip).map(|addr| *addr);
+                                if let Some(dst_addr) = dst_addr {
                                     // 向特定客户端发送数据
                                     log::debug!("向客户端 {} (IP: {}) 发送数据包, 长度: {}", dst_addr, dst_ip, packet_len);
-                                    if let Err(e) = socket.send_to(&encoded, dst_addr).await {
-                                        log::error!("向客户端 {} 发送数据错误: {}", dst_addr, e);
-                                    }
+                                    encode_data(&clients, &fec_notify, dst_addr, packet, &mut outgoing);
+                                } else if let Some(next_hop) = federation.next_hop(dst_ip) {
+                                    // 目标是其他服务端的客户端
+                                    log::debug!("向邻居服务端 {} 转发数据包 (目标: {}), 长度: {}", next_hop, dst_ip, packet_len);
+                                    outgoing.push((federation.seal_data(&packet), next_hop));
                                 } else {
                                     log::debug!("未找到目标IP对应的客户端: {}, 数据包被丢弃", dst_ip);
                                 }
                             }
+
+                            if let Err(e) = socket.send_batch(&outgoing).await {
+                                log::error!("发送数据错误: {}", e);
+                            }
+                        }
+                        Err(e) => {
+                            log::error!("从TUN设备读取错误: {}", e);
+                            time::sleep(Duration::from_secs(1)).await;
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    /// 延迟一段时间后响应NAT探测
+    ///
+    /// 只响应来自已连接客户端所在IP的探测 (客户端使用单独的套接字探测，端口与连接的端口不同)，
+    /// 等待中的探测数超过总数或单个IP的上限时丢弃请求
+    fn spawn_delayed_probe_reply(&self, socket: Arc<ServerTransport>, addr: SocketAddr, id: u32, delay_secs: u16, alt_port: u16) {
+        let pending_probes = self.pending_probes.clone();
+        if !pending_probes.acquire(addr.ip()) {
+            log::debug!("等待延迟响应的NAT探测过多，丢弃来自 {} 的探测请求", addr);
+            return;
+        }
+        if !self.clients.iter().any(|client| client.key().ip() == addr.ip()) {
+            pending_probes.release(addr.ip());
+            log::debug!("忽略未连接的地址 {} 的延迟NAT探测请求", addr);
+            return;
+        }
+
+        let delay = Duration::from_secs(delay_secs.min(MAX_PROBE_DELAY) as u64);
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            send_probe_reply(&socket, addr, id, alt_port).await;
+            pending_probes.release(addr.ip());
+        });
+    }
+
+    /// 启动NAT探测辅助端口
+    ///
+    /// 该端口只响应NAT探测请求，不处理其他消息
+    async fn spawn_probe_listener(&self, probe_addr: SocketAddr) -> Result<()> {
+        let listen_addr = ListenAddr::new(TransportKind::Udp, probe_addr);
+        let socket = ServerTransport::bind(&[listen_addr], None, self.obfs.clone(), 1, None).await.map_err(|e| {
+            log::error!("绑定NAT探测套接字失败 {}", e);
+            VswitchError::IoError(e)
+        })?;
+        let socket = Arc::new(socket);
+
+        log::info!("NAT探测辅助端口监听: {}", probe_addr);
+
+        tokio::spawn(async move {
+            loop {
+                match socket.recv_udp(0).await {
+                    Ok((mut datagram, addr)) => {
+                        let size = datagram.len();
+                        let probe = Message::decode(&mut datagram)
+                            .ok()
+                            .filter(|message| message.msg_type == MessageType::NatProbe)
+                            .and_then(|message| message.parse_nat_probe().ok());
+
+                        // 延迟探测只发往主端口，辅助端口只立即响应
+                        match probe {
+                            Some((id, 0)) => {
+                                send_probe_reply(&socket, addr, id, 0).await;
+                            }
+                            Some((id, delay_secs)) => {
+                                log::debug!("NAT探测端口忽略延迟 {} 秒的探测请求 (序号: {}) from {}", delay_secs, id, addr);
+                            }
                             None => {
-                                log::debug!("无法从数据包解析目标IP, 数据包被丢弃");
+                                log::debug!("NAT探测端口收到无效消息: {} bytes from {}", size, addr);
                             }
                         }
                     }
                     Err(e) => {
-                        log::error!("从TUN设备读取错误: {}", e);
-                        time::sleep(Duration::from_secs(1)).await;
+                        log::error!("NAT探测端口接收错误: {}", e);
+                        time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// 启动前向纠错校验分片发送任务
+    ///
+    /// 客户端的分组在超时前没有凑满时，由该任务发出已有数据包的校验分片
+    fn spawn_fec_flusher(&self, socket: Arc<ServerTransport>) {
+        let clients = self.clients.clone();
+        let notify = self.fec_notify.clone();
+
+        tokio::spawn(async move {
+            loop {
+                // 各分组的超时时间相同，等待期间新开始的分组不会更早超时
+                let deadline = clients.iter()
+                    .filter_map(|client| client.fec_encoder.as_ref().and_then(|encoder| encoder.deadline()))
+                    .min();
+                match deadline {
+                    Some(deadline) => time::sleep_until(deadline).await,
+                    None => {
+                        notify.notified().await;
+                        continue;
+                    }
+                }
+
+                let flushed: Vec<(SocketAddr, Vec<Bytes>)> = clients.iter_mut()
+                    .filter_map(|mut client| {
+                        let addr = *client.key();
+                        client.fec_encoder.as_mut().map(|encoder| (addr, encoder.flush_expired()))
+                    })
+                    .filter(|(_, messages)| !messages.is_empty())
+                    .collect();
+                for (addr, messages) in flushed {
+                    for message in messages {
+                        if let Err(e) = socket.send_to(&message, addr).await {
+                            log::debug!("向客户端 {} 发送前向纠错校验分片错误: {}", addr, e);
+                        }
                     }
                 }
             }
@@ -261,7 +932,9 @@ impl Server {
     }
 
     /// 启动心跳检测任务
-    fn spawn_heartbeat_checker(&self) {
+    ///
+    /// 同时清理长时间没有收到数据的回复路径
+    fn spawn_heartbeat_checker(&self, socket: Arc<ServerTransport>) {
         let clients = self.clients.clone();
         let ip_to_addr = self.ip_to_addr.clone();
         
@@ -275,49 +948,93 @@ impl Server {
                 // 等待检查间隔
                 time::sleep(heartbeat_interval).await;
                 let now = current_time_millis();
+                socket.expire_paths(Duration::from_millis(heartbeat_timeout));
                 
-                let mut clients_to_remove = Vec::new();
-                let mut ips_to_remove = Vec::new();
+                let mut removed = Vec::new();
                 
-                // 识别超时的客户端
-                {
-                    let clients_guard = clients.lock().await;
-                    
-                    for (addr, client) in clients_guard.iter() {
-                        // 如果超过超时时间没有心跳，认为客户端离线
-                        let time_since_last_heartbeat = now - client.last_heartbeat;
-                        if time_since_last_heartbeat > heartbeat_timeout && client.last_heartbeat > 0 {
-                            log::info!("客户端 {} 心跳超时 ({} ms)", addr, time_since_last_heartbeat);
-                            clients_to_remove.push(*addr);
-                            if let Some(ip) = client.ip_addr {
-                                ips_to_remove.push(ip);
-                            }
-                        }
+                // 识别并移除超时的客户端，检查时刚收到数据的客户端不会被误删
+                clients.retain(|addr, client| {
+                    // 如果超过超时时间没有心跳，认为客户端离线
+                    let last_heartbeat = client.last_heartbeat.load(Ordering::Relaxed);
+                    let time_since_last_heartbeat = now.saturating_sub(last_heartbeat);
+                    if time_since_last_heartbeat > heartbeat_timeout && last_heartbeat > 0 {
+                        log::info!("客户端 {} 心跳超时 ({} ms)", addr, time_since_last_heartbeat);
+                        removed.push((*addr, client.ip_addr));
+                        return false;
                     }
-                }
+                    true
+                });
                 
-                // 移除超时的客户端
-                if !clients_to_remove.is_empty() {
-                    let mut clients_guard = clients.lock().await;
-                    let mut ip_map = ip_to_addr.lock().await;
-                    
-                    for addr in &clients_to_remove {
-                        clients_guard.remove(addr);
-                        log::info!("移除超时客户端: {}, 剩余客户端: {}", addr, clients_guard.len());
-                    }
-                    
-                    for ip in &ips_to_remove {
-                        ip_map.remove(ip);
-                        log::info!("移除IP映射: {}", ip);
+                if !removed.is_empty() {
+                    for (addr, ip) in &removed {
+                        log::info!("移除超时客户端: {}, 剩余客户端: {}", addr, clients.len());
+                        
+                        // IP地址已经移动到其他客户端时保留映射
+                        if let Some(ip) = ip {
+                            if ip_to_addr.remove_if(ip, |_, mapped| mapped == addr).is_some() {
+                                log::info!("移除IP映射: {}", ip);
+                            }
+                        }
                     }
                     
-                    log::info!("心跳检测: 移除了 {} 个离线客户端", clients_to_remove.len());
+                    log::info!("心跳检测: 移除了 {} 个离线客户端", removed.len());
                 }
             }
         });
     }
 }
 
+/// 立即发送NAT探测响应
+async fn send_probe_reply(socket: &ServerTransport, addr: SocketAddr, id: u32, alt_port: u16) {
+    let reply = Message::nat_probe_reply(id, addr, alt_port).encode();
+    if let Err(e) = socket.send_to(&reply, addr).await {
+        log::error!("发送NAT探测响应错误 -> {}: {}", addr, e);
+    }
+}
+
+/// 向客户端发送一个数据包
+async fn send_data(
+    socket: &ServerTransport,
+    clients: &DashMap<SocketAddr, Client>,
+    fec_notify: &Notify,
+    addr: SocketAddr,
+    packet: &[u8],
+) -> std::io::Result<()> {
+    let mut messages = Vec::new();
+    encode_data(clients, fec_notify, addr, PacketBuf::copy_from_slice(packet), &mut messages);
+    for (message, addr) in messages {
+        socket.send_to(&message, addr).await?;
+    }
+    Ok(())
+}
+
+/// 将发往客户端的数据包编码为消息
+///
+/// 客户端启用前向纠错时编码为数据分片，分组凑满时附带校验分片。
+/// 未启用前向纠错的客户端只读取客户端表，消息头写入数据包的预留空间
+///
+/// 参数:
+/// - `addr`: 客户端地址
+/// - `packet`: 数据包
+/// - `out`: 编码后的消息及目标地址追加到其中
+fn encode_data(
+    clients: &DashMap<SocketAddr, Client>,
+    fec_notify: &Notify,
+    addr: SocketAddr,
+    packet: PacketBuf,
+    out: &mut Vec<(Bytes, SocketAddr)>,
+) {
+    let fec = clients.get(&addr).is_some_and(|client| client.fec_encoder.is_some());
+    if fec {
+        if let Some(encoder) = clients.get_mut(&addr).as_mut().and_then(|client| client.fec_encoder.as_mut()) {
+            out.extend(encoder.encode(packet.into_bytes()).into_iter().map(|message| (message, addr)));
+            fec_notify.notify_one();
+            return;
+        }
+    }
+    out.push((Message::encode_packet(packet), addr));
+}
+
 /// 获取当前时间戳（毫秒）
 fn current_time_millis() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
@@ -328,46 +1045,46 @@ fn current_time_millis() -> u64 {
         .as_millis() as u64
 }
 
-/// 提取IP数据包的源IP地址（全局函数）
-fn extract_src_ip(packet: &bytes::Bytes) -> Option<IpAddr> {
-    // 检查是否为IPv4数据包
-    if packet.len() >= 20 && (packet[0] >> 4) == 4 {
-        // IPv4: 源地址从12字节开始，长度4字节
-        let src_ip = std::net::Ipv4Addr::new(
-            packet[12], packet[13], packet[14], packet[15]
-        );
-        return Some(IpAddr::V4(src_ip));
-    } 
-    // 检查是否为IPv6数据包
-    else if packet.len() >= 40 && (packet[0] >> 4) == 6 {
-        // IPv6: 源地址从8字节开始，长度16字节
-        let mut src_ip_bytes = [0u8; 16];
-        src_ip_bytes.copy_from_slice(&packet[8..24]);
-        let src_ip = std::net::Ipv6Addr::from(src_ip_bytes);
-        return Some(IpAddr::V6(src_ip));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        Client::new("192.0.2.1:40000".parse().unwrap())
     }
-    
-    None
-}
 
-/// 提取IP数据包的目标IP地址（全局函数）
-fn extract_dst_ip(packet: &bytes::Bytes) -> Option<IpAddr> {
-    // 检查是否为IPv4数据包
-    if packet.len() >= 20 && (packet[0] >> 4) == 4 {
-        // IPv4: 目标地址从16字节开始，长度4字节
-        let dst_ip = std::net::Ipv4Addr::new(
-            packet[16], packet[17], packet[18], packet[19]
-        );
-        return Some(IpAddr::V4(dst_ip));
-    } 
-    // 检查是否为IPv6数据包
-    else if packet.len() >= 40 && (packet[0] >> 4) == 6 {
-        // IPv6: 目标地址从24字节开始，长度16字节
-        let mut dst_ip_bytes = [0u8; 16];
-        dst_ip_bytes.copy_from_slice(&packet[24..40]);
-        let dst_ip = std::net::Ipv6Addr::from(dst_ip_bytes);
-        return Some(IpAddr::V6(dst_ip));
+    #[test]
+    fn limits_introductions_per_window() {
+        let mut client = client();
+        let start = 1_000_000;
+        for _ in 0..MAX_INTRODUCTIONS {
+            assert!(client.allow_introduction(start));
+        }
+        assert!(!client.allow_introduction(start + INTRODUCTION_WINDOW_MS - 1));
+        assert!(client.allow_introduction(start + INTRODUCTION_WINDOW_MS));
     }
-    
-    None
-} 
\ No newline at end of file
+
+    #[test]
+    fn limits_pending_probes() {
+        let probes = PendingProbes::default();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        for _ in 0..MAX_PENDING_PROBES_PER_IP {
+            assert!(probes.acquire(ip));
+        }
+        assert!(!probes.acquire(ip));
+        probes.release(ip);
+        assert!(probes.acquire(ip));
+
+        // 总数达到上限后其他来源也被拒绝
+        let mut others = (1..=u32::MAX).map(|i| IpAddr::from(std::net::Ipv4Addr::from(0x0a00_0000 + i)));
+        while probes.total.load(Ordering::Relaxed) < MAX_PENDING_PROBES {
+            assert!(probes.acquire(others.next().unwrap()));
+        }
+        assert!(!probes.acquire(others.next().unwrap()));
+
+        for _ in 0..MAX_PENDING_PROBES_PER_IP {
+            probes.release(ip);
+        }
+        assert!(!probes.per_ip.contains_key(&ip));
+    }
+}
@@ -0,0 +1,216 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use quinn::Connection;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use crate::protocol::{HEADER_LEN, MAX_PAYLOAD_LEN};
+
+/// 每个流连接的发送队列长度
+pub const SEND_QUEUE_LEN: usize = 256;
+
+/// 从流中读取一个完整的消息帧 (消息头 + 负载)
+///
+/// 消息格式本身带有长度字段，直接作为流上的分帧依据；不完整的读取会继续等待剩余数据。
+///
+/// 返回:
+/// - 成功: 消息帧，连接在消息边界处正常关闭时返回 `None`
+/// - 错误: 读取错误、连接在消息中途断开或消息长度超过上限
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Bytes>> {
+    let mut header = [0u8; HEADER_LEN];
+
+    // 区分在消息边界处正常关闭和在消息中途断开
+    match reader.read_exact(&mut header[..1]).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    reader.read_exact(&mut header[1..]).await?;
+
+    let payload_len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if payload_len > MAX_PAYLOAD_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("消息过长: {} bytes", payload_len)));
+    }
+
+    let mut frame = BytesMut::zeroed(HEADER_LEN + payload_len);
+    frame[..HEADER_LEN].copy_from_slice(&header);
+    reader.read_exact(&mut frame[HEADER_LEN..]).await?;
+
+    Ok(Some(frame.freeze()))
+}
+
+/// 检查自带边界的消息 (如WebSocket消息、QUIC数据报) 是否恰好是一个完整的消息帧
+pub fn check_frame(data: &[u8]) -> io::Result<()> {
+    let complete = data.len() >= HEADER_LEN
+        && u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize == data.len() - HEADER_LEN;
+    if !complete {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("不完整的消息: {} bytes", data.len())));
+    }
+    Ok(())
+}
+
+/// 将字节流转换为消息帧流
+///
+/// 连接正常关闭时流结束；读取出错时产生错误后结束
+pub fn byte_frames<R>(reader: R) -> impl Stream<Item = io::Result<Bytes>> + Send + Unpin
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    Box::pin(futures::stream::unfold(Some(BufReader::new(reader)), |reader| async move {
+        let mut reader = reader?;
+        match read_frame(&mut reader).await {
+            Ok(Some(frame)) => Some((Ok(frame), Some(reader))),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    }))
+}
+
+/// 启动流连接的写任务
+///
+/// 发送队列中积压的消息帧会合并写入后再刷新，减少系统调用；
+/// 写入速度跟不上时队列填满，由发送方决定等待还是丢弃。
+/// 所有发送端都被释放后关闭写方向
+pub fn spawn_writer<W>(writer: W, peer: SocketAddr) -> (mpsc::Sender<Bytes>, JoinHandle<()>)
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::channel::<Bytes>(SEND_QUEUE_LEN);
+
+    let task = tokio::spawn(async move {
+        let mut writer = BufWriter::new(writer);
+
+        while let Some(frame) = rx.recv().await {
+            let mut result = writer.write_all(&frame).await;
+            while result.is_ok() {
+                match rx.try_recv() {
+                    Ok(frame) => result = writer.write_all(&frame).await,
+                    Err(_) => break,
+                }
+            }
+
+            if let Err(e) = result.and(writer.flush().await) {
+                log::debug!("向 {} 写入流连接错误: {}", peer, e);
+                return;
+            }
+        }
+
+        let _ = writer.shutdown().await;
+    });
+
+    (tx, task)
+}
+
+/// 客户端到服务器的流连接
+///
+/// 收到的消息帧转发到接收队列；连接断开后 [`StreamConnection::is_closed`] 返回 `true`。
+/// 释放时立即关闭连接，正在等待发送队列的发送方会收到错误
+pub struct StreamConnection {
+    /// 发送队列
+    tx: mpsc::Sender<Bytes>,
+    /// 连接是否已断开
+    closed: Arc<AtomicBool>,
+    /// 读任务
+    reader: JoinHandle<()>,
+    /// 写任务
+    writer: JoinHandle<()>,
+    /// 可发送数据报的QUIC连接
+    datagrams: Option<Connection>,
+}
+
+impl StreamConnection {
+    /// 在已建立的字节流上启动读写任务
+    ///
+    /// 参数:
+    /// - `stream`: 已建立的流
+    /// - `peer`: 服务器地址
+    /// - `inbound`: 接收队列，收到的消息帧连同服务器地址一起放入
+    pub fn spawn<S>(stream: S, peer: SocketAddr, inbound: mpsc::Sender<(SocketAddr, Bytes)>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let (tx, writer) = spawn_writer(writer, peer);
+        Self::from_parts(tx, writer, byte_frames(reader), peer, inbound)
+    }
+
+    /// 由已启动的写任务和消息帧流创建连接，并启动读任务
+    ///
+    /// 参数:
+    /// - `tx`: 写任务的发送队列
+    /// - `writer`: 写任务
+    /// - `frames`: 消息帧流
+    /// - `peer`: 服务器地址
+    /// - `inbound`: 接收队列
+    pub fn from_parts<F>(
+        tx: mpsc::Sender<Bytes>,
+        writer: JoinHandle<()>,
+        mut frames: F,
+        peer: SocketAddr,
+        inbound: mpsc::Sender<(SocketAddr, Bytes)>,
+    ) -> Self
+    where
+        F: Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+    {
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let reader_closed = closed.clone();
+        let reader = tokio::spawn(async move {
+            loop {
+                match frames.next().await {
+                    Some(Ok(frame)) => {
+                        if inbound.send((peer, frame)).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => {
+                        log::info!("服务器 {} 关闭了流连接", peer);
+                        break;
+                    }
+                    Some(Err(e)) => {
+                        log::warn!("读取服务器 {} 的流连接错误: {}", peer, e);
+                        break;
+                    }
+                }
+            }
+            reader_closed.store(true, Ordering::Relaxed);
+        });
+
+        Self { tx, closed, reader, writer, datagrams: None }
+    }
+
+    /// 设置可发送数据报的QUIC连接，释放时关闭该连接
+    pub fn with_datagrams(mut self, connection: Connection) -> Self {
+        self.datagrams = Some(connection);
+        self
+    }
+
+    /// 获取可发送数据报的QUIC连接
+    pub fn datagrams(&self) -> Option<Connection> {
+        self.datagrams.clone()
+    }
+
+    /// 检查连接是否已断开
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed) || self.tx.is_closed()
+    }
+
+    /// 获取发送队列，发送方可在不持有连接的情况下等待队列空闲
+    pub fn sender(&self) -> mpsc::Sender<Bytes> {
+        self.tx.clone()
+    }
+}
+
+impl Drop for StreamConnection {
+    fn drop(&mut self) {
+        self.reader.abort();
+        self.writer.abort();
+        if let Some(connection) = &self.datagrams {
+            connection.close(0u32.into(), b"");
+        }
+    }
+}
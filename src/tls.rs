@@ -0,0 +1,248 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+use sha2::{Digest, Sha256};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+use crate::error::{Result, VswitchError};
+
+/// 服务端TLS配置
+#[derive(Debug, Clone)]
+pub struct TlsServerOptions {
+    /// 证书链文件 (PEM)
+    pub cert: PathBuf,
+    /// 私钥文件 (PEM)
+    pub key: PathBuf,
+    /// 签发客户端证书的CA文件 (PEM)，指定时要求客户端出示由其签发的证书
+    pub client_ca: Option<PathBuf>,
+}
+
+/// 客户端TLS配置
+#[derive(Debug, Clone, Default)]
+pub struct TlsClientOptions {
+    /// 用于校验服务端证书的CA文件 (PEM)，不指定时使用内置的公共根证书
+    pub ca: Option<PathBuf>,
+    /// 固定的服务端证书指纹，指定时只接受指纹匹配的证书
+    pub pins: Vec<Fingerprint>,
+    /// 客户端证书链文件 (PEM)
+    pub cert: Option<PathBuf>,
+    /// 客户端私钥文件 (PEM)
+    pub key: Option<PathBuf>,
+}
+
+/// 证书的 SHA-256 指纹
+///
+/// 格式为64位十六进制数，字节之间可以用冒号分隔 (与 `openssl x509 -fingerprint -sha256` 的输出相同)
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// 计算证书的指纹
+    pub fn of(cert: &CertificateDer<'_>) -> Self {
+        Self(Sha256::digest(cert.as_ref()).into())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = VswitchError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let hex: String = s.chars().filter(|c| *c != ':').collect();
+        let invalid = || VswitchError::ConfigError(format!("无效的证书指纹: {}", s));
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+
+        let mut bytes = [0u8; 32];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// 创建服务端TLS配置
+pub fn server_config(options: &TlsServerOptions) -> Result<Arc<ServerConfig>> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &options.client_ca {
+        Some(path) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(load_roots(path)?), provider)
+                .build()
+                .map_err(|e| VswitchError::ConfigError(format!("无效的客户端CA {}: {}", path.display(), e)))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = builder.with_single_cert(load_certs(&options.cert)?, load_key(&options.key)?)?;
+    Ok(Arc::new(config))
+}
+
+/// 创建客户端TLS配置
+///
+/// 同时指定CA和证书指纹时，服务端证书需要同时通过两种校验
+pub fn client_config(options: &TlsClientOptions) -> Result<Arc<ClientConfig>> {
+    let provider = Arc::new(crypto::ring::default_provider());
+
+    let roots = match &options.ca {
+        Some(path) => load_roots(path)?,
+        None => RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() },
+    };
+    let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| VswitchError::ConfigError(format!("无效的CA证书: {}", e)))?;
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = if options.pins.is_empty() {
+        builder.with_webpki_verifier(webpki)
+    } else {
+        let verifier = PinnedVerifier {
+            pins: options.pins.clone(),
+            // 只指定证书指纹时不校验证书链和主机名，便于使用自签名证书
+            webpki: options.ca.as_ref().map(|_| webpki),
+            provider,
+        };
+        builder.dangerous().with_custom_certificate_verifier(Arc::new(verifier))
+    };
+
+    let config = match (&options.cert, &options.key) {
+        (Some(cert), Some(key)) => builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?,
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err(VswitchError::ConfigError("客户端证书和私钥需要同时指定".to_string())),
+    };
+    Ok(Arc::new(config))
+}
+
+/// 获取对端证书中的身份，依次使用主题的通用名 (CN) 和第一个DNS备用名称
+pub fn peer_identity(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(cert.as_ref()).ok()?;
+
+    if let Some(cn) = cert.subject().iter_common_name().next().and_then(|cn| cn.as_str().ok()) {
+        return Some(cn.to_string());
+    }
+
+    let san = cert.subject_alternative_name().ok()??;
+    san.value.general_names.iter().find_map(|name| match name {
+        GeneralName::DNSName(dns) => Some(dns.to_string()),
+        _ => None,
+    })
+}
+
+/// 将主机名转换为TLS服务器名称
+pub fn server_name(host: &str) -> Result<ServerName<'static>> {
+    ServerName::try_from(host.to_string())
+        .map_err(|e| VswitchError::ConfigError(format!("无效的TLS服务器名称 {}: {}", host, e)))
+}
+
+/// 读取PEM格式的证书链
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| VswitchError::ConfigError(format!("读取证书 {} 失败: {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(VswitchError::ConfigError(format!("证书文件 {} 中没有证书", path.display())));
+    }
+    Ok(certs)
+}
+
+/// 读取PEM格式的私钥
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| VswitchError::ConfigError(format!("读取私钥 {} 失败: {}", path.display(), e)))
+}
+
+/// 读取PEM格式的CA证书
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let (_, ignored) = roots.add_parsable_certificates(load_certs(path)?);
+    if ignored > 0 {
+        log::warn!("CA文件 {} 中有 {} 个证书无法解析，已忽略", path.display(), ignored);
+    }
+    Ok(roots)
+}
+
+/// 按证书指纹校验服务端证书
+///
+/// 握手签名仍按正常流程校验，确保对端持有证书对应的私钥
+#[derive(Debug)]
+struct PinnedVerifier {
+    pins: Vec<Fingerprint>,
+    /// 同时指定CA时的证书链校验
+    webpki: Option<Arc<WebPkiServerVerifier>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Fingerprint::of(end_entity);
+        if !self.pins.contains(&fingerprint) {
+            log::warn!("服务端证书指纹不匹配: {}", fingerprint);
+            return Err(rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure));
+        }
+
+        match &self.webpki {
+            Some(webpki) => webpki.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now),
+            None => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
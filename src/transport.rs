@@ -0,0 +1,1140 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
+use quinn::{Connection, Endpoint};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::{self, Duration};
+use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use crate::error::{Result, VswitchError};
+use crate::multipath::{Multipath, MultipathOptions, SessionTable};
+use crate::nat;
+use crate::obfs::Obfuscator;
+use crate::protocol::{Message, MessageType};
+use crate::proxy::HttpProxy;
+use crate::quic;
+use crate::resolve::ServerSpec;
+use crate::socks::{Socks5Proxy, UdpAssociation};
+use crate::stream::{byte_frames, spawn_writer, StreamConnection};
+use crate::tls;
+use crate::udp::{DualStackSocket, MultiSocket};
+use crate::websocket;
+
+/// 建立流连接的超时时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// 完成TLS或WebSocket握手的超时时间
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// 流连接接收队列长度，队列满时暂停读取，由传输层的流量控制反压发送方
+const INBOUND_QUEUE_LEN: usize = 1024;
+
+/// 服务端的一个流连接
+struct StreamPeer {
+    /// 发送队列
+    tx: mpsc::Sender<Bytes>,
+    /// 客户端证书中的身份，未使用客户端证书时为 `None`
+    identity: Option<String>,
+    /// QUIC连接，数据消息以数据报发送
+    datagrams: Option<Connection>,
+}
+
+/// 流连接表 (对端地址 -> 流连接)，发送每条消息都要查询，使用分片的并发哈希表
+type StreamTable = Arc<DashMap<SocketAddr, StreamPeer>>;
+
+/// 传输方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    /// UDP数据报
+    Udp,
+    /// TCP流，消息按自身的长度字段分帧
+    Tcp,
+    /// WebSocket，每个二进制消息承载一个协议消息
+    Ws,
+    /// TLS加密的TCP流
+    Tls,
+    /// TLS加密的WebSocket
+    Wss,
+    /// QUIC，数据消息使用不可靠的数据报，其余消息使用可靠流
+    Quic,
+}
+
+impl TransportKind {
+    /// 拆分地址中的传输方式前缀 (如 `tcp://`)，没有前缀时为UDP
+    fn split_scheme(s: &str) -> Result<(Self, &str)> {
+        match s.split_once("://") {
+            None => Ok((TransportKind::Udp, s)),
+            Some(("udp", rest)) => Ok((TransportKind::Udp, rest)),
+            Some(("tcp", rest)) => Ok((TransportKind::Tcp, rest)),
+            Some(("ws", rest)) => Ok((TransportKind::Ws, rest)),
+            Some(("tls", rest)) => Ok((TransportKind::Tls, rest)),
+            Some(("wss", rest)) => Ok((TransportKind::Wss, rest)),
+            Some(("quic", rest)) => Ok((TransportKind::Quic, rest)),
+            Some((scheme, _)) => Err(VswitchError::ConfigError(format!("不支持的传输方式: {}", scheme))),
+        }
+    }
+
+    /// 是否使用TLS加密
+    pub fn is_secure(self) -> bool {
+        matches!(self, TransportKind::Tls | TransportKind::Wss | TransportKind::Quic)
+    }
+
+    /// 是否以不可靠的数据报承载数据消息，流传输方式本身可靠，不需要前向纠错
+    pub fn is_datagram(self) -> bool {
+        matches!(self, TransportKind::Udp | TransportKind::Quic)
+    }
+
+    /// 是否使用WebSocket
+    fn is_websocket(self) -> bool {
+        matches!(self, TransportKind::Ws | TransportKind::Wss)
+    }
+}
+
+impl fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransportKind::Udp => "udp",
+            TransportKind::Tcp => "tcp",
+            TransportKind::Ws => "ws",
+            TransportKind::Tls => "tls",
+            TransportKind::Wss => "wss",
+            TransportKind::Quic => "quic",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 拆分WebSocket地址中的路径，没有路径时为 `/`；其他传输方式不允许带路径
+fn split_path(transport: TransportKind, s: &str) -> Result<(&str, String)> {
+    match (transport.is_websocket(), s.find('/')) {
+        (true, Some(pos)) => Ok((&s[..pos], s[pos..].to_string())),
+        (false, Some(_)) => Err(VswitchError::ConfigError(format!("只有WebSocket地址可以带路径: {}", s))),
+        (_, None) => Ok((s, "/".to_string())),
+    }
+}
+
+/// 服务端监听地址，格式为 `[传输方式://]IP:端口[/路径]`，路径仅用于WebSocket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenAddr {
+    pub transport: TransportKind,
+    pub addr: SocketAddr,
+    /// WebSocket请求路径
+    pub path: String,
+}
+
+impl ListenAddr {
+    /// 创建不带路径的监听地址
+    pub fn new(transport: TransportKind, addr: SocketAddr) -> Self {
+        Self { transport, addr, path: "/".to_string() }
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = VswitchError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (transport, rest) = TransportKind::split_scheme(s)?;
+        let (addr, path) = split_path(transport, rest)?;
+        let addr = addr.parse()
+            .map_err(|e| VswitchError::ConfigError(format!("无效的监听地址 {}: {}", s, e)))?;
+        Ok(Self { transport, addr, path })
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.transport.is_websocket() {
+            write!(f, "{}://{}{}", self.transport, self.addr, self.path)
+        } else {
+            write!(f, "{}://{}", self.transport, self.addr)
+        }
+    }
+}
+
+/// 客户端使用的服务器地址，格式为 `[传输方式://]服务器地址[/路径]`，路径仅用于WebSocket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerEndpoint {
+    pub transport: TransportKind,
+    pub spec: ServerSpec,
+    /// WebSocket请求路径
+    pub path: String,
+}
+
+impl ServerEndpoint {
+    /// WebSocket握手请求中的主机名，配置为域名时使用域名，否则使用解析后的地址
+    fn host_header(&self, addr: SocketAddr) -> String {
+        match &self.spec {
+            ServerSpec::Host(host, port) => format!("{}:{}", host, port),
+            _ => addr.to_string(),
+        }
+    }
+
+    /// TLS握手使用的服务器名称，配置为域名时校验域名，否则校验解析后的IP
+    fn tls_server_name(&self, addr: SocketAddr) -> io::Result<ServerName<'static>> {
+        match &self.spec {
+            ServerSpec::Host(host, _) => tls::server_name(host)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string())),
+            _ => Ok(ServerName::IpAddress(addr.ip().into())),
+        }
+    }
+}
+
+impl FromStr for ServerEndpoint {
+    type Err = VswitchError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (transport, rest) = TransportKind::split_scheme(s)?;
+        let (spec, path) = split_path(transport, rest)?;
+        Ok(Self { transport, spec: spec.parse()?, path })
+    }
+}
+
+impl fmt::Display for ServerEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.transport.is_websocket() {
+            write!(f, "{}://{}{}", self.transport, self.spec, self.path)
+        } else {
+            write!(f, "{}://{}", self.transport, self.spec)
+        }
+    }
+}
+
+/// 服务端传输层
+///
+/// 同时提供UDP和流传输方式的监听。所有流连接收到的消息帧汇总到同一个接收队列，
+/// 发送时按目标地址选择对应的流连接，其余地址使用UDP
+pub struct ServerTransport {
+    /// UDP监听套接字
+    udp: MultiSocket,
+    /// 流传输方式实际监听的地址
+    stream_addrs: Vec<ListenAddr>,
+    /// 已建立的流连接
+    streams: StreamTable,
+    /// 流连接的接收队列
+    inbound: Mutex<mpsc::Receiver<(SocketAddr, Bytes)>>,
+    /// UDP数据报混淆
+    obfs: Option<Arc<Obfuscator>>,
+    /// 多路径会话
+    sessions: SessionTable,
+    /// 已按序合并、等待交付的多路径消息
+    ready: Mutex<VecDeque<(SocketAddr, Bytes)>>,
+}
+
+impl ServerTransport {
+    /// 绑定所有监听地址
+    ///
+    /// 参数:
+    /// - `addrs`: 监听地址列表
+    /// - `tls`: TLS配置，监听地址中有加密的传输方式时必须提供
+    /// - `obfs`: UDP数据报混淆，流传输方式不使用
+    /// - `reuseport`: 每个UDP监听地址绑定的套接字数，大于1时以 `SO_REUSEPORT` 绑定
+    /// - `xdp`: 以 AF_XDP 截取UDP监听端口数据报的网络接口
+    pub async fn bind(
+        addrs: &[ListenAddr],
+        tls: Option<Arc<ServerConfig>>,
+        obfs: Option<Arc<Obfuscator>>,
+        reuseport: usize,
+        xdp: Option<&str>,
+    ) -> io::Result<Self> {
+        let udp_addrs: Vec<SocketAddr> = addrs.iter()
+            .filter(|listen| listen.transport == TransportKind::Udp)
+            .map(|listen| listen.addr)
+            .collect();
+        let udp = MultiSocket::bind(&udp_addrs, reuseport, xdp)?;
+
+        let streams: StreamTable = Arc::new(DashMap::new());
+        let (inbound_tx, inbound_rx) = mpsc::channel(INBOUND_QUEUE_LEN);
+
+        let mut stream_addrs = Vec::new();
+        for listen in addrs.iter().filter(|listen| listen.transport != TransportKind::Udp) {
+            let tls = match (listen.transport.is_secure(), &tls) {
+                (true, Some(config)) => Some(config),
+                (true, None) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: 未配置TLS证书", listen)));
+                }
+                (false, _) => None,
+            };
+
+            if let (TransportKind::Quic, Some(tls)) = (listen.transport, tls) {
+                let endpoint = quic::server_endpoint(listen.addr, tls)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", listen, e)))?;
+                stream_addrs.push(ListenAddr {
+                    addr: endpoint.local_addr()?,
+                    ..listen.clone()
+                });
+                spawn_quic_acceptor(endpoint, streams.clone(), inbound_tx.clone());
+                continue;
+            }
+
+            let acceptor = tls.map(|config| TlsAcceptor::from(config.clone()));
+            let listener = TcpListener::bind(listen.addr).await
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", listen, e)))?;
+            stream_addrs.push(ListenAddr {
+                addr: listener.local_addr()?,
+                ..listen.clone()
+            });
+            spawn_acceptor(listener, listen.clone(), acceptor, streams.clone(), inbound_tx.clone());
+        }
+
+        Ok(Self {
+            udp,
+            stream_addrs,
+            streams,
+            inbound: Mutex::new(inbound_rx),
+            obfs,
+            sessions: SessionTable::new(),
+            ready: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// 获取UDP监听套接字的数量
+    pub fn udp_listener_count(&self) -> usize {
+        self.udp.listener_count()
+    }
+
+    /// 从指定的UDP监听套接字接收消息
+    ///
+    /// 启用了混淆时丢弃无法还原的数据报。多路径消息合并后以会话的标识地址交付，
+    /// 路径探测请求直接在原路径上响应。返回的消息是接收缓冲区的切片
+    pub async fn recv_udp(&self, listener: usize) -> io::Result<(Bytes, SocketAddr)> {
+        loop {
+            if let Some((addr, message)) = self.ready.lock().await.pop_front() {
+                return Ok((message, addr));
+            }
+
+            // 有等待中的乱序消息时，超时后交付
+            let deadline = self.sessions.next_deadline();
+            let (mut buf, addr) = tokio::select! {
+                result = self.udp.recv_from(listener) => result?,
+                _ = time::sleep_until(deadline.unwrap_or_else(time::Instant::now)), if deadline.is_some() => {
+                    let flushed = self.sessions.flush_expired();
+                    self.ready.lock().await.extend(flushed);
+                    continue;
+                }
+            };
+
+            if let Some(obfs) = &self.obfs {
+                let len = buf.len();
+                match obfs.open(&mut buf, len) {
+                    Ok(len) => buf.truncate(len),
+                    Err(e) => {
+                        log::debug!("丢弃来自 {} 的数据报: {}", addr, e);
+                        continue;
+                    }
+                }
+            }
+
+            let mut buf = buf.freeze();
+            if buf.is_empty() || !matches!(MessageType::try_from(buf[0]), Ok(MessageType::Multipath | MessageType::PathProbe)) {
+                return Ok((buf, addr));
+            }
+            let message = match Message::decode(&mut buf) {
+                Ok(message) => message,
+                Err(e) => {
+                    log::debug!("丢弃来自 {} 的无效多路径消息: {}", addr, e);
+                    continue;
+                }
+            };
+
+            if message.msg_type == MessageType::PathProbe {
+                let reply = Message::path_probe_reply(&message).encode();
+                if let Err(e) = self.send_udp(&reply, addr).await {
+                    log::debug!("向 {} 发送路径探测响应失败: {}", addr, e);
+                }
+                continue;
+            }
+
+            match message.parse_multipath() {
+                Ok((session, seq, inner)) => {
+                    let (canonical, ready) = self.sessions.receive(addr, session, seq, inner);
+                    self.ready.lock().await.extend(ready.into_iter().map(|message| (canonical, message)));
+                }
+                Err(e) => log::debug!("丢弃来自 {} 的无效多路径消息: {}", addr, e),
+            }
+        }
+    }
+
+    /// 获取所有实际监听的地址
+    pub fn local_addrs(&self) -> Vec<ListenAddr> {
+        let udp = self.udp.local_addrs().into_iter()
+            .map(|addr| ListenAddr::new(TransportKind::Udp, addr));
+        udp.chain(self.stream_addrs.iter().cloned()).collect()
+    }
+
+    /// 检查地址是否为流连接的对端，流连接的地址不是UDP的NAT映射，无法用于打洞
+    pub fn is_stream(&self, addr: SocketAddr) -> bool {
+        self.streams.contains_key(&addr)
+    }
+
+    /// 获取流连接对端的客户端证书身份
+    pub async fn peer_identity(&self, addr: SocketAddr) -> Option<String> {
+        self.streams.get(&addr).and_then(|peer| peer.identity.clone())
+    }
+
+    /// 接收任一流连接上的下一个消息帧，没有流监听时返回 `None`
+    pub async fn recv_stream(&self) -> Option<(SocketAddr, Bytes)> {
+        self.inbound.lock().await.recv().await
+    }
+
+    /// 发送消息
+    ///
+    /// 目标为流连接时放入其发送队列；队列已满时丢弃该消息，与UDP丢包的行为一致，
+    /// 避免一个慢速客户端阻塞其他客户端。目标为QUIC连接时数据消息以数据报发送
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let stream = self.streams.get(&target).map(|peer| (peer.tx.clone(), peer.datagrams.clone()));
+
+        if let Some((_, Some(connection))) = &stream {
+            if quic::try_send_datagram(connection, buf)? {
+                return Ok(buf.len());
+            }
+        }
+
+        match stream {
+            Some((tx, _)) => match tx.try_send(Bytes::copy_from_slice(buf)) {
+                Ok(()) => Ok(buf.len()),
+                Err(TrySendError::Full(_)) => {
+                    log::debug!("流连接 {} 发送队列已满，丢弃消息", target);
+                    Ok(0)
+                }
+                Err(TrySendError::Closed(_)) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "流连接已断开")),
+            },
+            None => {
+                // 多路径会话的消息经最近收到消息的路径发送
+                let target = self.sessions.route(target).unwrap_or(target);
+                self.send_udp(buf, target).await
+            }
+        }
+    }
+
+    /// 按顺序发送多条消息
+    ///
+    /// 发往UDP客户端的消息以尽量少的系统调用批量发送，发往流连接的消息逐条发送。
+    /// 某条消息发送失败时继续发送其余消息，全部尝试后返回遇到的第一个错误
+    pub async fn send_batch(&self, messages: &[(Bytes, SocketAddr)]) -> io::Result<()> {
+        let streams: Vec<bool> = messages.iter().map(|(_, target)| self.streams.contains_key(target)).collect();
+
+        let mut result = Ok(());
+        let mut datagrams = Vec::with_capacity(messages.len());
+        for ((buf, target), stream) in messages.iter().zip(streams) {
+            if stream {
+                if let Err(e) = self.send_to(buf, *target).await {
+                    result = result.and(Err(e));
+                }
+                continue;
+            }
+
+            // 多路径会话的消息经最近收到消息的路径发送
+            let target = self.sessions.route(*target).unwrap_or(*target);
+            let datagram = match &self.obfs {
+                Some(obfs) => Bytes::from(obfs.seal(buf)),
+                None => buf.clone(),
+            };
+            datagrams.push((datagram, target));
+        }
+
+        if !datagrams.is_empty() {
+            let datagrams: Vec<_> = datagrams.iter().map(|(buf, target)| (&buf[..], *target)).collect();
+            if let Err(e) = self.udp.send_batch(&datagrams).await {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// 通过UDP发送消息，启用了混淆时先混淆
+    async fn send_udp(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        match &self.obfs {
+            Some(obfs) => {
+                self.udp.send_to(&obfs.seal(buf), target).await?;
+                Ok(buf.len())
+            }
+            None => self.udp.send_to(buf, target).await,
+        }
+    }
+
+    /// 删除超过指定时间没有收到数据的UDP回复路径和多路径会话
+    pub fn expire_paths(&self, idle: Duration) {
+        self.udp.expire_paths(idle);
+        self.sessions.expire(idle);
+    }
+}
+
+/// 启动TCP连接接受任务，按监听地址的传输方式处理新连接
+fn spawn_acceptor(
+    listener: TcpListener,
+    listen: ListenAddr,
+    acceptor: Option<TlsAcceptor>,
+    streams: StreamTable,
+    inbound: mpsc::Sender<(SocketAddr, Bytes)>,
+) {
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    if let Err(e) = stream.set_nodelay(true) {
+                        log::debug!("设置TCP_NODELAY失败 {}: {}", peer, e);
+                    }
+                    let listen = listen.clone();
+                    let acceptor = acceptor.clone();
+                    let streams = streams.clone();
+                    let inbound = inbound.clone();
+                    tokio::spawn(async move {
+                        match acceptor {
+                            Some(acceptor) => {
+                                let stream = match time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                                    Ok(Ok(stream)) => stream,
+                                    Ok(Err(e)) => {
+                                        log::debug!("与 {} 的TLS握手失败: {}", peer, e);
+                                        return;
+                                    }
+                                    Err(_) => {
+                                        log::debug!("与 {} 的TLS握手超时", peer);
+                                        return;
+                                    }
+                                };
+                                let identity = stream.get_ref().1.peer_certificates()
+                                    .and_then(|certs| certs.first())
+                                    .and_then(tls::peer_identity);
+                                serve_connection(stream, peer, &listen, identity, streams, inbound).await;
+                            }
+                            None => serve_connection(stream, peer, &listen, None, streams, inbound).await,
+                        }
+                    });
+                }
+                Err(e) => {
+                    log::error!("接受TCP连接错误: {}", e);
+                    time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    });
+}
+
+/// 启动QUIC连接接受任务
+///
+/// 每个连接由客户端打开的第一个双向流传输控制消息，数据消息使用数据报；
+/// 连接以建立时的客户端地址标识，客户端地址因连接迁移改变后仍使用原地址
+fn spawn_quic_acceptor(endpoint: Endpoint, streams: StreamTable, inbound: mpsc::Sender<(SocketAddr, Bytes)>) {
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            let streams = streams.clone();
+            let inbound = inbound.clone();
+            tokio::spawn(async move {
+                let peer = incoming.remote_address();
+                let accept = async {
+                    let connection = incoming.await?;
+                    let (send, recv) = connection.accept_bi().await?;
+                    Ok::<_, quinn::ConnectionError>((connection, send, recv))
+                };
+                let (connection, send, recv) = match time::timeout(HANDSHAKE_TIMEOUT, accept).await {
+                    Ok(Ok(accepted)) => accepted,
+                    Ok(Err(e)) => {
+                        log::debug!("与 {} 建立QUIC连接失败: {}", peer, e);
+                        return;
+                    }
+                    Err(_) => {
+                        log::debug!("与 {} 建立QUIC连接超时", peer);
+                        return;
+                    }
+                };
+
+                let identity = connection.peer_identity()
+                    .and_then(|certs| certs.downcast::<Vec<CertificateDer<'static>>>().ok())
+                    .and_then(|certs| certs.first().and_then(tls::peer_identity));
+                let (tx, _) = spawn_writer(send, peer);
+                let frames = futures::stream::select(byte_frames(recv), quic::datagrams(connection.clone()));
+                let stream_peer = StreamPeer { tx, identity, datagrams: Some(connection) };
+                serve_frames(peer, stream_peer, frames, streams, inbound).await;
+            });
+        }
+    });
+}
+
+/// 处理一个服务端流连接，直到连接断开
+///
+/// WebSocket握手失败或请求路径不匹配时关闭连接
+async fn serve_connection<S>(
+    stream: S,
+    peer: SocketAddr,
+    listen: &ListenAddr,
+    identity: Option<String>,
+    streams: StreamTable,
+    inbound: mpsc::Sender<(SocketAddr, Bytes)>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    if !listen.transport.is_websocket() {
+        let (reader, writer) = tokio::io::split(stream);
+        let (tx, _) = spawn_writer(writer, peer);
+        serve_frames(peer, StreamPeer { tx, identity, datagrams: None }, byte_frames(reader), streams, inbound).await;
+        return;
+    }
+
+    let ws = match time::timeout(HANDSHAKE_TIMEOUT, websocket::accept(stream, &listen.path)).await {
+        Ok(Ok(ws)) => ws,
+        Ok(Err(e)) => {
+            log::debug!("来自 {} 的WebSocket连接被拒绝: {}", peer, e);
+            return;
+        }
+        Err(_) => {
+            log::debug!("来自 {} 的WebSocket握手超时", peer);
+            return;
+        }
+    };
+
+    let (tx, _, frames) = websocket::split(ws, peer);
+    serve_frames(peer, StreamPeer { tx, identity, datagrams: None }, frames, streams, inbound).await;
+}
+
+/// 处理一个服务端流连接上的消息帧，直到连接断开
+///
+/// 收到的消息帧放入接收队列；连接在客户端未发送断开消息的情况下断开时，
+/// 代为投递一个断开消息，使服务端及时释放会话
+async fn serve_frames<F>(
+    peer: SocketAddr,
+    stream_peer: StreamPeer,
+    mut frames: F,
+    streams: StreamTable,
+    inbound: mpsc::Sender<(SocketAddr, Bytes)>,
+) where
+    F: Stream<Item = io::Result<Bytes>> + Unpin,
+{
+    match &stream_peer.identity {
+        Some(identity) => log::debug!("流连接建立: {} (证书身份: {})", peer, identity),
+        None => log::debug!("流连接建立: {}", peer),
+    }
+    streams.insert(peer, stream_peer);
+
+    let mut received = false;
+    let mut disconnected = false;
+
+    loop {
+        match frames.next().await {
+            Some(Ok(frame)) => {
+                received = true;
+                disconnected = frame[0] == MessageType::Disconnect as u8;
+                if inbound.send((peer, frame)).await.is_err() {
+                    break;
+                }
+            }
+            None => break,
+            Some(Err(e)) => {
+                log::warn!("读取 {} 的流连接错误: {}", peer, e);
+                break;
+            }
+        }
+    }
+
+    streams.remove(&peer);
+    log::debug!("流连接断开: {}", peer);
+
+    if received && !disconnected {
+        let _ = inbound.send((peer, Message::disconnect().encode())).await;
+    }
+}
+
+/// 到服务器的链路
+struct ServerLink {
+    /// 服务器地址
+    addr: SocketAddr,
+    /// 流连接，连接失败时为 `None`
+    connection: Option<StreamConnection>,
+}
+
+/// 客户端传输层
+///
+/// 与其他客户端之间始终使用UDP；与服务器之间按配置使用UDP或流连接
+/// 客户端传输层配置
+#[derive(Clone, Default)]
+pub struct ClientTransportOptions {
+    /// 建立流连接时使用的HTTP代理，UDP不经过代理
+    pub proxy: Option<HttpProxy>,
+    /// SOCKS5代理，流连接和UDP都经过代理；与 `proxy` 同时指定时流连接使用 `proxy`
+    pub socks5: Option<Socks5Proxy>,
+    /// TLS配置，服务器地址中有加密的传输方式时必须提供
+    pub tls: Option<Arc<ClientConfig>>,
+    /// UDP数据报混淆，流传输方式不使用
+    pub obfs: Option<Arc<Obfuscator>>,
+    /// 多路径配置，只用于UDP传输方式
+    pub multipath: Option<MultipathOptions>,
+}
+
+pub struct ClientTransport {
+    udp: DualStackSocket,
+    /// 建立流连接时使用的HTTP代理
+    proxy: Option<HttpProxy>,
+    /// SOCKS5代理，配置后流连接和UDP都经代理发送
+    socks5: Option<Socks5Proxy>,
+    /// SOCKS5代理的UDP转发关联
+    association: Mutex<Option<UdpAssociation>>,
+    /// SOCKS5代理是否不支持UDP转发，此时UDP服务器改用TCP连接
+    udp_unsupported: AtomicBool,
+    /// UDP数据报混淆
+    obfs: Option<Arc<Obfuscator>>,
+    /// 多路径发送
+    multipath: Option<Multipath>,
+    /// 加密传输方式使用的TLS配置
+    tls: Option<Arc<ClientConfig>>,
+    /// 使用流传输方式时到服务器的链路
+    link: Mutex<Option<ServerLink>>,
+    /// 流连接的接收队列
+    inbound_tx: mpsc::Sender<(SocketAddr, Bytes)>,
+    inbound_rx: Mutex<mpsc::Receiver<(SocketAddr, Bytes)>>,
+}
+
+impl ClientTransport {
+    /// 绑定客户端UDP套接字，配置了多路径时同时绑定各路径的套接字
+    pub fn bind(options: ClientTransportOptions) -> io::Result<Self> {
+        let (inbound_tx, inbound_rx) = mpsc::channel(INBOUND_QUEUE_LEN);
+        Ok(Self {
+            udp: DualStackSocket::bind()?,
+            proxy: options.proxy,
+            socks5: options.socks5,
+            association: Mutex::new(None),
+            udp_unsupported: AtomicBool::new(false),
+            obfs: options.obfs,
+            multipath: options.multipath.as_ref().map(Multipath::bind).transpose()?,
+            tls: options.tls,
+            link: Mutex::new(None),
+            inbound_tx,
+            inbound_rx: Mutex::new(inbound_rx),
+        })
+    }
+
+    /// 获取UDP套接字的本地地址
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.udp.local_addr()
+    }
+
+    /// 配置了SOCKS5代理时建立UDP转发关联，关联仍有效或未配置代理时直接返回
+    ///
+    /// 代理不支持UDP转发时记录下来，此后经代理以TCP连接UDP服务器的同一地址
+    pub async fn associate(&self) -> io::Result<()> {
+        let Some(socks5) = &self.socks5 else {
+            return Ok(());
+        };
+        if self.udp_unsupported.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "SOCKS5代理不支持UDP转发"));
+        }
+
+        let mut association = self.association.lock().await;
+        if association.as_ref().is_some_and(|association| !association.is_closed()) {
+            return Ok(());
+        }
+        *association = None;
+
+        match time::timeout(CONNECT_TIMEOUT, socks5.associate()).await {
+            Ok(Ok(created)) => {
+                *association = Some(created);
+                Ok(())
+            }
+            Ok(Err(e)) => {
+                if e.kind() == io::ErrorKind::Unsupported {
+                    log::warn!("SOCKS5代理 {} 不支持UDP转发，改用TCP连接UDP服务器", socks5);
+                    self.udp_unsupported.store(true, Ordering::Relaxed);
+                }
+                Err(e)
+            }
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "建立UDP转发超时")),
+        }
+    }
+
+    /// 检查能否使用服务器地址配置的传输方式
+    ///
+    /// 配置了SOCKS5代理时QUIC无法经过代理
+    pub fn supports(&self, endpoint: &ServerEndpoint) -> bool {
+        endpoint.transport != TransportKind::Quic || self.socks5.is_none()
+    }
+
+    /// 连接服务器实际使用的传输方式
+    ///
+    /// SOCKS5代理不支持UDP转发时，UDP服务器改为经代理以TCP连接同一地址
+    pub fn transport(&self, endpoint: &ServerEndpoint) -> TransportKind {
+        match endpoint.transport {
+            TransportKind::Udp if self.udp_unsupported.load(Ordering::Relaxed) => TransportKind::Tcp,
+            transport => transport,
+        }
+    }
+
+    /// 使用服务器地址配置的传输方式连接服务器，同时关闭到之前服务器的流连接
+    ///
+    /// UDP无需建立连接，配置了SOCKS5代理时确保UDP转发关联有效，代理不支持UDP转发时改用TCP连接同一地址。
+    /// 流连接建立失败时，发往该服务器的消息会返回错误，而不会改用UDP发送
+    ///
+    /// 参数:
+    /// - `endpoint`: 服务器地址配置，决定传输方式和WebSocket请求路径
+    /// - `server_addr`: 解析后的服务器地址
+    pub async fn connect(&self, endpoint: &ServerEndpoint, server_addr: SocketAddr) -> io::Result<()> {
+        let mut link = self.link.lock().await;
+        *link = None;
+
+        if let Some(multipath) = &self.multipath {
+            let server = (endpoint.transport == TransportKind::Udp).then_some(server_addr);
+            multipath.set_server(server).await;
+        }
+        if endpoint.transport == TransportKind::Udp {
+            match self.associate().await {
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {}
+                result => return result,
+            }
+        }
+
+        let endpoint = match self.transport(endpoint) {
+            TransportKind::Tcp if endpoint.transport == TransportKind::Udp => {
+                &ServerEndpoint { transport: TransportKind::Tcp, ..endpoint.clone() }
+            }
+            _ => endpoint,
+        };
+        match self.connect_stream(endpoint, server_addr).await {
+            Ok(connection) => {
+                log::info!("已建立到服务器 {} 的{}连接", server_addr, endpoint.transport);
+                *link = Some(ServerLink {
+                    addr: server_addr,
+                    connection: Some(connection),
+                });
+                Ok(())
+            }
+            Err(e) => {
+                *link = Some(ServerLink {
+                    addr: server_addr,
+                    connection: None,
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// 建立到服务器的流连接
+    async fn connect_stream(&self, endpoint: &ServerEndpoint, server_addr: SocketAddr) -> io::Result<StreamConnection> {
+        if endpoint.transport == TransportKind::Quic {
+            return self.connect_quic(endpoint, server_addr).await;
+        }
+
+        let stream = self.dial(server_addr).await?;
+
+        if !endpoint.transport.is_secure() {
+            return self.open_connection(endpoint, server_addr, stream).await;
+        }
+
+        let connector = TlsConnector::from(self.tls_config()?.clone());
+        let server_name = endpoint.tls_server_name(server_addr)?;
+        let stream = time::timeout(HANDSHAKE_TIMEOUT, connector.connect(server_name, stream)).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS握手超时"))??;
+        self.open_connection(endpoint, server_addr, stream).await
+    }
+
+    /// 建立到服务器的QUIC连接，并打开传输控制消息的双向流
+    async fn connect_quic(&self, endpoint: &ServerEndpoint, server_addr: SocketAddr) -> io::Result<StreamConnection> {
+        let server_name = endpoint.tls_server_name(server_addr)?.to_str().into_owned();
+        let connect = async {
+            let connection = quic::connect(server_addr, &server_name, self.tls_config()?).await?;
+            let (send, recv) = connection.open_bi().await?;
+            Ok::<_, io::Error>((connection, send, recv))
+        };
+        let (connection, send, recv) = time::timeout(HANDSHAKE_TIMEOUT, connect).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "QUIC握手超时"))??;
+
+        let (tx, writer) = spawn_writer(send, server_addr);
+        let frames = futures::stream::select(byte_frames(recv), quic::datagrams(connection.clone()));
+        Ok(StreamConnection::from_parts(tx, writer, frames, server_addr, self.inbound_tx.clone())
+            .with_datagrams(connection))
+    }
+
+    /// 获取TLS配置
+    fn tls_config(&self) -> io::Result<&Arc<ClientConfig>> {
+        self.tls.as_ref().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "未配置TLS"))
+    }
+
+    /// 在已建立 (并在需要时完成TLS握手) 的连接上创建流连接
+    async fn open_connection<S>(&self, endpoint: &ServerEndpoint, server_addr: SocketAddr, stream: S) -> io::Result<StreamConnection>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        if !endpoint.transport.is_websocket() {
+            return Ok(StreamConnection::spawn(stream, server_addr, self.inbound_tx.clone()));
+        }
+
+        let host = endpoint.host_header(server_addr);
+        let ws = time::timeout(HANDSHAKE_TIMEOUT, websocket::connect(stream, &host, &endpoint.path)).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "WebSocket握手超时"))??;
+        let (tx, writer, frames) = websocket::split(ws, server_addr);
+        Ok(StreamConnection::from_parts(tx, writer, frames, server_addr, self.inbound_tx.clone()))
+    }
+
+    /// 建立到指定地址的TCP连接，配置了代理时经代理建立隧道
+    async fn dial(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let connect = async {
+            match (&self.proxy, &self.socks5) {
+                (Some(proxy), _) => proxy.connect(addr).await,
+                (None, Some(socks5)) => socks5.connect(addr).await,
+                (None, None) => TcpStream::connect(addr).await,
+            }
+        };
+
+        let stream = time::timeout(CONNECT_TIMEOUT, connect).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "连接超时"))??;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    /// 探测服务器是否可达
+    ///
+    /// UDP使用NAT探测请求，QUIC尝试完成握手，
+    /// 其余流传输方式 (包括代理不支持UDP转发时的UDP服务器) 尝试建立TCP连接 (配置了代理时经代理建立)
+    pub async fn probe(&self, endpoint: &ServerEndpoint, addr: SocketAddr) -> bool {
+        match self.transport(endpoint) {
+            // NAT探测请求不经过代理，直接发出会绕过代理；只要代理支持UDP转发就视为可达
+            TransportKind::Udp if self.socks5.is_some() => true,
+            TransportKind::Udp => nat::ping(addr, self.obfs.as_deref()).await,
+            TransportKind::Quic => {
+                let (Ok(tls), Ok(server_name)) = (self.tls_config(), endpoint.tls_server_name(addr)) else {
+                    return false;
+                };
+                match time::timeout(HANDSHAKE_TIMEOUT, quic::connect(addr, &server_name.to_str(), tls)).await {
+                    Ok(Ok(connection)) => {
+                        connection.close(0u32.into(), b"");
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            _ => self.dial(addr).await.is_ok(),
+        }
+    }
+
+    /// 检查到服务器的流连接是否已断开
+    ///
+    /// 使用UDP时检查SOCKS5代理的UDP转发关联，未配置代理时始终返回 `false`
+    pub async fn is_stream_closed(&self) -> bool {
+        match &*self.link.lock().await {
+            Some(link) => link.connection.as_ref().is_none_or(|connection| connection.is_closed()),
+            None if self.socks5.is_some() => {
+                self.association.lock().await.as_ref().is_none_or(|association| association.is_closed())
+            }
+            None => false,
+        }
+    }
+
+    /// 发送消息
+    ///
+    /// 发往服务器的消息在使用流传输方式时写入流连接，发送队列已满时等待；
+    /// 其余使用UDP，配置了SOCKS5代理时经代理的UDP转发发送
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let stream = match &*self.link.lock().await {
+            Some(link) if link.addr == target => {
+                Some(link.connection.as_ref().map(|connection| (connection.sender(), connection.datagrams())))
+            }
+            _ => None,
+        };
+
+        if let Some(Some((_, Some(connection)))) = &stream {
+            if quic::try_send_datagram(connection, buf)? {
+                return Ok(buf.len());
+            }
+        }
+
+        match stream {
+            Some(Some((sender, _))) => {
+                sender.send(Bytes::copy_from_slice(buf)).await
+                    .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "流连接已断开"))?;
+                Ok(buf.len())
+            }
+            Some(None) => Err(io::Error::new(io::ErrorKind::NotConnected, "未连接到服务器")),
+            None => {
+                if let Some(multipath) = &self.multipath {
+                    if multipath.server().await == Some(target) {
+                        return self.send_multipath(multipath, buf, target).await;
+                    }
+                }
+
+                let sealed = self.obfs.as_ref().map(|obfs| obfs.seal(buf));
+                let datagram = sealed.as_deref().unwrap_or(buf);
+
+                if self.socks5.is_none() {
+                    self.udp.send_to(datagram, target).await?;
+                    return Ok(buf.len());
+                }
+
+                let (datagram, relay) = match &*self.association.lock().await {
+                    Some(association) => (association.encapsulate(datagram, target), association.relay()),
+                    None => return Err(io::Error::new(io::ErrorKind::NotConnected, "未建立UDP转发")),
+                };
+                self.udp.send_to(&datagram, relay).await?;
+                Ok(buf.len())
+            }
+        }
+    }
+
+    /// 按顺序发送多条消息
+    ///
+    /// 直接经UDP发送的消息以尽量少的系统调用批量发送；发往流连接或经多路径、SOCKS5代理发送的消息
+    /// 仍按 [`Self::send_to`] 逐条发送。某条消息发送失败时继续发送其余消息，全部尝试后返回遇到的第一个错误
+    pub async fn send_batch(&self, messages: &[(Bytes, SocketAddr)]) -> io::Result<()> {
+        let link = self.link.lock().await.as_ref().map(|link| link.addr);
+        let multipath = match &self.multipath {
+            Some(multipath) => multipath.server().await,
+            None => None,
+        };
+
+        let mut result = Ok(());
+        let mut datagrams = Vec::with_capacity(messages.len());
+        for (buf, target) in messages {
+            if self.socks5.is_some() || link == Some(*target) || multipath == Some(*target) {
+                if let Err(e) = self.send_to(buf, *target).await {
+                    result = result.and(Err(e));
+                }
+                continue;
+            }
+
+            let datagram = match &self.obfs {
+                Some(obfs) => Bytes::from(obfs.seal(buf)),
+                None => buf.clone(),
+            };
+            datagrams.push((datagram, *target));
+        }
+
+        if !datagrams.is_empty() {
+            let datagrams: Vec<_> = datagrams.iter().map(|(buf, target)| (&buf[..], *target)).collect();
+            if let Err(e) = self.udp.send_batch(&datagrams).await {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// 接收消息，来自UDP或到服务器的流连接
+    ///
+    /// 配置了SOCKS5代理时只接受代理转发的UDP数据报。返回的消息是接收缓冲区的切片
+    pub async fn recv_from(&self) -> io::Result<(Bytes, SocketAddr)> {
+        let mut inbound = self.inbound_rx.lock().await;
+
+        tokio::select! {
+            result = self.recv_udp() => result,
+            Some((addr, frame)) = inbound.recv() => Ok((frame, addr)),
+        }
+    }
+
+    /// 经多路径发送消息到服务器
+    ///
+    /// 消息加上会话标识和序号后在调度选出的每条路径上发送，至少一条路径发送成功即为成功
+    async fn send_multipath(&self, multipath: &Multipath, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let datagram = multipath.encapsulate(buf);
+        let sealed = self.obfs.as_ref().map(|obfs| obfs.seal(&datagram));
+        let datagram = sealed.as_deref().unwrap_or(&datagram);
+
+        let mut result = Err(io::Error::new(io::ErrorKind::NotConnected, "没有可用的路径"));
+        for index in multipath.select().await {
+            match multipath.send(index, datagram, target).await {
+                Ok(_) => result = Ok(buf.len()),
+                Err(e) if result.is_err() => result = Err(e),
+                Err(_) => {}
+            }
+        }
+        result
+    }
+
+    /// 在每条路径上向当前服务器发送路径探测请求，未配置多路径或未使用UDP时直接返回
+    ///
+    /// 需要定期调用，探测结果决定路径调度
+    pub async fn probe_paths(&self) {
+        let Some(multipath) = &self.multipath else {
+            return;
+        };
+        let Some(server) = multipath.server().await else {
+            return;
+        };
+
+        for (index, probe) in multipath.probes().await {
+            let sealed = self.obfs.as_ref().map(|obfs| obfs.seal(&probe));
+            if let Err(e) = multipath.send(index, sealed.as_deref().unwrap_or(&probe), server).await {
+                log::debug!("路径 {} 发送探测请求失败: {}", index, e);
+            }
+        }
+    }
+
+    /// 从主套接字或任一路径的套接字接收数据报，返回数据报、来源地址和路径索引
+    async fn recv_datagram(&self) -> io::Result<(BytesMut, SocketAddr, Option<usize>)> {
+        let Some(multipath) = &self.multipath else {
+            let (datagram, addr) = self.udp.recv_from().await?;
+            return Ok((datagram, addr, None));
+        };
+
+        loop {
+            tokio::select! {
+                result = self.udp.recv_from() => {
+                    let (datagram, addr) = result?;
+                    return Ok((datagram, addr, None));
+                }
+                index = multipath.readable() => {
+                    let index = index?;
+                    match multipath.try_recv_from(index) {
+                        Ok((datagram, addr)) => return Ok((datagram, addr, Some(index))),
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// 接收UDP数据报
+    ///
+    /// 配置了SOCKS5代理时去掉代理的请求头并丢弃其他来源的数据报，启用了混淆时丢弃无法还原的数据报。
+    /// 路径探测响应在此处理，不交给调用方
+    async fn recv_udp(&self) -> io::Result<(Bytes, SocketAddr)> {
+        loop {
+            let (mut buf, addr, path) = self.recv_datagram().await?;
+            let len = buf.len();
+            let (len, addr) = if self.socks5.is_none() {
+                (len, addr)
+            } else {
+                match &*self.association.lock().await {
+                    Some(association) if association.relay() == addr => match association.decapsulate(&mut buf, len) {
+                        Ok(result) => result,
+                        Err(e) => {
+                            log::debug!("丢弃代理转发的数据报: {}", e);
+                            continue;
+                        }
+                    },
+                    _ => {
+                        log::debug!("丢弃未经代理的数据报，来源: {}", addr);
+                        continue;
+                    }
+                }
+            };
+
+            let len = match &self.obfs {
+                Some(obfs) => match obfs.open(&mut buf, len) {
+                    Ok(len) => len,
+                    Err(e) => {
+                        log::debug!("丢弃来自 {} 的数据报: {}", addr, e);
+                        continue;
+                    }
+                },
+                None => len,
+            };
+            buf.truncate(len);
+            let mut buf = buf.freeze();
+
+            if let (Some(multipath), Some(index)) = (&self.multipath, path) {
+                if buf.first() == Some(&(MessageType::PathProbeReply as u8)) {
+                    match Message::decode(&mut buf).and_then(|reply| reply.parse_path_probe()) {
+                        Ok(id) => multipath.on_probe_reply(index, id).await,
+                        Err(e) => log::debug!("无效的路径探测响应: {}", e),
+                    }
+                    continue;
+                }
+            }
+            return Ok((buf, addr));
+        }
+    }
+}
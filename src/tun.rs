@@ -1,20 +1,63 @@
-use tun::platform::posix::{Reader, Writer};
-use tokio::sync::Mutex;
-use std::sync::Arc;
-use bytes::Bytes;
-use std::io::{Read, Write};
+use tun::Device as _;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc;
+use bytes::{Buf, Bytes};
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use crate::buffer::{BufferPool, PacketBuf, HEADROOM};
 use crate::error::{Result, VswitchError};
+use crate::vnet::{self, VnetHeader};
+#[cfg(feature = "io-uring")]
+use std::sync::Arc;
+#[cfg(feature = "io-uring")]
+use crate::uring;
+
+/// TUN设备最多支持的队列数 (Linux 的 MAX_TAP_QUEUES)
+pub const MAX_QUEUES: usize = 256;
+/// 普通TUN设备的读取缓冲区大小，足够容纳各种MTU的数据包
+const READ_BUF_LEN: usize = 2048;
+/// 启用卸载时的读取缓冲区大小，足够容纳 virtio-net 头部和64KB的超大分段
+const OFFLOAD_READ_BUF_LEN: usize = vnet::HEADER_LEN + u16::MAX as usize;
+/// virtio-net 头部的空间在读取后用作数据包的预留空间
+const _: () = assert!(HEADROOM <= vnet::HEADER_LEN);
+/// 启用卸载时等待写入的数据包队列长度
+const WRITE_QUEUE_LEN: usize = 1024;
+/// 启用卸载时一次最多合并写入的数据包数
+const WRITE_BATCH: usize = 64;
+/// io_uring 后端每个队列读到后等待取走的数据包数，超过时丢弃新读到的数据包
+#[cfg(feature = "io-uring")]
+const URING_QUEUE_LEN: usize = 1024;
+/// 内存中的设备每个方向等待取走的数据包数，超过时写入方等待
+const MEMORY_QUEUE_LEN: usize = 1024;
 
 /// TUN设备结构
 /// 
-/// 封装TUN设备的读写操作，提供线程安全的接口
+/// 封装TUN设备的读写操作，提供线程安全的接口。
+/// 设备以非阻塞模式注册到tokio，读写等待就绪通知，不占用运行时的工作线程；
+/// 读和写互不加锁，可以同时进行。
+///
+/// 多队列设备 (IFF_MULTI_QUEUE) 的每个队列有独立的文件描述符，系统按流将发出的数据包
+/// 分配到各队列，每个队列都需要有任务读取。
+///
+/// 启用卸载 (IFF_VNET_HDR) 时系统可以发出64KB的TCP超大分段，读取时拆分为普通数据包；
+/// 写入的数据包由单独的任务按批合并同一TCP连接的连续分段后写入。
+///
+/// 启用 io_uring 后端时队列切换回阻塞模式，每个队列由自己的 io_uring 实例以注册缓冲区连续读取，
+/// 读到的数据包经通道交给读取任务；写入也提交到该队列的实例
+///
+/// 基准测试使用内存中的设备 ([`TunDevice::memory`])，数据包经通道与 [`MemoryPort`] 交换，不经过系统协议栈
 pub struct TunDevice {
-    /// 设备读取器
-    reader: Arc<Mutex<Reader>>,
-    /// 设备写入器
-    writer: Arc<Mutex<Writer>>,
+    /// 设备的各个队列，内存中的设备没有队列
+    queues: Vec<Queue>,
+    /// 内存中的设备的各队列通道，系统TUN设备为 `None`
+    memory: Option<MemoryQueues>,
     /// TUN设备名称
     name: String,
+    /// 是否启用了 virtio-net 头部卸载
+    offload: bool,
+    /// 启用卸载时发往写入任务的数据包
+    writer: Option<mpsc::Sender<Bytes>>,
 }
 
 impl TunDevice {
@@ -23,62 +66,150 @@ impl TunDevice {
     /// 参数:
     /// - `name`: TUN设备名称
     /// - `mtu`: 最大传输单元大小
-    pub fn new(name: &str, mtu: usize) -> Result<Self> {
-        log::info!("正在创建TUN设备: {}, MTU: {}", name, mtu);
-        
-        // 配置TUN设备
-        let mut config = tun::Configuration::default();
-        config.name(name)
-            .mtu(mtu as i32)
-            .up();
+    /// - `queues`: 队列数，大于1时创建多队列设备
+    /// - `offload`: 是否启用 virtio-net 头部卸载 (TSO和校验和卸载)
+    pub fn new(name: &str, mtu: usize, queues: usize, offload: bool) -> Result<Self> {
+        log::info!("正在创建TUN设备: {}, MTU: {}, 队列数: {}, 卸载: {}", name, mtu, queues, if offload { "启用" } else { "禁用" });
         
-        // 创建TUN设备
-        let device = tun::create(&config).map_err(|e| {
-            log::error!("创建TUN设备失败: {}", e);
-            VswitchError::TunError(e)
-        })?;
-        
-        // 分离读写器
-        let (reader, writer) = device.split();
+        let (fds, name) = if offload {
+            open_vnet_device(name, mtu, queues).map_err(|e| {
+                log::error!("创建TUN设备失败: {}", e);
+                VswitchError::IoError(e)
+            })?
+        } else {
+            (open_device(name, mtu, queues)?, name.to_string())
+        };
+
+        let queues = fds.into_iter()
+            .map(|fd| Queue::new(fd, &name, offload))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|e| {
+                log::error!("注册TUN设备队列失败: {}", e);
+                VswitchError::IoError(e)
+            })?;
+
+        // 写入任务使用第一个队列的复制，设备释放时随发送端关闭而退出
+        let writer = match offload {
+            true => {
+                let queue = queues[0].try_clone().map_err(VswitchError::IoError)?;
+                let (tx, rx) = mpsc::channel(WRITE_QUEUE_LEN);
+                tokio::spawn(write_loop(queue, name.clone(), rx));
+                Some(tx)
+            }
+            false => None,
+        };
         
         log::info!("TUN设备 {} 创建成功", name);
         
         Ok(Self {
-            reader: Arc::new(Mutex::new(reader)),
-            writer: Arc::new(Mutex::new(writer)),
-            name: name.to_string(),
+            queues,
+            memory: None,
+            name,
+            offload,
+            writer,
         })
     }
 
+    /// 创建内存中的设备，返回设备和代替系统协议栈的另一端
+    ///
+    /// 另一端注入的数据包由设备的读取方按队列读出，设备写入的数据包由另一端取走
+    ///
+    /// 参数:
+    /// - `name`: 设备名称，只用于日志
+    /// - `queues`: 队列数
+    pub fn memory(name: &str, queues: usize) -> (Self, MemoryPort) {
+        let (inject, inbound): (Vec<_>, Vec<_>) = (0..queues)
+            .map(|_| {
+                let (tx, rx) = mpsc::channel(MEMORY_QUEUE_LEN);
+                (tx, tokio::sync::Mutex::new(rx))
+            })
+            .unzip();
+        let (outbound, written) = mpsc::channel(MEMORY_QUEUE_LEN);
+
+        let device = Self {
+            queues: Vec::new(),
+            memory: Some(MemoryQueues { inbound, outbound }),
+            name: name.to_string(),
+            offload: false,
+            writer: None,
+        };
+        (device, MemoryPort { inject, written })
+    }
+
     /// 获取TUN设备名称
     pub fn name(&self) -> &str {
         &self.name
     }
 
-    /// 从TUN设备读取数据包
-    /// 
+    /// 获取队列数
+    pub fn queue_count(&self) -> usize {
+        match &self.memory {
+            Some(memory) => memory.inbound.len(),
+            None => self.queues.len(),
+        }
+    }
+
+    /// 从TUN设备的指定队列读取一批数据包
+    ///
+    /// 等待第一个数据包到达后，继续读取队列中已经到达的数据包，没有更多数据包或读满 `max` 个时返回；
+    /// 批量发送时每批的大小因此随负载变化。启用卸载时一次读取的超大分段拆分后可能超过 `max` 个
+    ///
+    /// 参数:
+    /// - `queue`: 队列索引
+    /// - `max`: 最多读取的数据包数
+    /// - `pool`: 数据包直接读入从中取用的缓冲区，前面预留消息头的空间
+    ///
     /// 返回:
-    /// - 成功: 包含数据包内容的Bytes
-    /// - 错误: 读取过程中的错误
-    pub async fn read_packet(&self) -> Result<Bytes> {
-        // 锁定读取器
-        let mut reader = self.reader.lock().await;
-        
-        // 读取数据包
-        let mut buf = vec![0u8; 2048]; // 使用较大的缓冲区以适应各种MTU
-        let size = reader.read(&mut buf).map_err(|e| {
-            log::error!("从TUN设备 {} 读取失败: {}", self.name, e);
-            VswitchError::IoError(e)
-        })?;
-        
-        buf.truncate(size);
-        
-        log::trace!("从TUN设备 {} 读取了 {} 字节", self.name, size);
-        Ok(Bytes::from(buf))
+    /// - 成功: 至少包含一个数据包
+    /// - 错误: 读取第一个数据包时的错误
+    pub async fn read_packets_from(&self, queue: usize, max: usize, pool: &mut BufferPool) -> Result<Vec<PacketBuf>> {
+        if let Some(memory) = &self.memory {
+            return received_packets(&memory.inbound[queue], max, &self.name).await;
+        }
+        #[cfg(feature = "io-uring")]
+        if let Some(received) = &self.queues[queue].received {
+            return received_packets(received, max, &self.name).await;
+        }
+
+        let mut packets = Vec::new();
+
+        // 等待队列可读后读取数据包，其他任务已取走数据包时继续等待
+        while packets.is_empty() {
+            let mut guard = self.queues[queue].fd.readable().await.map_err(VswitchError::IoError)?;
+            if let Ok(result) = guard.try_io(|fd| self.read_into(fd.as_raw_fd(), pool, &mut packets)) {
+                result.map_err(|e| {
+                    log::error!("从TUN设备 {} 读取失败: {}", self.name, e);
+                    VswitchError::IoError(e)
+                })?;
+            }
+        }
+
+        let fd = self.queues[queue].fd.get_ref().as_raw_fd();
+        while packets.len() < max {
+            match self.read_into(fd, pool, &mut packets) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::debug!("从TUN设备 {} 读取失败: {}", self.name, e);
+                    break;
+                }
+            }
+        }
+
+        log::trace!("从TUN设备 {} 读取了 {} 个数据包", self.name, packets.len());
+        Ok(packets)
+    }
+
+    /// 从队列读取一个数据包追加到 `packets`
+    fn read_into(&self, fd: RawFd, pool: &mut BufferPool, packets: &mut Vec<PacketBuf>) -> io::Result<()> {
+        read_packet(&self.name, self.offload, |buf| read_fd(fd, buf), pool, packets)
     }
 
     /// 向TUN设备写入数据包
     /// 
+    /// 任一队列写入的数据包都会进入系统协议栈，多队列设备也只使用第一个队列写入。
+    /// 启用卸载时数据包交给写入任务，与同时等待写入的数据包合并后写入；写入队列已满时等待
+    /// 
     /// 参数:
     /// - `packet`: 要写入的数据包
     /// 
@@ -86,11 +217,20 @@ impl TunDevice {
     /// - 成功: 成功写入的字节数
     /// - 错误: 写入过程中的错误
     pub async fn write_packet(&self, packet: &Bytes) -> Result<usize> {
-        // 锁定写入器
-        let mut writer = self.writer.lock().await;
-        
-        // 写入数据包
-        let size = writer.write(packet).map_err(|e| {
+        if let Some(memory) = &self.memory {
+            memory.outbound.send(packet.clone()).await.map_err(|_| {
+                VswitchError::IoError(io::Error::new(io::ErrorKind::BrokenPipe, "内存中的设备的另一端已关闭"))
+            })?;
+            return Ok(packet.len());
+        }
+        if let Some(writer) = &self.writer {
+            writer.send(packet.clone()).await.map_err(|_| {
+                VswitchError::IoError(io::Error::new(io::ErrorKind::BrokenPipe, "TUN设备写入任务已退出"))
+            })?;
+            return Ok(packet.len());
+        }
+
+        let size = self.queues[0].write(packet).await.map_err(|e| {
             log::error!("写入TUN设备 {} 失败: {}", self.name, e);
             VswitchError::IoError(e)
         })?;
@@ -100,15 +240,358 @@ impl TunDevice {
     }
 }
 
+#[cfg(feature = "io-uring")]
+impl Drop for TunDevice {
+    fn drop(&mut self) {
+        // 停止各队列的连续读取，写入任务使用的第一个队列的实例随之关闭
+        for queue in &self.queues {
+            if let Some(ring) = &queue.ring {
+                ring.close();
+            }
+        }
+    }
+}
+
+/// 内存中的设备的另一端，代替系统协议栈收发数据包
+pub struct MemoryPort {
+    /// 发往设备各队列的数据包，设备的读取方从对应队列读出
+    pub inject: Vec<mpsc::Sender<PacketBuf>>,
+    /// 设备写入的数据包
+    pub written: mpsc::Receiver<Bytes>,
+}
+
+/// 内存中的设备的通道
+struct MemoryQueues {
+    /// 各队列等待读取的数据包
+    inbound: Vec<tokio::sync::Mutex<mpsc::Receiver<PacketBuf>>>,
+    /// 写入的数据包
+    outbound: mpsc::Sender<Bytes>,
+}
+
+/// TUN设备的一个队列
+struct Queue {
+    /// 注册到tokio的队列，启用 io_uring 后端时为阻塞模式，只用于保持队列打开
+    fd: AsyncFd<OwnedFd>,
+    /// 队列的 io_uring 实例，未启用 io_uring 后端时为 `None`
+    #[cfg(feature = "io-uring")]
+    ring: Option<Arc<uring::Ring>>,
+    /// io_uring 实例连续读到的数据包，写入任务使用的队列复制为 `None`
+    #[cfg(feature = "io-uring")]
+    received: Option<tokio::sync::Mutex<mpsc::Receiver<PacketBuf>>>,
+}
+
+impl Queue {
+    /// 注册队列，启用 io_uring 后端时在队列上开始连续读取
+    #[cfg_attr(not(feature = "io-uring"), allow(unused_variables))]
+    fn new(fd: OwnedFd, name: &str, offload: bool) -> io::Result<Self> {
+        #[cfg(feature = "io-uring")]
+        if uring::enabled() {
+            return Self::with_uring(fd, name, offload);
+        }
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+            #[cfg(feature = "io-uring")]
+            ring: None,
+            #[cfg(feature = "io-uring")]
+            received: None,
+        })
+    }
+
+    /// 在阻塞模式的队列上以 io_uring 连续读取，读到的数据包复制到缓冲池的缓冲区后放入通道
+    ///
+    /// 非阻塞的文件上 io_uring 读取直接返回 `EAGAIN`，阻塞模式下才会等待数据包到达
+    #[cfg(feature = "io-uring")]
+    fn with_uring(fd: OwnedFd, name: &str, offload: bool) -> io::Result<Self> {
+        set_blocking(fd.as_raw_fd())?;
+        let ring = uring::Ring::new()?;
+        let (tx, rx) = mpsc::channel(URING_QUEUE_LEN);
+        let len = if offload { OFFLOAD_READ_BUF_LEN } else { READ_BUF_LEN };
+        let name = name.to_string();
+        let mut pool = BufferPool::new();
+        let mut packets = Vec::new();
+        ring.read_fixed(fd.as_raw_fd(), len, move |data| {
+            let copy = |buf: &mut [u8]| {
+                buf[..data.len()].copy_from_slice(data);
+                Ok(data.len())
+            };
+            // 复制不会失败，无法解析的数据包已记录后丢弃
+            let _ = read_packet(&name, offload, copy, &mut pool, &mut packets);
+            for packet in packets.drain(..) {
+                if tx.try_send(packet).is_err() {
+                    log::debug!("TUN设备 {} 读到的数据包未及时取走，丢弃数据包", name);
+                }
+            }
+        })?;
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+            ring: Some(ring),
+            received: Some(tokio::sync::Mutex::new(rx)),
+        })
+    }
+
+    /// 复制队列用于写入
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            fd: AsyncFd::new(self.fd.get_ref().try_clone()?)?,
+            #[cfg(feature = "io-uring")]
+            ring: self.ring.clone(),
+            #[cfg(feature = "io-uring")]
+            received: None,
+        })
+    }
+
+    /// 写入一个完整的数据包，队列已满时等待可写
+    async fn write(&self, packet: &Bytes) -> io::Result<usize> {
+        #[cfg(feature = "io-uring")]
+        if let Some(ring) = &self.ring {
+            return ring.write(self.fd.get_ref().as_raw_fd(), packet.clone()).await;
+        }
+        loop {
+            let mut guard = self.fd.writable().await?;
+            match guard.try_io(|fd| write_fd(fd.as_raw_fd(), packet)) {
+                Ok(result) => break result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// 写入头部和数据包组成的一个数据包，队列已满时等待可写
+    async fn write_vectored(&self, header: &[u8], packet: &Bytes) -> io::Result<usize> {
+        #[cfg(feature = "io-uring")]
+        if let Some(ring) = &self.ring {
+            let bufs = vec![Bytes::copy_from_slice(header), packet.clone()];
+            return ring.write_vectored(self.fd.get_ref().as_raw_fd(), bufs).await;
+        }
+        loop {
+            let mut guard = self.fd.writable().await?;
+            match guard.try_io(|fd| write_vectored_fd(fd.as_raw_fd(), header, packet)) {
+                Ok(result) => break result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// 从 io_uring 读到或注入内存中的设备的数据包中取出一批，没有数据包时等待
+async fn received_packets(received: &tokio::sync::Mutex<mpsc::Receiver<PacketBuf>>, max: usize, name: &str) -> Result<Vec<PacketBuf>> {
+    let mut received = received.lock().await;
+    let mut packets = Vec::new();
+    if received.recv_many(&mut packets, max).await == 0 {
+        log::error!("TUN设备 {} 的读取已停止", name);
+        return Err(VswitchError::IoError(io::Error::new(io::ErrorKind::BrokenPipe, "TUN设备的读取已停止")));
+    }
+    log::trace!("从TUN设备 {} 读取了 {} 个数据包", name, packets.len());
+    Ok(packets)
+}
+
+/// 以 `read` 读取一个数据包追加到 `packets`，启用卸载时拆分超大分段
+///
+/// 无法解析的数据包记录后丢弃
+fn read_packet<F>(name: &str, offload: bool, read: F, pool: &mut BufferPool, packets: &mut Vec<PacketBuf>) -> io::Result<()>
+where
+    F: FnOnce(&mut [u8]) -> io::Result<usize>,
+{
+    if !offload {
+        let buf = pool.fill(HEADROOM + READ_BUF_LEN, |buf| read(&mut buf[HEADROOM..]).map(|size| HEADROOM + size))?;
+        packets.push(PacketBuf::new(buf));
+        return Ok(());
+    }
+
+    // virtio-net 头部读入缓冲区开头，解析后其末尾的空间作为数据包的预留空间
+    let mut buf = pool.fill(OFFLOAD_READ_BUF_LEN, read)?;
+    let result = VnetHeader::parse(&buf).and_then(|header| {
+        buf.advance(vnet::HEADER_LEN - HEADROOM);
+        vnet::split(&header, PacketBuf::new(buf), pool, packets)
+    });
+    if let Err(e) = result {
+        log::debug!("丢弃从TUN设备 {} 读取的数据包: {}", name, e);
+    }
+    Ok(())
+}
+
+/// 使用 tun 库创建普通TUN设备，返回每个队列的非阻塞文件描述符
+fn open_device(name: &str, mtu: usize, queues: usize) -> Result<Vec<OwnedFd>> {
+    // 配置TUN设备
+    let mut config = tun::Configuration::default();
+    config.name(name)
+        .mtu(mtu as i32)
+        .queues(queues)
+        .up();
+    
+    // 创建TUN设备
+    let mut device = tun::create(&config).map_err(|e| {
+        log::error!("创建TUN设备失败: {}", e);
+        VswitchError::TunError(e)
+    })?;
+    
+    // 复制每个队列的文件描述符并切换到非阻塞模式；
+    // 设备本身随后释放，队列由复制的文件描述符保持
+    let duplicate = |queue: &tun::platform::Queue| -> io::Result<OwnedFd> {
+        queue.set_nonblock()?;
+        // 借用的文件描述符在设备释放前一直有效
+        let fd = unsafe { BorrowedFd::borrow_raw(queue.as_raw_fd()) };
+        fd.try_clone_to_owned()
+    };
+    (0..queues)
+        .map(|index| match device.queue(index) {
+            Some(queue) => duplicate(queue),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("TUN设备队列 {} 不存在", index))),
+        })
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| {
+            log::error!("注册TUN设备队列失败: {}", e);
+            VswitchError::IoError(e)
+        })
+}
+
+/// 创建启用 virtio-net 头部 (IFF_VNET_HDR) 和TSO、校验和卸载的TUN设备
+///
+/// tun 库不支持这些选项，直接通过 `/dev/net/tun` 创建，再设置MTU并启用设备
+///
+/// 返回:
+/// - 成功: 每个队列的非阻塞文件描述符和实际的设备名称
+/// - 错误: 内核不支持或权限不足
+fn open_vnet_device(name: &str, mtu: usize, queues: usize) -> io::Result<(Vec<OwnedFd>, String)> {
+    let mut flags = libc::IFF_TUN | libc::IFF_NO_PI | libc::IFF_VNET_HDR;
+    if queues > 1 {
+        flags |= libc::IFF_MULTI_QUEUE;
+    }
+    let offloads = libc::TUN_F_CSUM | libc::TUN_F_TSO4 | libc::TUN_F_TSO6;
+
+    let mut actual = name.to_string();
+    let mut fds = Vec::with_capacity(queues);
+    for _ in 0..queues {
+        let fd = unsafe { libc::open(c"/dev/net/tun".as_ptr(), libc::O_RDWR | libc::O_CLOEXEC | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // 第一个队列创建设备，其余队列按实际名称加入
+        let mut ifr = interface_request(&actual)?;
+        ifr.ifr_ifru.ifru_flags = flags as libc::c_short;
+        ioctl(fd.as_raw_fd(), libc::TUNSETIFF as _, &mut ifr)?;
+        actual = interface_name(&ifr);
+
+        let ret = unsafe { libc::ioctl(fd.as_raw_fd(), libc::TUNSETOFFLOAD as _, offloads as libc::c_ulong) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        fds.push(fd);
+    }
+
+    // 通过任意套接字设置MTU并启用设备
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+    let mut ifr = interface_request(&actual)?;
+    ifr.ifr_ifru.ifru_mtu = mtu as libc::c_int;
+    ioctl(socket.as_raw_fd(), libc::SIOCSIFMTU as _, &mut ifr)?;
+
+    let mut ifr = interface_request(&actual)?;
+    ioctl(socket.as_raw_fd(), libc::SIOCGIFFLAGS as _, &mut ifr)?;
+    unsafe { ifr.ifr_ifru.ifru_flags |= (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short };
+    ioctl(socket.as_raw_fd(), libc::SIOCSIFFLAGS as _, &mut ifr)?;
+
+    Ok((fds, actual))
+}
+
+/// 创建指定网络接口名称的请求结构
+fn interface_request(name: &str) -> io::Result<libc::ifreq> {
+    let mut ifr: libc::ifreq = unsafe { std::mem::zeroed() };
+    if name.len() >= ifr.ifr_name.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("网络接口名称过长: {}", name)));
+    }
+    for (dst, src) in ifr.ifr_name.iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    Ok(ifr)
+}
+
+/// 读取请求结构中的网络接口名称
+fn interface_name(ifr: &libc::ifreq) -> String {
+    let bytes: Vec<u8> = ifr.ifr_name.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// 对网络接口请求结构执行 ioctl
+fn ioctl(fd: RawFd, request: libc::Ioctl, ifr: &mut libc::ifreq) -> io::Result<()> {
+    let ret = unsafe { libc::ioctl(fd, request, ifr as *mut libc::ifreq) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 启用卸载时的写入任务: 按批取出等待写入的数据包，合并后加上 virtio-net 头部写入
+async fn write_loop(queue: Queue, name: String, mut rx: mpsc::Receiver<Bytes>) {
+    let mut packets = Vec::with_capacity(WRITE_BATCH);
+    while rx.recv_many(&mut packets, WRITE_BATCH).await > 0 {
+        let count = packets.len();
+        let merged = vnet::coalesce(packets.drain(..));
+        if merged.len() < count {
+            log::trace!("向TUN设备 {} 写入的 {} 个数据包合并为 {} 个", name, count, merged.len());
+        }
+
+        for (header, packet) in merged {
+            if let Err(e) = queue.write_vectored(&header.encode(), &packet).await {
+                log::error!("写入TUN设备 {} 失败: {}", name, e);
+            }
+        }
+    }
+}
+
+/// 从非阻塞文件描述符读取一个数据包
+fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let size = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+/// 向非阻塞文件描述符写入头部和数据包组成的一个数据包
+fn write_vectored_fd(fd: RawFd, header: &[u8], buf: &[u8]) -> io::Result<usize> {
+    let iov = [
+        libc::iovec { iov_base: header.as_ptr() as *mut libc::c_void, iov_len: header.len() },
+        libc::iovec { iov_base: buf.as_ptr() as *mut libc::c_void, iov_len: buf.len() },
+    ];
+    let size = unsafe { libc::writev(fd, iov.as_ptr(), iov.len() as libc::c_int) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+/// 将文件描述符切换回阻塞模式
+#[cfg(feature = "io-uring")]
+fn set_blocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 向非阻塞文件描述符写入一个数据包
+fn write_fd(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    let size = unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
 /// 创建并返回TUN设备实例
 /// 
 /// 参数:
 /// - `name`: TUN设备名称
 /// - `mtu`: 最大传输单元大小
+/// - `queues`: 队列数
+/// - `offload`: 是否启用 virtio-net 头部卸载
 /// 
 /// 返回:
 /// - 成功: TUN设备实例
 /// - 错误: 创建过程中的错误
-pub fn create_tun_device(name: &str, mtu: u32) -> Result<TunDevice> {
-    TunDevice::new(name, mtu as usize)
+pub fn create_tun_device(name: &str, mtu: u32, queues: usize, offload: bool) -> Result<TunDevice> {
+    TunDevice::new(name, mtu as usize, queues, offload)
 } 
\ No newline at end of file
@@ -0,0 +1,1190 @@
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::sync::OnceLock;
+#[cfg(feature = "io-uring")]
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use bytes::BytesMut;
+use dashmap::DashMap;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+#[cfg(feature = "io-uring")]
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+use crate::buffer::BufferPool;
+use crate::xdp::Xdp;
+#[cfg(feature = "io-uring")]
+use crate::uring;
+
+/// 控制消息缓冲区大小，足够容纳一个 IPv4 或 IPv6 包信息和一个UDP分段大小
+const CONTROL_LEN: usize = 128;
+/// 一次系统调用最多收发的数据报数
+pub const MAX_BATCH: usize = 64;
+/// 批量接收的初始数据报数
+const INITIAL_BATCH: usize = 8;
+/// 批量接收时每个数据报的缓冲区大小，与接收循环的缓冲区一致
+const SLOT_LEN: usize = 4096;
+/// io_uring 多次接收时每个套接字提供给内核的缓冲区总大小
+#[cfg(feature = "io-uring")]
+const URING_RECV_BYTES: usize = 1024 * 1024;
+/// io_uring 接收后等待交付的数据报队列长度
+#[cfg(feature = "io-uring")]
+const URING_QUEUE_LEN: usize = 4096;
+/// 开启合并接收时每个数据报的缓冲区大小，足够容纳合并后的最大数据报
+const GRO_SLOT_LEN: usize = 65536;
+/// 一条分段卸载消息最多包含的数据报数 (内核的 UDP_MAX_SEGMENTS)
+const MAX_GSO_SEGMENTS: usize = 64;
+/// 一条分段卸载消息最多包含的字节数，加上 IPv6 和 UDP 头部不超过IP包的最大长度
+const MAX_GSO_BYTES: usize = u16::MAX as usize - 40 - 8;
+
+/// 双栈UDP套接字
+///
+/// 优先绑定 `[::]:0` 并关闭 IPV6_V6ONLY，使同一个套接字可以同时与IPv4和IPv6地址通信；
+/// 系统不支持IPv6时退回 `0.0.0.0:0`。收发时自动完成IPv4映射地址的转换
+pub struct DualStackSocket {
+    io: SocketIo,
+    /// 是否为双栈套接字
+    dual_stack: bool,
+}
+
+impl DualStackSocket {
+    /// 绑定一个随机端口的双栈UDP套接字
+    pub fn bind() -> io::Result<Self> {
+        match bind_dual_stack() {
+            Ok(socket) => Self::new(socket, true),
+            Err(e) => {
+                log::debug!("创建双栈UDP套接字失败: {}, 仅使用IPv4", e);
+                let socket = std::net::UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+                socket.set_nonblocking(true)?;
+                Self::new(UdpSocket::from_std(socket)?, false)
+            }
+        }
+    }
+
+    fn new(socket: UdpSocket, dual_stack: bool) -> io::Result<Self> {
+        Ok(Self { io: SocketIo::new(socket, false)?, dual_stack })
+    }
+
+    /// 绑定到指定本地IP的随机端口，只能与同一地址族的地址通信
+    pub fn bind_addr(ip: IpAddr) -> io::Result<Self> {
+        let domain = if ip.is_ipv4() { Domain::IPV4 } else { Domain::IPV6 };
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        if ip.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddr::new(ip, 0).into())?;
+        Self::new(UdpSocket::from_std(socket.into())?, false)
+    }
+
+    /// 绑定一个只经指定网络接口收发的双栈套接字 (`SO_BINDTODEVICE`)
+    pub fn bind_device(interface: &str) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(false)?;
+        socket.bind_device(Some(interface.as_bytes()))?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0).into())?;
+        Self::new(UdpSocket::from_std(socket.into())?, true)
+    }
+
+    /// 获取本地地址
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.socket.local_addr()
+    }
+
+    /// 等待套接字可读，接收队列中还有数据报时立即返回
+    pub async fn readable(&self) -> io::Result<()> {
+        self.io.readable().await
+    }
+
+    /// 尝试接收一个数据报，没有数据报时返回 [`io::ErrorKind::WouldBlock`]
+    pub fn try_recv_from(&self) -> io::Result<(BytesMut, SocketAddr)> {
+        let (datagram, addr, _) = self.io.try_recv()?;
+        Ok((datagram, SocketAddr::new(addr.ip().to_canonical(), addr.port())))
+    }
+
+    /// 发送数据报
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.io.send_to(buf, self.map_target(target), None).await
+    }
+
+    /// 以尽量少的系统调用 (`sendmmsg`) 按顺序发送多个数据报
+    ///
+    /// 某个数据报发送失败时继续发送其余数据报，全部尝试后返回遇到的第一个错误
+    pub async fn send_batch(&self, datagrams: &[(&[u8], SocketAddr)]) -> io::Result<()> {
+        let datagrams: Vec<_> = datagrams.iter()
+            .map(|&(buf, target)| (buf, self.map_target(target), None))
+            .collect();
+        self.io.send_all(&datagrams).await
+    }
+
+    /// 接收数据报，IPv4映射地址会转换回IPv4地址
+    ///
+    /// 一次 `recvmmsg` 接收已到达的多个数据报，其余数据报留在队列中由之后的调用直接返回。
+    /// 返回的数据报是接收缓冲区的切片
+    pub async fn recv_from(&self) -> io::Result<(BytesMut, SocketAddr)> {
+        let (datagram, addr, _) = self.io.recv().await?;
+        Ok((datagram, SocketAddr::new(addr.ip().to_canonical(), addr.port())))
+    }
+
+    /// 双栈套接字以IPv4映射地址发往IPv4目标
+    fn map_target(&self, target: SocketAddr) -> SocketAddr {
+        match target {
+            SocketAddr::V4(v4) if self.dual_stack => {
+                SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port())
+            }
+            _ => target,
+        }
+    }
+}
+
+/// 创建绑定 `[::]:0` 的双栈套接字
+fn bind_dual_stack() -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(false)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0).into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// 数据报到达的本地地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PacketInfo {
+    /// 本地IP地址
+    ip: IpAddr,
+    /// 接收数据报的网络接口索引
+    ifindex: u32,
+}
+
+/// 发往远端地址的回复路径
+#[derive(Debug, Clone, Copy)]
+struct ReplyPath {
+    /// 远端最近一次联系的监听套接字索引
+    listener: usize,
+    /// 远端联系的本地地址，仅通配地址监听时记录
+    local: Option<PacketInfo>,
+    /// 最近一次收到该远端数据的时间
+    last_seen: Instant,
+}
+
+/// 单个监听套接字
+struct Listener {
+    io: SocketIo,
+    /// 实际绑定的地址
+    addr: SocketAddr,
+}
+
+/// 服务端多地址UDP套接字
+///
+/// 同时监听多个地址和端口 (IPv4、IPv6、多个网卡)。每收到一个数据报都会记录远端联系的
+/// 监听套接字和本地地址，之后发往该远端的数据从同一个套接字、以同一个本地地址作为源地址发出，
+/// 避免多宿主机上回复从错误的源地址发出。
+///
+/// 启用 AF_XDP 时每个 AF_XDP 套接字也作为一个监听套接字，索引排在普通监听套接字之后；
+/// 经 AF_XDP 联系的远端同样经 AF_XDP 回复，不能经 AF_XDP 发送的数据报由普通监听套接字发送
+pub struct MultiSocket {
+    listeners: Vec<Listener>,
+    /// 截取监听端口数据报的 AF_XDP 快速路径
+    xdp: Option<Xdp>,
+    /// 是否需要记录回复路径，只有一个绑定具体地址的监听套接字时无需记录
+    track_paths: bool,
+    /// 回复路径表 (远端地址 -> 回复路径)，每收发一个数据报都要访问，
+    /// 使用分片的并发哈希表，各接收和发送任务之间互不阻塞
+    paths: DashMap<SocketAddr, ReplyPath>,
+}
+
+impl MultiSocket {
+    /// 绑定所有监听地址
+    ///
+    /// IPv6 监听套接字只接收 IPv6 数据，需要同时监听 IPv4 时应另外指定 IPv4 地址
+    ///
+    /// 参数:
+    /// - `addrs`: 监听地址列表
+    /// - `reuseport`: 每个地址绑定的套接字数，大于1时以 `SO_REUSEPORT` 绑定，
+    ///   由系统按远端地址将数据报分配到各套接字，每个套接字可以由单独的接收任务处理
+    /// - `xdp`: 以 AF_XDP 截取发往监听地址的数据报的网络接口
+    pub fn bind(addrs: &[SocketAddr], reuseport: usize, xdp: Option<&str>) -> io::Result<Self> {
+        let mut listeners = Vec::with_capacity(addrs.len() * reuseport.max(1));
+        for addr in addrs {
+            let bind = |addr: SocketAddr| bind_listener(addr, reuseport > 1)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", addr, e)));
+
+            // 端口为0时其余套接字绑定第一个套接字实际分配到的端口
+            let first = bind(*addr)?;
+            let actual = first.addr;
+            listeners.push(first);
+            for _ in 1..reuseport {
+                listeners.push(bind(actual)?);
+            }
+        }
+
+        // 普通套接字绑定后端口已确定，XDP 程序截取实际绑定的地址
+        let xdp = match xdp {
+            Some(interface) => {
+                let mut bound: Vec<SocketAddr> = listeners.iter().map(|listener| listener.addr).collect();
+                bound.sort_unstable();
+                bound.dedup();
+                Some(Xdp::attach(interface, &bound)?)
+            }
+            None => None,
+        };
+
+        // 同一地址的多个套接字共用端口，从任一套接字回复都相同，只有多个地址时需要记录
+        let track_paths = addrs.len() > 1 || xdp.is_some() || listeners.iter().any(|listener| listener.io.pktinfo);
+
+        Ok(Self {
+            listeners,
+            xdp,
+            track_paths,
+            paths: DashMap::new(),
+        })
+    }
+
+    /// 获取监听套接字数量，包括 AF_XDP 套接字
+    pub fn listener_count(&self) -> usize {
+        self.listeners.len() + self.xdp.as_ref().map_or(0, Xdp::queue_count)
+    }
+
+    /// 获取所有监听套接字实际绑定的地址，同一地址的多个套接字只返回一次
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs: Vec<SocketAddr> = self.listeners.iter().map(|listener| listener.addr).collect();
+        addrs.dedup();
+        addrs
+    }
+
+    /// 从指定的监听套接字接收数据报，并记录到来源地址的回复路径
+    ///
+    /// 一次 `recvmmsg` 接收已到达的多个数据报，其余数据报留在队列中由之后的调用直接返回。
+    /// 返回的数据报是接收缓冲区的切片
+    ///
+    /// 参数:
+    /// - `index`: 监听套接字索引
+    pub async fn recv_from(&self, index: usize) -> io::Result<(BytesMut, SocketAddr)> {
+        let (datagram, addr, local) = match self.xdp_queue(index) {
+            Some((xdp, queue)) => {
+                let (datagram, addr) = xdp.recv(queue).await?;
+                (datagram, addr, None)
+            }
+            None => self.listeners[index].io.recv().await?,
+        };
+
+        if self.track_paths {
+            self.paths.insert(addr, ReplyPath {
+                listener: index,
+                local,
+                last_seen: Instant::now(),
+            });
+        }
+
+        Ok((datagram, addr))
+    }
+
+    /// 发送数据报
+    ///
+    /// 目标联系过本服务端时沿原路径回复；否则使用第一个地址族相同的监听套接字，
+    /// 由系统选择源地址
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let (mut index, mut local) = self.route(target).ok_or_else(no_listener)?;
+        if let Some((xdp, queue)) = self.xdp_queue(index) {
+            if xdp.send(queue, &[(buf, target)]).is_empty() {
+                return Ok(buf.len());
+            }
+            (index, local) = (self.default_listener(target).ok_or_else(no_listener)?, None);
+        }
+
+        self.listeners[index].io.send_to(buf, target, local).await
+    }
+
+    /// 以尽量少的系统调用 (`sendmmsg`) 按顺序发送多个数据报，回复路径的选择与 [`Self::send_to`] 相同
+    ///
+    /// 某个数据报发送失败时继续发送其余数据报，全部尝试后返回遇到的第一个错误
+    pub async fn send_batch(&self, datagrams: &[(&[u8], SocketAddr)]) -> io::Result<()> {
+        let routes = datagrams.iter()
+            .map(|&(buf, target)| self.route(target).map(|(index, local)| (index, (buf, target, local))))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(no_listener)?;
+
+        // 连续使用同一个监听套接字的数据报一起发送，保持发送顺序
+        let mut result = Ok(());
+        for run in routes.chunk_by(|a, b| a.0 == b.0) {
+            let sent = match self.xdp_queue(run[0].0) {
+                Some((xdp, queue)) => {
+                    let datagrams: Vec<_> = run.iter().map(|&(_, (buf, target, _))| (buf, target)).collect();
+                    let rejected = xdp.send(queue, &datagrams);
+                    self.send_default(&rejected).await
+                }
+                None => {
+                    let datagrams: Vec<_> = run.iter().map(|(_, datagram)| *datagram).collect();
+                    self.listeners[run[0].0].io.send_all(&datagrams).await
+                }
+            };
+            result = result.and(sent);
+        }
+        result
+    }
+
+    /// 经默认的监听套接字发送不能经 AF_XDP 发送的数据报
+    async fn send_default(&self, datagrams: &[(&[u8], SocketAddr)]) -> io::Result<()> {
+        let routes = datagrams.iter()
+            .map(|&(buf, target)| self.default_listener(target).map(|index| (index, (buf, target, None))))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(no_listener)?;
+
+        let mut result = Ok(());
+        for run in routes.chunk_by(|a, b| a.0 == b.0) {
+            let datagrams: Vec<_> = run.iter().map(|(_, datagram)| *datagram).collect();
+            result = result.and(self.listeners[run[0].0].io.send_all(&datagrams).await);
+        }
+        result
+    }
+
+    /// 删除超过指定时间没有收到数据的回复路径
+    pub fn expire_paths(&self, idle: Duration) {
+        if !self.track_paths {
+            return;
+        }
+
+        if let Some(xdp) = &self.xdp {
+            xdp.expire(idle);
+        }
+        let before = self.paths.len();
+        self.paths.retain(|_, path| path.last_seen.elapsed() <= idle);
+        let removed = before.saturating_sub(self.paths.len());
+        if removed > 0 {
+            log::debug!("清理了 {} 条过期的回复路径，剩余 {} 条", removed, self.paths.len());
+        }
+    }
+
+    /// 选择发往目标的监听套接字和源地址: 目标联系过本服务端时沿原路径回复，否则使用默认的监听套接字
+    fn route(&self, target: SocketAddr) -> Option<(usize, Option<PacketInfo>)> {
+        let path = match self.track_paths {
+            true => self.paths.get(&target).map(|path| (path.listener, path.local)),
+            false => None,
+        };
+        path.or_else(|| self.default_listener(target).map(|index| (index, None)))
+    }
+
+    /// 监听套接字索引对应的 AF_XDP 套接字 (AF_XDP 快速路径, 队列索引)，普通监听套接字返回 `None`
+    fn xdp_queue(&self, index: usize) -> Option<(&Xdp, usize)> {
+        let queue = index.checked_sub(self.listeners.len())?;
+        self.xdp.as_ref().map(|xdp| (xdp, queue))
+    }
+
+    /// 选择向未知远端发送数据时使用的监听套接字，没有任何监听套接字时返回 `None`
+    fn default_listener(&self, target: SocketAddr) -> Option<usize> {
+        if self.listeners.is_empty() {
+            return None;
+        }
+        let index = self.listeners.iter()
+            .position(|listener| listener.addr.is_ipv6() == target.is_ipv6())
+            .unwrap_or(0);
+        Some(index)
+    }
+}
+
+fn no_listener() -> io::Error {
+    io::Error::new(io::ErrorKind::AddrNotAvailable, "没有可用的UDP监听套接字")
+}
+
+/// 创建并绑定单个监听套接字
+fn bind_listener(addr: SocketAddr, reuseport: bool) -> io::Result<Listener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    if reuseport {
+        socket.set_reuse_port(true)?;
+    }
+
+    let pktinfo = addr.ip().is_unspecified();
+    if pktinfo {
+        enable_pktinfo(&socket, addr.is_ipv6())?;
+    }
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    let socket = UdpSocket::from_std(socket.into())?;
+    let addr = socket.local_addr()?;
+    Ok(Listener { io: SocketIo::new(socket, pktinfo)?, addr })
+}
+
+/// 接收到的数据报 (数据, 来源地址, 本地地址)
+type Received = (BytesMut, SocketAddr, Option<PacketInfo>);
+
+/// 等待发送的数据报 (数据, 目标地址, 源地址)
+type Datagram<'a> = (&'a [u8], SocketAddr, Option<PacketInfo>);
+
+/// 单个UDP套接字的收发
+///
+/// 默认以 `recvmmsg`/`sendmmsg` 批量收发；启用 io_uring 后端时经套接字自己的 io_uring 实例收发
+struct SocketIo {
+    socket: UdpSocket,
+    /// 是否通过 IP_PKTINFO 获取数据报到达的本地地址 (绑定通配地址时启用)
+    pktinfo: bool,
+    /// 批量接收队列
+    batch: Mutex<RecvBatch>,
+    /// 使用的UDP卸载功能
+    offload: Offload,
+    /// io_uring 收发，未启用 io_uring 后端时为 `None`
+    #[cfg(feature = "io-uring")]
+    uring: Option<UringSocket>,
+}
+
+impl SocketIo {
+    fn new(socket: UdpSocket, pktinfo: bool) -> io::Result<Self> {
+        let offload = Offload::enable(socket.as_raw_fd());
+        #[cfg(feature = "io-uring")]
+        let uring = match uring::enabled() {
+            true => Some(UringSocket::new(socket.as_raw_fd(), pktinfo, offload.gro)?),
+            false => None,
+        };
+        Ok(Self {
+            batch: Mutex::new(RecvBatch::new(offload.gro)),
+            socket,
+            pktinfo,
+            offload,
+            #[cfg(feature = "io-uring")]
+            uring,
+        })
+    }
+
+    /// 等待套接字可读，接收队列中还有数据报时立即返回
+    async fn readable(&self) -> io::Result<()> {
+        #[cfg(feature = "io-uring")]
+        if let Some(uring) = &self.uring {
+            return uring.readable().await;
+        }
+        if !self.batch.lock().await.is_empty() {
+            return Ok(());
+        }
+        self.socket.readable().await
+    }
+
+    /// 接收一个数据报
+    async fn recv(&self) -> io::Result<Received> {
+        #[cfg(feature = "io-uring")]
+        if let Some(uring) = &self.uring {
+            return uring.recv().await;
+        }
+        self.batch.lock().await.recv(&self.socket, self.pktinfo).await
+    }
+
+    /// 尝试接收一个数据报，没有数据报时返回 [`io::ErrorKind::WouldBlock`]
+    fn try_recv(&self) -> io::Result<Received> {
+        #[cfg(feature = "io-uring")]
+        if let Some(uring) = &self.uring {
+            return uring.try_recv();
+        }
+        let mut batch = self.batch.try_lock().map_err(|_| io::Error::from(io::ErrorKind::WouldBlock))?;
+        batch.try_recv(&self.socket, self.pktinfo)
+    }
+
+    /// 发送数据报，指定了本地地址时以其作为源地址
+    async fn send_to(&self, buf: &[u8], target: SocketAddr, local: Option<PacketInfo>) -> io::Result<usize> {
+        #[cfg(feature = "io-uring")]
+        if let Some(uring) = &self.uring {
+            return uring.send_msgs(&[(buf, target, local)], &[1]).await.map(|_| buf.len());
+        }
+        match local {
+            Some(local) => {
+                let fd = self.socket.as_raw_fd();
+                self.socket.async_io(Interest::WRITABLE, || send_with_pktinfo(fd, buf, target, local)).await
+            }
+            None => self.socket.send_to(buf, target).await,
+        }
+    }
+
+    /// 以尽量少的系统调用发送所有数据报
+    ///
+    /// 支持分段卸载时，目标和源地址相同、长度相同 (最后一个可以较短) 的连续数据报合并为一条消息，
+    /// 由内核或网卡拆分。合并发送失败时这些数据报改为逐个发送，网卡不支持 (`EIO`) 时套接字不再合并发送。
+    /// 某个数据报发送失败时跳过该数据报继续发送，全部尝试后返回遇到的第一个错误
+    async fn send_all(&self, datagrams: &[Datagram<'_>]) -> io::Result<()> {
+        let mut result = Ok(());
+        let mut sent = 0;
+        // 此位置之前的数据报不合并发送
+        let mut single_until = 0;
+        while sent < datagrams.len() {
+            let groups = segment_groups(&datagrams[sent..], self.offload.gso() && sent >= single_until);
+            match self.send_msgs(&datagrams[sent..], &groups).await {
+                Ok(count) => sent += groups[..count].iter().sum::<usize>(),
+                Err(e) if groups[0] > 1 => {
+                    log::debug!("合并发送 {} 个数据报到 {} 失败: {}", groups[0], datagrams[sent].1, e);
+                    if e.raw_os_error() == Some(libc::EIO) {
+                        self.offload.disable_gso(&e);
+                    }
+                    single_until = sent + groups[0];
+                }
+                Err(e) => {
+                    log::debug!("发送数据报到 {} 失败: {}", datagrams[sent].1, e);
+                    result = result.and(Err(e));
+                    sent += 1;
+                }
+            }
+        }
+        result
+    }
+
+    /// 以一次 `sendmmsg` 发送多条消息，返回成功发送的消息数；第一条消息就发送失败时返回错误
+    async fn send_msgs(&self, datagrams: &[Datagram<'_>], groups: &[usize]) -> io::Result<usize> {
+        #[cfg(feature = "io-uring")]
+        if let Some(uring) = &self.uring {
+            return uring.send_msgs(datagrams, groups).await;
+        }
+        let fd = self.socket.as_raw_fd();
+        let mut batch = MsgBatch::new(datagrams, groups);
+        self.socket.async_io(Interest::WRITABLE, || batch.send(fd)).await
+    }
+}
+
+/// 套接字使用的UDP卸载功能
+struct Offload {
+    /// 是否以 UDP_SEGMENT 合并发送目标相同的连续数据报，网卡不支持时在发送出错后关闭
+    gso: AtomicBool,
+    /// 是否已开启 UDP_GRO，收到的数据报可能由多个数据报合并而成
+    gro: bool,
+}
+
+impl Offload {
+    /// 按系统的支持情况为套接字开启UDP卸载，不支持时逐个收发数据报
+    fn enable(fd: RawFd) -> Self {
+        let (gso, gro) = *OFFLOAD_SUPPORT.get_or_init(detect_offload);
+        let gro = gro && set_int_option(fd, libc::SOL_UDP, libc::UDP_GRO, 1).is_ok();
+        Self { gso: AtomicBool::new(gso), gro }
+    }
+
+    fn gso(&self) -> bool {
+        self.gso.load(Ordering::Relaxed)
+    }
+
+    /// 关闭分段卸载，之后逐个发送数据报
+    fn disable_gso(&self, e: &io::Error) {
+        if self.gso.swap(false, Ordering::Relaxed) {
+            log::warn!("UDP分段卸载发送失败，改为逐个发送数据报: {}", e);
+        }
+    }
+}
+
+/// 系统是否支持UDP分段卸载和合并接收，首次创建套接字时检测
+static OFFLOAD_SUPPORT: OnceLock<(bool, bool)> = OnceLock::new();
+
+/// 用临时套接字检测内核是否支持 UDP_SEGMENT (Linux 4.18 起) 和 UDP_GRO (Linux 5.0 起)
+fn detect_offload() -> (bool, bool) {
+    let detect = || -> io::Result<(bool, bool)> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        let fd = socket.as_raw_fd();
+        let gso = set_int_option(fd, libc::SOL_UDP, libc::UDP_SEGMENT, SLOT_LEN as libc::c_int).is_ok();
+        let gro = set_int_option(fd, libc::SOL_UDP, libc::UDP_GRO, 1).is_ok();
+        Ok((gso, gro))
+    };
+    let (gso, gro) = detect().unwrap_or((false, false));
+
+    let support = |enabled: bool| if enabled { "支持" } else { "不支持" };
+    log::info!("UDP分段卸载 (GSO): {}, UDP合并接收 (GRO): {}", support(gso), support(gro));
+    (gso, gro)
+}
+
+/// 批量接收队列
+///
+/// 一次 `recvmmsg` 接收多个数据报后逐个交给调用方。每批接收的数据报数随负载调整：
+/// 收满一批时加倍，直到缓冲区总大小达到 [`MAX_BATCH`] 个 [`SLOT_LEN`]；不足四分之一时减半，
+/// 空闲时不占用过多缓冲区。开启合并接收时每个缓冲区需要容纳合并后的数据报，每批的数据报数相应减少，
+/// 合并的数据报按分段大小拆分后交付。
+///
+/// 未开启合并接收时接收缓冲区从缓冲池中取出，交付的数据报是缓冲区的切片，不复制数据。
+/// 开启合并接收时每个数据报预留64KB，直接交付会让一个短数据报占住整个接收区、进而占住整个内存块，
+/// 因此接收到复用的接收区后按实际长度复制到缓冲池，交付的数据报在缓冲池中紧凑存放
+struct RecvBatch {
+    /// 缓冲池，未开启合并接收时每批每个数据报占 `slot_len` 字节，开启时只占数据报的实际长度
+    pool: BufferPool,
+    /// 开启合并接收时复用的接收区，不交付给调用方
+    scratch: Vec<u8>,
+    /// 每个数据报的来源地址
+    names: Vec<libc::sockaddr_storage>,
+    /// 每个数据报的控制消息缓冲区，使用 u64 数组保证按 cmsghdr 对齐
+    controls: Vec<[u64; CONTROL_LEN / 8]>,
+    /// 已接收、尚未交付的数据报
+    received: VecDeque<Received>,
+    /// 当前每批接收的数据报数
+    size: usize,
+    /// 每批最多接收的数据报数
+    max_size: usize,
+    /// 每个数据报的接收缓冲区大小
+    slot_len: usize,
+    /// 是否开启了合并接收
+    gro: bool,
+}
+
+impl RecvBatch {
+    fn new(gro: bool) -> Self {
+        let slot_len = if gro { GRO_SLOT_LEN } else { SLOT_LEN };
+        let max_size = (MAX_BATCH * SLOT_LEN / slot_len).max(1);
+        Self {
+            pool: BufferPool::new(),
+            scratch: Vec::new(),
+            names: Vec::new(),
+            controls: Vec::new(),
+            received: VecDeque::new(),
+            size: INITIAL_BATCH.min(max_size),
+            max_size,
+            slot_len,
+            gro,
+        }
+    }
+
+    /// 是否有尚未交付的数据报
+    fn is_empty(&self) -> bool {
+        self.received.is_empty()
+    }
+
+    /// 接收一个数据报，队列为空时等待套接字可读后批量接收
+    ///
+    /// 在等待可读时取消不会丢失数据报
+    async fn recv(&mut self, socket: &UdpSocket, pktinfo: bool) -> io::Result<Received> {
+        let fd = socket.as_raw_fd();
+        loop {
+            if let Some(datagram) = self.received.pop_front() {
+                return Ok(datagram);
+            }
+            socket.async_io(Interest::READABLE, || self.fill(fd, pktinfo)).await?;
+        }
+    }
+
+    /// 尝试接收一个数据报，队列为空且套接字没有数据报时返回 [`io::ErrorKind::WouldBlock`]
+    fn try_recv(&mut self, socket: &UdpSocket, pktinfo: bool) -> io::Result<Received> {
+        if self.received.is_empty() {
+            let fd = socket.as_raw_fd();
+            socket.try_io(Interest::READABLE, || self.fill(fd, pktinfo))?;
+        }
+        self.received.pop_front().ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))
+    }
+
+    /// 以一次 `recvmmsg` 接收已到达的数据报，并按本批接收的数量调整下一批的大小
+    fn fill(&mut self, fd: RawFd, pktinfo: bool) -> io::Result<()> {
+        let size = self.size;
+        let slot_len = self.slot_len;
+        let control = pktinfo || self.gro;
+        let mut buf = match self.gro {
+            true => {
+                self.scratch.resize(size * slot_len, 0);
+                BytesMut::new()
+            }
+            false => self.pool.take(size * slot_len),
+        };
+        self.names.resize(size, unsafe { mem::zeroed() });
+        if control {
+            self.controls.resize(size, [0; CONTROL_LEN / 8]);
+        }
+
+        let slots: &mut [u8] = match self.gro {
+            true => &mut self.scratch[..size * slot_len],
+            false => &mut buf,
+        };
+        let mut iovs: Vec<libc::iovec> = slots.chunks_mut(slot_len)
+            .map(|slot| libc::iovec { iov_base: slot.as_mut_ptr().cast(), iov_len: slot.len() })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = (0..size)
+            .map(|i| {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = (&mut self.names[i] as *mut libc::sockaddr_storage).cast();
+                msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+                msg.msg_hdr.msg_iov = &mut iovs[i];
+                msg.msg_hdr.msg_iovlen = 1;
+                if control {
+                    msg.msg_hdr.msg_control = self.controls[i].as_mut_ptr().cast();
+                    msg.msg_hdr.msg_controllen = CONTROL_LEN as _;
+                }
+                msg
+            })
+            .collect();
+
+        let received = unsafe { libc::recvmmsg(fd, msgs.as_mut_ptr(), size as _, 0, ptr::null_mut()) };
+        if received < 0 {
+            self.pool.give_back(buf);
+            return Err(io::Error::last_os_error());
+        }
+        let received = received as usize;
+
+        // 未使用的缓冲区归还缓冲池
+        if !self.gro {
+            let unused = buf.split_off(received * slot_len);
+            self.pool.give_back(unused);
+        }
+
+        for (i, msg) in msgs[..received].iter().enumerate() {
+            let len = msg.msg_len as usize;
+            let slot = match self.gro {
+                true => {
+                    let data = &self.scratch[i * slot_len..i * slot_len + len];
+                    let mut slot = self.pool.take(len);
+                    slot.copy_from_slice(data);
+                    slot
+                }
+                false => {
+                    let mut slot = buf.split_to(slot_len);
+                    slot.truncate(len);
+                    slot
+                }
+            };
+            let addr = unsafe { SockAddr::new(self.names[i], msg.msg_hdr.msg_namelen) };
+            let Some(addr) = addr.as_socket() else {
+                log::debug!("丢弃来自不支持的地址族的数据报");
+                continue;
+            };
+
+            let (local, segment) = match control {
+                true => unsafe { parse_control(&msg.msg_hdr) },
+                false => (None, None),
+            };
+            split_segments(slot, segment, addr, |datagram| self.received.push_back((datagram, addr, local)));
+        }
+
+        if received == size && size < self.max_size {
+            self.size = (size * 2).min(self.max_size);
+            log::trace!("批量接收数据报数增加到 {}", self.size);
+        } else if received < size / 4 && size > 1 {
+            self.size = size / 2;
+            log::trace!("批量接收数据报数减少到 {}", self.size);
+        }
+        Ok(())
+    }
+}
+
+/// 合并接收的数据报按分段大小拆分后逐个交给 `push`，最后一个分段可能较短
+fn split_segments<F>(mut datagram: BytesMut, segment: Option<usize>, addr: SocketAddr, mut push: F)
+where
+    F: FnMut(BytesMut),
+{
+    let len = datagram.len();
+    let segment = segment.unwrap_or(len).max(1);
+    if segment < len {
+        log::trace!("来自 {} 的合并数据报 ({} bytes) 按 {} bytes 拆分", addr, len, segment);
+    }
+    loop {
+        push(datagram.split_to(segment.min(datagram.len())));
+        if datagram.is_empty() {
+            break;
+        }
+    }
+}
+
+/// 将数据报划分为依次发送的消息，返回每条消息包含的数据报数，最多 [`MAX_BATCH`] 条消息
+///
+/// 不合并发送时每条消息一个数据报
+fn segment_groups(datagrams: &[Datagram<'_>], gso: bool) -> Vec<usize> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < datagrams.len() && groups.len() < MAX_BATCH {
+        let (first, target, local) = datagrams[start];
+        let mut count = 1;
+        let mut total = first.len();
+        while gso && !first.is_empty() && count < MAX_GSO_SEGMENTS {
+            let Some(&(buf, next_target, next_local)) = datagrams.get(start + count) else {
+                break;
+            };
+            if next_target != target || next_local != local || buf.len() > first.len() || total + buf.len() > MAX_GSO_BYTES {
+                break;
+            }
+            count += 1;
+            total += buf.len();
+            // 只有最后一个分段可以较短
+            if buf.len() < first.len() {
+                break;
+            }
+        }
+        groups.push(count);
+        start += count;
+    }
+    groups
+}
+
+/// 一次发送的消息数组，以及消息引用的目标地址和控制消息
+///
+/// 消息中的指针指向数组自身持有的内存 (在堆上，移动时不变) 和构造时给出的数据
+struct MsgBatch {
+    _iovs: Vec<libc::iovec>,
+    _targets: Vec<SockAddr>,
+    _controls: Vec<[u64; CONTROL_LEN / 8]>,
+    msgs: Vec<libc::mmsghdr>,
+}
+
+impl MsgBatch {
+    /// 构造多条消息，每条消息包含 `groups` 中对应数量的数据报，多于一个时附带 UDP_SEGMENT 控制消息
+    fn new(datagrams: &[Datagram<'_>], groups: &[usize]) -> Self {
+        let count: usize = groups.iter().sum();
+        let datagrams = &datagrams[..count];
+        let mut iovs: Vec<libc::iovec> = datagrams.iter()
+            .map(|(buf, _, _)| libc::iovec { iov_base: buf.as_ptr() as *mut libc::c_void, iov_len: buf.len() })
+            .collect();
+        let mut targets = Vec::with_capacity(groups.len());
+        let mut controls = vec![[0u64; CONTROL_LEN / 8]; groups.len()];
+
+        let mut msgs: Vec<libc::mmsghdr> = Vec::with_capacity(groups.len());
+        let mut start = 0;
+        for (i, &group) in groups.iter().enumerate() {
+            let (first, target, local) = datagrams[start];
+            targets.push(SockAddr::from(target));
+
+            let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+            msg.msg_hdr.msg_name = targets[i].as_ptr() as *mut libc::c_void;
+            msg.msg_hdr.msg_namelen = targets[i].len();
+            msg.msg_hdr.msg_iov = &mut iovs[start];
+            msg.msg_hdr.msg_iovlen = group as _;
+            msg.msg_hdr.msg_control = controls[i].as_mut_ptr().cast();
+            unsafe {
+                if let Some(local) = local {
+                    write_pktinfo(&mut msg.msg_hdr, local);
+                }
+                if group > 1 {
+                    log::trace!("合并发送 {} 个数据报到 {}", group, target);
+                    push_cmsg(&mut msg.msg_hdr, libc::SOL_UDP, libc::UDP_SEGMENT, first.len() as u16);
+                }
+            }
+            if msg.msg_hdr.msg_controllen == 0 {
+                msg.msg_hdr.msg_control = ptr::null_mut();
+            }
+            msgs.push(msg);
+            start += group;
+        }
+
+        Self { _iovs: iovs, _targets: targets, _controls: controls, msgs }
+    }
+
+    /// 以一次 `sendmmsg` 发送所有消息，返回成功发送的消息数；第一条消息就发送失败时返回错误
+    fn send(&mut self, fd: RawFd) -> io::Result<usize> {
+        let sent = unsafe { libc::sendmmsg(fd, self.msgs.as_mut_ptr(), self.msgs.len() as _, 0) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sent as usize)
+    }
+}
+
+// 消息中的指针只指向批次自己持有的缓冲区和构造时借用的数据报，可以随等待发送的任务转移到其他线程
+unsafe impl Send for MsgBatch {}
+
+/// 开启接收数据报本地地址的套接字选项
+fn enable_pktinfo(socket: &Socket, ipv6: bool) -> io::Result<()> {
+    let (level, name) = if ipv6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_PKTINFO)
+    };
+    set_int_option(socket.as_raw_fd(), level, name, 1)
+}
+
+/// 设置整数类型的套接字选项
+fn set_int_option(fd: RawFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            (&value as *const libc::c_int).cast(),
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 解析消息中的包信息和合并接收的分段大小
+///
+/// # Safety
+///
+/// `msg` 的控制消息必须是 `recvmsg` 返回的有效控制消息
+unsafe fn parse_control(msg: &libc::msghdr) -> (Option<PacketInfo>, Option<usize>) {
+    let mut local = None;
+    let mut segment = None;
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        local = local.or_else(|| parse_pktinfo(cmsg));
+        segment = segment.or_else(|| parse_gro(cmsg));
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    (local, segment)
+}
+
+/// 解析包信息控制消息
+///
+/// # Safety
+///
+/// `cmsg` 必须指向 `recvmsg` 返回的有效控制消息
+unsafe fn parse_pktinfo(cmsg: *const libc::cmsghdr) -> Option<PacketInfo> {
+    match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+        (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+            let info = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo);
+            Some(PacketInfo {
+                ip: IpAddr::V4(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr))),
+                ifindex: info.ipi_ifindex as u32,
+            })
+        }
+        (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+            let info = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in6_pktinfo);
+            Some(PacketInfo {
+                ip: IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)),
+                ifindex: info.ipi6_ifindex,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// 解析合并接收的分段大小控制消息
+///
+/// # Safety
+///
+/// `cmsg` 必须指向 `recvmsg` 返回的有效控制消息
+unsafe fn parse_gro(cmsg: *const libc::cmsghdr) -> Option<usize> {
+    match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+        (libc::SOL_UDP, libc::UDP_GRO) => {
+            let segment = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+            usize::try_from(segment).ok().filter(|&segment| segment > 0)
+        }
+        _ => None,
+    }
+}
+
+/// 以指定的本地地址作为源地址发送数据报
+fn send_with_pktinfo(fd: RawFd, buf: &[u8], target: SocketAddr, local: PacketInfo) -> io::Result<usize> {
+    let target = SockAddr::from(target);
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut control = [0u64; CONTROL_LEN / 8];
+
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_name = target.as_ptr() as *mut libc::c_void;
+        msg.msg_namelen = target.len();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        write_pktinfo(&mut msg, local);
+
+        let sent = libc::sendmsg(fd, &msg, 0);
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sent as usize)
+    }
+}
+
+/// 向消息追加指定源地址的包信息控制消息
+///
+/// # Safety
+///
+/// `msg.msg_control` 必须指向按 cmsghdr 对齐的缓冲区，且已有的控制消息之后还有足够的空间
+unsafe fn write_pktinfo(msg: &mut libc::msghdr, local: PacketInfo) {
+    match local.ip {
+        IpAddr::V4(ip) => {
+            let info = libc::in_pktinfo {
+                ipi_ifindex: 0,
+                ipi_spec_dst: libc::in_addr { s_addr: u32::from(ip).to_be() },
+                ipi_addr: libc::in_addr { s_addr: 0 },
+            };
+            push_cmsg(msg, libc::IPPROTO_IP, libc::IP_PKTINFO, info);
+        }
+        IpAddr::V6(ip) => {
+            // 链路本地地址只在接收数据报的接口上有效
+            let info = libc::in6_pktinfo {
+                ipi6_addr: libc::in6_addr { s6_addr: ip.octets() },
+                ipi6_ifindex: if ip.is_unicast_link_local() { local.ifindex } else { 0 },
+            };
+            push_cmsg(msg, libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, info);
+        }
+    }
+}
+
+/// 在消息已有的控制消息之后追加一条控制消息
+///
+/// # Safety
+///
+/// `msg.msg_control` 必须指向按 cmsghdr 对齐的缓冲区，且已有的控制消息之后还有至少
+/// `CMSG_SPACE(size_of::<T>())` 字节
+// musl 的 msg_controllen 不是 usize，需要保留类型转换
+#[allow(clippy::unnecessary_cast)]
+unsafe fn push_cmsg<T>(msg: &mut libc::msghdr, level: libc::c_int, kind: libc::c_int, data: T) {
+    let data_len = mem::size_of::<T>() as libc::c_uint;
+    let cmsg = msg.msg_control.cast::<u8>().add(msg.msg_controllen as usize).cast::<libc::cmsghdr>();
+    (*cmsg).cmsg_level = level;
+    (*cmsg).cmsg_type = kind;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+    ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut T, data);
+    msg.msg_controllen = (msg.msg_controllen as usize + libc::CMSG_SPACE(data_len) as usize) as _;
+}
+
+/// 通过 io_uring 收发的UDP套接字
+///
+/// 以多次接收 (multishot `recvmsg`) 从提供给内核的缓冲区环接收数据报，数据报复制到缓冲池的缓冲区后
+/// 放入接收队列，内核的缓冲区随即归还；接收队列已满时丢弃数据报，与套接字接收缓冲区溢出相同。
+/// 发送的数据复制到请求持有的缓冲区，等待发送完成的任务被取消时内核仍可安全读取
+#[cfg(feature = "io-uring")]
+struct UringSocket {
+    ring: Arc<uring::Ring>,
+    fd: RawFd,
+    /// 接收队列
+    inbox: Mutex<UringInbox>,
+}
+
+/// io_uring 接收的数据报队列
+#[cfg(feature = "io-uring")]
+struct UringInbox {
+    rx: mpsc::Receiver<Received>,
+    /// 等待可读时已经取出的数据报
+    peeked: Option<Received>,
+}
+
+#[cfg(feature = "io-uring")]
+impl UringSocket {
+    /// 创建套接字的 io_uring 实例并开始接收
+    ///
+    /// 参数:
+    /// - `fd`: 套接字，在实例关闭前保持打开
+    /// - `pktinfo`: 是否解析数据报到达的本地地址
+    /// - `gro`: 套接字是否开启了合并接收
+    fn new(fd: RawFd, pktinfo: bool, gro: bool) -> io::Result<Self> {
+        let ring = uring::Ring::new()?;
+        let (tx, rx) = mpsc::channel(URING_QUEUE_LEN);
+        let payload_len = if gro { GRO_SLOT_LEN } else { SLOT_LEN };
+        let control_len = if pktinfo || gro { CONTROL_LEN } else { 0 };
+        let slots = (URING_RECV_BYTES / payload_len) as u16;
+
+        let mut pool = BufferPool::new();
+        let result = ring.recv_multi(fd, slots, payload_len, control_len, move |out| {
+            let Some(addr) = parse_name(out.name_data()) else {
+                log::debug!("丢弃来自不支持的地址族的数据报");
+                return;
+            };
+            let (local, segment) = match out.control_data() {
+                [] => (None, None),
+                control => {
+                    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+                    msg.msg_control = control.as_ptr() as *mut libc::c_void;
+                    msg.msg_controllen = control.len() as _;
+                    unsafe { parse_control(&msg) }
+                }
+            };
+
+            let payload = out.payload_data();
+            let mut datagram = pool.take(payload.len());
+            datagram.copy_from_slice(payload);
+            split_segments(datagram, segment, addr, |datagram| {
+                if tx.try_send((datagram, addr, local)).is_err() {
+                    log::trace!("io_uring 接收队列已满，丢弃来自 {} 的数据报", addr);
+                }
+            });
+        });
+        if let Err(e) = result {
+            ring.close();
+            return Err(e);
+        }
+
+        Ok(Self { ring, fd, inbox: Mutex::new(UringInbox { rx, peeked: None }) })
+    }
+
+    /// 等待接收队列中有数据报
+    async fn readable(&self) -> io::Result<()> {
+        let mut inbox = self.inbox.lock().await;
+        if inbox.peeked.is_none() {
+            inbox.peeked = Some(inbox.rx.recv().await.ok_or_else(stopped)?);
+        }
+        Ok(())
+    }
+
+    /// 接收一个数据报
+    async fn recv(&self) -> io::Result<Received> {
+        let mut inbox = self.inbox.lock().await;
+        match inbox.peeked.take() {
+            Some(datagram) => Ok(datagram),
+            None => inbox.rx.recv().await.ok_or_else(stopped),
+        }
+    }
+
+    /// 尝试接收一个数据报，没有数据报时返回 [`io::ErrorKind::WouldBlock`]
+    fn try_recv(&self) -> io::Result<Received> {
+        let mut inbox = self.inbox.try_lock().map_err(|_| io::Error::from(io::ErrorKind::WouldBlock))?;
+        if let Some(datagram) = inbox.peeked.take() {
+            return Ok(datagram);
+        }
+        inbox.rx.try_recv().map_err(|e| match e {
+            mpsc::error::TryRecvError::Empty => io::Error::from(io::ErrorKind::WouldBlock),
+            mpsc::error::TryRecvError::Disconnected => stopped(),
+        })
+    }
+
+    /// 以链接的 `sendmsg` 请求按顺序发送多条消息，返回成功发送的消息数；第一条消息就发送失败时返回错误
+    async fn send_msgs(&self, datagrams: &[Datagram<'_>], groups: &[usize]) -> io::Result<usize> {
+        let count: usize = groups.iter().sum();
+        let datagrams = &datagrams[..count];
+        let mut data = Vec::with_capacity(datagrams.iter().map(|(buf, _, _)| buf.len()).sum());
+        for (buf, _, _) in datagrams {
+            data.extend_from_slice(buf);
+        }
+
+        let mut offset = 0;
+        let copied: Vec<Datagram<'_>> = datagrams.iter()
+            .map(|&(buf, target, local)| {
+                offset += buf.len();
+                (&data[offset - buf.len()..offset], target, local)
+            })
+            .collect();
+        let batch = MsgBatch::new(&copied, groups);
+        drop(copied);
+
+        // 消息只引用复制的数据和批次自身持有的内存
+        unsafe { self.ring.send_linked(self.fd, UringSend { _data: data, batch }) }.await
+    }
+}
+
+#[cfg(feature = "io-uring")]
+impl Drop for UringSocket {
+    fn drop(&mut self) {
+        self.ring.close();
+    }
+}
+
+/// io_uring 发送请求持有的数据和消息
+#[cfg(feature = "io-uring")]
+struct UringSend {
+    _data: Vec<u8>,
+    batch: MsgBatch,
+}
+
+#[cfg(feature = "io-uring")]
+impl AsRef<[libc::mmsghdr]> for UringSend {
+    fn as_ref(&self) -> &[libc::mmsghdr] {
+        &self.batch.msgs
+    }
+}
+
+// 消息中的指针只指向同一个结构持有的内存
+#[cfg(feature = "io-uring")]
+unsafe impl Send for UringSend {}
+
+/// io_uring 接收已停止时返回的错误
+#[cfg(feature = "io-uring")]
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "io_uring 接收已停止")
+}
+
+/// 解析接收结果中的来源地址
+#[cfg(feature = "io-uring")]
+fn parse_name(name: &[u8]) -> Option<SocketAddr> {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = name.len().min(mem::size_of::<libc::sockaddr_storage>());
+    unsafe {
+        ptr::copy_nonoverlapping(name.as_ptr(), (&mut storage as *mut libc::sockaddr_storage).cast::<u8>(), len);
+        SockAddr::new(storage, len as libc::socklen_t).as_socket()
+    }
+}
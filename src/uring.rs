@@ -0,0 +1,585 @@
+use std::alloc::{self, Layout};
+use std::any::Any;
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use bytes::Bytes;
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use io_uring::types::{BufRingEntry, RecvMsgOut};
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::oneshot;
+use tokio::time::{self, Duration};
+
+/// 每个 io_uring 实例的提交队列长度
+const ENTRIES: u32 = 256;
+/// 多次接收使用的缓冲区组，每个实例只有一组
+const BUF_GROUP: u16 = 0;
+/// 多次接收时每个缓冲区开头的接收结果头部长度 (`io_uring_recvmsg_out`)
+const RECVMSG_OUT_LEN: usize = 16;
+/// 缓冲区环的对齐，内核要求按页对齐
+const PAGE_SIZE: usize = 4096;
+
+/// 是否已启用 io_uring 后端
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 检测内核支持并启用 io_uring 后端，之后创建的UDP套接字和TUN设备都通过 io_uring 收发
+///
+/// 需要 Linux 6.0 起支持的多次接收 (multishot `recvmsg`)，启用前在回环地址上实际收发一个数据报确认可用
+pub async fn enable() -> io::Result<()> {
+    self_test().await
+        .map_err(|e| io::Error::new(e.kind(), format!("内核不支持 io_uring 多次接收: {}", e)))?;
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// 是否已启用 io_uring 后端
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 以临时套接字经多次接收收取一个发给自己的数据报
+async fn self_test() -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let ring = Ring::new()?;
+
+    // 多次接收无法启动时处理函数随之释放，等待结果的一端收到错误
+    let (tx, rx) = oneshot::channel();
+    let mut tx = Some(tx);
+    ring.recv_multi(socket.as_raw_fd(), 2, 64, 0, move |out| {
+        if let Some(tx) = tx.take() {
+            let _ = tx.send(out.payload_data().to_vec());
+        }
+    })?;
+    socket.send_to(b"vswitch", socket.local_addr()?)?;
+
+    let result = time::timeout(Duration::from_secs(1), rx).await;
+    ring.close();
+    match result {
+        Ok(Ok(payload)) if payload == b"vswitch" => Ok(()),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "多次接收未能启动")),
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "等待接收结果超时")),
+    }
+}
+
+/// io_uring 实例
+///
+/// 提交队列、完成队列和进行中的请求表由同一把锁保护，只在提交请求和处理完成事件时短暂持有，
+/// 不跨越等待。完成事件由创建时启动的任务在 io_uring 文件描述符可读时处理：单次请求的结果交给
+/// 等待的任务，持续接收的数据交给注册的处理函数后继续接收。
+///
+/// 每个实例最多有一个持续接收 ([`Ring::recv_multi`] 或 [`Ring::read_fixed`])，
+/// 每个套接字或TUN队列使用自己的实例
+pub struct Ring {
+    inner: Mutex<Inner>,
+}
+
+impl Ring {
+    /// 创建 io_uring 实例并启动处理完成事件的任务
+    pub fn new() -> io::Result<Arc<Self>> {
+        let ring = IoUring::new(ENTRIES)?;
+        let fd = AsyncFd::with_interest(ring.as_raw_fd(), Interest::READABLE)?;
+        let ring = Arc::new(Self {
+            inner: Mutex::new(Inner {
+                ring,
+                ops: HashMap::new(),
+                batches: HashMap::new(),
+                next_id: 1,
+                reader: None,
+                reader_op: None,
+                closed: false,
+            }),
+        });
+        tokio::spawn(drive(ring.clone(), fd));
+        Ok(ring)
+    }
+
+    /// 在套接字上启动多次接收 (multishot `recvmsg`)
+    ///
+    /// 内核从提供给它的 `slots` 个缓冲区中选取缓冲区写入数据报，每个缓冲区依次包含接收结果头部、
+    /// 来源地址、`control_len` 字节的控制消息和最多 `payload_len` 字节的数据报。
+    /// 处理函数返回后缓冲区立即归还内核；缓冲区暂时用尽等原因使多次接收结束时重新提交
+    ///
+    /// 参数:
+    /// - `fd`: 套接字，在实例关闭前必须保持打开
+    /// - `slots`: 缓冲区数，取整为2的幂
+    /// - `payload_len`: 每个缓冲区能容纳的数据报长度
+    /// - `control_len`: 控制消息的空间，不需要控制消息时为0
+    /// - `handler`: 处理收到的每个数据报
+    pub fn recv_multi<F>(&self, fd: RawFd, slots: u16, payload_len: usize, control_len: usize, handler: F) -> io::Result<()>
+    where
+        F: FnMut(RecvMsgOut<'_>) + Send + 'static,
+    {
+        let name_len = mem::size_of::<libc::sockaddr_storage>();
+        let buffers = BufRing::new(slots, RECVMSG_OUT_LEN + name_len + control_len + payload_len);
+
+        let mut inner = self.inner();
+        // 缓冲区环的内存随实例一直保持有效，停止接收时先注销
+        unsafe {
+            inner.ring.submitter().register_buf_ring_with_flags(buffers.entries as u64, buffers.count, BUF_GROUP, 0)?;
+        }
+
+        let mut msghdr: Box<libc::msghdr> = Box::new(unsafe { mem::zeroed() });
+        msghdr.msg_namelen = name_len as _;
+        msghdr.msg_controllen = control_len as _;
+        inner.start(Reader::Recv(MultiRecv {
+            fd,
+            msghdr,
+            buffers,
+            handler: Box::new(handler),
+        }));
+        Ok(())
+    }
+
+    /// 在文件上启动连续读取，每次读入注册到实例的 `len` 字节缓冲区 (`IORING_OP_READ_FIXED`)
+    ///
+    /// 同时只有一个读取请求，数据按读取的顺序交给处理函数，处理函数返回后提交下一次读取
+    ///
+    /// 参数:
+    /// - `fd`: 阻塞模式的文件描述符，在实例关闭前必须保持打开
+    /// - `len`: 读取缓冲区大小
+    /// - `handler`: 处理每次读到的数据
+    pub fn read_fixed<F>(&self, fd: RawFd, len: usize, handler: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        let mut buf = vec![0u8; len];
+        let iov = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+
+        let mut inner = self.inner();
+        // 缓冲区属于读取状态，随实例一直保持有效
+        unsafe { inner.ring.submitter().register_buffers(&[iov])? };
+        inner.start(Reader::Fixed(FixedRead {
+            fd,
+            buf,
+            handler: Box::new(handler),
+        }));
+        Ok(())
+    }
+
+    /// 向文件写入一个缓冲区
+    pub async fn write(&self, fd: RawFd, buf: Bytes) -> io::Result<usize> {
+        let entry = opcode::Write::new(types::Fd(fd), buf.as_ptr(), buf.len() as u32).build();
+        // 请求只引用 `buf` 的内存
+        let results = unsafe { self.submit(vec![entry], buf) }.await?;
+        result(results[0])
+    }
+
+    /// 向文件写入多个缓冲区组成的一次写入 (`writev`)
+    pub async fn write_vectored(&self, fd: RawFd, bufs: Vec<Bytes>) -> io::Result<usize> {
+        // 请求引用的 iovec 数组和缓冲区都在 `owned` 中
+        let (entry, owned) = {
+            let iovs: Vec<libc::iovec> = bufs.iter()
+                .map(|buf| libc::iovec { iov_base: buf.as_ptr() as *mut libc::c_void, iov_len: buf.len() })
+                .collect();
+            let entry = opcode::Writev::new(types::Fd(fd), iovs.as_ptr(), iovs.len() as u32).build();
+            (entry, Owned((iovs, bufs)))
+        };
+        let results = unsafe { self.submit(vec![entry], owned) }.await?;
+        result(results[0])
+    }
+
+    /// 在套接字上按顺序发送 `owned` 中的多条消息 (`sendmsg`)，返回成功发送的消息数
+    ///
+    /// 消息以链接 (`IOSQE_IO_LINK`) 提交，前一条完成后才发送下一条，某条消息发送失败时
+    /// 其后的消息不再发送。与 `sendmmsg` 相同，第一条消息就发送失败时返回错误
+    ///
+    /// # Safety
+    ///
+    /// 消息数组必须位于堆上 (移动 `owned` 不改变其地址)，消息引用的地址、数据和控制消息也必须属于 `owned`
+    pub async unsafe fn send_linked<T>(&self, fd: RawFd, owned: T) -> io::Result<usize>
+    where
+        T: AsRef<[libc::mmsghdr]> + Send + 'static,
+    {
+        let msgs = owned.as_ref();
+        let last = msgs.len().saturating_sub(1);
+        let entries = msgs.iter()
+            .enumerate()
+            .map(|(i, msg)| {
+                let entry = opcode::SendMsg::new(types::Fd(fd), &msg.msg_hdr).build();
+                if i < last { entry.flags(squeue::Flags::IO_LINK) } else { entry }
+            })
+            .collect();
+
+        let results = self.submit(entries, owned).await?;
+        match results.iter().position(|&result| result < 0) {
+            Some(0) => Err(io::Error::from_raw_os_error(-results[0])),
+            Some(sent) => Ok(sent),
+            None => Ok(results.len()),
+        }
+    }
+
+    /// 一起提交一组单次请求，等待全部完成后按顺序返回每个请求的结果 (负数为错误码)
+    ///
+    /// 等待的任务被取消时请求仍会执行完毕，`owned` 在全部请求完成后才释放
+    ///
+    /// # Safety
+    ///
+    /// 请求引用的内存必须属于 `owned`，或在请求完成前一直有效
+    async unsafe fn submit<T: Send + 'static>(&self, entries: Vec<squeue::Entry>, owned: T) -> io::Result<Vec<i32>> {
+        let rx = self.inner().submit(entries, Box::new(owned))?;
+        rx.await.map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "io_uring 已关闭"))
+    }
+
+    /// 停止持续接收并关闭实例，进行中的请求完成后处理完成事件的任务退出
+    pub fn close(&self) {
+        let mut inner = self.inner();
+        if inner.closed {
+            return;
+        }
+        inner.closed = true;
+
+        // 没有进行中的读取时取消请求同样会产生完成事件，唤醒处理完成事件的任务
+        let target = inner.reader_op.unwrap_or(0);
+        let id = inner.next_id();
+        inner.ops.insert(id, Op::Cancel);
+        inner.push(opcode::AsyncCancel::new(target).build().user_data(id));
+        inner.flush();
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// 将请求的结果转换为写入的字节数
+fn result(result: i32) -> io::Result<usize> {
+    if result < 0 {
+        return Err(io::Error::from_raw_os_error(-result));
+    }
+    Ok(result as usize)
+}
+
+/// 处理完成事件，直到实例关闭且没有进行中的请求
+async fn drive(ring: Arc<Ring>, fd: AsyncFd<RawFd>) {
+    loop {
+        let mut guard = match fd.readable().await {
+            Ok(guard) => guard,
+            Err(e) => {
+                log::error!("等待 io_uring 完成事件失败: {}", e);
+                return;
+            }
+        };
+
+        let mut inner = ring.inner();
+        if inner.complete() == 0 {
+            guard.clear_ready();
+        }
+        if inner.closed && inner.ops.is_empty() {
+            inner.stop_reader();
+            return;
+        }
+    }
+}
+
+struct Inner {
+    ring: IoUring,
+    /// 进行中的请求 (请求标识 -> 请求)
+    ops: HashMap<u64, Op>,
+    /// 等待全部完成的单次请求组
+    batches: HashMap<u64, Batch>,
+    next_id: u64,
+    /// 持续接收
+    reader: Option<Reader>,
+    /// 持续接收当前的请求标识
+    reader_op: Option<u64>,
+    /// 是否已关闭，关闭后不再提交新的请求
+    closed: bool,
+}
+
+/// 进行中的请求
+enum Op {
+    /// 单次请求组中的第 `index` 个请求
+    Part { batch: u64, index: usize },
+    /// 持续接收的请求
+    Read,
+    /// 取消请求
+    Cancel,
+}
+
+/// 一组一起提交的单次请求
+struct Batch {
+    /// 每个请求的结果
+    results: Vec<i32>,
+    /// 尚未完成的请求数
+    remaining: usize,
+    tx: oneshot::Sender<Vec<i32>>,
+    /// 请求引用的内存，全部请求完成前保持有效
+    _owned: Box<dyn Any + Send>,
+}
+
+/// 持续接收
+enum Reader {
+    Recv(MultiRecv),
+    Fixed(FixedRead),
+}
+
+/// 套接字上的多次接收
+struct MultiRecv {
+    fd: RawFd,
+    /// 提交时使用的消息头，只有地址和控制消息的长度有意义
+    msghdr: Box<libc::msghdr>,
+    /// 提供给内核的缓冲区
+    buffers: BufRing,
+    handler: Box<dyn FnMut(RecvMsgOut<'_>) + Send>,
+}
+
+/// 处理连续读取每次读到的数据
+type ReadHandler = Box<dyn FnMut(&[u8]) + Send>;
+
+/// 使用注册缓冲区的连续读取
+struct FixedRead {
+    fd: RawFd,
+    /// 注册到实例的读取缓冲区
+    buf: Vec<u8>,
+    handler: ReadHandler,
+}
+
+impl Inner {
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// 向提交队列追加一个请求，队列已满时先提交已有的请求
+    fn push(&mut self, entry: squeue::Entry) {
+        if self.ring.submission().is_full() {
+            self.flush();
+        }
+        // 调用方保证请求引用的内存在完成前有效
+        if unsafe { self.ring.submission().push(&entry) }.is_err() {
+            log::error!("io_uring 提交队列已满，请求被丢弃");
+        }
+    }
+
+    /// 提交队列中的请求，失败的请求留在队列中随下一次提交
+    fn flush(&mut self) {
+        if let Err(e) = self.ring.submit() {
+            log::debug!("提交 io_uring 请求失败: {}", e);
+        }
+    }
+
+    fn submit(&mut self, entries: Vec<squeue::Entry>, owned: Box<dyn Any + Send>) -> io::Result<oneshot::Receiver<Vec<i32>>> {
+        if self.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "io_uring 已关闭"));
+        }
+        // 链接的请求必须连续进入提交队列
+        let sq = self.ring.submission();
+        let free = sq.capacity() - sq.len();
+        drop(sq);
+        if entries.len() > free {
+            self.flush();
+        }
+
+        let batch = self.next_id();
+        let (tx, rx) = oneshot::channel();
+        let count = entries.len();
+        for (index, entry) in entries.into_iter().enumerate() {
+            let id = self.next_id();
+            self.ops.insert(id, Op::Part { batch, index });
+            self.push(entry.user_data(id));
+        }
+        self.batches.insert(batch, Batch {
+            results: vec![0; count],
+            remaining: count,
+            tx,
+            _owned: owned,
+        });
+        self.flush();
+        Ok(rx)
+    }
+
+    /// 开始持续接收
+    fn start(&mut self, reader: Reader) {
+        self.reader = Some(reader);
+        self.arm();
+        self.flush();
+    }
+
+    /// 提交持续接收的下一个请求
+    fn arm(&mut self) {
+        let id = self.next_id();
+        let entry = match &mut self.reader {
+            Some(Reader::Recv(recv)) => opcode::RecvMsgMulti::new(types::Fd(recv.fd), &*recv.msghdr, BUF_GROUP).build(),
+            Some(Reader::Fixed(read)) => opcode::ReadFixed::new(types::Fd(read.fd), read.buf.as_mut_ptr(), read.buf.len() as u32, 0).build(),
+            None => return,
+        };
+        self.ops.insert(id, Op::Read);
+        self.reader_op = Some(id);
+        self.push(entry.user_data(id));
+    }
+
+    /// 停止持续接收，注销提供给内核的缓冲区
+    fn stop_reader(&mut self) {
+        if let Some(Reader::Recv(_)) = self.reader.take() {
+            if let Err(e) = self.ring.submitter().unregister_buf_ring(BUF_GROUP) {
+                log::debug!("注销 io_uring 缓冲区环失败: {}", e);
+            }
+        }
+    }
+
+    /// 处理完成队列中的所有事件，返回处理的事件数
+    fn complete(&mut self) -> usize {
+        let events: Vec<(u64, i32, u32)> = self.ring.completion()
+            .map(|cqe| (cqe.user_data(), cqe.result(), cqe.flags()))
+            .collect();
+        for &(id, result, flags) in &events {
+            self.dispatch(id, result, flags);
+        }
+        self.flush();
+        events.len()
+    }
+
+    fn dispatch(&mut self, id: u64, result: i32, flags: u32) {
+        let more = cqueue::more(flags);
+        // 只有多次接收会产生多个完成事件，最后一个事件之前请求仍在进行
+        let op = match more {
+            true => self.ops.contains_key(&id).then_some(Op::Read),
+            false => self.ops.remove(&id),
+        };
+        match op {
+            Some(Op::Part { batch, index }) => {
+                let Some(entry) = self.batches.get_mut(&batch) else {
+                    return;
+                };
+                entry.results[index] = result;
+                entry.remaining -= 1;
+                if entry.remaining == 0 {
+                    if let Some(batch) = self.batches.remove(&batch) {
+                        let _ = batch.tx.send(batch.results);
+                    }
+                }
+            }
+            Some(Op::Read) => {
+                self.on_read(result, flags);
+                if !more && self.reader_op == Some(id) {
+                    self.reader_op = None;
+                    if !self.closed {
+                        self.arm();
+                    }
+                }
+            }
+            Some(Op::Cancel) | None => {}
+        }
+    }
+
+    /// 处理持续接收的一个结果
+    fn on_read(&mut self, result: i32, flags: u32) {
+        if result < 0 {
+            match -result {
+                // 缓冲区暂时用尽或被中断，之后重新提交
+                libc::ENOBUFS | libc::EAGAIN | libc::EINTR => {
+                    log::trace!("io_uring 接收暂停: {}", io::Error::from_raw_os_error(-result));
+                }
+                libc::ECANCELED => {}
+                errno => {
+                    log::error!("io_uring 接收失败，停止接收: {}", io::Error::from_raw_os_error(errno));
+                    self.stop_reader();
+                }
+            }
+            return;
+        }
+
+        match &mut self.reader {
+            Some(Reader::Recv(recv)) => {
+                let Some(bid) = cqueue::buffer_select(flags) else {
+                    return;
+                };
+                let buf = recv.buffers.slot(bid, result as usize);
+                match RecvMsgOut::parse(buf, &recv.msghdr) {
+                    Ok(out) => (recv.handler)(out),
+                    Err(()) => log::debug!("无法解析 io_uring 接收结果"),
+                }
+                recv.buffers.recycle(bid);
+            }
+            Some(Reader::Fixed(read)) => {
+                let len = (result as usize).min(read.buf.len());
+                (read.handler)(&read.buf[..len]);
+            }
+            None => {}
+        }
+    }
+}
+
+// 请求引用的内存由请求表持有，实例可以在线程间转移
+unsafe impl Send for Inner {}
+
+/// 持有请求引用的内存，其中的指针只指向同样被持有的缓冲区
+struct Owned<T>(T);
+
+unsafe impl<T> Send for Owned<T> {}
+
+/// 提供给内核的接收缓冲区环 (provided buffer ring)
+struct BufRing {
+    /// 环的条目，按页对齐
+    entries: *mut BufRingEntry,
+    /// 条目数，为2的幂
+    count: u16,
+    /// 所有缓冲区的内存
+    memory: Vec<u8>,
+    /// 每个缓冲区的大小
+    slot_len: usize,
+    /// 下一个归还的缓冲区在环中的位置
+    tail: u16,
+}
+
+impl BufRing {
+    fn new(count: u16, slot_len: usize) -> Self {
+        let count = count.max(1).next_power_of_two();
+        let layout = Self::layout(count);
+        let entries = unsafe { alloc::alloc_zeroed(layout) }.cast::<BufRingEntry>();
+        if entries.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        let mut ring = Self {
+            entries,
+            count,
+            memory: vec![0; count as usize * slot_len],
+            slot_len,
+            tail: 0,
+        };
+        for bid in 0..count {
+            ring.recycle(bid);
+        }
+        ring
+    }
+
+    fn layout(count: u16) -> Layout {
+        Layout::from_size_align(count as usize * mem::size_of::<BufRingEntry>(), PAGE_SIZE)
+            .expect("缓冲区环大小无效")
+    }
+
+    /// 获取缓冲区中内核写入的 `len` 字节
+    fn slot(&self, bid: u16, len: usize) -> &[u8] {
+        let start = bid as usize * self.slot_len;
+        &self.memory[start..start + len.min(self.slot_len)]
+    }
+
+    /// 将缓冲区归还内核
+    fn recycle(&mut self, bid: u16) {
+        let addr = self.memory[bid as usize * self.slot_len..].as_mut_ptr();
+        unsafe {
+            let entry = &mut *self.entries.add((self.tail & (self.count - 1)) as usize);
+            entry.set_addr(addr as u64);
+            entry.set_len(self.slot_len as u32);
+            entry.set_bid(bid);
+
+            // 条目写入后再发布新的尾部位置，内核才能看到完整的条目
+            self.tail = self.tail.wrapping_add(1);
+            let tail = &*(BufRingEntry::tail(self.entries) as *const AtomicU16);
+            tail.store(self.tail, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for BufRing {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.entries.cast(), Self::layout(self.count)) };
+    }
+}
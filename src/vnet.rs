@@ -0,0 +1,594 @@
+use std::io;
+use std::net::IpAddr;
+use bytes::{Bytes, BytesMut};
+use crate::buffer::{BufferPool, PacketBuf, HEADROOM};
+
+/// virtio-net 头部长度
+pub const HEADER_LEN: usize = 10;
+
+/// 头部标志: 校验和需要由接收方完成
+const F_NEEDS_CSUM: u8 = 1;
+/// 分段类型: 普通数据包
+const GSO_NONE: u8 = 0;
+/// 分段类型: IPv4 TCP超大分段
+const GSO_TCPV4: u8 = 1;
+/// 分段类型: IPv6 TCP超大分段
+const GSO_TCPV6: u8 = 4;
+/// 分段类型附加标志: 使用了ECN
+const GSO_ECN: u8 = 0x80;
+
+/// TCP标志位
+const TCP_FIN: u8 = 0x01;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_CWR: u8 = 0x80;
+/// TCP头部中校验和字段的偏移
+const TCP_CSUM_OFFSET: usize = 16;
+/// UDP头部中校验和字段的偏移
+const UDP_CSUM_OFFSET: usize = 6;
+/// IPv4 头部中校验和字段的偏移
+const IPV4_CSUM_OFFSET: usize = 10;
+/// IPv6 固定头部长度
+const IPV6_HEADER_LEN: usize = 40;
+/// 合并后的数据包最大长度
+const MAX_COALESCED_LEN: usize = u16::MAX as usize;
+
+/// virtio-net 头部 (`struct virtio_net_hdr`)
+///
+/// 启用 IFF_VNET_HDR 的TUN设备读写的每个数据包前都有该头部，描述超大分段的分段方式和
+/// 尚未计算的校验和。字段使用主机字节序
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VnetHeader {
+    pub flags: u8,
+    pub gso_type: u8,
+    /// 分段时需要复制的头部长度 (IP头部 + TCP头部)
+    pub hdr_len: u16,
+    /// 每个分段的最大负载长度
+    pub gso_size: u16,
+    /// 需要计算的校验和覆盖范围的起始位置
+    pub csum_start: u16,
+    /// 校验和字段相对 `csum_start` 的偏移
+    pub csum_offset: u16,
+}
+
+impl VnetHeader {
+    /// 解析头部
+    pub fn parse(buf: &[u8]) -> io::Result<Self> {
+        if buf.len() < HEADER_LEN {
+            return Err(invalid(format!("virtio-net 头部不完整: {} bytes", buf.len())));
+        }
+        let field = |offset: usize| u16::from_ne_bytes([buf[offset], buf[offset + 1]]);
+        Ok(Self {
+            flags: buf[0],
+            gso_type: buf[1],
+            hdr_len: field(2),
+            gso_size: field(4),
+            csum_start: field(6),
+            csum_offset: field(8),
+        })
+    }
+
+    /// 编码头部
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[0] = self.flags;
+        buf[1] = self.gso_type;
+        buf[2..4].copy_from_slice(&self.hdr_len.to_ne_bytes());
+        buf[4..6].copy_from_slice(&self.gso_size.to_ne_bytes());
+        buf[6..8].copy_from_slice(&self.csum_start.to_ne_bytes());
+        buf[8..10].copy_from_slice(&self.csum_offset.to_ne_bytes());
+        buf
+    }
+}
+
+/// 将从TUN设备读取的数据包还原为普通数据包
+///
+/// TCP超大分段按 `gso_size` 拆分为多个分段，每个分段重新计算IP头部和TCP校验和；
+/// 校验和尚未计算的数据包补全校验和
+///
+/// 参数:
+/// - `header`: 数据包的 virtio-net 头部
+/// - `packet`: 头部之后的IP数据包，普通数据包原地补全校验和
+/// - `pool`: 拆分出的分段从中取用缓冲区
+/// - `out`: 还原后的数据包追加到此处
+pub fn split(header: &VnetHeader, mut packet: PacketBuf, pool: &mut BufferPool, out: &mut Vec<PacketBuf>) -> io::Result<()> {
+    match header.gso_type & !GSO_ECN {
+        GSO_NONE => {
+            if header.flags & F_NEEDS_CSUM != 0 {
+                complete_checksum(&mut packet, header.csum_start as usize, header.csum_offset as usize)?;
+            }
+            out.push(packet);
+            Ok(())
+        }
+        GSO_TCPV4 => split_tcp(header, &packet, true, pool, out),
+        GSO_TCPV6 => split_tcp(header, &packet, false, pool, out),
+        other => Err(invalid(format!("不支持的分段类型: {}", other))),
+    }
+}
+
+/// 拆分TCP超大分段
+fn split_tcp(header: &VnetHeader, packet: &[u8], ipv4: bool, pool: &mut BufferPool, out: &mut Vec<PacketBuf>) -> io::Result<()> {
+    let ip_len = header.csum_start as usize;
+    let mss = header.gso_size as usize;
+    if mss == 0 || packet.len() < ip_len + 20 {
+        return Err(invalid(format!("无效的TCP超大分段: {} bytes", packet.len())));
+    }
+    let tcp_len = (packet[ip_len + 12] >> 4) as usize * 4;
+    let headers_len = ip_len + tcp_len;
+    if tcp_len < 20 || packet.len() <= headers_len {
+        return Err(invalid(format!("无效的TCP超大分段: {} bytes", packet.len())));
+    }
+
+    let (headers, payload) = packet.split_at(headers_len);
+    let seq = read_u32(headers, ip_len + 4);
+    let count = payload.len().div_ceil(mss);
+
+    for (index, chunk) in payload.chunks(mss).enumerate() {
+        let mut segment = PacketBuf::new(pool.take(HEADROOM + headers_len + chunk.len()));
+        segment[..headers_len].copy_from_slice(headers);
+        segment[headers_len..].copy_from_slice(chunk);
+
+        if ipv4 {
+            let id = read_u16(&segment, 4).wrapping_add(index as u16);
+            write_u16(&mut segment, 4, id);
+        }
+        set_ip_length(&mut segment, ip_len, ipv4);
+
+        // 只有最后一个分段保留 FIN 和 PSH，只有第一个分段保留 CWR
+        let tcp = ip_len;
+        write_u32(&mut segment, tcp + 4, seq.wrapping_add((index * mss) as u32));
+        if index + 1 < count {
+            segment[tcp + 13] &= !(TCP_FIN | TCP_PSH);
+        }
+        if index > 0 {
+            segment[tcp + 13] &= !TCP_CWR;
+        }
+
+        write_u16(&mut segment, tcp + TCP_CSUM_OFFSET, 0);
+        let pseudo = pseudo_header_sum(&segment, ipv4, segment.len() - ip_len);
+        let csum = !fold(sum(&segment[tcp..], pseudo));
+        write_u16(&mut segment, tcp + TCP_CSUM_OFFSET, csum);
+
+        out.push(segment);
+    }
+    Ok(())
+}
+
+/// 补全校验和: 校验和字段中已有伪头部的部分和，覆盖 `start` 到数据包结尾
+fn complete_checksum(packet: &mut [u8], start: usize, offset: usize) -> io::Result<()> {
+    if start + offset + 2 > packet.len() {
+        return Err(invalid(format!("校验和位置超出数据包: {} + {}", start, offset)));
+    }
+    let mut csum = !fold(sum(&packet[start..], 0));
+    // UDP校验和为0表示未计算校验和，计算结果为0时写入全1
+    if offset == UDP_CSUM_OFFSET && csum == 0 {
+        csum = 0xffff;
+    }
+    write_u16(packet, start + offset, csum);
+    Ok(())
+}
+
+/// 可以合并的TCP分段
+struct TcpSegment {
+    ipv4: bool,
+    ip_len: usize,
+    tcp_len: usize,
+    /// 连接标识 (源地址, 目标地址, 源端口, 目标端口)
+    flow: (IpAddr, IpAddr, u16, u16),
+    seq: u32,
+    flags: u8,
+    payload_len: usize,
+}
+
+impl TcpSegment {
+    /// 解析数据包，不是TCP或带有IPv4选项、IPv6扩展头部、分片时返回 `None`
+    fn parse(packet: &[u8]) -> Option<Self> {
+        let (ipv4, ip_len, src, dst) = match packet.first()? >> 4 {
+            4 => {
+                // 只合并没有选项、没有分片的IPv4数据包
+                if packet.len() < 20 || packet[0] & 0x0f != 5 || packet[9] != 6 || read_u16(packet, 6) & 0x3fff != 0 {
+                    return None;
+                }
+                if read_u16(packet, 2) as usize != packet.len() {
+                    return None;
+                }
+                let src: [u8; 4] = packet[12..16].try_into().ok()?;
+                let dst: [u8; 4] = packet[16..20].try_into().ok()?;
+                (true, 20, IpAddr::from(src), IpAddr::from(dst))
+            }
+            6 => {
+                if packet.len() < IPV6_HEADER_LEN || packet[6] != 6 {
+                    return None;
+                }
+                if read_u16(packet, 4) as usize + IPV6_HEADER_LEN != packet.len() {
+                    return None;
+                }
+                let src: [u8; 16] = packet[8..24].try_into().ok()?;
+                let dst: [u8; 16] = packet[24..40].try_into().ok()?;
+                (false, IPV6_HEADER_LEN, IpAddr::from(src), IpAddr::from(dst))
+            }
+            _ => return None,
+        };
+
+        if packet.len() < ip_len + 20 {
+            return None;
+        }
+        let tcp_len = (packet[ip_len + 12] >> 4) as usize * 4;
+        if tcp_len < 20 || packet.len() < ip_len + tcp_len {
+            return None;
+        }
+
+        Some(Self {
+            ipv4,
+            ip_len,
+            tcp_len,
+            flow: (src, dst, read_u16(packet, ip_len), read_u16(packet, ip_len + 2)),
+            seq: read_u32(packet, ip_len + 4),
+            flags: packet[ip_len + 13],
+            payload_len: packet.len() - ip_len - tcp_len,
+        })
+    }
+
+    /// 是否可以作为合并的分段: 只带 ACK (和 PSH) 标志且有负载
+    fn mergeable(&self) -> bool {
+        self.flags & !TCP_PSH == TCP_ACK && self.payload_len > 0
+    }
+}
+
+/// 正在合并的TCP分段
+struct Merge {
+    /// 合并后的数据包，第一个分段的头部加上所有分段的负载
+    packet: BytesMut,
+    ipv4: bool,
+    ip_len: usize,
+    tcp_len: usize,
+    flow: (IpAddr, IpAddr, u16, u16),
+    /// 下一个分段应有的序号
+    next_seq: u32,
+    /// 每个分段的负载长度，由第一个分段决定
+    gso_size: usize,
+    /// 已合并的分段数
+    count: usize,
+    /// 是否不再接受分段 (收到了较短或带 PSH 的分段)
+    closed: bool,
+}
+
+impl Merge {
+    /// 尝试将分段追加到合并的数据包
+    fn append(&mut self, packet: &[u8], segment: &TcpSegment) -> bool {
+        let headers_len = self.ip_len + self.tcp_len;
+        let compatible = !self.closed
+            && segment.seq == self.next_seq
+            && segment.ipv4 == self.ipv4
+            && segment.tcp_len == self.tcp_len
+            && segment.mergeable()
+            && segment.payload_len <= self.gso_size
+            && self.packet.len() + segment.payload_len <= MAX_COALESCED_LEN
+            && same_headers(&self.packet, packet, self.ipv4, self.ip_len, headers_len);
+        if !compatible {
+            return false;
+        }
+
+        self.packet.extend_from_slice(&packet[headers_len..]);
+        self.next_seq = self.next_seq.wrapping_add(segment.payload_len as u32);
+        self.count += 1;
+        // 最后一个分段的 PSH 标志保留在合并的数据包上
+        if segment.flags & TCP_PSH != 0 {
+            self.packet[self.ip_len + 13] |= TCP_PSH;
+            self.closed = true;
+        }
+        if segment.payload_len < self.gso_size {
+            self.closed = true;
+        }
+        true
+    }
+
+    /// 生成合并后的数据包及其头部，只有一个分段时原样返回
+    fn finish(mut self, original: Bytes) -> (VnetHeader, Bytes) {
+        if self.count == 1 {
+            return (VnetHeader::default(), original);
+        }
+
+        let ip_len = self.ip_len;
+        set_ip_length(&mut self.packet, ip_len, self.ipv4);
+
+        // 校验和字段填入伪头部的部分和，由接收方补全
+        let pseudo = pseudo_header_sum(&self.packet, self.ipv4, self.packet.len() - ip_len);
+        write_u16(&mut self.packet, ip_len + TCP_CSUM_OFFSET, fold(pseudo));
+
+        let header = VnetHeader {
+            flags: F_NEEDS_CSUM,
+            gso_type: if self.ipv4 { GSO_TCPV4 } else { GSO_TCPV6 },
+            hdr_len: (ip_len + self.tcp_len) as u16,
+            gso_size: self.gso_size as u16,
+            csum_start: ip_len as u16,
+            csum_offset: TCP_CSUM_OFFSET as u16,
+        };
+        (header, self.packet.freeze())
+    }
+}
+
+/// 将要写入TUN设备的数据包中同一TCP连接的连续分段合并为超大分段
+///
+/// 只合并序号连续、头部除长度和序号外相同、只带 ACK (和 PSH) 标志的分段。
+/// 同一连接的分段保持原有顺序，其他数据包原样返回
+///
+/// 返回:
+/// - 每个要写入的数据包及其 virtio-net 头部
+pub fn coalesce(packets: impl IntoIterator<Item = Bytes>) -> Vec<(VnetHeader, Bytes)> {
+    /// 输出的一项: 原样写入的数据包或合并中的数据包
+    enum Output {
+        Single(Bytes),
+        Merged(usize, Bytes),
+    }
+
+    let mut output = Vec::new();
+    let mut merges: Vec<Merge> = Vec::new();
+
+    for packet in packets {
+        let Some(segment) = TcpSegment::parse(&packet) else {
+            output.push(Output::Single(packet));
+            continue;
+        };
+
+        // 同一连接最近的合并数据包不能追加时关闭它，保证后续分段不会排到当前分段之前
+        if let Some(merge) = merges.iter_mut().rev().find(|merge| merge.flow == segment.flow) {
+            if merge.append(&packet, &segment) {
+                continue;
+            }
+            merge.closed = true;
+        }
+
+        if !segment.mergeable() {
+            output.push(Output::Single(packet));
+            continue;
+        }
+
+        merges.push(Merge {
+            packet: BytesMut::from(&packet[..]),
+            ipv4: segment.ipv4,
+            ip_len: segment.ip_len,
+            tcp_len: segment.tcp_len,
+            flow: segment.flow,
+            next_seq: segment.seq.wrapping_add(segment.payload_len as u32),
+            gso_size: segment.payload_len,
+            count: 1,
+            closed: segment.flags & TCP_PSH != 0,
+        });
+        output.push(Output::Merged(merges.len() - 1, packet));
+    }
+
+    let mut merges: Vec<Option<Merge>> = merges.into_iter().map(Some).collect();
+    output.into_iter()
+        .map(|item| match item {
+            Output::Single(packet) => (VnetHeader::default(), packet),
+            Output::Merged(index, original) => match merges[index].take() {
+                Some(merge) => merge.finish(original),
+                None => (VnetHeader::default(), original),
+            },
+        })
+        .collect()
+}
+
+/// 比较两个分段的头部是否只有长度、标识、校验和、序号和标志不同
+fn same_headers(first: &[u8], packet: &[u8], ipv4: bool, ip_len: usize, headers_len: usize) -> bool {
+    let ip_same = if ipv4 {
+        // 版本、服务类型、分片标志、TTL、协议和地址
+        first[..2] == packet[..2] && first[6..10] == packet[6..10] && first[12..20] == packet[12..20]
+    } else {
+        // 版本、流量类别、流标签、下一头部、跳数限制和地址
+        first[..4] == packet[..4] && first[6..40] == packet[6..40]
+    };
+    let tcp = ip_len;
+    // 端口、确认号、窗口、紧急指针和选项
+    ip_same
+        && first[tcp..tcp + 4] == packet[tcp..tcp + 4]
+        && first[tcp + 8..tcp + 12] == packet[tcp + 8..tcp + 12]
+        && first[tcp + 14..tcp + 16] == packet[tcp + 14..tcp + 16]
+        && first[tcp + 18..headers_len] == packet[tcp + 18..headers_len]
+}
+
+/// 按数据包实际长度更新IP头部的长度字段，IPv4 同时重新计算头部校验和
+fn set_ip_length(packet: &mut [u8], ip_len: usize, ipv4: bool) {
+    let total = packet.len();
+    if ipv4 {
+        write_u16(packet, 2, total as u16);
+        write_u16(packet, IPV4_CSUM_OFFSET, 0);
+        let csum = !fold(sum(&packet[..ip_len], 0));
+        write_u16(packet, IPV4_CSUM_OFFSET, csum);
+    } else {
+        write_u16(packet, 4, (total - IPV6_HEADER_LEN) as u16);
+    }
+}
+
+/// 计算TCP伪头部的部分和
+fn pseudo_header_sum(packet: &[u8], ipv4: bool, tcp_len: usize) -> u64 {
+    let addrs = if ipv4 { &packet[12..20] } else { &packet[8..40] };
+    sum(addrs, 6 + tcp_len as u64)
+}
+
+/// 按16位字累加 (互联网校验和)，奇数长度时最后一个字节补0
+pub fn sum(data: &[u8], initial: u64) -> u64 {
+    let mut chunks = data.chunks_exact(2);
+    let mut acc = initial;
+    for chunk in &mut chunks {
+        acc += u16::from_be_bytes([chunk[0], chunk[1]]) as u64;
+    }
+    if let [last] = chunks.remainder() {
+        acc += (*last as u64) << 8;
+    }
+    acc
+}
+
+/// 将累加和折叠为16位
+pub fn fold(mut acc: u64) -> u16 {
+    while acc > 0xffff {
+        acc = (acc & 0xffff) + (acc >> 16);
+    }
+    acc as u16
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 构造校验和正确的TCP数据包
+    fn tcp_packet(ipv4: bool, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let ip_len = if ipv4 { 20 } else { IPV6_HEADER_LEN };
+        let mut packet = vec![0u8; ip_len + 20];
+        if ipv4 {
+            packet[0] = 0x45;
+            write_u16(&mut packet, 4, 0x1234);
+            packet[8] = 64;
+            packet[9] = 6;
+            packet[12..16].copy_from_slice(&[10, 0, 0, 1]);
+            packet[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        } else {
+            packet[0] = 0x60;
+            packet[6] = 6;
+            packet[7] = 64;
+            packet[8] = 0xfd;
+            packet[23] = 1;
+            packet[24] = 0xfd;
+            packet[39] = 2;
+        }
+        write_u16(&mut packet, ip_len, 40000);
+        write_u16(&mut packet, ip_len + 2, 80);
+        write_u32(&mut packet, ip_len + 4, seq);
+        write_u32(&mut packet, ip_len + 8, 1);
+        packet[ip_len + 12] = 5 << 4;
+        packet[ip_len + 13] = flags;
+        write_u16(&mut packet, ip_len + 14, 65535);
+        packet.extend_from_slice(payload);
+
+        set_ip_length(&mut packet, ip_len, ipv4);
+        let pseudo = pseudo_header_sum(&packet, ipv4, packet.len() - ip_len);
+        let csum = !fold(sum(&packet[ip_len..], pseudo));
+        write_u16(&mut packet, ip_len + TCP_CSUM_OFFSET, csum);
+        packet
+    }
+
+    /// 检查IPv4头部校验和与TCP校验和
+    fn assert_checksums(packet: &[u8], ipv4: bool) {
+        let ip_len = if ipv4 { 20 } else { IPV6_HEADER_LEN };
+        if ipv4 {
+            assert_eq!(fold(sum(&packet[..ip_len], 0)), 0xffff, "IPv4头部校验和错误");
+        }
+        let pseudo = pseudo_header_sum(packet, ipv4, packet.len() - ip_len);
+        assert_eq!(fold(sum(&packet[ip_len..], pseudo)), 0xffff, "TCP校验和错误");
+    }
+
+    fn round_trip(ipv4: bool) {
+        let ip_len = if ipv4 { 20 } else { IPV6_HEADER_LEN };
+        let payload: Vec<u8> = (0..3500u32).map(|i| i as u8).collect();
+        let original = tcp_packet(ipv4, 1000, TCP_ACK | TCP_PSH, &payload);
+        let header = VnetHeader {
+            flags: F_NEEDS_CSUM,
+            gso_type: if ipv4 { GSO_TCPV4 } else { GSO_TCPV6 },
+            hdr_len: (ip_len + 20) as u16,
+            gso_size: 1000,
+            csum_start: ip_len as u16,
+            csum_offset: TCP_CSUM_OFFSET as u16,
+        };
+
+        let mut pool = BufferPool::new();
+        let mut segments = Vec::new();
+        split(&header, PacketBuf::copy_from_slice(&original), &mut pool, &mut segments).unwrap();
+        assert_eq!(segments.len(), 4);
+        for (index, segment) in segments.iter().enumerate() {
+            assert_checksums(segment, ipv4);
+            assert_eq!(read_u32(segment, ip_len + 4), 1000 + index as u32 * 1000);
+            let expected_flags = if index == 3 { TCP_ACK | TCP_PSH } else { TCP_ACK };
+            assert_eq!(segment[ip_len + 13], expected_flags);
+        }
+        assert_eq!(segments[3].len(), ip_len + 20 + 500);
+
+        let coalesced = coalesce(segments.into_iter().map(PacketBuf::into_bytes));
+        assert_eq!(coalesced.len(), 1);
+        let (merged_header, merged) = &coalesced[0];
+        assert_eq!(*merged_header, header);
+
+        // 合并后的校验和字段只有伪头部的部分和，补全后与原数据包相同
+        let mut merged = merged.to_vec();
+        complete_checksum(&mut merged, header.csum_start as usize, header.csum_offset as usize).unwrap();
+        assert_checksums(&merged, ipv4);
+        assert_eq!(merged, original);
+    }
+
+    #[test]
+    fn split_and_coalesce_ipv4() {
+        round_trip(true);
+    }
+
+    #[test]
+    fn split_and_coalesce_ipv6() {
+        round_trip(false);
+    }
+
+    #[test]
+    fn coalesce_keeps_out_of_order_segments() {
+        let payload = [7u8; 100];
+        let packets = vec![
+            Bytes::from(tcp_packet(true, 0, TCP_ACK, &payload)),
+            Bytes::from(tcp_packet(true, 200, TCP_ACK, &payload)),
+            Bytes::from(tcp_packet(true, 100, TCP_ACK, &payload)),
+        ];
+
+        let coalesced = coalesce(packets.clone());
+        let output: Vec<Bytes> = coalesced.iter().map(|(_, packet)| packet.clone()).collect();
+        assert_eq!(output, packets);
+        assert!(coalesced.iter().all(|(header, _)| *header == VnetHeader::default()));
+    }
+
+    #[test]
+    fn split_rejects_truncated_segment() {
+        let header = VnetHeader {
+            flags: F_NEEDS_CSUM,
+            gso_type: GSO_TCPV4,
+            hdr_len: 40,
+            gso_size: 1000,
+            csum_start: 20,
+            csum_offset: TCP_CSUM_OFFSET as u16,
+        };
+        let packet = tcp_packet(true, 0, TCP_ACK, &[]);
+        let mut pool = BufferPool::new();
+        let mut out = Vec::new();
+        assert!(split(&header, PacketBuf::copy_from_slice(&packet[..30]), &mut pool, &mut out).is_err());
+        assert!(split(&header, PacketBuf::copy_from_slice(&packet), &mut pool, &mut out).is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = VnetHeader {
+            flags: F_NEEDS_CSUM,
+            gso_type: GSO_TCPV6 | GSO_ECN,
+            hdr_len: 60,
+            gso_size: 1440,
+            csum_start: 40,
+            csum_offset: 16,
+        };
+        assert_eq!(VnetHeader::parse(&header.encode()).unwrap(), header);
+        assert!(VnetHeader::parse(&[0; HEADER_LEN - 1]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use std::io;
+use std::net::SocketAddr;
+use bytes::Bytes;
+use futures::stream::SplitStream;
+use futures::{SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::WebSocketStream;
+use crate::stream::{check_frame, SEND_QUEUE_LEN};
+
+/// 接受WebSocket握手，只允许指定路径
+pub async fn accept<S>(stream: S, path: &str) -> io::Result<WebSocketStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // 回调的错误类型由WebSocket库规定
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        if request.uri().path() == path {
+            Ok(response)
+        } else {
+            let mut error = ErrorResponse::new(None);
+            *error.status_mut() = StatusCode::NOT_FOUND;
+            Err(error)
+        }
+    };
+
+    tokio_tungstenite::accept_hdr_async(stream, callback).await
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("WebSocket握手失败: {}", e)))
+}
+
+/// 在已建立的连接上发起WebSocket握手
+///
+/// 参数:
+/// - `stream`: 到服务器 (或经代理到服务器) 的连接
+/// - `host`: 请求中的主机名
+/// - `path`: 请求路径
+pub async fn connect<S>(stream: S, host: &str, path: &str) -> io::Result<WebSocketStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let url = format!("ws://{}{}", host, path);
+    let (ws, _) = tokio_tungstenite::client_async(url, stream).await
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("WebSocket握手失败: {}", e)))?;
+    Ok(ws)
+}
+
+/// 拆分WebSocket连接
+///
+/// 每个二进制WebSocket消息承载一个完整的协议消息。
+///
+/// 返回:
+/// - 发送队列: 放入的消息帧由写任务以二进制消息发出，所有发送端释放后发送关闭帧
+/// - 写任务
+/// - 消息帧流: 收到关闭帧或连接断开时结束，收到无效消息时产生错误后结束
+pub fn split<S>(
+    ws: WebSocketStream<S>,
+    peer: SocketAddr,
+) -> (mpsc::Sender<Bytes>, JoinHandle<()>, impl Stream<Item = io::Result<Bytes>> + Send + Unpin)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sink, stream) = ws.split();
+    let (tx, mut rx) = mpsc::channel::<Bytes>(SEND_QUEUE_LEN);
+
+    let writer = tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            let mut result = sink.feed(WsMessage::Binary(frame.to_vec())).await;
+            while result.is_ok() {
+                match rx.try_recv() {
+                    Ok(frame) => result = sink.feed(WsMessage::Binary(frame.to_vec())).await,
+                    Err(_) => break,
+                }
+            }
+
+            if let Err(e) = result.and(sink.flush().await) {
+                log::debug!("向 {} 写入WebSocket连接错误: {}", peer, e);
+                return;
+            }
+        }
+
+        let _ = sink.close().await;
+    });
+
+    let frames = Box::pin(futures::stream::unfold(Some(stream), |stream| async move {
+        let mut stream = stream?;
+        match read_frame(&mut stream).await {
+            Ok(Some(frame)) => Some((Ok(frame), Some(stream))),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    }));
+
+    (tx, writer, frames)
+}
+
+/// 读取下一个承载协议消息的二进制WebSocket消息
+///
+/// 控制消息由WebSocket库处理后跳过；收到关闭帧或连接断开时返回 `None`
+async fn read_frame<S>(stream: &mut SplitStream<WebSocketStream<S>>) -> io::Result<Option<Bytes>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let message = match stream.next().await {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => return Ok(None),
+        };
+
+        match message {
+            WsMessage::Binary(data) => {
+                // 消息头中的长度必须与WebSocket消息长度一致
+                check_frame(&data)?;
+                return Ok(Some(Bytes::from(data)));
+            }
+            WsMessage::Close(_) => return Ok(None),
+            WsMessage::Text(_) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "不支持文本消息"));
+            }
+            WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_) => continue,
+        }
+    }
+}
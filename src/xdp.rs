@@ -0,0 +1,997 @@
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicU32, Ordering};
+use bytes::BytesMut;
+use dashmap::DashMap;
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+use tokio::time::{Duration, Instant};
+use crate::buffer::BufferPool;
+use crate::vnet;
+
+/// UMEM 中每个帧的大小
+const FRAME_SIZE: usize = 4096;
+/// 每个队列用于接收的帧数，也是接收环和填充环的长度
+const RX_FRAMES: u32 = 1024;
+/// 每个队列用于发送的帧数，也是发送环和完成环的长度
+const TX_FRAMES: u32 = 1024;
+/// 内核在接收的帧前面保留的空间 (`XDP_PACKET_HEADROOM`)
+const KERNEL_HEADROOM: usize = 256;
+/// 唤醒内核发送时最多重试的次数，复制模式下每次系统调用只发送一部分帧
+const MAX_KICKS: usize = 64;
+/// 加载失败时获取校验器日志的缓冲区大小
+const VERIFIER_LOG_LEN: usize = 64 * 1024;
+
+const ETH_HEADER_LEN: usize = 14;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const ETH_P_IPV4: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86dd;
+const IPPROTO_UDP: u8 = 17;
+/// 发出的IP包的TTL和跳数限制
+const TTL: u8 = 64;
+
+/// bpf 系统调用的命令和类型 (linux/bpf.h)
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_LINK_CREATE: libc::c_long = 28;
+const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+const BPF_PROG_TYPE_XDP: u32 = 6;
+const BPF_XDP: u32 = 37;
+const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+const XDP_PASS: i32 = 2;
+
+/// 网络接口上的 AF_XDP 快速路径
+///
+/// 在网络接口上挂载一个小的 XDP 程序，把目的端口为监听端口的UDP数据报直接重定向到用户态的
+/// AF_XDP 套接字 (每个接收队列一个)，不经过内核的IP和UDP协议栈；其他数据包、IP分片、带IPv4选项的
+/// 数据报和没有 AF_XDP 套接字的队列仍交给内核，由普通UDP套接字接收。
+///
+/// 回复截取到的数据报时，以学到的二层地址自行构造以太网帧，经收到数据报的队列发出；
+/// 没有学到二层地址 (没有联系过本机) 或超过接口MTU的数据报仍由普通UDP套接字发送。
+/// 释放时卸载 XDP 程序
+pub struct Xdp {
+    // 先于套接字释放，卸载程序后数据包不再重定向
+    _link: OwnedFd,
+    /// 网络接口名称
+    interface: String,
+    /// 每个接收队列的 AF_XDP 套接字
+    queues: Vec<XskQueue>,
+    /// 从截取的数据报学到的回复地址 (远端地址 -> 回复地址)
+    neighbors: DashMap<SocketAddr, Neighbor>,
+    /// 经 AF_XDP 发送的最大帧长度 (接口MTU加以太网头部)
+    max_frame: usize,
+    _program: OwnedFd,
+    _map: OwnedFd,
+}
+
+impl Xdp {
+    /// 在网络接口的每个接收队列上创建 AF_XDP 套接字，并挂载把发往 `listeners` 的数据报重定向到这些套接字的 XDP 程序
+    ///
+    /// 监听地址为未指定地址时截取该地址族发往其端口的所有数据报，否则同时匹配目的地址和端口。
+    /// 截取的数据报不经过内核协议栈，netfilter 防火墙规则对其不再生效。
+    /// 网卡驱动支持时以驱动模式挂载，否则使用通用模式；需要 Linux 5.9 起支持的 BPF 链接和
+    /// `CAP_NET_ADMIN`、`CAP_BPF` 权限
+    pub fn attach(interface: &str, listeners: &[SocketAddr]) -> io::Result<Self> {
+        let name = CString::new(interface).map_err(|_| invalid(format!("网络接口名称无效: {}", interface)))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("网络接口不存在: {}", interface)));
+        }
+
+        let mtu: usize = fs::read_to_string(format!("/sys/class/net/{}/mtu", interface))?
+            .trim()
+            .parse()
+            .map_err(|_| invalid(format!("无法读取网络接口 {} 的MTU", interface)))?;
+        let max_frame = ETH_HEADER_LEN + mtu;
+        if max_frame > FRAME_SIZE - KERNEL_HEADROOM {
+            return Err(invalid(format!("网络接口 {} 的MTU {} 超过 AF_XDP 帧的大小", interface, mtu)));
+        }
+
+        let queue_count = rx_queue_count(interface)?;
+        let map = create_xsk_map(queue_count)?;
+        let queues = (0..queue_count)
+            .map(|queue| {
+                let socket = XskQueue::new(ifindex, queue)?;
+                update_xsk_map(&map, queue, socket.fd.get_ref().as_raw_fd())?;
+                Ok(socket)
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|e| io::Error::new(e.kind(), format!("创建 AF_XDP 套接字失败: {}", e)))?;
+
+        let program = load_program(&steering_program(listeners, map.as_raw_fd()))?;
+        let link = attach_program(&program, ifindex)
+            .map_err(|e| io::Error::new(e.kind(), format!("挂载 XDP 程序到 {} 失败: {}", interface, e)))?;
+        log::info!("已在网络接口 {} 的 {} 个接收队列上启用 AF_XDP，截取发往 {:?} 的UDP数据报", interface, queue_count, listeners);
+
+        Ok(Self {
+            _link: link,
+            interface: interface.to_string(),
+            queues,
+            neighbors: DashMap::new(),
+            max_frame,
+            _program: program,
+            _map: map,
+        })
+    }
+
+    /// 获取 AF_XDP 套接字 (接收队列) 的数量
+    pub fn queue_count(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// 从指定队列的 AF_XDP 套接字接收一个截取到的数据报，并记录回复来源地址所需的地址
+    ///
+    /// 一次取出接收环中已到达的所有帧，数据报复制到缓冲池后帧立即归还内核
+    pub async fn recv(&self, queue: usize) -> io::Result<(BytesMut, SocketAddr)> {
+        let queue = &self.queues[queue];
+        loop {
+            if let Some(datagram) = queue.rx().pending.pop_front() {
+                return Ok(datagram);
+            }
+            let mut guard = queue.fd.readable().await?;
+            if queue.receive(&self.neighbors) == 0 {
+                guard.clear_ready();
+            }
+        }
+    }
+
+    /// 经指定队列的 AF_XDP 套接字发送数据报，返回需要改由普通UDP套接字发送的数据报
+    ///
+    /// 没有学到目标的回复地址、超过接口MTU或发送帧暂时用尽时数据报不经 AF_XDP 发送
+    pub fn send<'a>(&self, queue: usize, datagrams: &[(&'a [u8], SocketAddr)]) -> Vec<(&'a [u8], SocketAddr)> {
+        let queue = &self.queues[queue];
+        let mut tx = queue.tx();
+        tx.reclaim();
+
+        let mut rejected = Vec::new();
+        let mut queued = 0;
+        for &(buf, target) in datagrams {
+            let neighbor = match self.neighbors.get(&target) {
+                Some(neighbor) => *neighbor,
+                None => {
+                    rejected.push((buf, target));
+                    continue;
+                }
+            };
+            let len = frame_len(target, buf.len());
+            if len > self.max_frame {
+                rejected.push((buf, target));
+                continue;
+            }
+            let addr = match tx.free.pop() {
+                Some(addr) => addr,
+                None => {
+                    // 先提交已写入的帧，等内核发出后回收
+                    tx.tx.submit(queued);
+                    queued = 0;
+                    queue.kick();
+                    tx.reclaim();
+                    match tx.free.pop() {
+                        Some(addr) => addr,
+                        None => {
+                            rejected.push((buf, target));
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            // 发送帧只由持有发送状态锁的一方写入，提交前内核不会读取
+            let frame = unsafe { queue.umem.slice_mut(addr, len) };
+            write_frame(frame, &neighbor, target, buf);
+            unsafe {
+                *tx.tx.entry::<libc::xdp_desc>(queued) = libc::xdp_desc { addr, len: len as u32, options: 0 };
+            }
+            queued += 1;
+        }
+
+        if queued > 0 {
+            tx.tx.submit(queued);
+            queue.kick();
+        }
+        if !rejected.is_empty() {
+            log::trace!("{} 个数据报未经 AF_XDP 发送 ({})", rejected.len(), self.interface);
+        }
+        rejected
+    }
+
+    /// 删除超过指定时间没有收到数据的回复地址
+    pub fn expire(&self, idle: Duration) {
+        self.neighbors.retain(|_, neighbor| neighbor.last_seen.elapsed() <= idle);
+    }
+}
+
+/// 回复远端所需的地址，从远端发来的帧中学到
+#[derive(Clone, Copy)]
+struct Neighbor {
+    /// 远端 (或下一跳路由器) 的 MAC 地址
+    peer_mac: [u8; 6],
+    /// 本机网络接口的 MAC 地址
+    local_mac: [u8; 6],
+    /// 远端联系的本地地址和端口
+    local: SocketAddr,
+    /// 最近一次收到该远端数据的时间
+    last_seen: Instant,
+}
+
+/// 一个接收队列上的 AF_XDP 套接字和它的 UMEM
+struct XskQueue {
+    /// 注册到tokio的套接字，接收环有数据时可读
+    fd: AsyncFd<OwnedFd>,
+    rx: Mutex<RxState>,
+    tx: Mutex<TxState>,
+    /// 帧所在的内存，前 [`RX_FRAMES`] 个帧用于接收，其余用于发送；在套接字关闭后释放
+    umem: Mmap,
+}
+
+/// 接收方向的环和已取出、等待交付的数据报
+struct RxState {
+    /// 内核写入收到的帧
+    rx: XskRing,
+    /// 归还给内核用于接收的帧
+    fill: XskRing,
+    pending: VecDeque<(BytesMut, SocketAddr)>,
+    pool: BufferPool,
+}
+
+/// 发送方向的环和空闲的发送帧
+struct TxState {
+    /// 等待内核发送的帧
+    tx: XskRing,
+    /// 内核已发送完毕的帧
+    completion: XskRing,
+    free: Vec<u64>,
+}
+
+impl TxState {
+    /// 回收内核已发送完毕的帧
+    fn reclaim(&mut self) {
+        let done = self.completion.available();
+        for i in 0..done {
+            let addr = unsafe { *self.completion.entry::<u64>(i) };
+            self.free.push(addr);
+        }
+        self.completion.release(done);
+    }
+}
+
+impl XskQueue {
+    /// 创建 AF_XDP 套接字，注册 UMEM 和四个环后绑定到网络接口的接收队列
+    fn new(ifindex: u32, queue: u32) -> io::Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let raw = fd.as_raw_fd();
+
+        let umem = Mmap::anonymous((RX_FRAMES + TX_FRAMES) as usize * FRAME_SIZE)?;
+        let reg = libc::xdp_umem_reg {
+            addr: umem.ptr as u64,
+            len: umem.len as u64,
+            chunk_size: FRAME_SIZE as u32,
+            headroom: 0,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        set_option(raw, libc::XDP_UMEM_REG, &reg)?;
+        set_option(raw, libc::XDP_UMEM_FILL_RING, &RX_FRAMES)?;
+        set_option(raw, libc::XDP_UMEM_COMPLETION_RING, &TX_FRAMES)?;
+        set_option(raw, libc::XDP_RX_RING, &RX_FRAMES)?;
+        set_option(raw, libc::XDP_TX_RING, &TX_FRAMES)?;
+
+        let mut offsets: libc::xdp_mmap_offsets = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::xdp_mmap_offsets>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(raw, libc::SOL_XDP, libc::XDP_MMAP_OFFSETS, ptr::addr_of_mut!(offsets).cast(), &mut len)
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let desc_len = mem::size_of::<libc::xdp_desc>();
+        let rx = XskRing::map(raw, &offsets.rx, libc::XDP_PGOFF_RX_RING as u64, RX_FRAMES, desc_len, false)?;
+        let tx = XskRing::map(raw, &offsets.tx, libc::XDP_PGOFF_TX_RING as u64, TX_FRAMES, desc_len, true)?;
+        let mut fill = XskRing::map(raw, &offsets.fr, libc::XDP_UMEM_PGOFF_FILL_RING, RX_FRAMES, 8, true)?;
+        let completion = XskRing::map(raw, &offsets.cr, libc::XDP_UMEM_PGOFF_COMPLETION_RING, TX_FRAMES, 8, false)?;
+
+        // 所有接收帧交给内核，发送帧留作空闲
+        for i in 0..RX_FRAMES {
+            unsafe { *fill.entry::<u64>(i) = i as u64 * FRAME_SIZE as u64 };
+        }
+        fill.submit(RX_FRAMES);
+        let free = (RX_FRAMES..RX_FRAMES + TX_FRAMES).map(|i| i as u64 * FRAME_SIZE as u64).collect();
+
+        let addr = libc::sockaddr_xdp {
+            sxdp_family: libc::AF_XDP as u16,
+            sxdp_flags: 0,
+            sxdp_ifindex: ifindex,
+            sxdp_queue_id: queue,
+            sxdp_shared_umem_fd: 0,
+        };
+        let ret = unsafe {
+            libc::bind(raw, ptr::addr_of!(addr).cast(), mem::size_of::<libc::sockaddr_xdp>() as libc::socklen_t)
+        };
+        if ret < 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(e.kind(), format!("绑定接收队列 {}: {}", queue, e)));
+        }
+
+        Ok(Self {
+            fd: AsyncFd::with_interest(fd, Interest::READABLE)?,
+            rx: Mutex::new(RxState { rx, fill, pending: VecDeque::new(), pool: BufferPool::new() }),
+            tx: Mutex::new(TxState { tx, completion, free }),
+            umem,
+        })
+    }
+
+    /// 取出接收环中的所有帧，解析出数据报放入等待队列，帧归还填充环；返回取出的帧数
+    fn receive(&self, neighbors: &DashMap<SocketAddr, Neighbor>) -> u32 {
+        let mut rx = self.rx();
+        let RxState { rx: ring, fill, pending, pool } = &mut *rx;
+
+        let count = ring.available();
+        for i in 0..count {
+            let desc = unsafe { *ring.entry::<libc::xdp_desc>(i) };
+            // 内核写入的帧在归还前不会改变
+            let frame = unsafe { self.umem.slice(desc.addr, desc.len as usize) };
+            match parse_frame(frame) {
+                Some((payload, addr, neighbor)) => {
+                    let mut buf = pool.take(payload.len());
+                    buf.copy_from_slice(payload);
+                    pending.push_back((buf, addr));
+                    neighbors.insert(addr, neighbor);
+                }
+                None => log::trace!("丢弃无法解析的 AF_XDP 帧, 长度: {}", desc.len),
+            }
+            // 填充环与接收帧数相同，总能容纳归还的帧
+            unsafe { *fill.entry::<u64>(i) = desc.addr - desc.addr % FRAME_SIZE as u64 };
+        }
+        ring.release(count);
+        fill.submit(count);
+        count
+    }
+
+    /// 唤醒内核发送发送环中的帧，复制模式下一次只发送一部分，未发完时继续
+    fn kick(&self) {
+        let fd = self.fd.get_ref().as_raw_fd();
+        for _ in 0..MAX_KICKS {
+            let ret = unsafe { libc::sendto(fd, ptr::null(), 0, libc::MSG_DONTWAIT, ptr::null(), 0) };
+            if ret >= 0 {
+                return;
+            }
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::EAGAIN) => continue,
+                Some(libc::EBUSY) | Some(libc::ENOBUFS) => return,
+                _ => {
+                    log::debug!("唤醒 AF_XDP 发送失败: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn rx(&self) -> MutexGuard<'_, RxState> {
+        self.rx.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn tx(&self) -> MutexGuard<'_, TxState> {
+        self.tx.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// 与内核共享的单生产者单消费者环
+///
+/// 填充环和发送环由本端生产，接收环和完成环由本端消费
+struct XskRing {
+    _map: Mmap,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    entries: *mut u8,
+    size: u32,
+    /// 本端的位置: 生产的环为下一个写入位置，消费的环为下一个读取位置
+    head: u32,
+}
+
+// 环只在持有所在状态的锁时访问
+unsafe impl Send for XskRing {}
+
+impl XskRing {
+    /// 映射套接字的一个环
+    fn map(fd: RawFd, offsets: &libc::xdp_ring_offset, pgoff: u64, size: u32, entry_len: usize, producer: bool) -> io::Result<Self> {
+        let map = Mmap::shared(fd, offsets.desc as usize + size as usize * entry_len, pgoff)?;
+        let base = map.ptr;
+        let producer_ptr = unsafe { base.add(offsets.producer as usize) } as *const AtomicU32;
+        let consumer_ptr = unsafe { base.add(offsets.consumer as usize) } as *const AtomicU32;
+        let head = unsafe {
+            match producer {
+                true => (*producer_ptr).load(Ordering::Relaxed),
+                false => (*consumer_ptr).load(Ordering::Relaxed),
+            }
+        };
+        Ok(Self {
+            producer: producer_ptr,
+            consumer: consumer_ptr,
+            entries: unsafe { base.add(offsets.desc as usize) },
+            size,
+            head,
+            _map: map,
+        })
+    }
+
+    /// 本端位置之后第 `index` 个条目
+    ///
+    /// # Safety
+    ///
+    /// `T` 必须是环的条目类型，生产的环只能写入空闲的条目，消费的环只能读取已生产的条目
+    unsafe fn entry<T>(&self, index: u32) -> *mut T {
+        let slot = self.head.wrapping_add(index) & (self.size - 1);
+        self.entries.cast::<T>().add(slot as usize)
+    }
+
+    /// 消费的环中已生产、尚未读取的条目数
+    fn available(&self) -> u32 {
+        let producer = unsafe { (*self.producer).load(Ordering::Acquire) };
+        producer.wrapping_sub(self.head)
+    }
+
+    /// 读取完 `count` 个条目后交还内核
+    fn release(&mut self, count: u32) {
+        self.head = self.head.wrapping_add(count);
+        unsafe { (*self.consumer).store(self.head, Ordering::Release) };
+    }
+
+    /// 写入 `count` 个条目后交给内核
+    fn submit(&mut self, count: u32) {
+        self.head = self.head.wrapping_add(count);
+        unsafe { (*self.producer).store(self.head, Ordering::Release) };
+    }
+}
+
+/// 映射的内存区域，释放时解除映射
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// 映射的内存不属于任何线程，访问由持有者同步
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// 映射匿名内存，用作 UMEM
+    fn anonymous(len: usize) -> io::Result<Self> {
+        Self::map(len, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+    }
+
+    /// 映射套接字的环
+    fn shared(fd: RawFd, len: usize, offset: u64) -> io::Result<Self> {
+        Self::map(len, libc::MAP_SHARED, fd, offset)
+    }
+
+    fn map(len: usize, flags: libc::c_int, fd: RawFd, offset: u64) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, flags | libc::MAP_POPULATE, fd, offset as libc::off_t)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr: ptr.cast(), len })
+    }
+
+    /// 从 `addr` 开始的 `len` 字节，超出映射范围时为空
+    ///
+    /// # Safety
+    ///
+    /// 返回的切片存在期间其他方不能写入这段内存
+    unsafe fn slice(&self, addr: u64, len: usize) -> &[u8] {
+        match (addr as usize).checked_add(len) {
+            Some(end) if end <= self.len => slice::from_raw_parts(self.ptr.add(addr as usize), len),
+            _ => &[],
+        }
+    }
+
+    /// 从 `addr` 开始的 `len` 字节，调用方保证不超出映射范围
+    ///
+    /// # Safety
+    ///
+    /// 返回的切片存在期间其他方不能访问这段内存
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn slice_mut(&self, addr: u64, len: usize) -> &mut [u8] {
+        assert!(addr as usize + len <= self.len);
+        slice::from_raw_parts_mut(self.ptr.add(addr as usize), len)
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+/// 解析截取到的以太网帧，返回UDP数据、来源地址和回复来源所需的地址
+///
+/// 截取的数据报不再经过内核的校验和验证，损坏的数据报由消息解码丢弃
+fn parse_frame(frame: &[u8]) -> Option<(&[u8], SocketAddr, Neighbor)> {
+    if frame.len() < ETH_HEADER_LEN {
+        return None;
+    }
+    let ip = &frame[ETH_HEADER_LEN..];
+    let (src, dst, udp) = match read_u16(frame, 12) {
+        ETH_P_IPV4 => {
+            if ip.len() < IPV4_HEADER_LEN || ip[0] != 0x45 || ip[9] != IPPROTO_UDP {
+                return None;
+            }
+            let total = (read_u16(ip, 2) as usize).min(ip.len());
+            let src = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+            let dst = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+            (IpAddr::V4(src), IpAddr::V4(dst), ip.get(IPV4_HEADER_LEN..total)?)
+        }
+        ETH_P_IPV6 => {
+            if ip.len() < IPV6_HEADER_LEN || ip[6] != IPPROTO_UDP {
+                return None;
+            }
+            let end = (IPV6_HEADER_LEN + read_u16(ip, 4) as usize).min(ip.len());
+            let src: [u8; 16] = ip[8..24].try_into().ok()?;
+            let dst: [u8; 16] = ip[24..40].try_into().ok()?;
+            (IpAddr::V6(Ipv6Addr::from(src)), IpAddr::V6(Ipv6Addr::from(dst)), ip.get(IPV6_HEADER_LEN..end)?)
+        }
+        _ => return None,
+    };
+    if udp.len() < UDP_HEADER_LEN {
+        return None;
+    }
+    let payload = udp.get(UDP_HEADER_LEN..read_u16(udp, 4) as usize)?;
+
+    let neighbor = Neighbor {
+        peer_mac: frame[6..12].try_into().ok()?,
+        local_mac: frame[0..6].try_into().ok()?,
+        local: SocketAddr::new(dst, read_u16(udp, 2)),
+        last_seen: Instant::now(),
+    };
+    Some((payload, SocketAddr::new(src, read_u16(udp, 0)), neighbor))
+}
+
+/// 发往 `target` 的 `len` 字节数据报组成的以太网帧长度
+fn frame_len(target: SocketAddr, len: usize) -> usize {
+    let ip_len = if target.is_ipv4() { IPV4_HEADER_LEN } else { IPV6_HEADER_LEN };
+    ETH_HEADER_LEN + ip_len + UDP_HEADER_LEN + len
+}
+
+/// 在 `frame` 中构造从 `neighbor` 记录的本地地址发往 `target` 的以太网帧，计算IP和UDP校验和
+///
+/// 本地地址与目标的地址族相同 (从同一个帧中学到)
+fn write_frame(frame: &mut [u8], neighbor: &Neighbor, target: SocketAddr, payload: &[u8]) {
+    let udp_len = UDP_HEADER_LEN + payload.len();
+    frame[0..6].copy_from_slice(&neighbor.peer_mac);
+    frame[6..12].copy_from_slice(&neighbor.local_mac);
+
+    let udp_start = frame_len(target, 0) - UDP_HEADER_LEN;
+    let ip = &mut frame[ETH_HEADER_LEN..udp_start];
+    let pseudo = match (neighbor.local.ip(), target.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            ip.fill(0);
+            ip[0] = 0x45;
+            write_u16(ip, 2, (IPV4_HEADER_LEN + udp_len) as u16);
+            // 不分片 (DF)，标识可以为0
+            write_u16(ip, 6, 0x4000);
+            ip[8] = TTL;
+            ip[9] = IPPROTO_UDP;
+            ip[12..16].copy_from_slice(&src.octets());
+            ip[16..20].copy_from_slice(&dst.octets());
+            let csum = !vnet::fold(vnet::sum(ip, 0));
+            write_u16(ip, 10, csum);
+            vnet::sum(&ip[12..20], IPPROTO_UDP as u64 + udp_len as u64)
+        }
+        (src, dst) => {
+            let v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            ip[..4].copy_from_slice(&[0x60, 0, 0, 0]);
+            write_u16(ip, 4, udp_len as u16);
+            ip[6] = IPPROTO_UDP;
+            ip[7] = TTL;
+            ip[8..24].copy_from_slice(&v6(src).octets());
+            ip[24..40].copy_from_slice(&v6(dst).octets());
+            vnet::sum(&ip[8..40], IPPROTO_UDP as u64 + udp_len as u64)
+        }
+    };
+    write_u16(frame, 12, if target.is_ipv4() { ETH_P_IPV4 } else { ETH_P_IPV6 });
+
+    let udp = &mut frame[udp_start..udp_start + udp_len];
+    write_u16(udp, 0, neighbor.local.port());
+    write_u16(udp, 2, target.port());
+    write_u16(udp, 4, udp_len as u16);
+    write_u16(udp, 6, 0);
+    udp[UDP_HEADER_LEN..].copy_from_slice(payload);
+    // UDP校验和为0表示未计算校验和，计算结果为0时写入全1
+    let csum = match !vnet::fold(vnet::sum(udp, pseudo)) {
+        0 => 0xffff,
+        csum => csum,
+    };
+    write_u16(udp, 6, csum);
+}
+
+/// 网络接口的接收队列数
+fn rx_queue_count(interface: &str) -> io::Result<u32> {
+    let count = fs::read_dir(format!("/sys/class/net/{}/queues", interface))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("rx-"))
+        .count();
+    Ok(count.max(1) as u32)
+}
+
+/// 创建按接收队列索引保存 AF_XDP 套接字的映射表
+fn create_xsk_map(entries: u32) -> io::Result<OwnedFd> {
+    let attr = MapCreateAttr {
+        map_type: BPF_MAP_TYPE_XSKMAP,
+        key_size: 4,
+        value_size: 4,
+        max_entries: entries,
+        map_flags: 0,
+    };
+    bpf_fd(BPF_MAP_CREATE, &attr).map_err(|e| io::Error::new(e.kind(), format!("创建 XSKMAP 失败: {}", e)))
+}
+
+/// 把接收队列的 AF_XDP 套接字放入映射表
+fn update_xsk_map(map: &OwnedFd, queue: u32, socket: RawFd) -> io::Result<()> {
+    let value = socket as u32;
+    let attr = MapUpdateAttr {
+        map_fd: map.as_raw_fd() as u32,
+        _pad: 0,
+        key: ptr::addr_of!(queue) as u64,
+        value: ptr::addr_of!(value) as u64,
+        flags: 0,
+    };
+    bpf(BPF_MAP_UPDATE_ELEM, &attr).map(|_| ())
+}
+
+/// 加载 XDP 程序，校验失败时在错误中附带校验器日志
+fn load_program(insns: &[Insn]) -> io::Result<OwnedFd> {
+    let license = c"Dual MIT/GPL";
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_XDP,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 0,
+        log_size: 0,
+        log_buf: 0,
+        kern_version: 0,
+        prog_flags: 0,
+        prog_name: *b"vswitch_xdp\0\0\0\0\0",
+        prog_ifindex: 0,
+        expected_attach_type: BPF_XDP,
+    };
+    match bpf_fd(BPF_PROG_LOAD, &attr) {
+        Ok(fd) => Ok(fd),
+        Err(e) => {
+            let mut log = vec![0u8; VERIFIER_LOG_LEN];
+            attr.log_level = 1;
+            attr.log_size = log.len() as u32;
+            attr.log_buf = log.as_mut_ptr() as u64;
+            let _ = bpf_fd(BPF_PROG_LOAD, &attr);
+            let end = log.iter().position(|&b| b == 0).unwrap_or(log.len());
+            Err(io::Error::new(e.kind(), format!("加载 XDP 程序失败: {}\n{}", e, String::from_utf8_lossy(&log[..end]))))
+        }
+    }
+}
+
+/// 以 BPF 链接把程序挂载到网络接口，链接关闭时程序自动卸载
+fn attach_program(program: &OwnedFd, ifindex: u32) -> io::Result<OwnedFd> {
+    let attr = LinkCreateAttr {
+        prog_fd: program.as_raw_fd() as u32,
+        target_ifindex: ifindex,
+        attach_type: BPF_XDP,
+        flags: 0,
+    };
+    bpf_fd(BPF_LINK_CREATE, &attr)
+}
+
+/// 生成截取UDP监听地址的 XDP 程序
+///
+/// 只截取不带IPv4选项、不分片的IPv4数据报和没有扩展头部的IPv6数据报，目的端口和目的地址
+/// 匹配某个监听地址时 (未指定地址只匹配端口和地址族) 以接收队列索引查找 AF_XDP 套接字重定向，
+/// 该队列没有套接字时交给内核 (`XDP_PASS`)
+fn steering_program(listeners: &[SocketAddr], map: RawFd) -> Vec<Insn> {
+    use Label::*;
+    use Size::*;
+    // 数据包中的多字节字段按网络字节序存放，以本机字节序加载后比较
+    let wire = |value: u16| u16::from_ne_bytes(value.to_be_bytes()) as i32;
+    let word = |bytes: &[u8]| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i32;
+    let mut asm = Asm::default();
+
+    // r2 = data, r3 = data_end，先确认能容纳以太网、IPv4和UDP头部
+    asm.load(W, 2, 1, 0);
+    asm.load(W, 3, 1, 4);
+    asm.mov_reg(4, 2);
+    asm.add(4, (ETH_HEADER_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN) as i32);
+    asm.jump_gt_reg(4, 3, Pass);
+    asm.load(H, 5, 2, 12);
+    asm.jump_eq(5, wire(ETH_P_IPV4), Ipv4);
+    asm.jump_ne(5, wire(ETH_P_IPV6), Pass);
+
+    // IPv6: 下一个头部必须是UDP，r5 = 目的端口，r6~r9 = 目的地址
+    asm.mov_reg(4, 2);
+    asm.add(4, (ETH_HEADER_LEN + IPV6_HEADER_LEN + UDP_HEADER_LEN) as i32);
+    asm.jump_gt_reg(4, 3, Pass);
+    asm.load(B, 5, 2, (ETH_HEADER_LEN + 6) as i16);
+    asm.jump_ne(5, IPPROTO_UDP as i32, Pass);
+    asm.load(H, 5, 2, (ETH_HEADER_LEN + IPV6_HEADER_LEN + 2) as i16);
+    for (index, reg) in (6..10).enumerate() {
+        asm.load(W, reg, 2, (ETH_HEADER_LEN + 24 + index * 4) as i16);
+    }
+    for listener in listeners {
+        match listener.ip() {
+            IpAddr::V6(ip) if ip.is_unspecified() => asm.jump_eq(5, wire(listener.port()), Redirect),
+            IpAddr::V6(ip) if ip.to_ipv4_mapped().is_none() => {
+                let octets = ip.octets();
+                asm.skip_ne(5, wire(listener.port()), 4);
+                asm.skip_ne32(6, word(&octets[0..4]), 3);
+                asm.skip_ne32(7, word(&octets[4..8]), 2);
+                asm.skip_ne32(8, word(&octets[8..12]), 1);
+                asm.jump_eq32(9, word(&octets[12..16]), Redirect);
+            }
+            _ => {}
+        }
+    }
+    asm.jump(Pass);
+
+    // IPv4: 头部长度为20字节、协议为UDP、不是分片，r5 = 目的端口，r6 = 目的地址
+    asm.bind(Ipv4);
+    asm.load(B, 5, 2, ETH_HEADER_LEN as i16);
+    asm.jump_ne(5, 0x45, Pass);
+    asm.load(B, 5, 2, (ETH_HEADER_LEN + 9) as i16);
+    asm.jump_ne(5, IPPROTO_UDP as i32, Pass);
+    asm.load(H, 5, 2, (ETH_HEADER_LEN + 6) as i16);
+    asm.and(5, wire(0x3fff));
+    asm.jump_ne(5, 0, Pass);
+    asm.load(W, 6, 2, (ETH_HEADER_LEN + 16) as i16);
+    asm.load(H, 5, 2, (ETH_HEADER_LEN + IPV4_HEADER_LEN + 2) as i16);
+    for listener in listeners {
+        match listener.ip().to_canonical() {
+            IpAddr::V4(ip) if ip.is_unspecified() => asm.jump_eq(5, wire(listener.port()), Redirect),
+            IpAddr::V4(ip) => {
+                asm.skip_ne(5, wire(listener.port()), 1);
+                asm.jump_eq32(6, word(&ip.octets()), Redirect);
+            }
+            IpAddr::V6(_) => {}
+        }
+    }
+
+    asm.bind(Pass);
+    asm.mov(0, XDP_PASS);
+    asm.exit();
+
+    // bpf_redirect_map(&xsks, ctx->rx_queue_index, XDP_PASS)
+    asm.bind(Redirect);
+    asm.load(W, 2, 1, 16);
+    asm.load_map(1, map);
+    asm.mov(3, XDP_PASS);
+    asm.call(BPF_FUNC_REDIRECT_MAP);
+    asm.exit();
+
+    asm.finish()
+}
+
+/// eBPF 指令
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Insn {
+    code: u8,
+    /// 低4位为目标寄存器，高4位为源寄存器
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+/// 跳转目标
+#[derive(Clone, Copy)]
+enum Label {
+    Ipv4,
+    Pass,
+    Redirect,
+}
+
+/// 加载的数据宽度
+#[derive(Clone, Copy)]
+enum Size {
+    B = 0x10,
+    H = 0x08,
+    W = 0x00,
+}
+
+/// 生成 eBPF 指令，跳转到标签的偏移在 [`Asm::finish`] 时填入
+#[derive(Default)]
+struct Asm {
+    insns: Vec<Insn>,
+    labels: [Option<usize>; 3],
+    jumps: Vec<(usize, Label)>,
+}
+
+impl Asm {
+    fn emit(&mut self, code: u8, dst: u8, src: u8, off: i16, imm: i32) {
+        self.insns.push(Insn { code, regs: dst | (src << 4), off, imm });
+    }
+
+    fn emit_jump(&mut self, code: u8, dst: u8, src: u8, imm: i32, label: Label) {
+        self.jumps.push((self.insns.len(), label));
+        self.emit(code, dst, src, 0, imm);
+    }
+
+    fn bind(&mut self, label: Label) {
+        self.labels[label as usize] = Some(self.insns.len());
+    }
+
+    /// dst = *(size *)(src + off)
+    fn load(&mut self, size: Size, dst: u8, src: u8, off: i16) {
+        self.emit(0x61 | size as u8, dst, src, off, 0);
+    }
+
+    fn mov(&mut self, dst: u8, imm: i32) {
+        self.emit(0xb7, dst, 0, 0, imm);
+    }
+
+    fn mov_reg(&mut self, dst: u8, src: u8) {
+        self.emit(0xbf, dst, src, 0, 0);
+    }
+
+    fn add(&mut self, dst: u8, imm: i32) {
+        self.emit(0x07, dst, 0, 0, imm);
+    }
+
+    fn and(&mut self, dst: u8, imm: i32) {
+        self.emit(0x57, dst, 0, 0, imm);
+    }
+
+    fn jump(&mut self, label: Label) {
+        self.emit_jump(0x05, 0, 0, 0, label);
+    }
+
+    fn jump_eq(&mut self, dst: u8, imm: i32, label: Label) {
+        self.emit_jump(0x15, dst, 0, imm, label);
+    }
+
+    fn jump_ne(&mut self, dst: u8, imm: i32, label: Label) {
+        self.emit_jump(0x55, dst, 0, imm, label);
+    }
+
+    /// 32位比较: 低32位相等时跳转，加载的字视为无符号数，不能与符号扩展的立即数做64位比较
+    fn jump_eq32(&mut self, dst: u8, imm: i32, label: Label) {
+        self.emit_jump(0x16, dst, 0, imm, label);
+    }
+
+    /// 不相等时跳过之后的 `count` 条指令
+    fn skip_ne(&mut self, dst: u8, imm: i32, count: i16) {
+        self.emit(0x55, dst, 0, count, imm);
+    }
+
+    /// 32位比较: 低32位不相等时跳过之后的 `count` 条指令
+    fn skip_ne32(&mut self, dst: u8, imm: i32, count: i16) {
+        self.emit(0x56, dst, 0, count, imm);
+    }
+
+    fn jump_gt_reg(&mut self, dst: u8, src: u8, label: Label) {
+        self.emit_jump(0x2d, dst, src, 0, label);
+    }
+
+    /// 以映射表的文件描述符加载映射表地址 (占两条指令)
+    fn load_map(&mut self, dst: u8, map: RawFd) {
+        const BPF_PSEUDO_MAP_FD: u8 = 1;
+        self.emit(0x18, dst, BPF_PSEUDO_MAP_FD, 0, map);
+        self.emit(0, 0, 0, 0, 0);
+    }
+
+    fn call(&mut self, helper: i32) {
+        self.emit(0x85, 0, 0, 0, helper);
+    }
+
+    fn exit(&mut self) {
+        self.emit(0x95, 0, 0, 0, 0);
+    }
+
+    fn finish(mut self) -> Vec<Insn> {
+        for (at, label) in self.jumps {
+            let target = self.labels[label as usize].expect("跳转到未绑定的标签");
+            self.insns[at].off = (target as isize - at as isize - 1) as i16;
+        }
+        self.insns
+    }
+}
+
+/// `BPF_MAP_CREATE` 的参数
+#[repr(C)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+/// `BPF_MAP_UPDATE_ELEM` 的参数
+#[repr(C)]
+struct MapUpdateAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+/// `BPF_PROG_LOAD` 的参数
+#[repr(C)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+/// `BPF_LINK_CREATE` 的参数
+#[repr(C)]
+struct LinkCreateAttr {
+    prog_fd: u32,
+    target_ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+}
+
+/// 执行 bpf 系统调用
+fn bpf<T>(cmd: libc::c_long, attr: &T) -> io::Result<libc::c_long> {
+    let ret = unsafe { libc::syscall(libc::SYS_bpf, cmd, attr as *const T, mem::size_of::<T>() as libc::c_uint) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+/// 执行返回文件描述符的 bpf 系统调用
+fn bpf_fd<T>(cmd: libc::c_long, attr: &T) -> io::Result<OwnedFd> {
+    let fd = bpf(cmd, attr)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// 设置 AF_XDP 套接字选项
+fn set_option<T>(fd: RawFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(fd, libc::SOL_XDP, name, (value as *const T).cast(), mem::size_of::<T>() as libc::socklen_t)
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}